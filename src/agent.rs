@@ -3,165 +3,2353 @@
 //! Agent module for managing conversational interactions with OpenAI models
 //! via both HTTP and (optionally) WebSocket "Realtime" Beta.
 
+use crate::cache::ResponseCache;
 use crate::client::OpenAIClient;
 use crate::config::OpenAIConfig;
 use crate::error::OpenAIAgentError;
-use crate::models::{ChatMessage, ChatRequest, ChatResponse, ToolCall};
-use crate::tools::ToolRegistry;
-use crate::websocket_client::{WebSocketClient, RealtimeEvent, ServerEvent};
-use crate::persistence::{EntityId, PersistenceStore, generate_id};
-use std::sync::Arc;
+use crate::events::{AgentEvent, AgentEventKind, AgentEventSender};
+use crate::interceptor::Interceptor;
+use crate::partial_json::parse_partial_json;
+use crate::models::{
+    ChatMessage, ChatRequest, ChatResponse, FunctionCall, LogProbs, MessageContent, PredictionContent, ToolCall,
+    Usage,
+};
+use crate::pricing::estimate_cost;
+use crate::prompt::PromptTemplate;
+use crate::stream::StreamAccumulator;
+use crate::tools::{check_parameters_schema, validate_arguments, ContextMap, ToolContext, ToolError, ToolRegistry, ToolResultFormatter};
+use crate::transport::ChatTransport;
+use crate::utils::{count_message_tokens, count_tokens, count_tokens_or_estimate};
+use crate::websocket_client::{
+    ProcessEnd, RealtimeEvent, RealtimeSessionConfig, ServerEvent, StopHandle, WebSocketClient,
+};
+use crate::persistence::{EntityId, IdGenerator, PersistenceStore, derive_id, generate_id};
+use futures::{FutureExt, Stream, StreamExt};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::future::Future;
+use std::ops::Range;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Maximum number of characters of a tool output or tool-call argument list shown in
+/// a non-verbose transcript.
+const TRANSCRIPT_TRUNCATE_CHARS: usize = 200;
+
+/// Instructions given to the model when compacting conversation history.
+const SUMMARY_PROMPT: &str = "Summarize the following conversation transcript concisely. \
+Preserve facts, decisions, user preferences, and the results of any tool calls that later \
+messages may depend on.";
+
+/// Configuration for automatic conversation summarization.
+///
+/// When the conversation grows past the configured thresholds, the oldest messages
+/// (after any leading system prompts) are replaced by a single system message
+/// containing a model-generated summary. A later summarization folds the previous
+/// summary into the new one.
+#[derive(Debug, Clone)]
+pub struct SummarizeConfig {
+    /// Estimated prompt token count (history plus tool definitions) above which
+    /// the history is compacted
+    pub trigger_tokens: usize,
+
+    /// Optional message count above which the history is compacted
+    pub trigger_messages: Option<usize>,
+
+    /// Number of most recent messages kept verbatim
+    pub keep_recent: usize,
+
+    /// Model used for the summarization call; defaults to the agent's model
+    pub summary_model: Option<String>,
+}
+
+impl Default for SummarizeConfig {
+    fn default() -> Self {
+        Self {
+            trigger_tokens: 8000,
+            trigger_messages: None,
+            keep_recent: 6,
+            summary_model: None,
+        }
+    }
+}
+
+/// The current state of the agent, including conversation history and token usage.
+#[derive(Debug, Clone, Default)]
+pub struct AgentState {
+    /// Conversation history including all messages exchanged
+    pub messages: Vec<ChatMessage>,
+
+    /// Running count of tokens used in the conversation
+    pub token_count: usize,
+
+    /// Counter bumped on every mutation of the history, for syncing clients
+    pub version: u64,
+
+    /// Number of messages dropped by the history cap or folded into a summary so
+    /// far, less the summary messages that replaced them
+    pub dropped_count: usize,
+
+    /// Token usage of each model response, oldest first
+    pub turn_usage: Vec<TurnUsage>,
+
+    /// Tools offered to the model on each turn, oldest first
+    pub turn_tools: Vec<TurnTools>,
+}
+
+/// Token usage of a single model response, recorded in `AgentState::turn_usage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TurnUsage {
+    /// Position of the response's message in the conversation, counting messages
+    /// since dropped by the history cap (see `AgentState::oldest_kept_index`)
+    pub message_index: usize,
+
+    /// Tokens in the prompt sent for this turn
+    pub prompt_tokens: usize,
+
+    /// Tokens in the response
+    pub completion_tokens: usize,
+
+    /// Prompt and completion tokens together
+    pub total_tokens: usize,
+
+    /// Whether the counts were estimated because the API did not report them
+    #[serde(default)]
+    pub estimated: bool,
+}
+
+/// The tools sent with a single model request, recorded in `AgentState::turn_tools`.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TurnTools {
+    /// Position of the response's message in the conversation, as in `TurnUsage`
+    pub message_index: usize,
+
+    /// Names of the tools in the request, hosted tools by their type
+    pub tools: Vec<String>,
+}
+
+impl AgentState {
+    /// Returns the total number of tokens used in the conversation so far
+    pub fn token_count(&self) -> usize {
+        self.token_count
+    }
+
+    /// Returns the token usage of each model response, oldest first.
+    ///
+    /// Side requests such as history summarization are counted in `token_count`
+    /// but not listed here.
+    pub fn turn_usage(&self) -> &[TurnUsage] {
+        &self.turn_usage
+    }
+
+    /// Returns the tools offered to the model on each turn, oldest first.
+    ///
+    /// Unlike `turn_usage`, every turn is listed, including those the API
+    /// reported no usage for.
+    pub fn turn_tools(&self) -> &[TurnTools] {
+        &self.turn_tools
+    }
+
+    /// Returns the turn that used the most tokens, if any; the earliest one on ties.
+    pub fn most_expensive_turn(&self) -> Option<&TurnUsage> {
+        self.turn_usage
+            .iter()
+            .rev()
+            .max_by_key(|turn| turn.total_tokens)
+    }
+
+    /// Returns the number of messages in the conversation history
+    pub fn message_count(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Returns the number of messages in the full conversation, counting those
+    /// since dropped by the history cap or folded into a summary.
+    ///
+    /// Unlike `message_count`, this only grows as messages are added, so the
+    /// difference between two calls is the number of messages added in between,
+    /// which are always the last ones in the history.
+    pub fn total_message_count(&self) -> usize {
+        self.dropped_count + self.messages.len()
+    }
+
+    /// Returns an iterator over references to the messages in the conversation
+    pub fn messages(&self) -> impl Iterator<Item = &ChatMessage> {
+        self.messages.iter()
+    }
+
+    /// Returns the current version of the history.
+    ///
+    /// The version increases every time the agent changes the history, so a client
+    /// that mirrors the conversation can tell whether it is up to date.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the messages from `index` onwards (empty if `index` is past the end).
+    ///
+    /// A client that already holds the first `index` messages can use this to
+    /// fetch only what is new.
+    pub fn messages_since(&self, index: usize) -> &[ChatMessage] {
+        &self.messages[index.min(self.messages.len())..]
+    }
+
+    /// Returns the position, in the full conversation, of the oldest message still
+    /// kept after the leading system prompts.
+    ///
+    /// This is the number of leading system messages plus the number of messages
+    /// dropped by `AgentBuilder::with_max_history_messages` or folded into a
+    /// summary (see `dropped_count`), so a client holding the complete
+    /// conversation knows which of its messages the model still sees.
+    pub fn oldest_kept_index(&self) -> usize {
+        leading_system_count(&self.messages) + self.dropped_count
+    }
+
+    /// Drops the oldest messages after the leading system prompts until at most
+    /// `max_messages` remain besides them, returning how many were dropped.
+    ///
+    /// Tool results are dropped together with the call that produced them, so
+    /// the kept history never starts with an orphaned tool result. The most
+    /// recent user message and everything after it, such as a tool exchange in
+    /// progress, are always kept. Pinned messages, and the tool exchanges they
+    /// belong to, are kept in place too, even if more than `max_messages` then
+    /// remain.
+    pub(crate) fn trim_to(&mut self, max_messages: usize) -> usize {
+        let start = leading_system_count(&self.messages);
+        let mut end = start + (self.messages.len() - start).saturating_sub(max_messages);
+        while end < self.messages.len() && self.messages[end].role == "tool" {
+            end += 1;
+        }
+        if let Some(last_user) = self.messages.iter().rposition(|message| message.role == "user") {
+            end = end.min(last_user.max(start));
+        }
+
+        let keep = pinned_in(&self.messages, start..end);
+        let dropped = keep.iter().filter(|kept| !**kept).count();
+        if dropped > 0 {
+            let mut index = 0;
+            self.messages.retain(|_| {
+                let kept = !(start..end).contains(&index) || keep[index - start];
+                index += 1;
+                kept
+            });
+            self.dropped_count += dropped;
+            self.touch();
+        }
+        dropped
+    }
+
+    /// Pins the message at `index`, so history trimming and summarization keep it.
+    ///
+    /// Returns `false` if there is no message at `index`.
+    pub fn pin(&mut self, index: usize) -> bool {
+        self.set_pinned(index, true)
+    }
+
+    /// Unpins the message at `index`, letting it be trimmed again.
+    ///
+    /// Returns `false` if there is no message at `index`.
+    pub fn unpin(&mut self, index: usize) -> bool {
+        self.set_pinned(index, false)
+    }
+
+    fn set_pinned(&mut self, index: usize, pinned: bool) -> bool {
+        match self.messages.get_mut(index) {
+            Some(message) => {
+                message.pinned = pinned;
+                self.touch();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Appends a message, giving it an ID if it has none, and bumps the version
+    pub(crate) fn push_message(&mut self, mut message: ChatMessage) {
+        message.ensure_id();
+        self.messages.push(message);
+        self.touch();
+    }
+
+    /// Bumps the version after the history was changed in place
+    pub(crate) fn touch(&mut self) {
+        self.version += 1;
+    }
+
+    /// Renders the conversation as a training example in the JSON Lines format of
+    /// OpenAI's fine-tuning API: one `{"messages": [...]}` line, newline-terminated.
+    ///
+    /// Developer messages are written with the `system` role. Unfinished messages
+    /// from interrupted streams are left out. With `include_tools`, assistant tool
+    /// calls and `tool` results are kept in the endpoint's format, for training
+    /// function calling; otherwise tool results and assistant messages that only
+    /// call tools are dropped, and tool calls are removed from the rest. Returns
+    /// an empty string if no assistant message remains, since such an example
+    /// cannot be trained on.
+    pub fn to_finetune_jsonl(&self, include_tools: bool) -> Result<String, OpenAIAgentError> {
+        let mut messages = Vec::new();
+        for message in self.messages.iter().filter(|m| !m.incomplete) {
+            let content = message.content.as_ref().filter(|c| !c.is_blank());
+            let tool_calls = message.tool_calls.as_ref().filter(|calls| !calls.is_empty());
+
+            let mut entry = serde_json::Map::new();
+            match message.role.as_str() {
+                "tool" => {
+                    if !include_tools {
+                        continue;
+                    }
+                    entry.insert("role".into(), "tool".into());
+                    entry.insert("tool_call_id".into(), message.tool_call_id.clone().unwrap_or_default().into());
+                    entry.insert("content".into(), message.text().unwrap_or_default().into());
+                }
+                "assistant" => {
+                    let tool_calls = tool_calls.filter(|_| include_tools);
+                    if content.is_none() && tool_calls.is_none() {
+                        continue;
+                    }
+                    entry.insert("role".into(), "assistant".into());
+                    if let Some(content) = content {
+                        entry.insert("content".into(), serde_json::to_value(content)?);
+                    }
+                    if let Some(tool_calls) = tool_calls {
+                        let calls = tool_calls
+                            .iter()
+                            .map(|call| {
+                                serde_json::json!({
+                                    "id": call.id,
+                                    "type": "function",
+                                    "function": {
+                                        "name": call.get_name(),
+                                        "arguments": call.get_arguments(),
+                                    },
+                                })
+                            })
+                            .collect();
+                        entry.insert("tool_calls".into(), serde_json::Value::Array(calls));
+                    }
+                }
+                role => {
+                    let Some(content) = content else {
+                        continue;
+                    };
+                    let role = if role == "developer" { "system" } else { role };
+                    entry.insert("role".into(), role.into());
+                    entry.insert("content".into(), serde_json::to_value(content)?);
+                }
+            }
+            if let Some(name) = message.name.as_ref().filter(|_| message.role != "tool") {
+                entry.insert("name".into(), name.clone().into());
+            }
+            messages.push(serde_json::Value::Object(entry));
+        }
+
+        if !messages.iter().any(|m| m["role"] == "assistant") {
+            return Ok(String::new());
+        }
+
+        let mut line = serde_json::to_string(&serde_json::json!({ "messages": messages }))?;
+        line.push('\n');
+        Ok(line)
+    }
+
+    /// Renders the conversation as a readable transcript.
+    ///
+    /// Each message becomes a line such as `[user] ...` or `[tool:name] ...`. Tool calls
+    /// are summarized as `→ call tool_name(args)` and long tool outputs are truncated.
+    pub fn transcript(&self) -> String {
+        self.render_transcript(false)
+    }
+
+    /// Renders the conversation like [`AgentState::transcript`], but without truncation
+    /// and with the raw JSON of every message appended.
+    pub fn transcript_verbose(&self) -> String {
+        self.render_transcript(true)
+    }
+
+    fn render_transcript(&self, verbose: bool) -> String {
+        let mut out = String::new();
+
+        for message in &self.messages {
+            let label = match (message.role.as_str(), &message.name) {
+                ("tool", Some(name)) => format!("tool:{}", name),
+                (role, _) => role.to_string(),
+            };
+
+            let content = message.text().unwrap_or_default();
+            let content = if message.role == "tool" && !verbose {
+                truncate_chars(&content, TRANSCRIPT_TRUNCATE_CHARS)
+            } else {
+                content
+            };
+
+            if content.is_empty() {
+                let _ = writeln!(out, "[{}]", label);
+            } else {
+                let _ = writeln!(out, "[{}] {}", label, content);
+            }
+
+            if let Some(tool_calls) = &message.tool_calls {
+                for tool_call in tool_calls {
+                    let arguments = tool_call.get_arguments();
+                    let arguments = if verbose {
+                        arguments
+                    } else {
+                        truncate_chars(&arguments, TRANSCRIPT_TRUNCATE_CHARS)
+                    };
+                    let _ = writeln!(out, "  → call {}({})", tool_call.get_name(), arguments);
+                }
+            }
+
+            if verbose
+                && let Ok(raw) = serde_json::to_string(message)
+            {
+                let _ = writeln!(out, "  raw: {}", raw);
+            }
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for AgentState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.transcript())
+    }
+}
+
+/// Prefix of the system message holding a conversation summary.
+const SUMMARY_PREFIX: &str = "Summary of the conversation so far:\n";
+
+/// Returns whether a message is a summary written by `Agent::summarize_now`.
+fn is_summary(message: &ChatMessage) -> bool {
+    message.role == "system" && message.text().is_some_and(|text| text.starts_with(SUMMARY_PREFIX))
+}
+
+/// Determines which messages to compact: everything after the leading system
+/// prompts up to the most recent `keep_recent` messages, extended so that tool
+/// results are never separated from the call that produced them. A previous
+/// summary, the last of the leading system messages, is compacted too.
+fn compaction_range(messages: &[ChatMessage], keep_recent: usize) -> Option<Range<usize>> {
+    let mut start = leading_system_count(messages);
+    if start > 0 && is_summary(&messages[start - 1]) {
+        start -= 1;
+    }
+
+    let mut end = messages.len().saturating_sub(keep_recent);
+    while end < messages.len() && messages[end].role == "tool" {
+        end += 1;
+    }
+
+    (end > start).then_some(start..end)
+}
+
+/// Flags the messages in `range` that must survive trimming: pinned messages and
+/// the rest of any tool call exchange one belongs to, so no call loses its results.
+fn pinned_in(messages: &[ChatMessage], range: Range<usize>) -> Vec<bool> {
+    let mut keep = vec![false; range.len()];
+    let mut start = range.start;
+    while start < range.end {
+        let mut end = start + 1;
+        if has_tool_calls(&messages[start]) {
+            while end < range.end && messages[end].role == "tool" {
+                end += 1;
+            }
+        }
+        if messages[start..end].iter().any(|m| m.pinned) {
+            keep[start - range.start..end - range.start].fill(true);
+        }
+        start = end;
+    }
+    keep
+}
+
+/// Counts the system and developer prompts at the start of the history.
+fn leading_system_count(messages: &[ChatMessage]) -> usize {
+    messages
+        .iter()
+        .take_while(|m| m.role == "system" || m.role == "developer")
+        .count()
+}
+
+/// Returns whether a message is an assistant message requesting tool calls.
+fn has_tool_calls(message: &ChatMessage) -> bool {
+    message
+        .tool_calls
+        .as_ref()
+        .is_some_and(|tool_calls| !tool_calls.is_empty())
+}
+
+/// Returns whether a message takes part in a tool call/result pairing.
+fn is_tool_exchange(message: &ChatMessage) -> bool {
+    message.role == "tool" || has_tool_calls(message)
+}
+
+/// Truncates `text` to at most `max_chars` characters, noting the original length.
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    let total = text.chars().count();
+    if total <= max_chars {
+        return text.to_string();
+    }
+
+    let truncated: String = text.chars().take(max_chars).collect();
+    format!("{}… ({} chars total)", truncated, total)
+}
+
+/// Stream of assistant text fragments returned by `Agent::run_stream`.
+pub type TextStream<'a> = Pin<Box<dyn Stream<Item = Result<String, OpenAIAgentError>> + Send + 'a>>;
+
+/// An item of the stream returned by `Agent::run_typed_stream`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartialValue<T> {
+    /// Best-effort parse of the JSON received so far
+    Partial(serde_json::Value),
+
+    /// The complete, parsed answer; always the last item of a successful run
+    Complete(T),
+}
+
+/// Stream of partial and final structured output returned by `Agent::run_typed_stream`.
+pub type TypedStream<'a, T> = Pin<Box<dyn Stream<Item = Result<PartialValue<T>, OpenAIAgentError>> + Send + 'a>>;
+
+/// Post-processing function applied to the final response content before it is returned.
+pub type ResponseTransform = Arc<dyn Fn(String) -> String + Send + Sync>;
+
+/// Callback deciding how a run proceeds after each model response, given its
+/// finish reason and message. See `AgentBuilder::with_finish_reason_handler`.
+pub type FinishReasonHandler = Arc<dyn Fn(&str, &ChatMessage) -> FinishAction + Send + Sync>;
+
+/// What a run does with a model response, as chosen by a `FinishReasonHandler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FinishAction {
+    /// Apply the built-in policy, as without a handler (the default)
+    #[default]
+    Default,
+
+    /// End the run with the message's content; tool calls are not executed
+    Return,
+
+    /// Keep the message and request another turn: tool calls are executed as
+    /// usual, and for a message without tool calls the next request ends with a
+    /// user message asking the model to continue where it left off. That message
+    /// is sent with the next request only and never stored in the history. The run
+    /// returns the content of its final response only
+    Continue,
+
+    /// End the run with `OpenAIAgentError::Agent`; the message is not kept
+    Error,
+}
+
+/// A way of asking the model for JSON output, used by `Agent::run_typed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructuredOutputMode {
+    /// A `json_schema` response format carrying the output type's schema
+    JsonSchema,
+
+    /// A `json_object` response format, with the schema described in the system prompt
+    JsonObject,
+
+    /// No response format; the schema is described in the system prompt and the
+    /// JSON is extracted from the text answer
+    PlainText,
+}
+
+impl StructuredOutputMode {
+    /// The default fallback order, from most to least strict.
+    pub const DEFAULT_ORDER: [StructuredOutputMode; 3] = [
+        StructuredOutputMode::JsonSchema,
+        StructuredOutputMode::JsonObject,
+        StructuredOutputMode::PlainText,
+    ];
+}
+
+/// Request changes applied while `run_typed` is running.
+#[derive(Debug, Clone)]
+struct StructuredOutput {
+    response_format: Option<serde_json::Value>,
+    instructions: Option<String>,
+}
+
+impl StructuredOutput {
+    fn new(mode: StructuredOutputMode, name: &str, schema: &serde_json::Value) -> Self {
+        let describe = |prefix: &str| {
+            Some(format!(
+                "{} Respond only with a JSON value matching this JSON Schema:\n{}",
+                prefix, schema
+            ))
+        };
+
+        match mode {
+            StructuredOutputMode::JsonSchema => Self {
+                response_format: Some(serde_json::json!({
+                    "type": "json_schema",
+                    "json_schema": { "name": name, "schema": schema },
+                })),
+                instructions: None,
+            },
+            StructuredOutputMode::JsonObject => Self {
+                response_format: Some(serde_json::json!({ "type": "json_object" })),
+                instructions: describe("Your final answer must be JSON."),
+            },
+            StructuredOutputMode::PlainText => Self {
+                response_format: None,
+                instructions: describe("Your final answer must be JSON, without any other text."),
+            },
+        }
+    }
+}
+
+/// Appends instructions to the leading system message of a request, adding one if needed.
+fn inject_system_instructions(messages: &mut Vec<ChatMessage>, instructions: Option<&str>) {
+    let Some(instructions) = instructions else {
+        return;
+    };
+
+    match messages.first_mut() {
+        Some(first) if first.role == "system" => {
+            let text = first.text().unwrap_or_default();
+            first.content = Some(MessageContent::Text(format!("{}\n\n{}", text, instructions)));
+        }
+        _ => messages.insert(0, ChatMessage::system(instructions)),
+    }
+}
+
+/// Sent as a user message to have the model carry on with a response cut off mid-way.
+const CONTINUE_PROMPT: &str = "Continue exactly where you left off.";
+
+/// Delay before the first retry of a failed turn; doubled for each further
+/// retry of the same request
+const RUN_RETRY_INITIAL_DELAY_MS: u64 = 200;
+
+/// Added to the request when retrying a turn stopped by the content filter.
+const CONTENT_FILTER_RETRY_NOTE: &str = "Your previous answer was stopped by the content filter. \
+Answer again, rephrasing as needed to stay within the content policy, or explain briefly what you cannot help with.";
+
+/// Extracts the message of a caught panic.
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic payload".to_string())
+}
+
+/// Whether an error is the API rejecting the requested response format.
+fn is_unsupported_response_format(error: &OpenAIAgentError) -> bool {
+    match error {
+        OpenAIAgentError::Api { status: 400, message } => {
+            let message = message.to_lowercase();
+            message.contains("response_format") || message.contains("json_schema")
+        }
+        _ => false,
+    }
+}
+
+/// Parses JSON from a model answer, tolerating markdown code fences and
+/// surrounding prose.
+fn parse_json_output<T: DeserializeOwned>(content: &str) -> Result<T, OpenAIAgentError> {
+    let trimmed = content.trim();
+    let unfenced = trimmed
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map(|inner| {
+            // Drop the language tag on the opening fence, e.g. ```json
+            match inner.split_once('\n') {
+                Some((tag, body)) if !tag.trim_start().starts_with(['{', '[']) => body,
+                _ => inner,
+            }
+        })
+        .unwrap_or(trimmed)
+        .trim();
+
+    let first_error = match serde_json::from_str(unfenced) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+
+    // Fall back to the outermost object or array embedded in the text
+    let start = unfenced.find(['{', '[']);
+    let end = unfenced.rfind(['}', ']']);
+    if let (Some(start), Some(end)) = (start, end)
+        && start < end
+        && let Ok(value) = serde_json::from_str(&unfenced[start..=end])
+    {
+        return Ok(value);
+    }
+
+    Err(OpenAIAgentError::Parse(format!(
+        "Failed to parse structured output: {}; raw content: {}",
+        first_error, content
+    )))
+}
+
+/// How tool calls are answered when replaying a conversation with `Agent::replay_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplayTools {
+    /// Execute the tools again
+    #[default]
+    Execute,
+
+    /// Answer with the result recorded in the stored conversation for the same tool
+    /// name and arguments, without running the tool
+    Recorded,
+}
+
+/// Maps each recorded tool call's name and arguments to the result stored for it.
+fn recorded_tool_results(history: &[ChatMessage]) -> HashMap<(String, String), String> {
+    let results: HashMap<&str, String> = history
+        .iter()
+        .filter(|message| message.role == "tool")
+        .filter_map(|message| Some((message.tool_call_id.as_deref()?, message.text().unwrap_or_default())))
+        .collect();
+
+    history
+        .iter()
+        .filter_map(|message| message.tool_calls.as_ref())
+        .flatten()
+        .filter_map(|call| {
+            let result = results.get(call.id.as_str())?;
+            Some(((call.get_name(), call.get_arguments()), result.clone()))
+        })
+        .collect()
+}
+
+/// What a run does when it reaches the maximum number of turns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MaxTurnsBehavior {
+    /// Fail with `OpenAIAgentError::Agent` (the default)
+    #[default]
+    Error,
+
+    /// Return the last non-empty assistant content produced during the run
+    /// (empty if there was none) with `RunOutcome::truncated` set
+    ReturnPartial,
+}
+
+/// How the builder's system prompts are sent to the API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SystemPromptMode {
+    /// One system message per prompt, in order (the default)
+    #[default]
+    Separate,
+
+    /// A single system message with the prompts joined by blank lines
+    Joined,
+}
+
+/// Detailed result of a run, returned by `Agent::run_detailed`.
+#[derive(Debug, Clone, Default)]
+pub struct RunOutcome {
+    /// Final assistant content
+    pub content: String,
+
+    /// Number of model round-trips made during the run
+    pub turns: usize,
+
+    /// Log probabilities of the final response, if requested and returned
+    pub logprobs: Option<LogProbs>,
+
+    /// Token usage summed over every request made during the run, including
+    /// cached prompt and reasoning token breakdowns
+    pub usage: Usage,
+
+    /// Whether the run hit the turn limit and `content` is only the last partial
+    /// content (see `MaxTurnsBehavior::ReturnPartial`)
+    pub truncated: bool,
+
+    /// Whether any response in the run was received as a stream rather than a
+    /// single JSON body (via `run_stream` or `Config::with_stream`)
+    pub streamed: bool,
+
+    /// Whether `usage` includes approximate counts, estimated locally for
+    /// streamed responses that did not report usage
+    pub usage_estimated: bool,
+
+    /// The model's question when it paused the run to ask the user (see
+    /// `AskUser`); `content` holds the same question. The next run's input is
+    /// delivered to the model as the answer.
+    pub needs_user_input: Option<String>,
+
+    /// Number of failed API calls retried during the run (see
+    /// `AgentBuilder::with_total_retries_per_run`)
+    pub retries: usize,
+
+    /// The tool calls exactly as the model emitted them, one entry per model
+    /// response (empty for responses without calls), including hosted tool calls
+    /// and calls whose execution failed
+    pub tool_calls: Vec<Vec<ToolCall>>,
+}
+
+impl RunOutcome {
+    /// Estimated cost of the run in USD using the built-in pricing for `model`,
+    /// or `None` if the model's pricing is unknown
+    pub fn estimated_cost(&self, model: &str) -> Option<f64> {
+        estimate_cost(model, &self.usage)
+    }
+}
+
+/// An agent that manages conversations with OpenAI models.
+///
+/// The agent handles the conversation flow, including sending requests to the API,
+/// processing responses, and optionally executing tool calls when the model requests them.
+/// It can also connect to the (hypothetical) OpenAI "Realtime" Beta API over WebSockets.
+pub struct Agent {
+    /// Configuration used to build each request
+    config: OpenAIConfig,
+
+    /// Transport used to send chat completion requests (the HTTP client by default)
+    transport: Arc<dyn ChatTransport>,
+
+    /// Tools that the agent can use when prompted by the model
+    tools: Arc<ToolRegistry>,
+
+    /// Current state of the agent including message history
+    state: AgentState,
+
+    /// Maximum number of turns before terminating to prevent infinite loops
+    max_turns: usize,
+
+    /// What to do when `max_turns` is reached
+    max_turns_behavior: MaxTurnsBehavior,
+
+    /// Optional cap on the total number of tool calls executed within a single run
+    max_tool_calls: Option<usize>,
+
+    /// Whether a turn stopped by the content filter is retried once
+    retry_content_filter: bool,
+
+    /// Whether tool failures are sent to the model as `ToolError` JSON
+    structured_tool_errors: bool,
+
+    /// How many times a response cut off by the token limit is continued per run
+    max_continuations: usize,
+
+    /// Retries of failed API calls allowed across a whole run
+    retries_per_run: usize,
+
+    /// Number of tool calls executed during the current (or most recent) run
+    tool_call_count: usize,
+
+    /// Tool calls emitted per model response during the current (or most recent) run
+    run_tool_calls: Vec<Vec<ToolCall>>,
+
+    /// Whether empty or whitespace-only user input is accepted
+    allow_empty_input: bool,
+
+    /// Whether control characters are stripped from messages sent to the API
+    sanitize_messages: bool,
+
+    /// Whether control characters are also stripped from messages as they are stored
+    sanitize_history: bool,
+
+    /// Optional automatic summarization settings
+    summarize: Option<SummarizeConfig>,
+
+    /// Optional transform applied to the returned response (never to stored history)
+    response_transform: Option<ResponseTransform>,
+
+    /// Optional callback overriding how finish reasons are handled
+    finish_reason_handler: Option<FinishReasonHandler>,
+
+    /// Optional WebSocket client for the OpenAI "Realtime" Beta API
+    websocket_client: Option<WebSocketClient>,
+    
+    /// Unique identifier for this agent instance
+    id: EntityId,
+    
+    /// Optional persistence store
+    persistence: Option<Arc<dyn PersistenceStore>>,
+
+    /// Whether state is saved to the persistence store after every run
+    autosave: bool,
+
+    /// How often a streaming response is checkpointed to the persistence store
+    stream_checkpoint_interval: Option<Duration>,
+
+    /// Interceptors applied to every request and response, in registration order
+    interceptors: Vec<Arc<dyn Interceptor>>,
+
+    /// Structured output modes `run_typed` tries, in order
+    structured_output_modes: Vec<StructuredOutputMode>,
+
+    /// Maximum number of messages kept besides the leading system prompts
+    max_history_messages: Option<usize>,
+
+    /// Maximum size of a serialized request body, in bytes
+    max_request_bytes: Option<usize>,
+
+    /// How tool output is written into tool messages
+    tool_result_formatter: ToolResultFormatter,
+
+    /// Values tools can look up by type
+    context: ContextMap,
+
+    /// Optional channel activity events are published to
+    events: Option<AgentEventSender>,
+
+    /// Request changes for the `run_typed` call in progress
+    structured_output: Option<StructuredOutput>,
+
+    /// Stop signal for the `run_stream_with_stop` call in progress
+    stop: Option<StopHandle>,
+
+    /// Predicted output for the `run_with_prediction` call in progress
+    prediction: Option<PredictionContent>,
+
+    /// Token counts of the system messages and tool definitions, keyed by a hash of both
+    overhead_cache: Mutex<Option<(u64, usize, usize)>>,
+}
+
+impl Agent {
+    /// Creates a new agent from an `AgentBuilder` configuration.
+    ///
+    /// This method is intended for internal use by the `AgentBuilder`.
+    #[doc(hidden)]
+    pub(crate) fn from_builder(builder: AgentBuilder) -> Result<Self, OpenAIAgentError> {
+        let config = builder.config.clone().unwrap_or_default();
+
+        let transport: Arc<dyn ChatTransport> = match builder.transport {
+            Some(transport) => transport,
+            None => {
+                let mut client = OpenAIClient::new(config.clone())?;
+                if let Some(cache) = builder.response_cache {
+                    client = client.with_response_cache(cache, builder.force_response_cache);
+                }
+                Arc::new(client)
+            }
+        };
+
+        // System prompts always lead, ahead of any seeded messages
+        let mut messages: Vec<ChatMessage> = match builder.system_prompt_mode {
+            SystemPromptMode::Separate => builder.system_prompts.into_iter().map(ChatMessage::system).collect(),
+            SystemPromptMode::Joined if builder.system_prompts.is_empty() => Vec::new(),
+            SystemPromptMode::Joined => vec![ChatMessage::system(builder.system_prompts.join("\n\n"))],
+        };
+        messages.extend(builder.messages);
+        for message in &mut messages {
+            message.ensure_id();
+        }
+
+        let state = AgentState {
+            messages,
+            token_count: 0,
+            version: 0,
+            dropped_count: 0,
+            turn_usage: Vec::new(),
+            turn_tools: Vec::new(),
+        };
+    
+        let agent = Self {
+            config,
+            transport,
+            tools: builder.tools,
+            state,
+            max_turns: builder.max_turns,
+            max_turns_behavior: builder.max_turns_behavior,
+            max_tool_calls: builder.max_tool_calls,
+            retry_content_filter: builder.retry_content_filter,
+            structured_tool_errors: builder.structured_tool_errors,
+            max_continuations: builder.max_continuations,
+            retries_per_run: builder.retries_per_run,
+            tool_call_count: 0,
+            run_tool_calls: Vec::new(),
+            allow_empty_input: builder.allow_empty_input,
+            sanitize_messages: builder.sanitize_messages,
+            sanitize_history: builder.sanitize_history,
+            summarize: builder.summarize,
+            response_transform: builder.response_transform,
+            finish_reason_handler: builder.finish_reason_handler,
+            websocket_client: builder.websocket_client,
+            id: builder.id.unwrap_or_else(|| match &builder.id_generator {
+                Some(generator) => generator.generate(),
+                None => generate_id(),
+            }),
+            persistence: builder.persistence,
+            autosave: builder.autosave,
+            stream_checkpoint_interval: builder.stream_checkpoint_interval,
+            interceptors: builder.interceptors,
+            structured_output_modes: builder.structured_output_modes,
+            structured_output: None,
+            stop: None,
+            prediction: None,
+            overhead_cache: Mutex::new(None),
+            max_history_messages: builder.max_history_messages,
+            max_request_bytes: builder.max_request_bytes,
+            tool_result_formatter: builder.tool_result_formatter,
+            context: builder.context,
+            events: builder.events,
+        };
+
+        Ok(agent)
+    }
+
+    /// Primary entry point for user → agent → OpenAI conversation using HTTP-based chat completions.
+    ///
+    /// If you plan to use the WebSocket "Realtime" approach, you might either
+    /// not use this method or adapt it to handle real-time streaming directly.
+    pub async fn run(&mut self, input: impl Into<String>) -> Result<String, OpenAIAgentError> {
+        let outcome = self.run_detailed(input).await?;
+        Ok(outcome.content)
+    }
+
+    /// Like `run`, but returns a `RunOutcome` with details about the run
+    /// (turn count, log probabilities, ...) alongside the final content.
+    pub async fn run_detailed(&mut self, input: impl Into<String>) -> Result<RunOutcome, OpenAIAgentError> {
+        self.run_with_delta(input.into(), None).await
+    }
+
+    /// Like `run_detailed`, but sends `predicted_text` as the expected output.
+    ///
+    /// Suited to edit-style tasks where most of the answer is known up front,
+    /// such as returning a file with a few lines changed: the API accepts the
+    /// matching tokens instead of generating them. The prediction is sent with
+    /// every request of the run. Check `Usage::accepted_prediction_tokens` and
+    /// `Usage::rejected_prediction_tokens` on the outcome's usage to see how well
+    /// it matched; rejected tokens are billed as completion tokens.
+    pub async fn run_with_prediction(
+        &mut self,
+        input: impl Into<String>,
+        predicted_text: impl Into<String>,
+    ) -> Result<RunOutcome, OpenAIAgentError> {
+        self.prediction = Some(PredictionContent::text(predicted_text));
+        let result = self.run_with_delta(input.into(), None).await;
+        self.prediction = None;
+        result
+    }
+
+    /// Like `run`, but streams the assistant's text as it is generated.
+    ///
+    /// Yields content fragments from every turn as they arrive. Tool calls are
+    /// executed between turns as usual and never appear in the stream, and the
+    /// response transform is not applied to fragments. A failed run yields its error
+    /// as the last item. History and autosave behave as in `run`; see also
+    /// `AgentBuilder::with_stream_checkpoint_interval`.
+    pub fn run_stream<'a>(&'a mut self, input: impl Into<String>) -> TextStream<'a> {
+        let input = input.into();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let run = Box::pin(async move {
+            let mut on_delta = move |delta: &str| {
+                let _ = tx.send(delta.to_string());
+            };
+            self.run_with_delta(input, Some(&mut on_delta)).await
+        });
+
+        Self::delta_stream(run, rx)
+    }
+
+    /// Like `run`, but streams the response and calls `on_token` with each content
+    /// fragment as it arrives, for callers that would rather not consume a `Stream`.
+    ///
+    /// The fragments are the same as those of `run_stream`: tool calls are
+    /// executed between turns and never passed to the callback. Returns the final
+    /// assembled content, with the response transform applied.
+    pub async fn run_with_callback(
+        &mut self,
+        input: impl Into<String>,
+        mut on_token: impl FnMut(&str) + Send,
+    ) -> Result<String, OpenAIAgentError> {
+        let outcome = self.run_with_delta(input.into(), Some(&mut on_token)).await?;
+        Ok(outcome.content)
+    }
+
+    /// Yields the deltas received on `rx` while `run` progresses, followed by the
+    /// run's error if it fails.
+    fn delta_stream<'a, T: Send + 'a>(
+        run: Pin<Box<dyn Future<Output = Result<T, OpenAIAgentError>> + Send + 'a>>,
+        rx: tokio::sync::mpsc::UnboundedReceiver<String>,
+    ) -> TextStream<'a> {
+        Box::pin(futures::stream::unfold(
+            (Some(run), rx, None),
+            |(mut run, mut rx, mut error)| async move {
+                loop {
+                    let Some(pending) = run.as_mut() else {
+                        // The run has finished: drain the remaining deltas, then report any error
+                        return match rx.recv().await {
+                            Some(delta) => Some((Ok(delta), (run, rx, error))),
+                            None => error.take().map(|e| (Err(e), (None, rx, None))),
+                        };
+                    };
+
+                    // Prefer pending deltas so they are emitted in order before completion
+                    let (delta, finished) = tokio::select! {
+                        biased;
+                        Some(delta) = rx.recv() => (Some(delta), None),
+                        result = pending => (None, Some(result)),
+                    };
+
+                    if let Some(delta) = delta {
+                        return Some((Ok(delta), (run, rx, error)));
+                    }
+                    if let Some(result) = finished {
+                        run = None;
+                        error = result.err();
+                    }
+                }
+            },
+        ))
+    }
+
+    /// Like `run_stream`, but the run can be cancelled with `stop` from another task.
+    ///
+    /// Once stopped, the stream ends with `OpenAIAgentError::Cancelled`. Text the
+    /// model had already streamed for the current turn is kept in the history as an
+    /// assistant message marked `incomplete`, and saved if autosave is enabled, so
+    /// the conversation shows where it was stopped. Stopping between turns (e.g.
+    /// while tools run) cancels before the next request.
+    pub fn run_stream_with_stop<'a>(&'a mut self, input: impl Into<String>, stop: StopHandle) -> TextStream<'a> {
+        let input = input.into();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let run = Box::pin(async move {
+            let mut on_delta = move |delta: &str| {
+                let _ = tx.send(delta.to_string());
+            };
+            self.stop = Some(stop);
+            let result = self.run_with_delta(input, Some(&mut on_delta)).await;
+            self.stop = None;
+
+            if matches!(result, Err(OpenAIAgentError::Cancelled)) && self.autosave && self.persistence.is_some() {
+                self.save().await?;
+            }
+            result
+        });
+
+        Self::delta_stream(run, rx)
+    }
+
+    /// Runs the conversation loop, streaming content to `on_delta` if given, then
+    /// saves state and applies the response transform.
+    async fn run_with_delta(
+        &mut self,
+        input: String,
+        on_delta: Option<&mut (dyn FnMut(&str) + Send)>,
+    ) -> Result<RunOutcome, OpenAIAgentError> {
+        let mut outcome = self.run_internal(input, on_delta).await?;
+        
+        // Optionally save state after each interaction
+    
+        if self.autosave && self.persistence.is_some() {
+            self.save().await?;
+        }
+
+        // Transform only the returned content; the stored history keeps the original
+        if let Some(transform) = &self.response_transform {
+            outcome.content = transform(outcome.content);
+        }
+        
+        Ok(outcome)
+    }
+    
+    /// Internal implementation of run that doesn't save state
+    async fn run_internal(
+        &mut self,
+        input: String,
+        on_delta: Option<&mut (dyn FnMut(&str) + Send)>,
+    ) -> Result<RunOutcome, OpenAIAgentError> {
+        let result = self.run_turns(input, on_delta).await;
+        if let Err(e) = &result {
+            self.emit(AgentEventKind::Error(e.to_string()));
+        }
+        result
+    }
+
+    /// The turn loop behind `run_internal`
+    async fn run_turns(
+        &mut self,
+        input: String,
+        mut on_delta: Option<&mut (dyn FnMut(&str) + Send)>,
+    ) -> Result<RunOutcome, OpenAIAgentError> {
+        let input = self.check_input(input)?;
+        let message = self.input_message(input);
+        self.record_message(message);
+        self.tool_call_count = 0;
+        self.run_tool_calls.clear();
+
+        let mut turns = 0;
+        let mut usage = Usage::default();
+        let mut last_content = None;
+        let mut streamed = false;
+        let mut usage_estimated = false;
+        let mut content_filter_retried = false;
+        let mut retrying_content_filter = false;
+        // Set when a finish reason handler asked the model to keep going
+        let mut nudging = false;
+        let mut continuations = 0;
+        let mut retries = 0;
+        // A response cut off by the token limit, awaiting its continuation. It is only
+        // recorded once stitched together with the rest.
+        let mut continued: Option<ChatMessage> = None;
+
+        while turns < self.max_turns {
+            if self.is_cancelled() {
+                return Err(OpenAIAgentError::Cancelled);
+            }
+            turns += 1;
+
+            if let Some(summary_usage) = self.maybe_summarize(&mut retries).await? {
+                usage.add(&summary_usage);
+            }
+            if let Some(max_messages) = self.max_history_messages {
+                self.state.trim_to(max_messages);
+            }
+
+            let mut request = self.prepare_request()?;
+            if std::mem::take(&mut retrying_content_filter) {
+                inject_system_instructions(&mut request.messages, Some(CONTENT_FILTER_RETRY_NOTE));
+            }
+            if let Some(partial) = &continued {
+                request.messages.push(partial.clone());
+                request.messages.push(ChatMessage::user(CONTINUE_PROMPT));
+            }
+            if std::mem::take(&mut nudging) {
+                request.messages.push(ChatMessage::user(CONTINUE_PROMPT));
+            }
+            for interceptor in &self.interceptors {
+                interceptor.before_request(&mut request)?;
+            }
+            if let Some(max_bytes) = self.max_request_bytes {
+                let size = serde_json::to_vec(&request)?.len();
+                if size > max_bytes {
+                    return Err(OpenAIAgentError::Agent(format!(
+                        "Request is {} bytes, exceeding the limit of {} bytes",
+                        size, max_bytes
+                    )));
+                }
+            }
+
+            // Captured after the interceptors, so this is what the model actually saw
+            let offered_tools: Vec<String> = request
+                .tools
+                .iter()
+                .flatten()
+                .map(|tool| tool.name().to_string())
+                .collect();
+
+            let stream_turn = on_delta.is_some() || request.stream == Some(true);
+            streamed |= stream_turn;
+            let mut response = match on_delta.as_deref_mut() {
+                Some(on_delta) => self.stream_completion(request, on_delta, &mut retries).await?,
+                None => self.send_with_retries(request, &mut retries).await?,
+            };
+            if self.is_cancelled() {
+                // Keep the text streamed so far, marked as partial
+                if let Some(choice) = response.choices.first()
+                    && choice.message.content.as_ref().is_some_and(|c| !c.is_blank())
+                {
+                    let mut partial = choice.message.clone();
+                    partial.tool_calls = None;
+                    partial.incomplete = true;
+                    if let Some(prefix) = continued.take() {
+                        let text = prefix.text().unwrap_or_default() + &partial.text().unwrap_or_default();
+                        partial.content = Some(MessageContent::Text(text));
+                    }
+                    self.record_message(partial);
+                }
+                return Err(OpenAIAgentError::Cancelled);
+            }
+            for interceptor in self.interceptors.iter().rev() {
+                interceptor.after_response(&mut response)?;
+            }
+
+            if let Some(prefix) = continued.take() {
+                // The partial answer and the rest are recorded as one message
+                if let Some(choice) = response.choices.first_mut() {
+                    let rest = choice.message.text().unwrap_or_default();
+                    let text = prefix.text().unwrap_or_default() + &rest;
+                    choice.message.content = Some(MessageContent::Text(text));
+                }
+            }
+
+            self.run_tool_calls.push(
+                response
+                    .choices
+                    .first()
+                    .and_then(|choice| choice.message.tool_calls.clone())
+                    .unwrap_or_default(),
+            );
+
+            // Streams only report usage when asked to, so estimate it instead
+            let turn_estimated = stream_turn && response.usage.is_none();
+            if turn_estimated {
+                response.usage = Some(self.estimate_usage(&response));
+                usage_estimated = true;
+            }
+
+            let message_index = self.state.dropped_count + self.state.messages.len();
+            self.state.turn_tools.push(TurnTools {
+                message_index,
+                tools: offered_tools,
+            });
+            if let Some(turn_usage) = response.usage.as_ref() {
+                self.state.token_count += turn_usage.total_tokens;
+                self.state.turn_usage.push(TurnUsage {
+                    message_index,
+                    prompt_tokens: turn_usage.prompt_tokens,
+                    completion_tokens: turn_usage.completion_tokens,
+                    total_tokens: turn_usage.total_tokens,
+                    estimated: turn_estimated,
+                });
+                usage.add(turn_usage);
+            }
+
+            self.emit(AgentEventKind::TurnCompleted {
+                turn: turns,
+                usage: response.usage.clone(),
+            });
+
+            if let Some(choice) = response.choices.first() {
+                let action = match &self.finish_reason_handler {
+                    Some(handler) => handler(&choice.finish_reason, &choice.message),
+                    None => FinishAction::Default,
+                };
+                match action {
+                    FinishAction::Default => {}
+                    FinishAction::Error => {
+                        return Err(OpenAIAgentError::Agent(format!(
+                            "Run stopped on finish reason '{}'",
+                            choice.finish_reason
+                        )));
+                    }
+                    FinishAction::Return => {
+                        // Unanswered tool calls would make the next request invalid
+                        let mut message = choice.message.clone();
+                        message.tool_calls = None;
+                        let content = message.text().unwrap_or_default();
+                        if message.content.as_ref().is_some_and(|c| !c.is_blank()) {
+                            self.record_message(message);
+                        }
+                        return Ok(RunOutcome {
+                            content,
+                            turns,
+                            logprobs: choice.logprobs.clone(),
+                            usage,
+                            truncated: false,
+                            streamed,
+                            usage_estimated,
+                            needs_user_input: None,
+                            tool_calls: self.run_tool_calls.clone(),
+                            retries,
+                        });
+                    }
+                    FinishAction::Continue if !has_tool_calls(&choice.message) => {
+                        if let Some(content) = choice.message.content.as_ref().filter(|c| !c.is_blank()) {
+                            last_content = Some(content.to_text());
+                            self.record_message(choice.message.clone());
+                        }
+                        nudging = true;
+                        continue;
+                    }
+                    // Tool calls are executed below, then the loop continues
+                    FinishAction::Continue => {}
+                }
+
+                if choice.finish_reason == "length"
+                    && continuations < self.max_continuations
+                    && !has_tool_calls(&choice.message)
+                    && let Some(content) = choice.message.content.as_ref().filter(|c| !c.is_blank())
+                {
+                    continuations += 1;
+                    last_content = Some(content.to_text());
+                    continued = Some(choice.message.clone());
+                    continue;
+                }
+
+                if choice.finish_reason == "content_filter" {
+                    // The filtered message is not kept, so a retry starts from the same history
+                    if self.retry_content_filter && !content_filter_retried {
+                        content_filter_retried = true;
+                        retrying_content_filter = true;
+                        continue;
+                    }
+                    return Err(OpenAIAgentError::ContentFiltered {
+                        partial_content: choice
+                            .message
+                            .content
+                            .as_ref()
+                            .filter(|c| !c.is_blank())
+                            .map(|c| c.to_text()),
+                    });
+                }
+
+                // Hosted tools are resolved by the API, so only function calls run locally
+                let local_calls: Vec<&ToolCall> = choice
+                    .message
+                    .tool_calls
+                    .iter()
+                    .flatten()
+                    .filter(|call| !self.tools.is_hosted_call(call))
+                    .collect();
+
+                // Checked before the response is recorded, so the history never holds
+                // tool calls without results
+                if let Some(max_tool_calls) = self.max_tool_calls
+                    && self.tool_call_count + local_calls.len() > max_tool_calls
+                {
+                    return Err(OpenAIAgentError::Agent(format!(
+                        "Agent exceeded maximum tool calls ({})",
+                        max_tool_calls
+                    )));
+                }
+
+                self.record_message(choice.message.clone());
+
+                if let Some(content) = choice.message.content.as_ref().filter(|c| !c.is_blank()) {
+                    last_content = Some(content.to_text());
+                }
+
+                if !local_calls.is_empty() {
+                    let mut question = None;
+
+                    // Process each tool call
+                    for tool_call in local_calls {
+                        self.tool_call_count += 1;
+
+                        if let Some(asked) = self.user_question(tool_call) {
+                            if question.is_none() {
+                                // Answered by the next run's input
+                                question = Some(asked);
+                            } else {
+                                // Only one question can be pending; the model sees the refusal
+                                // alongside the answer and can ask again
+                                self.record_message(self.rejected_question(tool_call));
+                            }
+                            continue;
+                        }
+
+                        let started = Instant::now();
+                        let result_msg = self.execute_tool_call(tool_call).await?;
+                        self.emit(AgentEventKind::ToolExecuted {
+                            name: tool_call.function.name.clone(),
+                            call_id: tool_call.id.clone(),
+                            duration: started.elapsed(),
+                        });
+                        self.record_message(result_msg);
+                    }
+
+                    if let Some(question) = question {
+                        return Ok(RunOutcome {
+                            content: question.clone(),
+                            turns,
+                            logprobs: None,
+                            usage,
+                            truncated: false,
+                            streamed,
+                            usage_estimated,
+                            needs_user_input: Some(question),
+                            tool_calls: self.run_tool_calls.clone(),
+                            retries,
+                        });
+                    }
+
+                    // Once we've processed tool calls, go back to top of the loop
+                    continue;
+                }
+
+                // If there's direct content, return it
+                if let Some(content) = &choice.message.content
+                    && !content.is_blank()
+                {
+                    return Ok(RunOutcome {
+                        content: content.to_text(),
+                        turns,
+                        logprobs: choice.logprobs.clone(),
+                        usage,
+                        truncated: false,
+                        streamed,
+                        usage_estimated,
+                        needs_user_input: None,
+                        tool_calls: self.run_tool_calls.clone(),
+                        retries,
+                    });
+                }
+
+                // If the finish reason was "tool_calls", we might want to continue
+                if choice.finish_reason == "tool_calls" {
+                    continue;
+                }
+
+                // Newer models explain a refusal in a field of its own, leaving content empty
+                if let Some(refusal) = choice.message.refusal.as_ref().filter(|r| !r.trim().is_empty()) {
+                    return Err(OpenAIAgentError::Refusal(refusal.clone()));
+                }
+
+                return Err(OpenAIAgentError::Parse(
+                    format!("Assistant returned empty message with finish_reason: {}", choice.finish_reason),
+                ));
+            }
+            return Err(OpenAIAgentError::Parse(
+                "No response choices received".to_string(),
+            ));
+        }
+
+        // Out of turns before the continuation was requested; keep what was generated
+        if let Some(partial) = continued.take() {
+            self.record_message(partial);
+        }
+
+        match self.max_turns_behavior {
+            MaxTurnsBehavior::ReturnPartial => Ok(RunOutcome {
+                content: last_content.unwrap_or_default(),
+                turns,
+                logprobs: None,
+                usage,
+                truncated: true,
+                streamed,
+                usage_estimated,
+                needs_user_input: None,
+                tool_calls: self.run_tool_calls.clone(),
+                retries,
+            }),
+            MaxTurnsBehavior::Error => Err(OpenAIAgentError::Agent(format!(
+                "Agent exceeded maximum turns ({})",
+                self.max_turns
+            ))),
+        }
+    }
+
+    /// Streams one completion, forwarding content fragments to `on_delta` and
+    /// checkpointing the partial message if configured, and returns the assembled response.
+    ///
+    /// Opening the stream is retried within the run's retry budget; once deltas
+    /// have been delivered, a failure ends the turn.
+    async fn stream_completion(
+        &self,
+        request: ChatRequest,
+        on_delta: &mut (dyn FnMut(&str) + Send),
+        retries: &mut usize,
+    ) -> Result<ChatResponse, OpenAIAgentError> {
+        let mut chunks = self
+            .retry_within_budget(retries, || self.transport.chat_completion_stream(request.clone()))
+            .await?;
+        let mut accumulator = StreamAccumulator::default();
+        let mut last_checkpoint = Instant::now();
+        let mut checkpointed = false;
+        let mut stopped = false;
+
+        loop {
+            let next = match &self.stop {
+                Some(stop) => tokio::select! {
+                    biased;
+                    _ = stop.stopped() => {
+                        stopped = true;
+                        None
+                    }
+                    chunk = chunks.next() => chunk,
+                },
+                None => chunks.next().await,
+            };
+            // The stream ended, or the run was cancelled and the text so far is kept
+            let Some(chunk) = next else {
+                break;
+            };
+
+            if let Some(delta) = accumulator.push(chunk?) {
+                on_delta(&delta);
+            }
+
+            if let Some(interval) = self.stream_checkpoint_interval
+                && last_checkpoint.elapsed() >= interval && !accumulator.content().is_empty()
+            {
+                let mut partial = accumulator.message();
+                partial.incomplete = true;
+                self.checkpoint(partial).await?;
+                checkpointed = true;
+                last_checkpoint = Instant::now();
+            }
+        }
+
+        let response = accumulator.finish();
+
+        // Replace the stored partial message with the finished one, or with the
+        // text received before the stop, still marked as partial
+        if checkpointed
+            && let Some(choice) = response.choices.first()
+        {
+            let mut message = choice.message.clone();
+            message.incomplete = stopped;
+            self.checkpoint(message).await?;
+        }
+
+        Ok(response)
+    }
+
+    /// Whether the `run_stream_with_stop` call in progress has been asked to stop
+    fn is_cancelled(&self) -> bool {
+        self.stop.as_ref().is_some_and(StopHandle::is_stopped)
+    }
+
+    /// Stores the current history plus an in-progress message without adding it to the state
+    async fn checkpoint(&self, message: ChatMessage) -> Result<(), OpenAIAgentError> {
+        if let Some(store) = &self.persistence {
+            let mut state = self.state.clone();
+            state.messages.push(message);
+            store.store_conversation(&self.id, &state).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends a request, retrying retryable failures while the run's retry budget
+    /// lasts. `retries` counts the retries made so far in the run. Every attempt
+    /// carries the same idempotency key.
+    async fn send_with_retries(
+        &self,
+        request: ChatRequest,
+        retries: &mut usize,
+    ) -> Result<ChatResponse, OpenAIAgentError> {
+        // One key per logical request, so retries can be deduplicated server side
+        let idempotency_key = uuid::Uuid::new_v4().to_string();
+        self.retry_within_budget(retries, || {
+            self.transport.chat_completion_with_key(request.clone(), &idempotency_key)
+        })
+        .await
+    }
+
+    /// Makes an API call with `attempt`, retrying retryable failures with
+    /// exponential backoff while the run's retry budget lasts.
+    async fn retry_within_budget<T, F, Fut>(&self, retries: &mut usize, mut attempt: F) -> Result<T, OpenAIAgentError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, OpenAIAgentError>>,
+    {
+        let mut delay_ms = RUN_RETRY_INITIAL_DELAY_MS;
+        loop {
+            match attempt().await {
+                Err(err) if err.is_retryable() && *retries < self.retries_per_run => {
+                    *retries += 1;
+                    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                    delay_ms *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Internal helper that executes a given tool call (function call).
+    async fn execute_tool_call(&self, tc: &ToolCall) -> Result<ChatMessage, OpenAIAgentError> {
+        let tool_name = &tc.function.name;
+        let arguments = &tc.function.arguments;
+        let tool_call_id = &tc.id;
+        let failure = |error: ToolError| {
+            ChatMessage::tool_result(error.to_content(), tool_name.clone(), tool_call_id.clone())
+        };
+
+        let Some(tool) = self.tools.get(tool_name) else {
+            let message = format!("Tool not found: {}", tool_name);
+            if self.structured_tool_errors {
+                return Ok(failure(ToolError::new("not_found", message)));
+            }
+            return Err(OpenAIAgentError::Tool(message));
+        };
+
+        // Parse the JSON arguments
+        let parsed_args = match serde_json::from_str(arguments) {
+            Ok(args) => args,
+            Err(e) => {
+                let message = format!("Failed to parse tool arguments: {}", e);
+                if self.structured_tool_errors {
+                    return Ok(failure(ToolError::new("invalid_arguments", message)));
+                }
+                return Err(OpenAIAgentError::Parse(message));
+            }
+        };
+
+        // Strict tools get their arguments checked against the declared schema; a
+        // mismatch is reported back to the model instead of reaching the tool
+        let definition = tool.definition();
+        if definition.strict == Some(true)
+            && let Err(violations) = validate_arguments(&definition.parameters, &parsed_args)
+        {
+            let message = format!("invalid arguments for tool '{}': {}", tool_name, violations);
+            if self.structured_tool_errors {
+                return Ok(failure(ToolError::new("invalid_arguments", message)));
+            }
+            return Ok(ChatMessage::tool_result(
+                format!("Error: {}", message),
+                tool_name.clone(),
+                tool_call_id.clone(),
+            ));
+        }
+
+        // Execute the tool; a panic is reported to the model like an invalid call
+        // instead of unwinding through the run
+        let context = ToolContext::new(tool_call_id, &self.context);
+        let execution = AssertUnwindSafe(tool.execute_with_context(parsed_args, &context))
+            .catch_unwind()
+            .await;
+        let result = match execution {
+            Ok(Ok(result)) => result,
+            Ok(Err(OpenAIAgentError::Cancelled)) => return Err(OpenAIAgentError::Cancelled),
+            Ok(Err(error)) if self.structured_tool_errors => {
+                return Ok(failure(ToolError::from_agent_error(error)));
+            }
+            Ok(Err(error)) => return Err(error),
+            Err(panic) if self.structured_tool_errors => {
+                let message = format!("tool panicked: {}", panic_message(&*panic));
+                return Ok(failure(ToolError::new("panic", message)));
+            }
+            Err(panic) => {
+                let error = OpenAIAgentError::Tool(format!("tool panicked: {}", panic_message(&*panic)));
+                return Ok(ChatMessage::tool_result(
+                    format!("Error: {}", error),
+                    tool_name.clone(),
+                    tool_call_id.clone(),
+                ));
+            }
+        };
+
+        // Create a message that records the tool's result
+        let result = self.tool_result_formatter.format(tool_name, result);
+        let response = ChatMessage::tool_result(result, tool_name.clone(), tool_call_id.clone());
+
+        Ok(response)
+    }
+
+    /// Summarizes older history if the configured thresholds have been crossed,
+    /// returning the usage of the summarization call if one was made. The call
+    /// draws on the run's retry budget, counted in `retries`.
+    async fn maybe_summarize(&mut self, retries: &mut usize) -> Result<Option<Usage>, OpenAIAgentError> {
+        let should_summarize = match &self.summarize {
+            Some(config) => {
+                let over_messages = config
+                    .trigger_messages
+                    .is_some_and(|max| self.state.messages.len() > max);
+                // Tool definitions are sent with every request, so they count too
+                let prompt_tokens = self.estimate_prompt_tokens() + self.overhead_tokens().1;
+                over_messages || prompt_tokens > config.trigger_tokens
+            }
+            None => return Ok(None),
+        };
+
+        if !should_summarize {
+            return Ok(None);
+        }
+        self.summarize(retries).await
+    }
+
+    /// Returns the tokens every request spends before any conversation history: the
+    /// leading system and developer messages plus the tool definitions.
+    ///
+    /// Subtract it from a context budget to get the room left for the dynamic
+    /// history. Tool definitions are counted from their serialized JSON. The result
+    /// is cached until the system messages, tools or model change. Counts are exact
+    /// with the `tokenizer` feature and for models it knows, estimated otherwise.
+    pub fn fixed_overhead_tokens(&self) -> usize {
+        let (system, tools) = self.overhead_tokens();
+        system + tools
+    }
+
+    /// Returns how many more prompt tokens fit in the model's context window.
+    ///
+    /// Computed as the context window, minus the current prompt (history plus
+    /// tool definitions), minus the `max_tokens` reserved for the response, and
+    /// clamped to zero. Returns `None` if the model's context window is unknown;
+    /// set it with `Config::with_context_window`. Counts are exact with the
+    /// `tokenizer` feature, estimated otherwise.
+    pub fn context_headroom(&self) -> Option<usize> {
+        let window = self.config.context_window()?;
+        let prompt_tokens = self.estimate_prompt_tokens() + self.overhead_tokens().1;
+        Some(window.saturating_sub(prompt_tokens).saturating_sub(self.config.max_tokens()))
+    }
+
+    /// Returns whether at least `threshold_fraction` of the context window is
+    /// used, counting the `max_tokens` reserved for the response.
+    ///
+    /// For example `0.8` reports whether 80% of the window is taken. Always
+    /// `false` if the model's context window is unknown.
+    pub fn is_near_context_limit(&self, threshold_fraction: f64) -> bool {
+        match (self.config.context_window(), self.context_headroom()) {
+            (Some(window), Some(headroom)) => (window - headroom) as f64 >= window as f64 * threshold_fraction,
+            _ => false,
+        }
+    }
+
+    /// Returns the token counts of the system messages and of the tool definitions
+    fn overhead_tokens(&self) -> (usize, usize) {
+        let model = self.config.model();
+        let system = &self.state.messages[..leading_system_count(&self.state.messages)];
+        let definitions = if self.tools.is_empty() {
+            String::new()
+        } else {
+            serde_json::to_string(&self.tools.definitions()).unwrap_or_default()
+        };
+
+        let mut hasher = DefaultHasher::new();
+        model.hash(&mut hasher);
+        serde_json::to_string(system).unwrap_or_default().hash(&mut hasher);
+        definitions.hash(&mut hasher);
+        let key = hasher.finish();
 
-/// The current state of the agent, including conversation history and token usage.
-#[derive(Debug, Clone)]
-pub struct AgentState {
-    /// Conversation history including all messages exchanged
-    pub messages: Vec<ChatMessage>,
+        let cached = self.overhead_cache.lock().ok().and_then(|cache| *cache);
+        if let Some((_, system_tokens, tool_tokens)) = cached.filter(|(cached_key, ..)| *cached_key == key) {
+            return (system_tokens, tool_tokens);
+        }
 
-    /// Running count of tokens used in the conversation
-    pub token_count: usize,
-}
+        let system_tokens = if system.is_empty() {
+            0
+        } else {
+            count_message_tokens(system, model).unwrap_or_else(|_| {
+                system
+                    .iter()
+                    .filter_map(|m| m.content.as_ref())
+                    .map(|c| c.to_text().chars().count().div_ceil(4))
+                    .sum()
+            })
+        };
+        let tool_tokens = if definitions.is_empty() {
+            0
+        } else {
+            count_tokens_or_estimate(&definitions, model)
+        };
 
-impl AgentState {
-    /// Returns the total number of tokens used in the conversation so far
-    pub fn token_count(&self) -> usize {
-        self.token_count
+        if let Ok(mut cache) = self.overhead_cache.lock() {
+            *cache = Some((key, system_tokens, tool_tokens));
+        }
+        (system_tokens, tool_tokens)
     }
 
-    /// Returns the number of messages in the conversation history
-    pub fn message_count(&self) -> usize {
-        self.messages.len()
+    /// Estimates the prompt tokens of the current history, falling back to a
+    /// character-based approximation when the model's tokenizer is unknown.
+    fn estimate_prompt_tokens(&self) -> usize {
+        count_message_tokens(&self.state.messages, self.config.model()).unwrap_or_else(|_| {
+            self.state
+                .messages
+                .iter()
+                .filter_map(|m| m.content.as_ref())
+                .map(|c| c.to_text().chars().count().div_ceil(4))
+                .sum()
+        })
     }
 
-    /// Returns an iterator over references to the messages in the conversation
-    pub fn messages(&self) -> impl Iterator<Item = &ChatMessage> {
-        self.messages.iter()
+    /// Estimates the usage of a response that did not report it, counting the
+    /// current history as the prompt and the response's content and tool call
+    /// arguments as the completion.
+    fn estimate_usage(&self, response: &ChatResponse) -> Usage {
+        let completion: String = response
+            .choices
+            .first()
+            .map(|choice| {
+                let mut text = choice.message.text().unwrap_or_default();
+                for tool_call in choice.message.tool_calls.iter().flatten() {
+                    text.push_str(&tool_call.get_name());
+                    text.push_str(&tool_call.get_arguments());
+                }
+                text
+            })
+            .unwrap_or_default();
+
+        let prompt_tokens = self.estimate_prompt_tokens();
+        let completion_tokens = count_tokens(&completion, self.config.model())
+            .unwrap_or_else(|_| completion.chars().count().div_ceil(4));
+
+        Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            ..Default::default()
+        }
     }
-}
-
-/// An agent that manages conversations with OpenAI models.
-///
-/// The agent handles the conversation flow, including sending requests to the API,
-/// processing responses, and optionally executing tool calls when the model requests them.
-/// It can also connect to the (hypothetical) OpenAI "Realtime" Beta API over WebSockets.
-pub struct Agent {
-    /// OpenAI client for making normal HTTP API requests
-    client: OpenAIClient,
 
-    /// Tools that the agent can use when prompted by the model
-    tools: Arc<ToolRegistry>,
+    /// Replaces the oldest messages with a model-generated summary.
+    ///
+    /// Leading system messages and the most recent `keep_recent` messages are kept
+    /// verbatim. Tool calls and their results in the compacted range are summarized as
+    /// text, and the boundary never separates a tool call from its results. Pinned
+    /// messages in the range are not summarized; they follow the summary verbatim.
+    /// The summary is a system message placed after the leading system messages; a
+    /// previous summary is part of the next compacted range.
+    ///
+    /// # Returns
+    ///
+    /// `true` if messages were compacted, `false` if there was nothing to compact
+    pub async fn summarize_now(&mut self) -> Result<bool, OpenAIAgentError> {
+        Ok(self.summarize(&mut 0).await?.is_some())
+    }
 
-    /// Current state of the agent including message history
-    state: AgentState,
+    /// Compacts the history as `summarize_now` does, returning the usage of the
+    /// summarization call, or `None` if there was nothing to compact.
+    async fn summarize(&mut self, retries: &mut usize) -> Result<Option<Usage>, OpenAIAgentError> {
+        let defaults = SummarizeConfig::default();
+        let settings = self.summarize.as_ref().unwrap_or(&defaults);
 
-    /// Maximum number of turns before terminating to prevent infinite loops
-    max_turns: usize,
+        let range = match compaction_range(&self.state.messages, settings.keep_recent) {
+            Some(range) => range,
+            None => return Ok(None),
+        };
 
-    /// Optional WebSocket client for the OpenAI "Realtime" Beta API
-    websocket_client: Option<WebSocketClient>,
-    
-    /// Unique identifier for this agent instance
-    id: EntityId,
-    
-    /// Optional persistence store
-    persistence: Option<Arc<dyn PersistenceStore>>,
-}
+        let config = &self.config;
+        let model = settings
+            .summary_model
+            .clone()
+            .unwrap_or_else(|| config.model().to_string());
+
+        // Pinned messages are kept verbatim after the summary instead of being summarized
+        let keep = pinned_in(&self.state.messages, range.clone());
+        let (pinned, summarized): (Vec<_>, Vec<_>) = self.state.messages[range.clone()]
+            .iter()
+            .cloned()
+            .zip(keep)
+            .partition(|(_, kept)| *kept);
+        if summarized.is_empty() {
+            return Ok(None);
+        }
+        let summarized_count = summarized.len();
 
-impl Agent {
-    /// Creates a new agent from an `AgentBuilder` configuration.
-    ///
-    /// This method is intended for internal use by the `AgentBuilder`.
-    #[doc(hidden)]
-    pub(crate) fn from_builder(builder: AgentBuilder) -> Result<Self, OpenAIAgentError> {
-        let client = OpenAIClient::new(builder.config.clone().unwrap_or_default())?;
+        let compacted = AgentState {
+            messages: summarized.into_iter().map(|(message, _)| message).collect(),
+            ..Default::default()
+        };
 
-        let state = AgentState {
-            messages: builder.messages,
-            token_count: 0,
+        let (max_tokens, max_completion_tokens) = config.token_limit_fields();
+        let messages = vec![
+            ChatMessage::system(SUMMARY_PROMPT),
+            ChatMessage::user(compacted.transcript()),
+        ];
+        let request = ChatRequest {
+            max_tokens,
+            max_completion_tokens,
+            temperature: Some(0.0),
+            ..ChatRequest::new(model, messages)
         };
-    
-        let agent = Self {
-            client,
-            tools: builder.tools,
-            state,
-            max_turns: builder.max_turns,
-            websocket_client: builder.websocket_client,
-            id: builder.id,
-            persistence: builder.persistence,
+
+        let response = self.send_with_retries(request, retries).await?;
+
+        if let Some(usage) = response.usage.as_ref() {
+            self.state.token_count += usage.total_tokens;
+        }
+
+        let summary = response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.text())
+            .filter(|content| !content.trim().is_empty())
+            .ok_or_else(|| OpenAIAgentError::Parse("Summarization returned no content".to_string()))?;
+
+        let mut summary = ChatMessage::system(format!("{}{}", SUMMARY_PREFIX, summary));
+        summary.ensure_id();
+        // The summary takes the place of the messages it folds in
+        self.state.dropped_count += summarized_count - 1;
+        self.state.messages.splice(
+            range,
+            std::iter::once(summary).chain(pinned.into_iter().map(|(message, _)| message)),
+        );
+        self.state.touch();
+
+        Ok(Some(response.usage.unwrap_or_default()))
+    }
+
+    /// Appends a message to the history and publishes it as an event
+    fn record_message(&mut self, mut message: ChatMessage) {
+        if self.sanitize_history {
+            message.sanitize();
+        }
+        // Assigned before the event, so subscribers see the ID the history keeps
+        message.ensure_id();
+        if self.events.is_some() {
+            self.emit(AgentEventKind::Message(message.clone()));
+        }
+        self.state.push_message(message);
+    }
+
+    /// Publishes an event if an event sender is configured
+    fn emit(&self, kind: AgentEventKind) {
+        if let Some(events) = &self.events {
+            // Sending only fails when nobody is subscribed
+            let _ = events.send(AgentEvent {
+                agent_id: self.id.clone(),
+                kind,
+            });
+        }
+    }
+
+    /// Returns the question if the tool call is to a tool that pauses for the user.
+    fn user_question(&self, tool_call: &ToolCall) -> Option<String> {
+        let tool = self.tools.get(&tool_call.function.name)?;
+        if !tool.pauses_for_user() {
+            return None;
+        }
+
+        let arguments = &tool_call.function.arguments;
+        let question = serde_json::from_str::<serde_json::Value>(arguments)
+            .ok()
+            .and_then(|args| args["question"].as_str().map(str::to_string))
+            .unwrap_or_else(|| arguments.clone());
+        Some(question)
+    }
+
+    /// The result recorded for a question asked while another is already pending.
+    fn rejected_question(&self, tool_call: &ToolCall) -> ChatMessage {
+        let message = "Only one question can be asked at a time; ask again after the user answers".to_string();
+        let content = if self.structured_tool_errors {
+            ToolError::new("rejected", message).to_content()
+        } else {
+            message
         };
+        ChatMessage::tool_result(content, tool_call.function.name.clone(), tool_call.id.clone())
+    }
 
-        Ok(agent)
+    /// Finds a question the conversation is paused on, as `(tool call id, tool name)`.
+    ///
+    /// Derived from the history rather than stored, so a paused conversation can
+    /// be resumed after being saved and loaded.
+    fn pending_user_question(&self) -> Option<(String, String)> {
+        let messages = &self.state.messages;
+        let last_call = messages.iter().rposition(has_tool_calls)?;
+        let later = &messages[last_call + 1..];
+        if later.iter().any(|message| message.role != "tool") {
+            return None;
+        }
+
+        messages[last_call]
+            .tool_calls
+            .iter()
+            .flatten()
+            .filter(|call| {
+                !later
+                    .iter()
+                    .any(|message| message.tool_call_id.as_deref() == Some(call.id.as_str()))
+            })
+            .find(|call| {
+                self.tools
+                    .get(&call.function.name)
+                    .is_some_and(|tool| tool.pauses_for_user())
+            })
+            .map(|call| (call.id.clone(), call.function.name.clone()))
     }
 
-    /// Primary entry point for user → agent → OpenAI conversation using HTTP-based chat completions.
+    /// Prepares a request to the OpenAI API with the current state and tools.
+    fn prepare_request(&self) -> Result<ChatRequest, OpenAIAgentError> {
+        self.request_for(self.state.messages.clone())
+    }
+
+    /// Returns the size in bytes of the JSON body that `run(input)` would send first.
     ///
-    /// If you plan to use the WebSocket "Realtime" approach, you might either
-    /// not use this method or adapt it to handle real-time streaming directly.
-    pub async fn run(&mut self, input: impl Into<String>) -> Result<String, OpenAIAgentError> {
-        let result = self.run_internal(input).await?;
-        
-        // Optionally save state after each interaction
-    
-        if self.persistence.is_some() {
-            self.save().await?;
+    /// The request is built from the current history plus `input`, with the agent's
+    /// config, tools and interceptors, exactly as a run would; nothing is sent and
+    /// the state is unchanged. Summarization and history trimming, which happen
+    /// during the run, are not applied.
+    pub fn request_byte_size(&self, input: impl Into<String>) -> Result<usize, OpenAIAgentError> {
+        let mut messages = self.state.messages.clone();
+        messages.push(self.input_message(input.into()));
+
+        let mut request = self.request_for(messages)?;
+        for interceptor in &self.interceptors {
+            interceptor.before_request(&mut request)?;
         }
-        
-        Ok(result)
+        Ok(serde_json::to_vec(&request)?.len())
     }
-    
-    /// Internal implementation of run that doesn't save state
-    async fn run_internal(&mut self, input: impl Into<String>) -> Result<String, OpenAIAgentError> {
-        self.state.messages.push(ChatMessage::user(input.into()));
 
-        let mut turns = 0;
-        let mut final_response = String::new();
+    /// The message a run's input is added to the history as.
+    fn input_message(&self, input: String) -> ChatMessage {
+        match self.pending_user_question() {
+            // The input answers the question the model paused on
+            Some((tool_call_id, tool_name)) => ChatMessage::tool_result(input, tool_name, tool_call_id),
+            None => ChatMessage::user(input),
+        }
+    }
 
-        while turns < self.max_turns {
-            turns += 1;
+    /// Builds a request for the given messages with the agent's config and tools.
+    fn request_for(&self, mut messages: Vec<ChatMessage>) -> Result<ChatRequest, OpenAIAgentError> {
+        if self.sanitize_messages {
+            for message in &mut messages {
+                message.sanitize();
+            }
+        }
+
+        let config = &self.config;
+        let (max_tokens, max_completion_tokens) = config.token_limit_fields();
+
+        let mut request = ChatRequest {
+            max_tokens,
+            max_completion_tokens,
+            temperature: Some(config.temperature()),
+            response_format: config
+                .json_mode()
+                .then(|| serde_json::json!({ "type": "json_object" })),
+            stream: Some(config.stream()),
+            logprobs: config.logprobs().then_some(true),
+            top_logprobs: config.top_logprobs(),
+            store: config.store(),
+            seed: config.seed(),
+            metadata: config.request_metadata().cloned(),
+            prediction: self.prediction.clone(),
+            ..ChatRequest::new(config.model(), messages)
+        };
 
-            let request = self.prepare_request()?;
-            let response = self.client.chat_completion(request).await?;
+        if !self.tools.is_empty() {
+            request.tools = Some(self.tools.definitions());
+        }
 
-            if let Some(usage) = response.usage.as_ref() {
-                self.state.token_count += usage.total_tokens;
+        if let Some(output) = &self.structured_output {
+            if output.response_format.is_some() {
+                request.response_format = output.response_format.clone();
             }
+            inject_system_instructions(&mut request.messages, output.instructions.as_deref());
+        }
 
-            if let Some(choice) = response.choices.first() {
-                self.state.messages.push(choice.message.clone());
-
-                if let Some(tool_calls) = &choice.message.tool_calls {
-                    if !tool_calls.is_empty() {
-                        // Process each tool call
-                        for tool_call in tool_calls {
-                            let result_msg = self.execute_tool_call(tool_call).await?;
-                            self.state.messages.push(result_msg);
-                        }
+        Ok(request)
+    }
 
-                        // Once we've processed tool calls, go back to top of the loop
-                        continue;
+    /// Asks a one-off side question against the current conversation without
+    /// changing it.
+    ///
+    /// The input is appended to a copy of the history and sent as a single
+    /// completion with no tools offered, so there is no tool loop. Interceptors
+    /// apply as usual, but nothing is added to the history, counted in the token
+    /// total or saved. The call has its own retry budget of the same size as a run's.
+    pub async fn ask_ephemeral(&self, input: impl Into<String>) -> Result<String, OpenAIAgentError> {
+        let input = self.check_input(input.into())?;
+        let mut messages = self.state.messages.clone();
+        messages.push(ChatMessage::user(input));
+
+        let mut request = self.request_for(messages)?;
+        request.tools = None;
+        for interceptor in &self.interceptors {
+            interceptor.before_request(&mut request)?;
+        }
+
+        let mut response = self.send_with_retries(request, &mut 0).await?;
+        for interceptor in self.interceptors.iter().rev() {
+            interceptor.after_response(&mut response)?;
+        }
+
+        response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.text())
+            .filter(|content| !content.trim().is_empty())
+            .ok_or_else(|| OpenAIAgentError::Parse("No content in response".to_string()))
+    }
+
+    /// Runs the agent and parses the final answer as `T`.
+    ///
+    /// Structured output is requested with each mode from
+    /// `AgentBuilder::with_structured_output_modes` in turn (by default a
+    /// `json_schema` response format, then `json_object` with the schema added to
+    /// the system prompt, then plain text), moving to the next mode when the API
+    /// rejects the response format for the configured model. Markdown code fences
+    /// around the JSON are stripped before parsing. If the answer cannot be
+    /// parsed, an `OpenAIAgentError::Parse` including the raw content is returned.
+    /// The response transform is not applied.
+    pub async fn run_typed<T>(&mut self, input: impl Into<String>) -> Result<T, OpenAIAgentError>
+    where
+        T: DeserializeOwned + JsonSchema,
+    {
+        let outcome = self.run_structured::<T>(input.into(), None).await?;
+        parse_json_output(&outcome.content)
+    }
+
+    /// Like `run_typed`, but streams the structured answer as it is generated.
+    ///
+    /// Each time more of the answer arrives, the JSON received so far is parsed
+    /// leniently (closing unterminated strings, objects and arrays, and leaving out
+    /// members that are still incomplete) and yielded as `PartialValue::Partial` if
+    /// it changed. The last item is `PartialValue::Complete` with the parsed `T`, or
+    /// an `OpenAIAgentError::Parse` if the final answer does not match `T`. Partial
+    /// values follow the JSON as sent, so they are not checked against `T`'s schema.
+    /// Any text the model streams before calling tools is parsed the same way.
+    pub fn run_typed_stream<'a, T>(&'a mut self, input: impl Into<String>) -> TypedStream<'a, T>
+    where
+        T: DeserializeOwned + JsonSchema + Send + 'a,
+    {
+        let input = input.into();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let run = Box::pin(async move {
+            let mut on_delta = move |delta: &str| {
+                let _ = tx.send(delta.to_string());
+            };
+            let outcome = self.run_structured::<T>(input, Some(&mut on_delta)).await?;
+            parse_json_output::<T>(&outcome.content)
+        });
+
+        Box::pin(futures::stream::unfold(
+            (Some(run), rx, String::new(), None::<serde_json::Value>, None::<Result<T, OpenAIAgentError>>),
+            |(mut run, mut rx, mut buffer, mut last, mut finished)| async move {
+                loop {
+                    if let Some(result) = finished.take() {
+                        return Some((result.map(PartialValue::Complete), (None, rx, buffer, last, None)));
+                    }
+                    let pending = run.as_mut()?;
+
+                    let (delta, result) = tokio::select! {
+                        biased;
+                        Some(delta) = rx.recv() => (Some(delta), None),
+                        result = pending => (None, Some(result)),
+                    };
+
+                    match (delta, result) {
+                        (Some(delta), _) => buffer.push_str(&delta),
+                        (None, Some(result)) => {
+                            // Take in the remaining deltas so the last partial precedes the final value
+                            run = None;
+                            while let Ok(delta) = rx.try_recv() {
+                                buffer.push_str(&delta);
+                            }
+                            finished = Some(result);
+                        }
+                        (None, None) => {}
                     }
-                }
 
-                // If there's direct content, return it
-                if let Some(content) = &choice.message.content {
-                    if !content.trim().is_empty() {
-                        final_response = content.clone();
-                        return Ok(final_response);
+                    if let Some(value) = parse_partial_json(&buffer).filter(|v| last.as_ref() != Some(v)) {
+                        last = Some(value.clone());
+                        return Some((Ok(PartialValue::Partial(value)), (run, rx, buffer, last, finished)));
                     }
                 }
+            },
+        ))
+    }
 
-                // If the finish reason was "tool_calls", we might want to continue
-                if choice.finish_reason == "tool_calls" {
+    /// Runs the agent with structured output requested for `T`, walking the
+    /// fallback ladder of `structured_output_modes`, and saves state.
+    async fn run_structured<T: JsonSchema>(
+        &mut self,
+        input: String,
+        mut on_delta: Option<&mut (dyn FnMut(&str) + Send)>,
+    ) -> Result<RunOutcome, OpenAIAgentError> {
+        let schema = serde_json::to_value(schemars::schema_for!(T))?;
+        let schema_name: String = T::schema_name()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+            .take(64)
+            .collect();
+        let schema_name = if schema_name.is_empty() { "response".to_string() } else { schema_name };
+
+        let modes = self.structured_output_modes.clone();
+        let mut last_error = None;
+        for (attempt, mode) in modes.iter().enumerate() {
+            let message_count = self.state.message_count();
+            self.structured_output = Some(StructuredOutput::new(*mode, &schema_name, &schema));
+            let delta = on_delta.as_mut().map(|f| &mut **f as &mut (dyn FnMut(&str) + Send));
+            let result = self.run_internal(input.clone(), delta).await;
+            self.structured_output = None;
+
+            let outcome = match result {
+                Ok(outcome) => outcome,
+                Err(e) if attempt + 1 < modes.len() && is_unsupported_response_format(&e) => {
+                    // Forget the rejected attempt before trying the next mode
+                    self.state.messages.truncate(message_count);
+                    self.state.touch();
+                    last_error = Some(e);
                     continue;
                 }
+                Err(e) => return Err(e),
+            };
 
-                return Err(OpenAIAgentError::Parse(
-                    format!("Assistant returned empty message with finish_reason: {}", choice.finish_reason),
-                ));
+            if self.autosave && self.persistence.is_some() {
+                self.save().await?;
+            }
+            return Ok(outcome);
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            OpenAIAgentError::Config("No structured output modes configured".to_string())
+        }))
+    }
+
+    /// Re-runs a stored conversation against the agent's current config and tools.
+    ///
+    /// For every user message in the stored history, the conversation up to and
+    /// including that message is sent again and the model's new final answer is
+    /// collected next to the original one, as `(original assistant message, new content)`.
+    /// Each turn uses the original history as context, so turns are compared
+    /// independently. Tool calls are re-executed; use `replay_with` to answer them
+    /// from the recorded results instead. The agent's own state is not modified.
+    pub async fn replay(
+        &self,
+        store: &dyn PersistenceStore,
+        id: &str,
+    ) -> Result<Vec<(ChatMessage, String)>, OpenAIAgentError> {
+        self.replay_with(store, id, ReplayTools::Execute).await
+    }
+
+    /// Like `replay`, choosing how tool calls made during the replay are answered.
+    pub async fn replay_with(
+        &self,
+        store: &dyn PersistenceStore,
+        id: &str,
+        tools: ReplayTools,
+    ) -> Result<Vec<(ChatMessage, String)>, OpenAIAgentError> {
+        let history = store
+            .get_conversation(id)
+            .await?
+            .ok_or_else(|| OpenAIAgentError::Agent(format!("Conversation not found: {}", id)))?
+            .messages;
+
+        // Results of the original tool calls, keyed by tool name and raw arguments
+        let recorded: HashMap<(String, String), String> = match tools {
+            ReplayTools::Execute => HashMap::new(),
+            ReplayTools::Recorded => recorded_tool_results(&history),
+        };
+
+        let user_turns: Vec<usize> = history
+            .iter()
+            .enumerate()
+            .filter(|(_, message)| message.role == "user")
+            .map(|(index, _)| index)
+            .collect();
+
+        // The whole replay shares one retry budget, like a run
+        let mut retries = 0;
+        let mut results = Vec::with_capacity(user_turns.len());
+        for (turn, &start) in user_turns.iter().enumerate() {
+            let end = user_turns.get(turn + 1).copied().unwrap_or(history.len());
+            let original = history[start + 1..end]
+                .iter()
+                .rev()
+                .find(|message| {
+                    message.role == "assistant"
+                        && message.content.as_ref().is_some_and(|content| !content.is_blank())
+                })
+                .cloned()
+                .unwrap_or_else(|| ChatMessage::assistant(""));
+
+            let mut messages = history[..=start].to_vec();
+            let new_content = self.replay_turn(&mut messages, tools, &recorded, &mut retries).await?;
+            results.push((original, new_content));
+        }
+
+        Ok(results)
+    }
+
+    /// Runs the turn loop on a detached history until the model answers with content.
+    async fn replay_turn(
+        &self,
+        messages: &mut Vec<ChatMessage>,
+        tools: ReplayTools,
+        recorded: &HashMap<(String, String), String>,
+        retries: &mut usize,
+    ) -> Result<String, OpenAIAgentError> {
+        for _ in 0..self.max_turns {
+            let response = self.send_with_retries(self.request_for(messages.clone())?, retries).await?;
+            let choice = response
+                .choices
+                .into_iter()
+                .next()
+                .ok_or_else(|| OpenAIAgentError::Parse("No response choices received".to_string()))?;
+            messages.push(choice.message.clone());
+
+            match choice.message.tool_calls.as_deref() {
+                Some(tool_calls) if !tool_calls.is_empty() => {
+                    for tool_call in tool_calls {
+                        let result = match tools {
+                            ReplayTools::Execute => self.execute_tool_call(tool_call).await?,
+                            ReplayTools::Recorded => {
+                                let key = (tool_call.get_name(), tool_call.get_arguments());
+                                let content = recorded.get(&key).cloned().unwrap_or_else(|| {
+                                    "Error: no recorded result for this tool call".to_string()
+                                });
+                                ChatMessage::tool_result(content, key.0, tool_call.id.clone())
+                            }
+                        };
+                        messages.push(result);
+                    }
+                }
+                _ => return Ok(choice.message.text().unwrap_or_default()),
             }
-            return Err(OpenAIAgentError::Parse(
-                "No response choices received".to_string(),
-            ));
         }
 
         Err(OpenAIAgentError::Agent(format!(
@@ -170,69 +2358,104 @@ impl Agent {
         )))
     }
 
-    /// Internal helper that executes a given tool call (function call).
-    async fn execute_tool_call(&self, tc: &ToolCall) -> Result<ChatMessage, OpenAIAgentError> {
-        let tool_name = &tc.function.name;
-        let arguments = &tc.function.arguments;
-        let tool_call_id = &tc.id;
-
-        let tool = self
-            .tools
-            .get(tool_name)
-            .ok_or_else(|| OpenAIAgentError::Tool(format!("Tool not found: {}", tool_name)))?;
-
-        // Parse the JSON arguments
-        let parsed_args = serde_json::from_str(arguments)
-            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to parse tool arguments: {}", e)))?;
+    /// Returns a reference to the current agent state.
+    pub fn state(&self) -> &AgentState {
+        &self.state
+    }
 
-        // Execute the tool
-        let result = tool.execute(parsed_args).await?;
+    /// Returns the names of the tools offered to the model on the given turn of
+    /// the conversation, counting from 0 for the oldest recorded turn.
+    ///
+    /// Useful when debugging why a tool was never called: the list is captured
+    /// from each request as sent, and persisted with the conversation. Returns
+    /// `None` past the last turn, or for turns stored before this was recorded.
+    pub fn available_tools_at_turn(&self, index: usize) -> Option<&[String]> {
+        self.state.turn_tools.get(index).map(|turn| turn.tools.as_slice())
+    }
 
-        // Create a message that records the tool's result
-        let response = ChatMessage {
-            role: "tool".to_string(),
-            content: Some(result),
-            name: Some(tool_name.clone()),
-            tool_call_id: Some(tool_call_id.clone()),
-            tool_calls: None,
-        };
+    /// Returns the number of tool calls executed during the current (or most recent) run.
+    pub fn tool_call_count(&self) -> usize {
+        self.tool_call_count
+    }
 
-        Ok(response)
+    /// Returns the tool calls of the current (or most recent) run exactly as the
+    /// model emitted them, with ids and raw argument strings, one entry per model
+    /// response.
+    ///
+    /// Unlike `RunOutcome::tool_calls`, this is also available after a run failed,
+    /// e.g. because a tool returned an error.
+    pub fn last_tool_calls(&self) -> &[Vec<ToolCall>] {
+        &self.run_tool_calls
     }
 
-    /// Prepares a request to the OpenAI API with the current state and tools.
-    fn prepare_request(&self) -> Result<ChatRequest, OpenAIAgentError> {
-        let config = self.client.config();
+    /// Changes the turn limit for subsequent runs.
+    ///
+    /// Fails with `OpenAIAgentError::Config` if `max_turns` is 0, like `build`.
+    pub fn set_max_turns(&mut self, max_turns: usize) -> Result<(), OpenAIAgentError> {
+        if max_turns == 0 {
+            return Err(OpenAIAgentError::Config("max_turns must be at least 1".to_string()));
+        }
+        self.max_turns = max_turns;
+        Ok(())
+    }
 
-        let mut request = ChatRequest {
-            model: config.model().to_string(),
-            messages: self.state.messages.clone(),
-            tools: None,
-            max_tokens: Some(config.max_tokens()),
-            temperature: Some(config.temperature()),
-            response_format: None,
-            stream: Some(config.stream()),
-        };
+    /// Changes the sampling temperature used for subsequent requests.
+    ///
+    /// Fails with `OpenAIAgentError::Config` unless it is between 0.0 and 2.0.
+    pub fn set_temperature(&mut self, temperature: f32) -> Result<(), OpenAIAgentError> {
+        self.update_config(|config| config.with_temperature(temperature))
+    }
 
-        if !self.tools.is_empty() {
-            request.tools = Some(self.tools.definitions());
-        }
+    /// Changes the completion token limit used for subsequent requests.
+    ///
+    /// Fails with `OpenAIAgentError::Config` if `max_tokens` is 0.
+    pub fn set_max_tokens(&mut self, max_tokens: usize) -> Result<(), OpenAIAgentError> {
+        self.update_config(|config| config.with_max_tokens(max_tokens))
+    }
 
-        Ok(request)
+    /// Changes the model used for subsequent requests. Token counting and
+    /// context window lookups follow the new model.
+    ///
+    /// Fails with `OpenAIAgentError::Config` if the model name is empty.
+    pub fn set_model(&mut self, model: impl Into<String>) -> Result<(), OpenAIAgentError> {
+        let model = model.into();
+        self.update_config(|config| config.with_model(model))
     }
 
-    /// Returns a reference to the current agent state.
-    pub fn state(&self) -> &AgentState {
-        &self.state
+    /// Applies a change to the agent's configuration, keeping the old one if the
+    /// result fails the checks `build` runs.
+    fn update_config(
+        &mut self,
+        change: impl FnOnce(OpenAIConfig) -> OpenAIConfig,
+    ) -> Result<(), OpenAIAgentError> {
+        let config = change(self.config.clone());
+        if let Some(problem) = config.problems().into_iter().next() {
+            return Err(OpenAIAgentError::Config(problem));
+        }
+        self.config = config;
+        Ok(())
     }
 
     /// Adds a user message to the conversation history.
     ///
+    /// Fails with `OpenAIAgentError::Agent` if the content is empty or whitespace-only,
+    /// unless the agent was built with `with_allow_empty_input(true)`.
+    ///
     /// # Arguments
     ///
     /// * `content` - The content of the user message
-    pub fn push_user_message(&mut self, content: impl Into<String>) {
-        self.state.messages.push(ChatMessage::user(content.into()));
+    pub fn push_user_message(&mut self, content: impl Into<String>) -> Result<(), OpenAIAgentError> {
+        let content = self.check_input(content.into())?;
+        self.state.push_message(ChatMessage::user(content));
+        Ok(())
+    }
+
+    /// Rejects empty or whitespace-only user input unless explicitly allowed.
+    fn check_input(&self, input: String) -> Result<String, OpenAIAgentError> {
+        if !self.allow_empty_input && input.trim().is_empty() {
+            return Err(OpenAIAgentError::Agent("empty user input".to_string()));
+        }
+        Ok(input)
     }
 
     /// Adds an assistant message to the conversation history.
@@ -241,17 +2464,84 @@ impl Agent {
     ///
     /// * `content` - The content of the assistant message
     pub fn push_assistant_message(&mut self, content: impl Into<String>) {
-        self.state.messages.push(ChatMessage::assistant(content.into()));
+        self.state.push_message(ChatMessage::assistant(content.into()));
     }
     
-    /// Get the agent's unique identifier
+    /// Pins the message at `index` in the history, so `with_max_history_messages`
+    /// and summarization never drop it. Pinned state is saved with the conversation.
+    ///
+    /// Fails with `OpenAIAgentError::Agent` if there is no message at `index`.
+    pub fn pin_message(&mut self, index: usize) -> Result<(), OpenAIAgentError> {
+        if !self.state.pin(index) {
+            return Err(OpenAIAgentError::Agent(format!("No message at index {}", index)));
+        }
+        Ok(())
+    }
+
+    /// Unpins the message at `index`, letting history management drop it again.
+    ///
+    /// Fails with `OpenAIAgentError::Agent` if there is no message at `index`.
+    pub fn unpin_message(&mut self, index: usize) -> Result<(), OpenAIAgentError> {
+        if !self.state.unpin(index) {
+            return Err(OpenAIAgentError::Agent(format!("No message at index {}", index)));
+        }
+        Ok(())
+    }
+
+    /// Returns a mutable reference to the last message so it can be edited in place.
+    ///
+    /// Returns `None` if the history is empty or the last message is part of a tool
+    /// exchange (a `tool` result, or an assistant message carrying `tool_calls`), since
+    /// editing those could break the call/result pairing the API requires. Edits are
+    /// sent as context on the next turn and captured by the next `save()`.
+    pub fn last_message_mut(&mut self) -> Option<&mut ChatMessage> {
+        if self.state.messages.last().is_none_or(is_tool_exchange) {
+            return None;
+        }
+
+        // The caller may edit the message, so treat handing it out as a mutation
+        self.state.touch();
+        self.state.messages.last_mut()
+    }
+
+    /// Replaces the last message in the conversation history.
+    ///
+    /// Fails if there is no message, if the current last message is part of a tool
+    /// exchange, or if the replacement itself is a tool result or carries tool calls.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to put in place of the last one
+    pub fn replace_last_message(&mut self, mut message: ChatMessage) -> Result<(), OpenAIAgentError> {
+        let last = self
+            .state
+            .messages
+            .last_mut()
+            .ok_or_else(|| OpenAIAgentError::Agent("No message to replace".to_string()))?;
+
+        if is_tool_exchange(last) {
+            return Err(OpenAIAgentError::Agent(
+                "Cannot replace a message that is part of a tool call exchange".to_string(),
+            ));
+        }
+        if is_tool_exchange(&message) {
+            return Err(OpenAIAgentError::Agent(
+                "Replacement message must not be a tool result or carry tool calls".to_string(),
+            ));
+        }
 
+        message.ensure_id();
+        *last = message;
+        self.state.touch();
+        Ok(())
+    }
+
+    /// Get the agent's unique identifier
     pub fn id(&self) -> &str {
         &self.id
     }
     
     /// Save the current agent state to the persistence store
-
     pub async fn save(&self) -> Result<(), OpenAIAgentError> {
         if let Some(store) = &self.persistence {
             store.store_conversation(&self.id, &self.state).await?;
@@ -260,19 +2550,25 @@ impl Agent {
     }
     
     /// Load agent state from the persistence store
-
     pub async fn load(&mut self) -> Result<bool, OpenAIAgentError> {
-        if let Some(store) = &self.persistence {
-            if let Some(state) = store.get_conversation(&self.id).await? {
-                self.state = state;
-                return Ok(true);
-            }
+        if let Some(store) = &self.persistence
+            && let Some(state) = store.get_conversation(&self.id).await?
+        {
+            self.restore_state(state);
+            return Ok(true);
         }
         Ok(false)
     }
+
+    /// Replaces the state with one loaded from the persistence store.
+    pub(crate) fn restore_state(&mut self, state: AgentState) {
+        // Keep the version monotonic across reloads so clients notice the change
+        let version = self.state.version + 1;
+        self.state = state;
+        self.state.version = self.state.version.max(version);
+    }
     
     /// Delete agent data from the persistence store
-
     pub async fn delete(&self) -> Result<(), OpenAIAgentError> {
         if let Some(store) = &self.persistence {
             store.delete_conversation(&self.id).await?;
@@ -311,6 +2607,21 @@ impl Agent {
         ws_client.send_event(event).await
     }
 
+    /// Apply session settings (voice, modalities, turn detection, ...) to the Realtime session.
+    pub async fn update_realtime_session(
+        &mut self,
+        config: &RealtimeSessionConfig
+    ) -> Result<(), OpenAIAgentError> {
+        let ws_client = self
+            .websocket_client
+            .as_mut()
+            .ok_or_else(|| OpenAIAgentError::Agent(
+                "No WebSocket client configured (call `with_websocket()` first).".to_string()
+            ))?;
+
+        ws_client.update_session(config).await
+    }
+
     /// Start reading events from the Realtime API in a loop, calling the provided handler for each.
     ///
     /// The handler can do whatever you like with inbound `ServerEvent`s,
@@ -332,6 +2643,200 @@ impl Agent {
         ws_client.process_incoming(on_event).await
     }
 
+    /// Like `process_realtime_events`, but stops when `stop` is triggered (closing
+    /// the connection gracefully) or when no message arrives within `idle_timeout`.
+    ///
+    /// See `WebSocketClient::process_incoming_until`.
+    pub async fn process_realtime_events_until<F>(
+        &mut self,
+        stop: &StopHandle,
+        idle_timeout: Option<Duration>,
+        on_event: F,
+    ) -> Result<ProcessEnd, OpenAIAgentError>
+    where
+        F: FnMut(ServerEvent) -> Result<(), OpenAIAgentError>
+    {
+        let ws_client = self
+            .websocket_client
+            .as_mut()
+            .ok_or_else(|| OpenAIAgentError::Agent(
+                "No WebSocket client configured (call `with_websocket()` first).".to_string()
+            ))?;
+
+        ws_client.process_incoming_until(stop, idle_timeout, on_event).await
+    }
+
+    /// Process Realtime events, running the agent's tools when the model calls them.
+    ///
+    /// When a `response.function_call_arguments.done` event arrives, the matching
+    /// tool is executed exactly as in `run` (including argument validation), its
+    /// output is sent back as a `function_call_output` conversation item, and a
+    /// `response.create` event asks the model to continue. Every event, including
+    /// function call events, is also passed to `on_event`. Runs until the server
+    /// closes the connection or `stop` is triggered, which closes it gracefully.
+    ///
+    /// The session must offer the tools to the model, e.g. with
+    /// `RealtimeSessionConfig::with_tools`.
+    pub async fn handle_realtime_tool_calls<F>(
+        &mut self,
+        stop: &StopHandle,
+        mut on_event: F,
+    ) -> Result<ProcessEnd, OpenAIAgentError>
+    where
+        F: FnMut(&ServerEvent) -> Result<(), OpenAIAgentError>
+    {
+        loop {
+            let ws_client = self
+                .websocket_client
+                .as_mut()
+                .ok_or_else(|| OpenAIAgentError::Agent(
+                    "No WebSocket client configured (call `with_websocket()` first).".to_string()
+                ))?;
+
+            // `None` means stop was requested
+            let next = tokio::select! {
+                biased;
+                _ = stop.stopped() => None,
+                event = ws_client.next_event() => Some(event?),
+            };
+            let event = match next {
+                Some(Some(event)) => event,
+                Some(None) => return Ok(ProcessEnd::Closed),
+                None => {
+                    ws_client.close().await?;
+                    return Ok(ProcessEnd::Stopped);
+                }
+            };
+
+            on_event(&event)?;
+
+            if event.event_type != "response.function_call_arguments.done" {
+                continue;
+            }
+
+            let call_id = event.get_str("call_id").unwrap_or_default().to_string();
+            let tool_call = ToolCall {
+                id: call_id.clone(),
+                call_type: "function".to_string(),
+                function: FunctionCall {
+                    name: event.get_str("name").unwrap_or_default().to_string(),
+                    arguments: event.get_str("arguments").unwrap_or("{}").to_string(),
+                },
+                name: None,
+                arguments: None,
+            };
+            let output = self.execute_tool_call(&tool_call).await?.text().unwrap_or_default();
+
+            let ws_client = self.websocket_client.as_mut().ok_or_else(|| {
+                OpenAIAgentError::Agent("WebSocket client was removed".to_string())
+            })?;
+            ws_client
+                .send_json(&serde_json::json!({
+                    "type": "conversation.item.create",
+                    "item": {
+                        "type": "function_call_output",
+                        "call_id": call_id,
+                        "output": output,
+                    },
+                }))
+                .await?;
+            ws_client.send_json(&serde_json::json!({ "type": "response.create" })).await?;
+        }
+    }
+
+    /// Sends `input` as a user message over the Realtime connection and streams the
+    /// text response.
+    ///
+    /// Sends a `conversation.item.create` followed by a text-only `response.create`,
+    /// then yields every `response.text.delta` fragment until `response.done`. Both
+    /// messages are added to the history, and saved if autosave is enabled. A server
+    /// `error` event, or the connection closing mid-response, ends the stream with
+    /// an error. Tool calls are not handled; see `handle_realtime_tool_calls`.
+    ///
+    /// The connection must already be open (see `connect_realtime`).
+    pub fn run_realtime<'a>(&'a mut self, input: impl Into<String>) -> TextStream<'a> {
+        let input = input.into();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let run = Box::pin(async move {
+            let mut on_delta = move |delta: &str| {
+                let _ = tx.send(delta.to_string());
+            };
+            self.run_realtime_turn(input, &mut on_delta).await
+        });
+
+        Self::delta_stream(run, rx)
+    }
+
+    /// The exchange behind `run_realtime`
+    async fn run_realtime_turn(
+        &mut self,
+        input: String,
+        on_delta: &mut (dyn FnMut(&str) + Send),
+    ) -> Result<String, OpenAIAgentError> {
+        let input = self.check_input(input)?;
+        let ws_client = self
+            .websocket_client
+            .as_mut()
+            .ok_or_else(|| OpenAIAgentError::Agent(
+                "No WebSocket client configured (call `with_websocket()` first).".to_string()
+            ))?;
+
+        ws_client
+            .send_json(&serde_json::json!({
+                "type": "conversation.item.create",
+                "item": {
+                    "type": "message",
+                    "role": "user",
+                    "content": [{ "type": "input_text", "text": input }],
+                },
+            }))
+            .await?;
+        ws_client
+            .send_json(&serde_json::json!({
+                "type": "response.create",
+                "response": { "modalities": ["text"] },
+            }))
+            .await?;
+
+        let mut content = String::new();
+        let result = loop {
+            let Some(event) = ws_client.next_event().await? else {
+                break Err(OpenAIAgentError::Request(
+                    "Realtime connection closed before the response completed".to_string(),
+                ));
+            };
+
+            match event.event_type.as_str() {
+                "response.text.delta" => {
+                    let delta = event.get_str("delta").unwrap_or_default();
+                    content.push_str(delta);
+                    on_delta(delta);
+                }
+                "response.done" => break Ok(()),
+                "error" => {
+                    let message = event
+                        .data
+                        .get("error")
+                        .and_then(|error| error.get("message"))
+                        .and_then(|message| message.as_str())
+                        .unwrap_or("unknown error");
+                    break Err(OpenAIAgentError::Agent(format!("Realtime error: {}", message)));
+                }
+                _ => {}
+            }
+        };
+
+        // The server already holds the user message, so keep it even if the response failed
+        self.record_message(ChatMessage::user(input));
+        result?;
+        self.record_message(ChatMessage::assistant(content.clone()));
+
+        if self.autosave && self.persistence.is_some() {
+            self.save().await?;
+        }
+        Ok(content)
+    }
+
     /// Close the WebSocket connection gracefully, if open.
     pub async fn close_realtime(&mut self) -> Result<(), OpenAIAgentError> {
         if let Some(ws_client) = &mut self.websocket_client {
@@ -349,38 +2854,143 @@ pub struct AgentBuilder {
     /// Tools available to the agent
     pub(crate) tools: Arc<ToolRegistry>,
 
+    /// System prompts, in the order they are sent
+    pub(crate) system_prompts: Vec<String>,
+
+    /// Whether system prompts are sent separately or joined
+    pub(crate) system_prompt_mode: SystemPromptMode,
+
     /// Initial messages to seed the conversation
     pub(crate) messages: Vec<ChatMessage>,
 
     /// Maximum number of turns to prevent infinite loops
     pub(crate) max_turns: usize,
 
+    /// What to do when the turn limit is reached
+    pub(crate) max_turns_behavior: MaxTurnsBehavior,
+
+    /// Optional cap on tool calls per run
+    pub(crate) max_tool_calls: Option<usize>,
+
+    /// Whether a turn stopped by the content filter is retried once
+    pub(crate) retry_content_filter: bool,
+
+    /// Whether tool failures are sent to the model as `ToolError` JSON
+    pub(crate) structured_tool_errors: bool,
+
+    /// How many times a response cut off by the token limit is continued per run
+    pub(crate) max_continuations: usize,
+
+    /// Retries of failed API calls allowed across a whole run
+    pub(crate) retries_per_run: usize,
+
+    /// Whether empty or whitespace-only user input is accepted
+    pub(crate) allow_empty_input: bool,
+
+    /// Whether control characters are stripped from messages sent to the API
+    pub(crate) sanitize_messages: bool,
+
+    /// Whether control characters are also stripped from messages as they are stored
+    pub(crate) sanitize_history: bool,
+
+    /// Optional automatic summarization settings
+    pub(crate) summarize: Option<SummarizeConfig>,
+
+    /// Optional transform applied to the final response content
+    pub(crate) response_transform: Option<ResponseTransform>,
+
+    /// Optional callback overriding how finish reasons are handled
+    pub(crate) finish_reason_handler: Option<FinishReasonHandler>,
+
+    /// Optional custom transport replacing the default HTTP client
+    pub(crate) transport: Option<Arc<dyn ChatTransport>>,
+
+    /// Optional cache for chat completion responses
+    pub(crate) response_cache: Option<Arc<dyn ResponseCache>>,
+
+    /// Whether to cache non-deterministic requests too
+    pub(crate) force_response_cache: bool,
+
     /// Optional WebSocket client for the Realtime Beta
     pub(crate) websocket_client: Option<WebSocketClient>,
     
-    /// Unique identifier for the agent
+    /// Unique identifier for the agent; generated at build time when not set
+    pub(crate) id: Option<EntityId>,
 
-    pub(crate) id: EntityId,
+    /// Generator for the agent's ID, overriding the process-wide one
+    pub(crate) id_generator: Option<Arc<dyn IdGenerator>>,
     
     /// Optional persistence store
 
     pub(crate) persistence: Option<Arc<dyn PersistenceStore>>,
+
+    /// Whether to save after every run
+    pub(crate) autosave: bool,
+
+    /// Optional interval for checkpointing streamed responses
+    pub(crate) stream_checkpoint_interval: Option<Duration>,
+
+    /// Request/response interceptors in registration order
+    pub(crate) interceptors: Vec<Arc<dyn Interceptor>>,
+
+    /// Structured output modes `run_typed` tries, in order
+    pub(crate) structured_output_modes: Vec<StructuredOutputMode>,
+
+    /// Optional cap on the number of non-system messages kept
+    pub(crate) max_history_messages: Option<usize>,
+
+    /// Optional cap on the serialized request size
+    pub(crate) max_request_bytes: Option<usize>,
+
+    /// How tool output is written into tool messages
+    pub(crate) tool_result_formatter: ToolResultFormatter,
+
+    /// Values tools can look up by type
+    pub(crate) context: ContextMap,
+
+    /// Optional channel activity events are published to
+    pub(crate) events: Option<AgentEventSender>,
 }
 
 impl AgentBuilder {
     /// Creates a new AgentBuilder with default settings.
     pub fn new() -> Self {
-        let builder = Self {
+        Self {
             config: None,
             tools: Arc::new(ToolRegistry::new()),
+            system_prompts: Vec::new(),
+            system_prompt_mode: SystemPromptMode::Separate,
             messages: Vec::new(),
             max_turns: 10,
+            max_turns_behavior: MaxTurnsBehavior::Error,
+            max_tool_calls: None,
+            retry_content_filter: false,
+            structured_tool_errors: false,
+            max_continuations: 0,
+            retries_per_run: 0,
+            allow_empty_input: false,
+            sanitize_messages: false,
+            sanitize_history: false,
+            summarize: None,
+            response_transform: None,
+            finish_reason_handler: None,
+            transport: None,
+            response_cache: None,
+            force_response_cache: false,
             websocket_client: None,
-            id: generate_id(),
+            id: None,
+            id_generator: None,
             persistence: None,
-        };
-        
-        builder
+            autosave: true,
+            stream_checkpoint_interval: None,
+            interceptors: Vec::new(),
+            structured_output_modes: StructuredOutputMode::DEFAULT_ORDER.to_vec(),
+            max_history_messages: None,
+            max_request_bytes: None,
+            tool_result_formatter: ToolResultFormatter::Raw,
+            context: ContextMap::new(),
+            events: None,
+        }
     }
 
     /// Sets the OpenAI configuration.
@@ -395,9 +3005,60 @@ impl AgentBuilder {
         self
     }
 
-    /// Adds a system prompt message to the initial conversation.
+    /// Adds a system prompt after any already added.
+    ///
+    /// System prompts always start the conversation, ahead of messages added with
+    /// `with_message` or `with_developer_prompt`, in the order given by this and
+    /// the other `with_system_prompt*` methods. By default each is sent as its own
+    /// system message; see `with_system_prompt_mode`.
     pub fn with_system_prompt(mut self, prompt: impl Into<String>) -> Self {
-        self.messages.push(ChatMessage::system(prompt.into()));
+        self.system_prompts.push(prompt.into());
+        self
+    }
+
+    /// Adds a system prompt after any already added; same as `with_system_prompt`.
+    pub fn with_system_prompt_append(self, prompt: impl Into<String>) -> Self {
+        self.with_system_prompt(prompt)
+    }
+
+    /// Adds a system prompt before any already added, so it is sent first.
+    pub fn with_system_prompt_prepend(mut self, prompt: impl Into<String>) -> Self {
+        self.system_prompts.insert(0, prompt.into());
+        self
+    }
+
+    /// Adds several system prompts, in order, after any already added.
+    pub fn with_system_prompts(mut self, prompts: Vec<String>) -> Self {
+        self.system_prompts.extend(prompts);
+        self
+    }
+
+    /// Sets whether system prompts are sent as separate system messages (the
+    /// default) or joined into one.
+    pub fn with_system_prompt_mode(mut self, mode: SystemPromptMode) -> Self {
+        self.system_prompt_mode = mode;
+        self
+    }
+
+    /// Renders a prompt template and adds the result as a system prompt.
+    ///
+    /// Fails with `OpenAIAgentError::Config` if the template references a variable
+    /// that is neither in `vars` nor has a default.
+    pub fn with_system_template(
+        self,
+        template: &PromptTemplate,
+        vars: &HashMap<String, String>,
+    ) -> Result<Self, OpenAIAgentError> {
+        let prompt = template.render(vars)?;
+        Ok(self.with_system_prompt(prompt))
+    }
+
+    /// Adds a developer prompt message to the initial conversation.
+    ///
+    /// Use this instead of `with_system_prompt` for models that expect
+    /// instructions in the `developer` role, such as the o-series.
+    pub fn with_developer_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.messages.push(ChatMessage::developer(prompt.into()));
         self
     }
 
@@ -418,12 +3079,271 @@ impl AgentBuilder {
         self.max_turns = max_turns;
         self
     }
+
+    /// Sets what a run does when it reaches the maximum number of turns.
+    ///
+    /// Defaults to `MaxTurnsBehavior::Error`. With `ReturnPartial`, `run` returns the
+    /// last assistant content produced instead, and `run_detailed` marks the
+    /// outcome as `truncated`.
+    pub fn with_max_turns_behavior(mut self, behavior: MaxTurnsBehavior) -> Self {
+        self.max_turns_behavior = behavior;
+        self
+    }
+
+    /// Sets the maximum number of tool calls allowed within a single run.
+    ///
+    /// Unlike `with_max_turns`, this counts every individual tool invocation, so a
+    /// model that requests many tools in one turn is also bounded. A response whose
+    /// calls would exceed the budget fails the run with `OpenAIAgentError::Agent`
+    /// before any of them execute, and is not added to the history, so the agent
+    /// can be run again.
+    pub fn with_max_tool_calls(mut self, max_tool_calls: usize) -> Self {
+        self.max_tool_calls = Some(max_tool_calls);
+        self
+    }
+
+    /// Retries a turn once when the response is stopped by the content filter
+    /// (default: disabled).
+    ///
+    /// The retry asks the model to answer again within the content policy; the
+    /// note is added to the request only and never stored in the history. If the
+    /// retry is filtered too, or retrying is disabled, the run fails with
+    /// `OpenAIAgentError::ContentFiltered`.
+    pub fn with_content_filter_retry(mut self, retry: bool) -> Self {
+        self.retry_content_filter = retry;
+        self
+    }
+
+    /// Reports every tool failure to the model as
+    /// `{"error": {"type": ..., "message": ...}}` (default: disabled).
+    ///
+    /// This covers unknown tools, arguments that don't parse or match a strict
+    /// schema, panics, and errors returned by the tool, which keep their type when
+    /// they are a `ToolError`. The model sees the failure and can retry or give up;
+    /// only cancellation still ends the run. When disabled, invalid arguments and
+    /// panics are reported as plain `Error: ...` text and other failures end the run.
+    pub fn with_structured_tool_errors(mut self, enabled: bool) -> Self {
+        self.structured_tool_errors = enabled;
+        self
+    }
+
+    /// Continues responses cut off by the token limit (`finish_reason == "length"`)
+    /// up to `max_continuations` times per run (default: 0, disabled).
+    ///
+    /// Each continuation asks the model to carry on where it stopped, and the
+    /// pieces are stitched into a single assistant message, which is what the run
+    /// returns and what the history keeps. Once the cap is reached the last
+    /// response is returned as it is. Continuations count towards `max_turns`.
+    pub fn with_auto_continue(mut self, max_continuations: usize) -> Self {
+        self.max_continuations = max_continuations;
+        self
+    }
+
+    /// Retries failed API calls up to `retries` times in total per run
+    /// (default: 0, no retries).
+    ///
+    /// Only retryable errors (timeouts, connection failures, 429 and 5xx
+    /// responses) are retried, with exponential backoff. The budget is shared by
+    /// every API call of the run, including history summarization, so a long
+    /// tool loop cannot retry on each turn and the worst-case latency of a run
+    /// stays bounded; once it is used up the next failure ends the run. Streamed
+    /// turns (`run_stream`) are retried only while opening the stream, since
+    /// deltas already delivered cannot be taken back. `RunOutcome::retries`
+    /// reports how much of the budget a run used. `ask_ephemeral`, `summarize_now`
+    /// and each `replay` get a budget of the same size of their own.
+    pub fn with_total_retries_per_run(mut self, retries: usize) -> Self {
+        self.retries_per_run = retries;
+        self
+    }
     
+    /// Allows empty or whitespace-only user input to be sent to the model.
+    ///
+    /// Disabled by default: `run` and `push_user_message` reject such input
+    /// with `OpenAIAgentError::Agent("empty user input")`.
+    pub fn with_allow_empty_input(mut self, allow: bool) -> Self {
+        self.allow_empty_input = allow;
+        self
+    }
+
+    /// Strips control characters other than tab, newline and carriage return from
+    /// message text before each request (default: disabled).
+    ///
+    /// Stray characters such as `\u{0000}` from tool output or user input can make
+    /// the API reject the whole request. Only the outgoing request is cleaned; the
+    /// stored history keeps the original text unless `with_sanitize_history` is
+    /// also enabled. See `ChatMessage::sanitize`.
+    pub fn with_sanitize_messages(mut self, sanitize: bool) -> Self {
+        self.sanitize_messages = sanitize;
+        self
+    }
+
+    /// Strips control characters from messages as they are added to the history
+    /// during runs, so the stored conversation is cleaned too (default: disabled).
+    pub fn with_sanitize_history(mut self, sanitize: bool) -> Self {
+        self.sanitize_history = sanitize;
+        self
+    }
+
+    /// Enables automatic summarization of older history.
+    ///
+    /// Before each request, if the estimated prompt tokens exceed
+    /// `trigger_tokens` (or the message count exceeds `trigger_messages`),
+    /// the oldest messages are replaced by a summary. See `Agent::summarize_now`.
+    pub fn with_auto_summarize(mut self, config: SummarizeConfig) -> Self {
+        self.summarize = Some(config);
+        self
+    }
+
+    /// Sets a transform applied to the final response content before `run` returns.
+    ///
+    /// Useful for stripping boilerplate or redacting output. The transform only
+    /// affects the returned value; the assistant message in `state.messages` is
+    /// stored unchanged.
+    pub fn with_response_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(String) -> String + Send + Sync + 'static,
+    {
+        self.response_transform = Some(Arc::new(transform));
+        self
+    }
+
+    /// Sets a callback that decides how the run proceeds after each model response.
+    ///
+    /// The callback receives the finish reason (`stop`, `length`, `tool_calls`,
+    /// `content_filter`, ...) and the response message, and returns a
+    /// `FinishAction`. Returning `FinishAction::Default` keeps the built-in
+    /// behavior, so a handler only needs to cover the reasons it cares about; for
+    /// example, returning `Continue` for `length` asks the model to keep going
+    /// after hitting the token limit. Continued turns count towards `max_turns`.
+    pub fn with_finish_reason_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&str, &ChatMessage) -> FinishAction + Send + Sync + 'static,
+    {
+        self.finish_reason_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Registers an interceptor that can modify requests and responses.
+    ///
+    /// Multiple interceptors compose like middleware: `before_request` runs in
+    /// registration order and `after_response` in reverse order, so the first
+    /// interceptor registered is the outermost layer.
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn Interceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Keeps at most `max_messages` messages besides the leading system prompts.
+    ///
+    /// Before each request the oldest messages are dropped, together with any tool
+    /// results belonging to a dropped tool call, so a pair is never split. The
+    /// latest user message and the tool exchange following it are never dropped
+    /// either, so the cap can be exceeded during a long tool loop. Pinned
+    /// messages (see `Agent::pin_message`) are never dropped. This is a cheap
+    /// alternative to summarization when exact token accounting isn't needed; see
+    /// `AgentState::oldest_kept_index`.
+    pub fn with_max_history_messages(mut self, max_messages: usize) -> Self {
+        self.max_history_messages = Some(max_messages);
+        self
+    }
+
+    /// Fails a run with `OpenAIAgentError::Agent` instead of sending a request whose
+    /// JSON body is larger than `max_bytes`.
+    ///
+    /// Checked before every request of a run, after interceptors have been applied,
+    /// so oversized histories or images are caught without a round-trip. See
+    /// `Agent::request_byte_size`.
+    pub fn with_max_request_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_request_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Publishes the agent's activity (messages, turns, tool executions, errors)
+    /// to a broadcast channel.
+    ///
+    /// Publishing never blocks; subscribers that fall behind miss events rather
+    /// than slowing the agent down.
+    pub fn with_event_sender(mut self, events: AgentEventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Makes a value available to tools through `ToolContext::get`.
+    ///
+    /// Values are keyed by type, so each tool can fetch what it needs (say a
+    /// `DbPool` or a `UserSession`); adding a second value of the same type
+    /// replaces the first. Wrap a type in a newtype to store several of it.
+    pub fn with_context_value<T: Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.context.insert(value);
+        self
+    }
+
+    /// Sets how tool output is written into the tool messages sent to the model.
+    ///
+    /// Defaults to `ToolResultFormatter::Raw`, which sends output unchanged.
+    pub fn with_tool_result_formatter(mut self, formatter: ToolResultFormatter) -> Self {
+        self.tool_result_formatter = formatter;
+        self
+    }
+
+    /// Sets the structured output modes `run_typed` tries, in order.
+    ///
+    /// Defaults to `StructuredOutputMode::DEFAULT_ORDER`. Pass a single mode to
+    /// disable fallback, e.g. for models known to support `json_schema`.
+    pub fn with_structured_output_modes(mut self, modes: impl Into<Vec<StructuredOutputMode>>) -> Self {
+        self.structured_output_modes = modes.into();
+        self
+    }
+
+    /// Sets a custom transport for chat completion requests.
+    ///
+    /// By default the agent talks to the OpenAI API over HTTP. A custom transport,
+    /// such as `MockTransport`, replaces that client entirely; in that case no API
+    /// key is required and `with_response_cache` has no effect.
+    pub fn with_transport(mut self, transport: Arc<dyn ChatTransport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Enables response caching for chat completion requests.
+    ///
+    /// Identical requests are served from the cache instead of calling the API.
+    /// Only deterministic requests (temperature 0, not streaming) are cached unless
+    /// `with_forced_response_cache(true)` is also set.
+    pub fn with_response_cache(mut self, cache: Arc<dyn ResponseCache>) -> Self {
+        self.response_cache = Some(cache);
+        self
+    }
+
+    /// Caches responses even when temperature is above 0 or streaming is enabled.
+    pub fn with_forced_response_cache(mut self, force: bool) -> Self {
+        self.force_response_cache = force;
+        self
+    }
+
     /// Set a specific ID for the agent
     pub fn with_id(mut self, id: impl Into<String>) -> Self {
-        self.id = id.into();
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Generate the agent's ID with `generator` instead of the process-wide
+    /// generator (see `persistence::set_id_generator`).
+    ///
+    /// Ignored when an ID is set with `with_id` or `with_derived_id`.
+    pub fn with_id_generator(mut self, generator: Arc<dyn IdGenerator>) -> Self {
+        self.id_generator = Some(generator);
         self
     }
+
+    /// Derive the agent's ID from a namespace and a stable key, such as a user ID.
+    ///
+    /// The same inputs always give the same ID (see `persistence::derive_id`), so
+    /// `build_async` loads the existing conversation or starts a new one without
+    /// the caller storing a random ID.
+    pub fn with_derived_id(self, namespace: &str, key: &str) -> Self {
+        self.with_id(derive_id(namespace, key))
+    }
     
     /// Add persistence capabilities to the agent
     pub fn with_persistence(mut self, store: Arc<dyn PersistenceStore>) -> Self {
@@ -431,6 +3351,29 @@ impl AgentBuilder {
         self
     }
 
+    /// Sets whether state is saved to the persistence store after every run (default `true`).
+    ///
+    /// With autosave disabled, call `agent.save()` yourself, e.g. periodically
+    /// and before the program exits; unsaved changes are otherwise lost.
+    pub fn with_autosave(mut self, autosave: bool) -> Self {
+        self.autosave = autosave;
+        self
+    }
+
+    /// Periodically writes the in-progress assistant message to the persistence store
+    /// while streaming with `run_stream`.
+    ///
+    /// At most once per `interval` (checked as chunks arrive), the history plus the
+    /// partial message, marked `incomplete`, is stored with `store_conversation`; once
+    /// the stream finishes the completed message is stored in its place. A crash
+    /// mid-generation then loses at most one interval of text. Each checkpoint
+    /// rewrites the whole conversation, so on backends where writes are costly
+    /// (e.g. `PostgresStore` with long histories) prefer intervals of several seconds.
+    pub fn with_stream_checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.stream_checkpoint_interval = Some(interval);
+        self
+    }
+
     /// Instantiates a WebSocketClient for Realtime usage, storing it in this builder.
     /// This does NOT immediately connect; call `agent.connect_realtime(...)` after build.
     pub fn with_websocket(mut self) -> Result<Self, OpenAIAgentError> {
@@ -440,14 +3383,109 @@ impl AgentBuilder {
         Ok(self)
     }
 
+    /// Uses an already configured WebSocketClient for Realtime usage, e.g. one
+    /// with a custom connect timeout or message size limits.
+    /// This does NOT immediately connect; call `agent.connect_realtime(...)` after build.
+    pub fn with_websocket_client(mut self, client: WebSocketClient) -> Self {
+        self.websocket_client = Some(client);
+        self
+    }
+
     /// Builds the Agent from the current configuration.
     pub fn build(self) -> Result<Agent, OpenAIAgentError> {
+        if let Err(mut problems) = self.validate() {
+            if problems.len() == 1 {
+                return Err(problems.remove(0));
+            }
+            let summary = problems
+                .iter()
+                .map(|problem| problem.to_string())
+                .collect::<Vec<_>>()
+                .join("; ");
+            return Err(OpenAIAgentError::Config(format!(
+                "{} configuration problems: {}",
+                problems.len(),
+                summary
+            )));
+        }
         Agent::from_builder(self)
     }
+
+    /// Checks the builder's settings and reports every problem found, rather
+    /// than stopping at the first one.
+    ///
+    /// Covers the configuration (missing config or API key when the default HTTP
+    /// transport is used, out-of-range values), the turn limits, and the tools
+    /// (names registered more than once, malformed parameter schemas). `build`
+    /// calls this automatically.
+    pub fn validate(&self) -> Result<(), Vec<OpenAIAgentError>> {
+        let mut problems = Vec::new();
+
+        match &self.config {
+            Some(config) => {
+                if self.transport.is_none() && config.api_key().is_empty() {
+                    problems.push(OpenAIAgentError::Config("API key not provided".to_string()));
+                }
+                problems.extend(config.problems().into_iter().map(OpenAIAgentError::Config));
+            }
+            None if self.transport.is_none() => {
+                problems.push(OpenAIAgentError::Config(
+                    "No configuration set; call with_config".to_string(),
+                ));
+            }
+            None => {}
+        }
+
+        if self.max_turns == 0 {
+            problems.push(OpenAIAgentError::Config("max_turns must be at least 1".to_string()));
+        }
+        if self.max_history_messages == Some(0) {
+            problems.push(OpenAIAgentError::Config(
+                "max_history_messages must be at least 1".to_string(),
+            ));
+        }
+        if self.max_request_bytes == Some(0) {
+            problems.push(OpenAIAgentError::Config(
+                "max_request_bytes must be at least 1".to_string(),
+            ));
+        }
+        if self.structured_output_modes.is_empty() {
+            problems.push(OpenAIAgentError::Config(
+                "At least one structured output mode is required".to_string(),
+            ));
+        }
+
+        for name in self.tools.duplicate_names() {
+            problems.push(OpenAIAgentError::Config(format!(
+                "Tool '{}' is registered more than once",
+                name
+            )));
+        }
+        for name in self.tools.invalid_names() {
+            problems.push(OpenAIAgentError::Config(format!(
+                "Tool name '{}' must be 1-64 characters of letters, digits, '_' or '-'",
+                name
+            )));
+        }
+        for definition in self.tools.definitions().into_iter().filter_map(|spec| spec.function) {
+            if let Err(e) = check_parameters_schema(&definition.parameters) {
+                problems.push(OpenAIAgentError::Config(format!(
+                    "Tool '{}' has a malformed parameter schema: {}",
+                    definition.name, e
+                )));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
     
     /// Build the agent, optionally loading state from persistence
     pub async fn build_async(self) -> Result<Agent, OpenAIAgentError> {
-        let mut agent = Agent::from_builder(self)?;
+        let mut agent = self.build()?;
         
         // If persistence is configured, try to load existing state
         if agent.persistence.is_some() {
@@ -463,3 +3501,57 @@ impl Default for AgentBuilder {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weather_conversation(output: &str) -> AgentState {
+        AgentState {
+            messages: vec![
+                ChatMessage::system("Be brief."),
+                ChatMessage::user("Weather in Paris?"),
+                ChatMessage::assistant_with_tool_calls(
+                    None,
+                    vec![ToolCall::function("call_1", "get_weather", r#"{"city":"Paris"}"#)],
+                ),
+                ChatMessage::tool_result(output, "get_weather", "call_1"),
+                ChatMessage::assistant("Sunny."),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_transcript() {
+        let state = weather_conversation("Sunny, 21C");
+        assert_eq!(
+            state.transcript(),
+            concat!(
+                "[system] Be brief.\n",
+                "[user] Weather in Paris?\n",
+                "[assistant]\n",
+                "  → call get_weather({\"city\":\"Paris\"})\n",
+                "[tool:get_weather] Sunny, 21C\n",
+                "[assistant] Sunny.\n",
+            )
+        );
+        assert_eq!(state.to_string(), state.transcript());
+        assert!(!state.transcript().contains("raw:"));
+
+        let verbose = state.transcript_verbose();
+        assert_eq!(verbose.matches("  raw: {").count(), 5);
+        assert!(verbose.contains(r#""tool_call_id":"call_1""#), "{}", verbose);
+    }
+
+    #[test]
+    fn test_transcript_truncates_tool_output() {
+        let output = "x".repeat(TRANSCRIPT_TRUNCATE_CHARS + 50);
+        let state = weather_conversation(&output);
+
+        let line = state.transcript().lines().find(|line| line.starts_with("[tool:")).unwrap().to_string();
+        let expected = format!("[tool:get_weather] {}… (250 chars total)", "x".repeat(TRANSCRIPT_TRUNCATE_CHARS));
+        assert_eq!(line, expected);
+        assert!(state.transcript_verbose().contains(&output));
+    }
+}
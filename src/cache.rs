@@ -0,0 +1,222 @@
+//! Response caching for chat completion requests.
+//!
+//! This module provides the `ResponseCache` trait used by the client to short-circuit
+//! identical requests, along with a bounded in-memory LRU implementation.
+
+use crate::error::OpenAIAgentError;
+use crate::models::{ChatRequest, ChatResponse};
+use async_trait::async_trait;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Storage for cached chat completion responses keyed by request hash.
+#[async_trait]
+pub trait ResponseCache: Send + Sync {
+    /// Look up a cached response by key
+    async fn get(&self, key: &str) -> Option<ChatResponse>;
+
+    /// Store a response under the given key
+    async fn put(&self, key: &str, response: ChatResponse);
+}
+
+/// Computes a stable cache key from the serialized request.
+///
+/// The key is the hex SHA-256 digest of the complete request body, so any
+/// difference in model, messages, tools, seed or sampling parameters produces a
+/// different key. The body is normalized first (object keys sorted), so maps
+/// such as the request metadata hash the same regardless of their iteration
+/// order. Keys do not depend on the Rust version, so they can be shared through
+/// external caches.
+pub fn cache_key(request: &ChatRequest) -> Result<String, OpenAIAgentError> {
+    let mut canonical = String::new();
+    write_canonical(&serde_json::to_value(request)?, &mut canonical)?;
+    Ok(format!("{:x}", Sha256::digest(canonical.as_bytes())))
+}
+
+/// Writes `value` as compact JSON with object keys in sorted order.
+fn write_canonical(value: &Value, out: &mut String) -> Result<(), OpenAIAgentError> {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<_> = object.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(key)?);
+                out.push(':');
+                write_canonical(value, out)?;
+            }
+            out.push('}');
+        }
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(value, out)?;
+            }
+            out.push(']');
+        }
+        scalar => out.push_str(&serde_json::to_string(scalar)?),
+    }
+    Ok(())
+}
+
+/// In-memory LRU implementation of `ResponseCache`.
+pub struct MemoryResponseCache {
+    capacity: usize,
+    state: Mutex<LruState>,
+}
+
+#[derive(Default)]
+struct LruState {
+    entries: HashMap<String, ChatResponse>,
+    /// Keys ordered from least to most recently used
+    order: VecDeque<String>,
+}
+
+impl LruState {
+    fn touch(&mut self, key: &str) {
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            self.order.remove(pos);
+        }
+        self.order.push_back(key.to_string());
+    }
+}
+
+impl MemoryResponseCache {
+    /// Create a cache holding at most `capacity` responses
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(LruState::default()),
+        }
+    }
+
+    /// Number of cached responses
+    pub fn len(&self) -> usize {
+        self.state.lock().map(|state| state.entries.len()).unwrap_or(0)
+    }
+
+    /// Whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for MemoryResponseCache {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+#[async_trait]
+impl ResponseCache for MemoryResponseCache {
+    async fn get(&self, key: &str) -> Option<ChatResponse> {
+        let mut state = self.state.lock().ok()?;
+        let response = state.entries.get(key).cloned()?;
+        state.touch(key);
+        Some(response)
+    }
+
+    async fn put(&self, key: &str, response: ChatResponse) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+
+        state.entries.insert(key.to_string(), response);
+        state.touch(key);
+
+        while state.entries.len() > self.capacity {
+            match state.order.pop_front() {
+                Some(oldest) => {
+                    state.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ChatMessage;
+
+    fn request(content: &str) -> ChatRequest {
+        ChatRequest {
+            temperature: Some(0.0),
+            ..ChatRequest::new("gpt-4o", vec![ChatMessage::user(content)])
+        }
+    }
+
+    fn response(content: &str) -> ChatResponse {
+        crate::transport::MockTransport::text_response(content)
+    }
+
+    #[test]
+    fn test_cache_key_is_a_stable_digest() {
+        let key = cache_key(&request("Hello")).unwrap();
+        assert_eq!(key.len(), 64);
+        assert!(key.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(key, cache_key(&request("Hello")).unwrap());
+        assert_ne!(key, cache_key(&request("Hello!")).unwrap());
+
+        let mut canonical = String::new();
+        let value = serde_json::json!({"b": [1, {"d": null, "c": "x"}], "a": true});
+        write_canonical(&value, &mut canonical).unwrap();
+        assert_eq!(canonical, r#"{"a":true,"b":[1,{"c":"x","d":null}]}"#);
+    }
+
+    #[test]
+    fn test_cache_key_ignores_metadata_order() {
+        let mut first = request("Hello");
+        let mut second = request("Hello");
+        let mut a = HashMap::new();
+        let mut b = HashMap::new();
+        for (key, value) in [("team", "search"), ("env", "prod"), ("user", "42")] {
+            a.insert(key.to_string(), value.to_string());
+        }
+        for (key, value) in [("user", "42"), ("env", "prod"), ("team", "search")] {
+            b.insert(key.to_string(), value.to_string());
+        }
+        first.metadata = Some(a);
+        second.metadata = Some(b);
+        assert_eq!(cache_key(&first).unwrap(), cache_key(&second).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_lru_evicts_least_recently_used() {
+        let cache = MemoryResponseCache::new(2);
+        cache.put("a", response("A")).await;
+        cache.put("b", response("B")).await;
+
+        // Reading "a" makes "b" the least recently used
+        assert!(cache.get("a").await.is_some());
+        cache.put("c", response("C")).await;
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("c").await.is_some());
+
+        // Overwriting an entry refreshes it without growing the cache
+        cache.put("a", response("A2")).await;
+        cache.put("d", response("D")).await;
+        assert!(cache.get("c").await.is_none());
+        assert_eq!(cache.get("a").await.unwrap().choices[0].message.text().as_deref(), Some("A2"));
+        assert_eq!(cache.len(), 2);
+
+        let disabled = MemoryResponseCache::new(0);
+        disabled.put("a", response("A")).await;
+        assert!(disabled.is_empty());
+    }
+}
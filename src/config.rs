@@ -4,15 +4,23 @@
 //! of the OpenAI API client, including API keys, model selection, and request parameters.
 
 use serde::{Deserialize, Serialize};
+use crate::error::OpenAIAgentError;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
 use std::time::Duration;
 
 /// Configuration for the OpenAI API client.
 ///
 /// This struct contains all the settings needed to customize requests to OpenAI,
 /// including authentication, model selection, and request parameters.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+///
+/// The `Debug` output masks the API key, organization ID and project ID, so
+/// configs can be logged safely.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OpenAIConfig {
     /// OpenAI API key for authentication
+    #[serde(default)]
     api_key: String,
 
     /// Model identifier to use (e.g., "gpt-4o", "gpt-4", "gpt-3.5-turbo")
@@ -22,10 +30,26 @@ pub struct OpenAIConfig {
     #[serde(default = "default_base_url")]
     base_url: String,
 
+    /// Path of the chat completions endpoint, relative to the base URL
+    #[serde(default = "default_chat_path")]
+    chat_path: String,
+
+    /// Path of the Realtime WebSocket endpoint, relative to the base URL
+    #[serde(default = "default_realtime_path")]
+    realtime_path: String,
+
     /// Organization ID for team accounts (optional)
     #[serde(skip_serializing_if = "Option::is_none")]
     organization: Option<String>,
 
+    /// Project ID, sent as the `OpenAI-Project` header (optional)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    project: Option<String>,
+
+    /// `User-Agent` header sent with HTTP requests and the Realtime handshake
+    #[serde(default = "default_user_agent")]
+    user_agent: String,
+
     /// Timeout duration for API requests
     #[serde(with = "humantime_serde", default = "default_timeout")]
     timeout: Duration,
@@ -45,6 +69,98 @@ pub struct OpenAIConfig {
     /// Whether to stream responses instead of waiting for completion
     #[serde(default)]
     stream: bool,
+
+    /// Whether to request log probabilities of output tokens
+    #[serde(default)]
+    logprobs: bool,
+
+    /// Number of most likely tokens to return per position when logprobs are enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    top_logprobs: Option<u8>,
+
+    /// Whether to send an `Idempotency-Key` header with chat completion requests
+    #[serde(default)]
+    idempotency: bool,
+
+    /// Forces (`true`) or forbids (`false`) the deprecated `max_tokens` request field;
+    /// chosen from the model and base URL when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    legacy_max_tokens: Option<bool>,
+
+    /// Context window of the model in tokens; looked up from the model name when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context_window: Option<usize>,
+
+    /// Whether to skip TLS certificate verification (testing only). Never read
+    /// from config files, so it can only be enabled in code.
+    #[serde(skip)]
+    danger_accept_invalid_certs: bool,
+
+    /// Whether OpenAI should store completions server-side
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    store: Option<bool>,
+
+    /// Seed sent with every request for best-effort deterministic sampling
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+
+    /// Metadata tags sent with every request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_metadata: Option<HashMap<String, String>>,
+
+    /// Largest response body read for non-streamed requests, after decompression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_response_bytes: Option<usize>,
+
+    /// Largest number of chat completion requests a client has in flight at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_concurrent_requests: Option<usize>,
+}
+
+impl fmt::Debug for OpenAIConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenAIConfig")
+            .field("api_key", &self.api_key_masked())
+            .field("model", &self.model)
+            .field("base_url", &self.base_url)
+            .field("chat_path", &self.chat_path)
+            .field("realtime_path", &self.realtime_path)
+            .field("organization", &self.organization.as_deref().map(mask_secret))
+            .field("project", &self.project.as_deref().map(mask_secret))
+            .field("user_agent", &self.user_agent)
+            .field("timeout", &self.timeout)
+            .field("max_tokens", &self.max_tokens)
+            .field("temperature", &self.temperature)
+            .field("json_mode", &self.json_mode)
+            .field("stream", &self.stream)
+            .field("logprobs", &self.logprobs)
+            .field("top_logprobs", &self.top_logprobs)
+            .field("idempotency", &self.idempotency)
+            .field("legacy_max_tokens", &self.legacy_max_tokens)
+            .field("context_window", &self.context_window)
+            .field("danger_accept_invalid_certs", &self.danger_accept_invalid_certs)
+            .field("store", &self.store)
+            .field("seed", &self.seed)
+            .field("request_metadata", &self.request_metadata)
+            .field("max_response_bytes", &self.max_response_bytes)
+            .field("max_concurrent_requests", &self.max_concurrent_requests)
+            .finish()
+    }
+}
+
+/// Masks a secret for display, keeping a short prefix and the last four
+/// characters of long values (`sk-...abcd`) and hiding short ones entirely.
+fn mask_secret(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    match chars.len() {
+        0 => String::new(),
+        len if len < 12 => "***".to_string(),
+        len => format!(
+            "{}...{}",
+            chars[..3].iter().collect::<String>(),
+            chars[len - 4..].iter().collect::<String>()
+        ),
+    }
 }
 
 /// Default base URL for the OpenAI API.
@@ -52,6 +168,21 @@ fn default_base_url() -> String {
     "https://api.openai.com/v1".to_string()
 }
 
+/// Default path of the chat completions endpoint.
+fn default_chat_path() -> String {
+    "/chat/completions".to_string()
+}
+
+/// Default path of the Realtime WebSocket endpoint.
+fn default_realtime_path() -> String {
+    "/realtime".to_string()
+}
+
+/// Default `User-Agent` header, `agio/{version}`.
+fn default_user_agent() -> String {
+    format!("agio/{}", env!("CARGO_PKG_VERSION"))
+}
+
 /// Default timeout duration for API requests.
 fn default_timeout() -> Duration {
     Duration::from_secs(30)
@@ -62,6 +193,24 @@ fn default_max_tokens() -> usize {
     1024
 }
 
+/// Context windows of known models in tokens, matched by model prefix. More
+/// specific prefixes come first.
+const KNOWN_CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-0125", 128_000),
+    ("gpt-4-1106", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo-instruct", 4_096),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+];
+
 /// Default temperature setting for response randomness.
 fn default_temperature() -> f32 {
     0.7
@@ -74,13 +223,85 @@ impl OpenAIConfig {
             api_key: String::new(),
             model: "gpt-4".to_string(),
             base_url: default_base_url(),
+            chat_path: default_chat_path(),
+            realtime_path: default_realtime_path(),
             organization: None,
+            project: None,
+            user_agent: default_user_agent(),
             timeout: default_timeout(),
             max_tokens: default_max_tokens(),
             temperature: default_temperature(),
             json_mode: false,
             stream: false,
+            logprobs: false,
+            top_logprobs: None,
+            idempotency: false,
+            legacy_max_tokens: None,
+            context_window: None,
+            danger_accept_invalid_certs: false,
+            store: None,
+            seed: None,
+            request_metadata: None,
+            max_response_bytes: None,
+            max_concurrent_requests: None,
+        }
+    }
+
+    /// Loads a configuration from a TOML file.
+    ///
+    /// The file uses the same field names as the struct, with the timeout written
+    /// in humantime form (e.g. `timeout = "30s"`). The `OPENAI_API_KEY`,
+    /// `OPENAI_ORG_ID` and `OPENAI_PROJECT_ID` environment variables, where set,
+    /// override the key, organization and project in the file, so the secret can
+    /// stay out of it.
+    ///
+    /// # Returns
+    ///
+    /// The configuration, `OpenAIAgentError::Io` if the file cannot be read, or
+    /// `OpenAIAgentError::Config` if it cannot be parsed
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self, OpenAIAgentError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        let config: Self = toml::from_str(&contents).map_err(|e| {
+            OpenAIAgentError::Config(format!("Invalid config file {}: {}", path.display(), e))
+        })?;
+        Ok(config.with_env_overrides())
+    }
+
+    /// Loads a configuration from a JSON file.
+    ///
+    /// Behaves like [`OpenAIConfig::from_toml_file`], including the
+    /// environment overrides.
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self, OpenAIAgentError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        let config: Self = serde_json::from_str(&contents).map_err(|e| {
+            OpenAIAgentError::Config(format!("Invalid config file {}: {}", path.display(), e))
+        })?;
+        Ok(config.with_env_overrides())
+    }
+
+    /// Creates a default configuration with the API key from `OPENAI_API_KEY`,
+    /// the organization from `OPENAI_ORG_ID` and the project from
+    /// `OPENAI_PROJECT_ID`, where set.
+    pub fn from_env() -> Self {
+        Self::new().with_env_overrides()
+    }
+
+    /// Replaces the API key, organization and project with `OPENAI_API_KEY`,
+    /// `OPENAI_ORG_ID` and `OPENAI_PROJECT_ID` where those are set and non-empty.
+    fn with_env_overrides(mut self) -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        if let Some(api_key) = var("OPENAI_API_KEY") {
+            self.api_key = api_key;
+        }
+        if let Some(organization) = var("OPENAI_ORG_ID") {
+            self.organization = Some(organization);
+        }
+        if let Some(project) = var("OPENAI_PROJECT_ID") {
+            self.project = Some(project);
         }
+        self
     }
 
     /// Sets the API key for authentication.
@@ -113,6 +334,31 @@ impl OpenAIConfig {
         self
     }
 
+    /// Sets the path of the chat completions endpoint, relative to the base URL.
+    ///
+    /// Defaults to `/chat/completions`. Useful for gateways that mount the API
+    /// under a different route.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The endpoint path (e.g., "/openai/chat/completions")
+    pub fn with_chat_path(mut self, path: impl Into<String>) -> Self {
+        self.chat_path = path.into();
+        self
+    }
+
+    /// Sets the path of the Realtime WebSocket endpoint, relative to the base URL.
+    ///
+    /// Defaults to `/realtime`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The endpoint path
+    pub fn with_realtime_path(mut self, path: impl Into<String>) -> Self {
+        self.realtime_path = path.into();
+        self
+    }
+
     /// Sets the organization ID for team accounts.
     ///
     /// # Arguments
@@ -123,6 +369,27 @@ impl OpenAIConfig {
         self
     }
 
+    /// Sets the project ID, sent as the `OpenAI-Project` header so usage is
+    /// attributed to the project, as expected with project-scoped API keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `project` - The project identifier
+    pub fn with_project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    /// Sets the `User-Agent` header sent to the API (default: `agio/{version}`).
+    ///
+    /// # Arguments
+    ///
+    /// * `user_agent` - The header value, e.g. to attribute traffic at a gateway
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
     /// Sets the timeout duration for API requests.
     ///
     /// # Arguments
@@ -135,6 +402,9 @@ impl OpenAIConfig {
 
     /// Sets the maximum number of tokens to generate.
     ///
+    /// The limit is sent as `max_completion_tokens`, or as the deprecated
+    /// `max_tokens` where required; see `with_legacy_max_tokens`.
+    ///
     /// # Arguments
     ///
     /// * `max_tokens` - The maximum token count
@@ -143,6 +413,46 @@ impl OpenAIConfig {
         self
     }
 
+    /// Sets the maximum number of tokens to generate, including reasoning tokens.
+    ///
+    /// Same as `with_max_tokens`, named after the request field it is sent as.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_completion_tokens` - The maximum token count
+    pub fn with_max_completion_tokens(self, max_completion_tokens: usize) -> Self {
+        self.with_max_tokens(max_completion_tokens)
+    }
+
+    /// Chooses which request field carries the token limit.
+    ///
+    /// By default the limit is sent as `max_completion_tokens`, except to base URLs
+    /// other than `api.openai.com` (OpenAI-compatible servers and proxies commonly
+    /// only understand `max_tokens`) and for legacy `gpt-3.5` and `gpt-4` snapshots.
+    /// `true` always sends the deprecated `max_tokens`; `false` always sends
+    /// `max_completion_tokens`.
+    ///
+    /// # Arguments
+    ///
+    /// * `legacy` - Whether to send `max_tokens`
+    pub fn with_legacy_max_tokens(mut self, legacy: bool) -> Self {
+        self.legacy_max_tokens = Some(legacy);
+        self
+    }
+
+    /// Sets the model's context window in tokens.
+    ///
+    /// Needed for models not in the built-in list, such as fine-tunes with custom
+    /// names or models behind OpenAI-compatible servers.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens` - The context window size
+    pub fn with_context_window(mut self, tokens: usize) -> Self {
+        self.context_window = Some(tokens);
+        self
+    }
+
     /// Sets the temperature for response randomness.
     ///
     /// # Arguments
@@ -173,11 +483,201 @@ impl OpenAIConfig {
         self
     }
 
+    /// Enables or disables log probabilities in responses.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether to request log probabilities
+    pub fn with_logprobs(mut self, enabled: bool) -> Self {
+        self.logprobs = enabled;
+        self
+    }
+
+    /// Sets the number of alternative tokens returned per position (0 to 20).
+    ///
+    /// Only takes effect when log probabilities are enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `top_logprobs` - The number of alternatives to return
+    pub fn with_top_logprobs(mut self, top_logprobs: u8) -> Self {
+        self.top_logprobs = Some(top_logprobs);
+        self
+    }
+
+    /// Enables or disables idempotency keys on chat completion requests.
+    ///
+    /// When enabled, each logical request carries a UUID in the `Idempotency-Key`
+    /// header. Retries made by the agent's run loop and by
+    /// `OpenAIClient::chat_completion_with_retries` reuse the same key, so a
+    /// request that succeeded but timed out is not processed twice.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether to send idempotency keys
+    pub fn with_idempotency(mut self, enabled: bool) -> Self {
+        self.idempotency = enabled;
+        self
+    }
+
+    /// Disables TLS certificate verification for HTTP requests.
+    ///
+    /// **For testing only.** This accepts any certificate, including self-signed
+    /// and expired ones, which exposes requests (and the API key) to interception.
+    /// Use it only to reach a local HTTPS proxy or mock such as mitmproxy. Never the
+    /// default, and config files cannot set it. A warning is printed to stderr
+    /// whenever a client is created with it enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `accept` - Whether to accept invalid certificates
+    pub fn with_danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.danger_accept_invalid_certs = accept;
+        self
+    }
+
+    /// Opts completions in or out of server-side storage.
+    ///
+    /// Stored completions appear in the OpenAI dashboard and can be used for
+    /// evals and distillation. When unset, the field is omitted and the API
+    /// default applies.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - Whether to store completions
+    pub fn with_store(mut self, store: bool) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Sets a seed sent with every request.
+    ///
+    /// Requests with the same seed and parameters return the same result on a
+    /// best-effort basis. The seed is part of the response cache key, so cached
+    /// responses are never shared between seeds.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The sampling seed
+    pub fn with_seed(mut self, seed: i64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sets metadata tags sent with every request, for filtering stored completions.
+    ///
+    /// The API allows up to 16 pairs, with keys of at most 64 characters and
+    /// values of at most 512 characters.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - Key/value tags
+    pub fn with_request_metadata(mut self, metadata: HashMap<String, String>) -> Self {
+        self.request_metadata = Some(metadata);
+        self
+    }
+
+    /// Sets the largest response body read for non-streamed requests.
+    ///
+    /// Reading stops as soon as the (decompressed) body exceeds the limit, and the
+    /// request fails with `OpenAIAgentError::Request` instead of buffering an
+    /// oversized or malicious response in memory. Streamed responses are read
+    /// chunk by chunk and are not limited. Unlimited by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_bytes` - The limit in bytes
+    pub fn with_max_response_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_response_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Caps the number of chat completion requests an `OpenAIClient` built from
+    /// this config has in flight at once. Unlimited by default.
+    ///
+    /// Requests over the limit wait for a slot instead of failing, so a busy
+    /// service queues work rather than opening more connections than the API or
+    /// the host allows. The limit is per client, which `AgentManager` shares
+    /// between all of its agents. A streamed request holds its slot until the
+    /// stream is finished or dropped. See `OpenAIClient::in_flight_requests`.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_requests` - The limit
+    pub fn with_max_concurrent_requests(mut self, max_requests: usize) -> Self {
+        self.max_concurrent_requests = Some(max_requests);
+        self
+    }
+
+    /// Returns a description of every invalid setting, or an empty list if the
+    /// configuration is usable.
+    ///
+    /// An empty API key is not reported here, since it is only required when
+    /// the default HTTP transport is used.
+    pub(crate) fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.model.trim().is_empty() {
+            problems.push("Model name is empty".to_string());
+        }
+        if !(0.0..=2.0).contains(&self.temperature) {
+            problems.push(format!("Temperature must be between 0.0 and 2.0, got {}", self.temperature));
+        }
+        if self.max_tokens == 0 {
+            problems.push("max_tokens must be greater than 0".to_string());
+        }
+        if self.timeout.is_zero() {
+            problems.push("Timeout must be greater than 0".to_string());
+        }
+        if self.context_window == Some(0) {
+            problems.push("Context window must be greater than 0".to_string());
+        }
+        if self.max_response_bytes == Some(0) {
+            problems.push("max_response_bytes must be greater than 0".to_string());
+        }
+        if self.max_concurrent_requests == Some(0) {
+            problems.push("max_concurrent_requests must be greater than 0".to_string());
+        }
+        if let Some(top_logprobs) = self.top_logprobs
+            && top_logprobs > 20
+        {
+            problems.push(format!("top_logprobs must be between 0 and 20, got {}", top_logprobs));
+        }
+        if let Some(metadata) = &self.request_metadata {
+            if metadata.len() > 16 {
+                problems.push(format!("Request metadata allows at most 16 pairs, got {}", metadata.len()));
+            }
+            for (key, value) in metadata {
+                if key.chars().count() > 64 {
+                    problems.push(format!("Request metadata key '{}' is longer than 64 characters", key));
+                }
+                if value.chars().count() > 512 {
+                    problems.push(format!("Request metadata value for '{}' is longer than 512 characters", key));
+                }
+            }
+        }
+        if self.user_agent.trim().is_empty() {
+            problems.push("User agent is empty".to_string());
+        }
+        if !self.base_url.starts_with("http://") && !self.base_url.starts_with("https://") {
+            problems.push(format!("Base URL must start with http:// or https://, got '{}'", self.base_url));
+        }
+
+        problems
+    }
+
     /// Returns the API key.
     pub fn api_key(&self) -> &str {
         &self.api_key
     }
 
+    /// Returns the API key masked for display, e.g. `sk-...abcd`.
+    ///
+    /// Keys shorter than 12 characters are shown as `***`; an unset key stays empty.
+    pub fn api_key_masked(&self) -> String {
+        mask_secret(&self.api_key)
+    }
+
     /// Returns the model identifier.
     pub fn model(&self) -> &str {
         &self.model
@@ -188,11 +688,46 @@ impl OpenAIConfig {
         &self.base_url
     }
 
+    /// Returns the chat completions endpoint path.
+    pub fn chat_path(&self) -> &str {
+        &self.chat_path
+    }
+
+    /// Returns the Realtime WebSocket endpoint path.
+    pub fn realtime_path(&self) -> &str {
+        &self.realtime_path
+    }
+
+    /// Returns the full URL of the chat completions endpoint.
+    pub fn chat_url(&self) -> String {
+        join_url(&self.base_url, &self.chat_path)
+    }
+
+    /// Returns the full URL of the Realtime WebSocket endpoint, with the base
+    /// URL's `http` scheme swapped for `ws` (or `https` for `wss`).
+    pub fn realtime_url(&self) -> String {
+        let ws_base = self
+            .base_url
+            .replace("https://", "wss://")
+            .replace("http://", "ws://");
+        join_url(&ws_base, &self.realtime_path)
+    }
+
     /// Returns the organization ID if set.
     pub fn organization(&self) -> Option<&str> {
         self.organization.as_deref()
     }
 
+    /// Returns the project ID if set.
+    pub fn project(&self) -> Option<&str> {
+        self.project.as_deref()
+    }
+
+    /// Returns the `User-Agent` header value.
+    pub fn user_agent(&self) -> &str {
+        &self.user_agent
+    }
+
     /// Returns the timeout duration.
     pub fn timeout(&self) -> Duration {
         self.timeout
@@ -203,6 +738,41 @@ impl OpenAIConfig {
         self.max_tokens
     }
 
+    /// Returns the model's context window in tokens: the value set with
+    /// `with_context_window`, or the built-in value for the model, if known.
+    pub fn context_window(&self) -> Option<usize> {
+        self.context_window.or_else(|| {
+            KNOWN_CONTEXT_WINDOWS
+                .iter()
+                .find(|(prefix, _)| self.model.starts_with(prefix))
+                .map(|(_, tokens)| *tokens)
+        })
+    }
+
+    /// Returns whether the token limit is sent as the deprecated `max_tokens` field.
+    pub fn uses_legacy_max_tokens(&self) -> bool {
+        if let Some(legacy) = self.legacy_max_tokens {
+            return legacy;
+        }
+
+        let openai = self.base_url.starts_with("https://api.openai.com/");
+        let model = self.model.as_str();
+        let legacy_model = model.starts_with("gpt-3.5")
+            || model == "gpt-4"
+            || model.starts_with("gpt-4-0")
+            || model.starts_with("gpt-4-32k");
+        !openai || legacy_model
+    }
+
+    /// Returns the token limit as the `(max_tokens, max_completion_tokens)` request fields.
+    pub(crate) fn token_limit_fields(&self) -> (Option<usize>, Option<usize>) {
+        if self.uses_legacy_max_tokens() {
+            (Some(self.max_tokens), None)
+        } else {
+            (None, Some(self.max_tokens))
+        }
+    }
+
     /// Returns the temperature setting.
     pub fn temperature(&self) -> f32 {
         self.temperature
@@ -217,10 +787,172 @@ impl OpenAIConfig {
     pub fn stream(&self) -> bool {
         self.stream
     }
+
+    /// Returns whether log probabilities are requested.
+    pub fn logprobs(&self) -> bool {
+        self.logprobs
+    }
+
+    /// Returns the number of alternative tokens requested per position, if set.
+    pub fn top_logprobs(&self) -> Option<u8> {
+        self.logprobs.then_some(self.top_logprobs).flatten()
+    }
+
+    /// Returns whether idempotency keys are sent.
+    pub fn idempotency(&self) -> bool {
+        self.idempotency
+    }
+
+    /// Returns whether TLS certificate verification is disabled.
+    pub fn danger_accept_invalid_certs(&self) -> bool {
+        self.danger_accept_invalid_certs
+    }
+
+    /// Returns whether completions are stored server-side, if set.
+    pub fn store(&self) -> Option<bool> {
+        self.store
+    }
+
+    /// Returns the sampling seed sent with every request, if set.
+    pub fn seed(&self) -> Option<i64> {
+        self.seed
+    }
+
+    /// Returns the metadata tags sent with every request, if set.
+    pub fn request_metadata(&self) -> Option<&HashMap<String, String>> {
+        self.request_metadata.as_ref()
+    }
+
+    /// Returns the largest response body read for non-streamed requests, if limited.
+    pub fn max_response_bytes(&self) -> Option<usize> {
+        self.max_response_bytes
+    }
+
+    /// Returns the limit on concurrent chat completion requests, if any.
+    pub fn max_concurrent_requests(&self) -> Option<usize> {
+        self.max_concurrent_requests
+    }
+}
+
+/// Joins a base URL and a path, normalizing the slash between them.
+pub(crate) fn join_url(base: &str, path: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), path.trim_start_matches('/'))
 }
 
 impl Default for OpenAIConfig {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_masks_secrets() {
+        let config = OpenAIConfig::new()
+            .with_api_key("sk-proj-1234567890abcdef")
+            .with_organization("org-0123456789secret");
+
+        assert_eq!(config.api_key_masked(), "sk-...cdef");
+        assert_eq!(OpenAIConfig::new().with_api_key("short").api_key_masked(), "***");
+
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("sk-proj-1234567890abcdef"), "{}", debug);
+        assert!(!debug.contains("org-0123456789secret"), "{}", debug);
+        assert!(debug.contains("sk-...cdef"), "{}", debug);
+        assert!(debug.contains("org...cret"), "{}", debug);
+    }
+
+    #[test]
+    fn test_context_window() {
+        assert_eq!(OpenAIConfig::new().with_model("gpt-4o-mini").context_window(), Some(128_000));
+        assert_eq!(OpenAIConfig::new().with_model("gpt-4-32k-0613").context_window(), Some(32_768));
+        assert_eq!(OpenAIConfig::new().with_model("gpt-4").context_window(), Some(8_192));
+        assert_eq!(OpenAIConfig::new().with_model("my-local-model").context_window(), None);
+        assert_eq!(
+            OpenAIConfig::new().with_model("my-local-model").with_context_window(4096).context_window(),
+            Some(4096)
+        );
+    }
+
+    #[test]
+    fn test_token_limit_fields() {
+        let config = OpenAIConfig::new().with_max_completion_tokens(256);
+        assert_eq!(config.with_model("gpt-4o").token_limit_fields(), (None, Some(256)));
+
+        let config = OpenAIConfig::new().with_max_tokens(256);
+        assert_eq!(config.clone().with_model("o3-mini").token_limit_fields(), (None, Some(256)));
+        assert_eq!(config.clone().with_model("gpt-4-turbo").token_limit_fields(), (None, Some(256)));
+        assert_eq!(config.clone().with_model("gpt-4").token_limit_fields(), (Some(256), None));
+        assert_eq!(config.clone().with_model("gpt-3.5-turbo").token_limit_fields(), (Some(256), None));
+        assert_eq!(
+            config.clone().with_model("gpt-4o").with_base_url("http://localhost:8000/v1").token_limit_fields(),
+            (Some(256), None)
+        );
+        assert_eq!(
+            config.clone().with_model("gpt-4o").with_legacy_max_tokens(true).token_limit_fields(),
+            (Some(256), None)
+        );
+        assert_eq!(
+            config.with_model("gpt-3.5-turbo").with_legacy_max_tokens(false).token_limit_fields(),
+            (None, Some(256))
+        );
+    }
+
+    #[test]
+    fn test_endpoint_urls() {
+        let config = OpenAIConfig::new();
+        assert_eq!(config.chat_url(), "https://api.openai.com/v1/chat/completions");
+        assert_eq!(config.realtime_url(), "wss://api.openai.com/v1/realtime");
+
+        // Slashes between the base URL and the path are normalized
+        let gateway = OpenAIConfig::new()
+            .with_base_url("http://gateway.local/openai/")
+            .with_chat_path("deployments/gpt-4o/chat")
+            .with_realtime_path("/deployments/gpt-4o/realtime");
+        assert_eq!(gateway.chat_url(), "http://gateway.local/openai/deployments/gpt-4o/chat");
+        assert_eq!(gateway.realtime_url(), "ws://gateway.local/openai/deployments/gpt-4o/realtime");
+
+        // Paths omitted from a config file keep their defaults
+        let config: OpenAIConfig = serde_json::from_str(r#"{"model": "gpt-4o"}"#).unwrap();
+        assert_eq!(config.chat_path(), "/chat/completions");
+        assert_eq!(config.realtime_path(), "/realtime");
+    }
+
+    #[test]
+    fn test_from_files() {
+        let dir = std::env::temp_dir().join(format!("agio-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_path = dir.join("agio.toml");
+        std::fs::write(&toml_path, "model = \"gpt-4o\"\ntimeout = \"45s\"\ntemperature = 0.2\n").unwrap();
+        let config = OpenAIConfig::from_toml_file(&toml_path).unwrap();
+        assert_eq!(config.model(), "gpt-4o");
+        assert_eq!(config.timeout(), Duration::from_secs(45));
+        assert_eq!(config.temperature(), 0.2);
+
+        let json_path = dir.join("agio.json");
+        std::fs::write(&json_path, r#"{"model": "gpt-4o-mini", "max_tokens": 512}"#).unwrap();
+        let config = OpenAIConfig::from_json_file(&json_path).unwrap();
+        assert_eq!(config.model(), "gpt-4o-mini");
+        assert_eq!(config.max_tokens(), 512);
+
+        // TLS verification can only be turned off in code
+        std::fs::write(&json_path, r#"{"model": "gpt-4o", "danger_accept_invalid_certs": true}"#).unwrap();
+        assert!(!OpenAIConfig::from_json_file(&json_path).unwrap().danger_accept_invalid_certs());
+        std::fs::write(&toml_path, "model = \"gpt-4o\"\ndanger_accept_invalid_certs = true\n").unwrap();
+        assert!(!OpenAIConfig::from_toml_file(&toml_path).unwrap().danger_accept_invalid_certs());
+        let serialized = serde_json::to_value(OpenAIConfig::new().with_danger_accept_invalid_certs(true)).unwrap();
+        assert!(serialized.get("danger_accept_invalid_certs").is_none());
+
+        std::fs::write(&json_path, "{ not json").unwrap();
+        assert!(matches!(OpenAIConfig::from_json_file(&json_path), Err(OpenAIAgentError::Config(_))));
+        assert!(matches!(
+            OpenAIConfig::from_toml_file(dir.join("missing.toml")),
+            Err(OpenAIAgentError::Io(_))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,6 +3,7 @@
 //! This module defines a comprehensive error type that encapsulates
 //! all possible error conditions that can occur during agent operation.
 
+use crate::tools::ToolError;
 use thiserror::Error;
 
 /// Comprehensive error type for OpenAI agent operations.
@@ -15,6 +16,15 @@ pub enum OpenAIAgentError {
     #[error("Request error: {0}")]
     Request(String),
 
+    /// Non-success HTTP response from the OpenAI API
+    #[error("API error {status}: {message}")]
+    Api {
+        /// HTTP status code returned by the API
+        status: u16,
+        /// Response body describing the error
+        message: String,
+    },
+
     /// Error from the reqwest HTTP client
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
@@ -31,10 +41,29 @@ pub enum OpenAIAgentError {
     #[error("Tool error: {0}")]
     Tool(String),
 
+    /// A tool failed with a structured error, see `ToolError`
+    #[error("Tool error: {0}")]
+    ToolFailed(#[from] ToolError),
+
     /// Error parsing data or responses
     #[error("Parse error: {0}")]
     Parse(String),
 
+    /// The response was stopped by the API's content filter
+    #[error("Response blocked by the content filter")]
+    ContentFiltered {
+        /// Any content generated before the filter stopped the response
+        partial_content: Option<String>,
+    },
+
+    /// The model declined to answer, with its explanation
+    #[error("Model refused the request: {0}")]
+    Refusal(String),
+
+    /// The run was cancelled before it finished
+    #[error("Run cancelled")]
+    Cancelled,
+
     /// Error in agent operation logic
     #[error("Agent error: {0}")]
     Agent(String),
@@ -54,4 +83,97 @@ pub enum OpenAIAgentError {
     /// Persistence error
     #[error("Persistence error: {0}")]
     Persistence(String),
-}
\ No newline at end of file
+}
+
+impl OpenAIAgentError {
+    /// Returns the HTTP status code associated with this error, if any.
+    pub fn status_code(&self) -> Option<u16> {
+        match self {
+            Self::Api { status, .. } => Some(*status),
+            Self::Reqwest(err) => err.status().map(|status| status.as_u16()),
+            _ => None,
+        }
+    }
+
+    /// Returns whether retrying the same request might succeed.
+    ///
+    /// This covers timeouts, connection failures, rate limiting, and server-side
+    /// (5xx) errors.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Reqwest(err) if err.is_timeout() || err.is_connect() => true,
+            _ => matches!(self.status_code(), Some(408 | 409 | 429 | 500..=599)),
+        }
+    }
+
+    /// Returns whether the API rejected the request due to rate limiting (HTTP 429).
+    pub fn is_rate_limited(&self) -> bool {
+        self.status_code() == Some(429)
+    }
+
+    /// Returns whether the API rejected the credentials (HTTP 401 or 403).
+    pub fn is_auth_error(&self) -> bool {
+        matches!(self.status_code(), Some(401 | 403))
+    }
+
+    /// Returns whether the request itself was invalid (an HTTP 4xx error).
+    pub fn is_client_error(&self) -> bool {
+        matches!(self.status_code(), Some(400..=499))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api(status: u16) -> OpenAIAgentError {
+        OpenAIAgentError::Api { status, message: String::new() }
+    }
+
+    #[test]
+    fn test_api_error_classification() {
+        // (status, retryable, rate limited, auth, client error)
+        let cases = [
+            (400, false, false, false, true),
+            (401, false, false, true, true),
+            (403, false, false, true, true),
+            (404, false, false, false, true),
+            (408, true, false, false, true),
+            (409, true, false, false, true),
+            (429, true, true, false, true),
+            (500, true, false, false, false),
+            (503, true, false, false, false),
+        ];
+        for (status, retryable, rate_limited, auth, client) in cases {
+            let error = api(status);
+            assert_eq!(error.status_code(), Some(status));
+            assert_eq!(error.is_retryable(), retryable, "{}", status);
+            assert_eq!(error.is_rate_limited(), rate_limited, "{}", status);
+            assert_eq!(error.is_auth_error(), auth, "{}", status);
+            assert_eq!(error.is_client_error(), client, "{}", status);
+        }
+
+        for error in [
+            OpenAIAgentError::Agent("boom".to_string()),
+            OpenAIAgentError::Parse("bad".to_string()),
+            OpenAIAgentError::Cancelled,
+        ] {
+            assert_eq!(error.status_code(), None);
+            assert!(!error.is_retryable());
+            assert!(!error.is_client_error());
+        }
+    }
+
+    #[test]
+    fn test_connection_errors_are_retryable() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        // Nothing listens on port 1, so the connection is refused
+        let error: OpenAIAgentError = rt
+            .block_on(reqwest::Client::new().get("http://127.0.0.1:1").send())
+            .unwrap_err()
+            .into();
+        assert_eq!(error.status_code(), None);
+        assert!(error.is_retryable());
+        assert!(!error.is_auth_error());
+    }
+}
@@ -0,0 +1,52 @@
+//! Activity events published by agents.
+//!
+//! An agent given an event sender (see `AgentBuilder::with_event_sender`)
+//! publishes an `AgentEvent` whenever it adds a message, finishes a turn,
+//! executes a tool or fails a run. `AgentManager::subscribe` uses this to offer
+//! a live view across every managed agent.
+
+use crate::models::{ChatMessage, Usage};
+use crate::persistence::EntityId;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Something that happened in an agent.
+#[derive(Debug, Clone)]
+pub struct AgentEvent {
+    /// ID of the agent the event came from
+    pub agent_id: EntityId,
+
+    /// What happened
+    pub kind: AgentEventKind,
+}
+
+/// The kinds of `AgentEvent`.
+#[derive(Debug, Clone)]
+pub enum AgentEventKind {
+    /// A message was added to the history during a run
+    Message(ChatMessage),
+
+    /// A model round-trip finished
+    TurnCompleted {
+        /// Turn number within the run, starting at 1
+        turn: usize,
+        /// Usage reported (or estimated) for the turn
+        usage: Option<Usage>,
+    },
+
+    /// A tool finished executing
+    ToolExecuted {
+        /// Name of the tool
+        name: String,
+        /// ID of the tool call
+        call_id: String,
+        /// How long the tool took
+        duration: Duration,
+    },
+
+    /// A run failed
+    Error(String),
+}
+
+/// Sending half of an agent event channel.
+pub type AgentEventSender = broadcast::Sender<AgentEvent>;
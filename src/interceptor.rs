@@ -0,0 +1,27 @@
+//! Request/response interceptors for the agent's chat completions.
+//!
+//! Interceptors can modify every outgoing `ChatRequest` and every incoming
+//! `ChatResponse` of a run, which makes it possible to compose middleware for
+//! A/B testing, parameter overrides, redaction and compliance checks.
+
+use crate::error::OpenAIAgentError;
+use crate::models::{ChatRequest, ChatResponse};
+
+/// Hook that can mutate chat completion requests and responses.
+///
+/// Both methods default to doing nothing, so implementors only override what
+/// they need. Returning an error aborts the run with that error.
+pub trait Interceptor: Send + Sync {
+    /// Called with each request just before it is sent
+    fn before_request(&self, _request: &mut ChatRequest) -> Result<(), OpenAIAgentError> {
+        Ok(())
+    }
+
+    /// Called with each response before the agent processes it.
+    ///
+    /// For streamed runs this receives the assembled response after the stream
+    /// has finished, so fragments already yielded are not affected.
+    fn after_response(&self, _response: &mut ChatResponse) -> Result<(), OpenAIAgentError> {
+        Ok(())
+    }
+}
@@ -30,12 +30,20 @@
 
 // Internal modules
 mod agent;
+mod cache;
 mod config;
 mod client;
 mod error;
+mod events;
+mod interceptor;
 mod models;
+mod partial_json;
+mod pricing;
+mod prompt;
+mod stream;
 mod utils;
 mod tools;
+mod transport;
 pub mod websocket_client;
 
 // Persistence and server modules
@@ -56,7 +64,7 @@ pub mod prelude {
     
     // Re-export persistence types
     pub use crate::persistence::{PersistenceStore, EntityId, ConversationMetadata, MemoryStore};
-    pub use crate::persistence::postgres::PostgresStore;
+    pub use crate::persistence::postgres::{PgStoreOptions, PostgresStore};
     
     // Re-export server types
     pub use crate::server::AgentManager;
@@ -66,18 +74,48 @@ pub mod prelude {
 pub use prelude::*;
 
 // Re-export from models for public use
-pub use crate::models::ToolDefinition;
+pub use crate::models::{
+    ChatChoice, ChatCompletionChunk, ChatDelta, ChatMessage, ChatMessageBuilder, ChatRequest,
+    ChatResponse, ChunkChoice, CompletionTokensDetails, ContentPart, FunctionCall, FunctionCallDelta,
+    ImageDetail, ImageUrl, InputAudio, LogProbs, MessageContent, PredictionContent, PromptTokensDetails,
+    TokenLogProb, ToolCall,
+    ToolCallDelta, ToolDefinition, ToolSpec, TopLogProb, Usage, validate_name,
+};
+#[cfg(feature = "experimental")]
+pub use crate::models::Message;
+
+// Re-export the transport abstraction
+pub use crate::stream::ChunkStream;
+pub use crate::transport::{ChatTransport, MockTransport};
+
+// Re-export agent activity events
+pub use crate::events::{AgentEvent, AgentEventKind, AgentEventSender};
+
+// Re-export request/response interceptors
+pub use crate::interceptor::Interceptor;
+
+// Re-export response caching
+pub use crate::cache::{cache_key, MemoryResponseCache, ResponseCache};
+
+// One-shot completions without an agent
+pub use crate::client::{complete, complete_messages, ChunkStrategy};
+
+// Direct client access, e.g. for `OpenAIClient::chat_completion_raw`
+pub use crate::client::OpenAIClient;
 
 // Re-export FunctionTool
-pub use crate::tools::FunctionTool;
+pub use crate::tools::{AskUser, ContextMap, FunctionTool, HostedTool, ToolConflict, ToolContext, ToolError, ToolResultFormatter};
+pub use crate::pricing::{estimate_cost, ModelPricing};
+pub use crate::prompt::PromptTemplate;
 
 // Selective re-exports of internal types that are needed in public APIs
 // but should not be directly constructed by users
-pub use agent::AgentState;
+pub use agent::{AgentState, FinishAction, FinishReasonHandler, MaxTurnsBehavior, PartialValue, ReplayTools, ResponseTransform, RunOutcome, StructuredOutputMode, SummarizeConfig, SystemPromptMode, TextStream, TurnTools, TurnUsage, TypedStream};
 
 // Explicitly re-export persistence and server modules
-pub use persistence::{PersistenceStore, EntityId, ConversationMetadata, MemoryStore};
-pub use persistence::postgres::PostgresStore;
+pub use persistence::{AuditStore, PersistenceStore, EntityId, ConversationMetadata, MemoryStore};
+pub use persistence::postgres::{PgStoreOptions, PostgresStore};
+pub use persistence::vector::{VectorMemory, VectorMemoryTool};
 pub use server::AgentManager;
 
 // Define the tool_fn macro directly in lib.rs to avoid module path issues
@@ -4,21 +4,27 @@
 //! responses, and other data related to the OpenAI API, with particular
 //! focus on chat completions and tool calls.
 
+use crate::error::OpenAIAgentError;
+use base64::engine::general_purpose;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use uuid::Uuid;
 
 /// A message in a conversation with various roles (system, user, assistant, tool).
 ///
 /// Messages form the core of chat interactions with OpenAI models. Each message
 /// has a role, optional content, and may include tool calls for agent functionality.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ChatMessage {
     /// Role of the message sender (system, user, assistant, or tool)
     pub role: String,
 
     /// Content of the message, optional when using tool calls
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub content: Option<String>,
+    pub content: Option<MessageContent>,
 
     /// Name of the speaker if applicable (e.g., tool name)
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -31,6 +37,28 @@ pub struct ChatMessage {
     /// Tool calls array for OpenAI's newer API format
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCall>>,
+
+    /// Explanation from the model when it declined to answer, given instead of content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+
+    /// Whether this is a partial assistant message from an unfinished stream.
+    /// Kept locally and in persistence; never sent to the API.
+    #[serde(skip)]
+    pub incomplete: bool,
+
+    /// Whether history trimming and summarization must keep this message.
+    /// Kept locally and in persistence; never sent to the API.
+    #[serde(skip)]
+    pub pinned: bool,
+
+    /// Stable identifier, assigned (as a UUID) when the message is added to an
+    /// agent's history and kept across saves, reloads and history rewrites, so
+    /// external references stay valid. Unique within a conversation; a forked or
+    /// copied conversation keeps the IDs of the messages it shares with the
+    /// original. Kept locally and in persistence; never sent to the API.
+    #[serde(skip)]
+    pub id: Option<String>,
 }
 
 impl ChatMessage {
@@ -42,10 +70,24 @@ impl ChatMessage {
     pub fn system(content: impl Into<String>) -> Self {
         Self {
             role: "system".to_string(),
-            content: Some(content.into()),
-            name: None,
-            tool_call_id: None,
-            tool_calls: None,
+            content: Some(MessageContent::Text(content.into())),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a developer message.
+    ///
+    /// Newer models, including the o-series reasoning models, treat `developer`
+    /// as the high-priority instruction role in place of `system`.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the developer message
+    pub fn developer(content: impl Into<String>) -> Self {
+        Self {
+            role: "developer".to_string(),
+            content: Some(MessageContent::Text(content.into())),
+            ..Default::default()
         }
     }
 
@@ -57,10 +99,8 @@ impl ChatMessage {
     pub fn user(content: impl Into<String>) -> Self {
         Self {
             role: "user".to_string(),
-            content: Some(content.into()),
-            name: None,
-            tool_call_id: None,
-            tool_calls: None,
+            content: Some(MessageContent::Text(content.into())),
+            ..Default::default()
         }
     }
 
@@ -72,10 +112,27 @@ impl ChatMessage {
     pub fn assistant(content: impl Into<String>) -> Self {
         Self {
             role: "assistant".to_string(),
-            content: Some(content.into()),
-            name: None,
-            tool_call_id: None,
-            tool_calls: None,
+            content: Some(MessageContent::Text(content.into())),
+            ..Default::default()
+        }
+    }
+
+    /// Creates an assistant message requesting tool calls.
+    ///
+    /// Useful for rebuilding a function-calling history, e.g. with
+    /// `AgentBuilder::with_message`. Each call should be followed by a matching
+    /// `ChatMessage::tool_result`.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - Optional text sent alongside the tool calls
+    /// * `tool_calls` - The tool calls, see `ToolCall::function`
+    pub fn assistant_with_tool_calls(content: Option<String>, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            role: "assistant".to_string(),
+            content: content.map(MessageContent::Text),
+            tool_calls: Some(tool_calls),
+            ..Default::default()
         }
     }
 
@@ -89,10 +146,395 @@ impl ChatMessage {
     pub fn tool_result(content: impl Into<String>, tool_name: impl Into<String>, tool_call_id: impl Into<String>) -> Self {
         Self {
             role: "tool".to_string(),
-            content: Some(content.into()),
+            content: Some(MessageContent::Text(content.into())),
             name: Some(tool_name.into()),
             tool_call_id: Some(tool_call_id.into()),
-            tool_calls: None,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a user message with text followed by one or more images.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text of the user message
+    /// * `image_urls` - URLs (or `data:` URLs) of the images to attach
+    pub fn user_with_images<I, S>(text: impl Into<String>, image_urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        image_urls
+            .into_iter()
+            .fold(Self::builder().text(text), |builder, url| builder.image_url(url))
+            .build()
+    }
+
+    /// Creates a user message with text followed by one image at the given detail level.
+    ///
+    /// `ImageDetail::Low` is much cheaper for images that don't need fine detail.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text of the user message
+    /// * `image_url` - URL (or `data:` URL) of the image to attach
+    /// * `detail` - Resolution at which the model should process the image
+    pub fn user_with_image_detail(
+        text: impl Into<String>,
+        image_url: impl Into<String>,
+        detail: ImageDetail,
+    ) -> Self {
+        Self::builder().text(text).image_url_with_detail(image_url, detail).build()
+    }
+
+    /// Creates a user message with text followed by an image read from a local file.
+    ///
+    /// The file is embedded as a base64 `data:` URL, with the MIME type taken from
+    /// the extension (`png`, `jpg`/`jpeg`, `gif` or `webp`). Files above the API's
+    /// 20 MB per-image limit are rejected before they are read.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text of the user message
+    /// * `path` - Path of the image file to attach
+    ///
+    /// # Returns
+    ///
+    /// The message, `OpenAIAgentError::Io` if the file cannot be read, or
+    /// `OpenAIAgentError::Config` if the file type is not supported or the file
+    /// is too large
+    pub fn user_with_image_file(
+        text: impl Into<String>,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, OpenAIAgentError> {
+        let path = path.as_ref();
+        let mime_type = image_mime_type(path).ok_or_else(|| {
+            OpenAIAgentError::Config(format!("Unsupported image type: {}", path.display()))
+        })?;
+
+        let size = std::fs::metadata(path)?.len();
+        if size > MAX_IMAGE_BYTES as u64 {
+            return Err(OpenAIAgentError::Config(format!(
+                "Image {} is {} bytes, above the {} byte per-image limit",
+                path.display(),
+                size,
+                MAX_IMAGE_BYTES
+            )));
+        }
+
+        let bytes = std::fs::read(path)?;
+
+        let url = format!("data:{};base64,{}", mime_type, general_purpose::STANDARD.encode(&bytes));
+        Ok(Self::builder().text(text).image_url(url).build())
+    }
+
+    /// Assigns a new ID unless the message already has one, and returns it.
+    pub(crate) fn ensure_id(&mut self) -> &str {
+        self.id.get_or_insert_with(|| Uuid::new_v4().to_string())
+    }
+
+    /// Marks the message as pinned, so history trimming and summarization keep it.
+    pub fn pinned(mut self) -> Self {
+        self.pinned = true;
+        self
+    }
+
+    /// Starts building a message from multiple content parts.
+    ///
+    /// The role defaults to `user`.
+    pub fn builder() -> ChatMessageBuilder {
+        ChatMessageBuilder::default()
+    }
+
+    /// Returns the text of the message content, if any.
+    ///
+    /// For multi-part content, the text parts are joined with newlines.
+    pub fn text(&self) -> Option<String> {
+        self.content.as_ref().map(MessageContent::to_text)
+    }
+
+    /// Strips control characters other than tab, newline and carriage return from
+    /// the message's text content, which the API may reject.
+    ///
+    /// Returns whether anything was removed.
+    pub fn sanitize(&mut self) -> bool {
+        match &mut self.content {
+            Some(MessageContent::Text(text)) => strip_control_chars(text),
+            Some(MessageContent::Parts(parts)) => parts.iter_mut().fold(false, |changed, part| match part {
+                ContentPart::Text { text } => strip_control_chars(text) || changed,
+                _ => changed,
+            }),
+            None => false,
+        }
+    }
+}
+
+/// Removes control characters except `\t`, `\n` and `\r` in place, returning
+/// whether any were found.
+fn strip_control_chars(text: &mut String) -> bool {
+    let disallowed = |c: char| c.is_control() && !matches!(c, '\t' | '\n' | '\r');
+    if !text.contains(disallowed) {
+        return false;
+    }
+    text.retain(|c| !disallowed(c));
+    true
+}
+
+/// Checks a message or tool name against the API's constraints: 1-64
+/// characters of ASCII letters, digits, `_` or `-`.
+///
+/// Fails with `OpenAIAgentError::Config` describing the offending name.
+pub fn validate_name(name: &str) -> Result<(), OpenAIAgentError> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(OpenAIAgentError::Config(format!(
+            "Name '{}' must be 1-64 characters of letters, digits, '_' or '-'",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Content of a chat message: either plain text or a list of typed parts.
+///
+/// `Text` serializes as a bare string and `Parts` as the array form accepted by
+/// the API for multimodal messages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    /// Plain text content
+    Text(String),
+
+    /// Multi-part content (text, images, audio)
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// Returns the content as a string slice if it is plain text.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            MessageContent::Text(text) => Some(text),
+            MessageContent::Parts(_) => None,
+        }
+    }
+
+    /// Returns the textual content, joining text parts with newlines.
+    pub fn to_text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Returns whether the content has no text and no non-text parts.
+    pub fn is_blank(&self) -> bool {
+        match self {
+            MessageContent::Text(text) => text.trim().is_empty(),
+            MessageContent::Parts(parts) => parts.iter().all(|part| match part {
+                ContentPart::Text { text } => text.trim().is_empty(),
+                _ => false,
+            }),
+        }
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+impl From<Vec<ContentPart>> for MessageContent {
+    fn from(parts: Vec<ContentPart>) -> Self {
+        MessageContent::Parts(parts)
+    }
+}
+
+impl PartialEq<str> for MessageContent {
+    fn eq(&self, other: &str) -> bool {
+        self.as_text() == Some(other)
+    }
+}
+
+impl fmt::Display for MessageContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_text())
+    }
+}
+
+/// A single part of a multi-part message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    /// A text part
+    Text {
+        /// The text
+        text: String,
+    },
+
+    /// An image referenced by URL or `data:` URL
+    ImageUrl {
+        /// The image reference
+        image_url: ImageUrl,
+    },
+
+    /// Base64-encoded input audio
+    InputAudio {
+        /// The audio payload
+        input_audio: InputAudio,
+    },
+}
+
+/// Largest image file accepted by the API without being rejected (20 MB).
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// MIME type of a supported image file, based on its extension.
+fn image_mime_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Image reference within a content part.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    /// URL or `data:` URL of the image
+    pub url: String,
+
+    /// Resolution the model sees the image at; omitted means `auto`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<ImageDetail>,
+}
+
+/// Resolution at which the model processes an image, trading cost for detail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageDetail {
+    /// Let the model choose based on the image size
+    #[default]
+    Auto,
+
+    /// A fixed low-resolution view at a small, fixed token cost
+    Low,
+
+    /// A detailed view, billed by image size
+    High,
+}
+
+/// Audio payload within a content part.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputAudio {
+    /// Base64-encoded audio data
+    pub data: String,
+
+    /// Audio format (e.g., "wav", "mp3")
+    pub format: String,
+}
+
+/// Fluent builder for messages with multi-part content.
+///
+/// Created with `ChatMessage::builder()`.
+#[derive(Debug, Clone, Default)]
+pub struct ChatMessageBuilder {
+    role: Option<String>,
+    name: Option<String>,
+    parts: Vec<ContentPart>,
+}
+
+impl ChatMessageBuilder {
+    /// Sets the role of the message (defaults to `user`).
+    pub fn role(mut self, role: impl Into<String>) -> Self {
+        self.role = Some(role.into());
+        self
+    }
+
+    /// Sets the name of the participant.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Appends a text part.
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.part(ContentPart::Text { text: text.into() })
+    }
+
+    /// Appends an image part referenced by URL or `data:` URL.
+    pub fn image_url(self, url: impl Into<String>) -> Self {
+        self.part(ContentPart::ImageUrl {
+            image_url: ImageUrl { url: url.into(), detail: None },
+        })
+    }
+
+    /// Appends an image part with an explicit detail level.
+    pub fn image_url_with_detail(self, url: impl Into<String>, detail: ImageDetail) -> Self {
+        self.part(ContentPart::ImageUrl {
+            image_url: ImageUrl { url: url.into(), detail: Some(detail) },
+        })
+    }
+
+    /// Appends a base64-encoded audio part.
+    pub fn input_audio(self, data: impl Into<String>, format: impl Into<String>) -> Self {
+        self.part(ContentPart::InputAudio {
+            input_audio: InputAudio {
+                data: data.into(),
+                format: format.into(),
+            },
+        })
+    }
+
+    /// Appends an arbitrary content part.
+    pub fn part(mut self, part: ContentPart) -> Self {
+        self.parts.push(part);
+        self
+    }
+
+    /// Builds the message, rejecting a participant name the API would refuse
+    /// (see `validate_name`).
+    pub fn try_build(self) -> Result<ChatMessage, OpenAIAgentError> {
+        if let Some(name) = &self.name {
+            validate_name(name)?;
+        }
+        Ok(self.build())
+    }
+
+    /// Builds the message.
+    ///
+    /// A message consisting of a single text part is stored as plain text.
+    pub fn build(self) -> ChatMessage {
+        let content = if self.parts.is_empty() {
+            None
+        } else if let [ContentPart::Text { text }] = self.parts.as_slice() {
+            Some(MessageContent::Text(text.clone()))
+        } else {
+            Some(MessageContent::Parts(self.parts))
+        };
+
+        ChatMessage {
+            role: self.role.unwrap_or_else(|| "user".to_string()),
+            content,
+            name: self.name,
+            ..Default::default()
         }
     }
 }
@@ -119,16 +561,46 @@ pub struct ToolDefinition {
 
 /// Top-level wrapper for a tool in the API request.
 ///
-/// The OpenAI API expects tools to be wrapped with a type field and
-/// nested function definition.
+/// The OpenAI API expects function tools to be wrapped with a type field and
+/// nested function definition. Hosted tools (see `HostedTool`) carry only their
+/// type and settings.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) struct ToolSpec {
-    /// Type of the tool (currently always "function")
+pub struct ToolSpec {
+    /// Type of the tool, e.g. "function" or "web_search_preview"
     #[serde(rename = "type")]
     pub r#type: String,
 
-    /// The tool definition
-    pub function: ToolDefinition,
+    /// The tool definition; `None` for hosted tools
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<ToolDefinition>,
+
+    /// Settings of a hosted tool, such as `vector_store_ids` for `file_search`
+    #[serde(flatten)]
+    pub options: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ToolSpec {
+    /// Wraps a function definition.
+    pub fn function(definition: ToolDefinition) -> Self {
+        Self {
+            r#type: "function".to_string(),
+            function: Some(definition),
+            options: serde_json::Map::new(),
+        }
+    }
+
+    /// Returns whether this is a locally executed function tool.
+    pub fn is_function(&self) -> bool {
+        self.r#type == "function"
+    }
+
+    /// Returns the function's name, or the tool type for hosted tools.
+    pub fn name(&self) -> &str {
+        match &self.function {
+            Some(definition) => &definition.name,
+            None => &self.r#type,
+        }
+    }
 }
 
 /// Function data within a tool call.
@@ -174,6 +646,26 @@ pub struct ToolCall {
 }
 
 impl ToolCall {
+    /// Creates a function tool call.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Identifier of the call, echoed by the tool result message
+    /// * `name` - Name of the tool to call
+    /// * `arguments` - The arguments as a JSON string
+    pub fn function(id: impl Into<String>, name: impl Into<String>, arguments: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            call_type: "function".to_string(),
+            function: FunctionCall {
+                name: name.into(),
+                arguments: arguments.into(),
+            },
+            name: None,
+            arguments: None,
+        }
+    }
+
     /// Helper to get the name regardless of API format.
     ///
     /// # Returns
@@ -197,8 +689,8 @@ impl ToolCall {
 ///
 /// This struct contains all the parameters for a chat completion request,
 /// including the model, messages, tools, and generation settings.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) struct ChatRequest {
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatRequest {
     /// Model identifier to use for completion
     pub model: String,
 
@@ -209,21 +701,90 @@ pub(crate) struct ChatRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<ToolSpec>>,
 
-    /// Maximum number of tokens to generate
+    /// Maximum number of tokens to generate (deprecated in favor of `max_completion_tokens`)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<usize>,
 
+    /// Maximum number of tokens to generate, including reasoning tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<usize>,
+
     /// Temperature for controlling randomness
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
 
     /// Format for the response (e.g., {"type": "json_object"})
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub response_format: Option<HashMap<String, String>>,
+    pub response_format: Option<serde_json::Value>,
 
     /// Whether to stream the response
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
+
+    /// Whether to return log probabilities of the output tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+
+    /// Number of most likely tokens to return at each position (0 to 20)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+
+    /// Whether OpenAI should store the completion for its dashboard and evals
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+
+    /// Seed for best-effort deterministic sampling
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+
+    /// Tags attached to stored completions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+
+    /// Expected output, letting the API accept matching tokens instead of generating them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prediction: Option<PredictionContent>,
+}
+
+impl ChatRequest {
+    /// Creates a request for `model` with the given messages and every other
+    /// parameter unset.
+    ///
+    /// Set further fields with struct update syntax:
+    /// `ChatRequest { temperature: Some(0.0), ..ChatRequest::new(model, messages) }`.
+    pub fn new(model: impl Into<String>, messages: Vec<ChatMessage>) -> Self {
+        Self {
+            model: model.into(),
+            messages,
+            ..Default::default()
+        }
+    }
+}
+
+/// Predicted output for a chat completion.
+///
+/// When most of a response is known in advance, as when rewriting a file with
+/// small edits, sending it as a prediction lets the model skip generating the
+/// tokens that match, which cuts latency. Tokens that do not match are still
+/// billed; see `Usage::rejected_prediction_tokens`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PredictionContent {
+    /// Type of the prediction, always "content"
+    #[serde(rename = "type")]
+    pub prediction_type: String,
+
+    /// The expected output text
+    pub content: String,
+}
+
+impl PredictionContent {
+    /// Creates a prediction of the given output text.
+    pub fn text(content: impl Into<String>) -> Self {
+        Self {
+            prediction_type: "content".to_string(),
+            content: content.into(),
+        }
+    }
 }
 
 /// A single choice/response from the model.
@@ -231,7 +792,7 @@ pub(crate) struct ChatRequest {
 /// Represents one possible completion from the model,
 /// including the generated message and finish reason.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) struct ChatChoice {
+pub struct ChatChoice {
     /// Index of this choice in the array of choices
     pub index: usize,
 
@@ -244,6 +805,54 @@ pub(crate) struct ChatChoice {
 
     /// Reason why the model stopped generating
     pub finish_reason: String,
+
+    /// Log probability information, present only when requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<LogProbs>,
+}
+
+/// Log probability information for a choice.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogProbs {
+    /// Log probabilities of the message content tokens
+    #[serde(default)]
+    pub content: Option<Vec<TokenLogProb>>,
+
+    /// Log probabilities of the message refusal tokens
+    #[serde(default)]
+    pub refusal: Option<Vec<TokenLogProb>>,
+}
+
+/// Log probability of a single output token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenLogProb {
+    /// The token
+    pub token: String,
+
+    /// Log probability of this token
+    pub logprob: f64,
+
+    /// UTF-8 bytes of the token, if available
+    #[serde(default)]
+    pub bytes: Option<Vec<u8>>,
+
+    /// Most likely alternative tokens at this position
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogProb>,
+}
+
+/// A candidate token and its log probability at a given position.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopLogProb {
+    /// The token
+    pub token: String,
+
+    /// Log probability of this token
+    pub logprob: f64,
+
+    /// UTF-8 bytes of the token, if available
+    #[serde(default)]
+    pub bytes: Option<Vec<u8>>,
 }
 
 /// Response from the OpenAI Chat Completions API.
@@ -251,7 +860,7 @@ pub(crate) struct ChatChoice {
 /// This struct contains the complete response from the API,
 /// including all generated choices and token usage information.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) struct ChatResponse {
+pub struct ChatResponse {
     /// Unique identifier for this completion
     pub id: String,
 
@@ -271,12 +880,112 @@ pub(crate) struct ChatResponse {
     pub usage: Option<Usage>,
 }
 
+/// A single server-sent chunk of a streamed chat completion.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionChunk {
+    /// Identifier of the completion this chunk belongs to
+    #[serde(default)]
+    pub id: String,
+
+    /// Object type (always "chat.completion.chunk")
+    #[serde(default)]
+    pub object: String,
+
+    /// Timestamp when the completion was created
+    #[serde(default)]
+    pub created: u64,
+
+    /// Model used for the completion
+    #[serde(default)]
+    pub model: String,
+
+    /// Incremental updates to each choice
+    #[serde(default)]
+    pub choices: Vec<ChunkChoice>,
+
+    /// Token usage, only present on the final chunk when requested
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+/// Incremental update to one choice in a streamed completion.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkChoice {
+    /// Index of the choice being updated
+    #[serde(default)]
+    pub index: usize,
+
+    /// New content for the choice
+    #[serde(default)]
+    pub delta: ChatDelta,
+
+    /// Reason the generation stopped, set on the last chunk of the choice
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+
+    /// Log probabilities of the tokens in this chunk, if requested
+    #[serde(default)]
+    pub logprobs: Option<LogProbs>,
+}
+
+/// Fragment of an assistant message in a streamed completion.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatDelta {
+    /// Role of the message, only present on the first chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+
+    /// Text fragment to append to the content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+
+    /// Fragments of tool calls, keyed by their index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+
+    /// Fragment of a refusal to append to the message's refusal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+}
+
+/// Fragment of a tool call in a streamed completion.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ToolCallDelta {
+    /// Position of the tool call in the message
+    #[serde(default)]
+    pub index: usize,
+
+    /// Tool call identifier, only present on the first fragment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// Type of the call, only present on the first fragment
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub call_type: Option<String>,
+
+    /// Function name and argument fragments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<FunctionCallDelta>,
+}
+
+/// Fragment of a function call in a streamed completion.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FunctionCallDelta {
+    /// Function name, only present on the first fragment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// Fragment of the JSON arguments string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
 /// Token usage statistics for a request/response.
 ///
 /// This struct tracks the number of tokens used in the prompt,
 /// completion, and in total for billing purposes.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) struct Usage {
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
     /// Number of tokens in the prompt
     pub prompt_tokens: usize,
 
@@ -285,6 +994,139 @@ pub(crate) struct Usage {
 
     /// Total number of tokens used
     pub total_tokens: usize,
+
+    /// Breakdown of the prompt tokens, including cached tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+
+    /// Breakdown of the completion tokens, including reasoning tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+impl Usage {
+    /// Number of prompt tokens served from OpenAI's prompt cache
+    pub fn cached_tokens(&self) -> usize {
+        self.prompt_tokens_details
+            .as_ref()
+            .and_then(|d| d.cached_tokens)
+            .unwrap_or(0)
+    }
+
+    /// Number of completion tokens spent on reasoning (o-series models)
+    pub fn reasoning_tokens(&self) -> usize {
+        self.completion_tokens_details
+            .as_ref()
+            .and_then(|d| d.reasoning_tokens)
+            .unwrap_or(0)
+    }
+
+    /// Number of predicted output tokens that appeared in the completion
+    pub fn accepted_prediction_tokens(&self) -> usize {
+        self.completion_tokens_details
+            .as_ref()
+            .and_then(|d| d.accepted_prediction_tokens)
+            .unwrap_or(0)
+    }
+
+    /// Number of predicted output tokens that did not appear in the completion,
+    /// billed as completion tokens all the same
+    pub fn rejected_prediction_tokens(&self) -> usize {
+        self.completion_tokens_details
+            .as_ref()
+            .and_then(|d| d.rejected_prediction_tokens)
+            .unwrap_or(0)
+    }
+
+    /// Adds another usage report to this one, summing all counts
+    pub fn add(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+
+        if let Some(details) = &other.prompt_tokens_details {
+            let total = self.prompt_tokens_details.get_or_insert_with(Default::default);
+            total.cached_tokens = sum_counts(total.cached_tokens, details.cached_tokens);
+            total.audio_tokens = sum_counts(total.audio_tokens, details.audio_tokens);
+        }
+
+        if let Some(details) = &other.completion_tokens_details {
+            let total = self.completion_tokens_details.get_or_insert_with(Default::default);
+            total.reasoning_tokens = sum_counts(total.reasoning_tokens, details.reasoning_tokens);
+            total.audio_tokens = sum_counts(total.audio_tokens, details.audio_tokens);
+            total.accepted_prediction_tokens =
+                sum_counts(total.accepted_prediction_tokens, details.accepted_prediction_tokens);
+            total.rejected_prediction_tokens =
+                sum_counts(total.rejected_prediction_tokens, details.rejected_prediction_tokens);
+        }
+    }
+}
+
+/// Sums two optional counts, staying `None` only if both are absent
+fn sum_counts(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+    }
+}
+
+/// Breakdown of prompt tokens reported in `Usage`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptTokensDetails {
+    /// Prompt tokens served from the prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_tokens: Option<usize>,
+
+    /// Audio input tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_tokens: Option<usize>,
+}
+
+/// Breakdown of completion tokens reported in `Usage`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompletionTokensDetails {
+    /// Tokens generated for reasoning (o-series models)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_tokens: Option<usize>,
+
+    /// Audio output tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_tokens: Option<usize>,
+
+    /// Predicted output tokens that appeared in the completion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accepted_prediction_tokens: Option<usize>,
+
+    /// Predicted output tokens that did not appear in the completion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected_prediction_tokens: Option<usize>,
+}
+
+/// Request to the OpenAI Embeddings API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct EmbeddingRequest {
+    /// Embedding model identifier (e.g., "text-embedding-3-small")
+    pub model: String,
+
+    /// Texts to embed
+    pub input: Vec<String>,
+}
+
+/// A single embedding vector returned by the Embeddings API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct EmbeddingData {
+    /// Index of the input this embedding corresponds to
+    pub index: usize,
+
+    /// The embedding vector
+    pub embedding: Vec<f32>,
+}
+
+/// Response from the OpenAI Embeddings API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct EmbeddingResponse {
+    /// Embedding vectors, one per input
+    pub data: Vec<EmbeddingData>,
 }
 
 // Public interfaces - only expose what users actually need directly
@@ -319,6 +1161,194 @@ impl Message {
 
     /// Get the content of this message, if any
     pub fn content(&self) -> Option<&str> {
-        self.inner.content.as_deref()
+        self.inner.content.as_ref().and_then(|c| c.as_text())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_strips_control_characters() {
+        let mut message = ChatMessage::user("a\u{0000}b\tc\nd\u{001b}[0m\u{007f}é");
+        assert!(message.sanitize());
+        assert_eq!(message.text().as_deref(), Some("ab\tc\nd[0mé"));
+        assert!(!message.sanitize());
+
+        let mut parts = ChatMessage::builder().text("x\u{0008}y").image_url("https://example.com/a.png").build();
+        assert!(parts.sanitize());
+        assert_eq!(parts.text().as_deref(), Some("xy"));
+    }
+
+    #[test]
+    fn test_user_with_image_file() {
+        let path = std::env::temp_dir().join(format!("agio-test-{}.png", uuid::Uuid::new_v4()));
+        std::fs::write(&path, [0x89, b'P', b'N', b'G']).unwrap();
+
+        let message = ChatMessage::user_with_image_file("What is this?", &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let Some(MessageContent::Parts(parts)) = &message.content else {
+            panic!("expected multi-part content");
+        };
+        assert_eq!(parts.len(), 2);
+        let ContentPart::ImageUrl { image_url } = &parts[1] else {
+            panic!("expected an image part");
+        };
+        assert_eq!(image_url.url, "data:image/png;base64,iVBORw==");
+    }
+
+    #[test]
+    fn test_image_detail_serialization() {
+        let message = ChatMessage::user_with_image_detail("Describe", "https://example.com/a.png", ImageDetail::Low);
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["content"][1]["image_url"]["detail"], "low");
+
+        let plain = ChatMessage::user_with_images("Describe", ["https://example.com/a.png"]);
+        let json = serde_json::to_value(&plain).unwrap();
+        assert!(json["content"][1]["image_url"].get("detail").is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_message_content_round_trip() {
+        // Text goes on the wire as a plain string, as it did before content had parts
+        let text = ChatMessage::user("Hello");
+        let json = serde_json::to_value(&text).unwrap();
+        assert_eq!(json["content"], "Hello");
+        let back: ChatMessage = serde_json::from_value(json).unwrap();
+        assert_eq!(back.content, Some(MessageContent::Text("Hello".to_string())));
+
+        let parts = ChatMessage::user_with_images("Describe", ["https://example.com/a.png"]);
+        let json = serde_json::to_value(&parts).unwrap();
+        assert_eq!(
+            json["content"],
+            serde_json::json!([
+                {"type": "text", "text": "Describe"},
+                {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}}
+            ])
+        );
+        let back: ChatMessage = serde_json::from_value(json).unwrap();
+        assert_eq!(back.content, parts.content);
+        assert_eq!(back.text().as_deref(), Some("Describe"));
+
+        // Missing and null content both read as no content
+        let message: ChatMessage = serde_json::from_str(r#"{"role": "assistant"}"#).unwrap();
+        assert_eq!(message.content, None);
+        let message: ChatMessage = serde_json::from_str(r#"{"role": "assistant", "content": null}"#).unwrap();
+        assert_eq!(message.content, None);
+        assert!(serde_json::to_value(&message).unwrap().get("content").is_none());
+    }
+
+    #[test]
+    fn test_logprobs_parsing() {
+        let response: ChatResponse = serde_json::from_value(serde_json::json!({
+            "id": "c1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Yes"},
+                "finish_reason": "stop",
+                "logprobs": {
+                    "content": [{
+                        "token": "Yes",
+                        "logprob": -0.01,
+                        "bytes": [89, 101, 115],
+                        "top_logprobs": [
+                            {"token": "Yes", "logprob": -0.01, "bytes": [89, 101, 115]},
+                            {"token": "No", "logprob": -4.6, "bytes": null}
+                        ]
+                    }],
+                    "refusal": null
+                }
+            }]
+        }))
+        .unwrap();
+
+        let logprobs = response.choices[0].logprobs.as_ref().unwrap();
+        assert!(logprobs.refusal.is_none());
+        let token = &logprobs.content.as_ref().unwrap()[0];
+        assert_eq!(token.token, "Yes");
+        assert_eq!(token.logprob, -0.01);
+        assert_eq!(token.bytes.as_deref(), Some(&b"Yes"[..]));
+        assert_eq!(token.top_logprobs.len(), 2);
+        assert_eq!(token.top_logprobs[1].token, "No");
+        assert!(token.top_logprobs[1].bytes.is_none());
+
+        // Models that don't return logprobs leave the field out or null
+        let choice: ChatChoice = serde_json::from_str(
+            r#"{"index": 0, "message": {"role": "assistant", "content": "Yes"}, "finish_reason": "stop"}"#,
+        )
+        .unwrap();
+        assert!(choice.logprobs.is_none());
+        let choice: ChatChoice = serde_json::from_str(
+            r#"{"index": 0, "message": {"role": "assistant"}, "finish_reason": "stop", "logprobs": null}"#,
+        )
+        .unwrap();
+        assert!(choice.logprobs.is_none());
+    }
+
+    #[test]
+    fn test_assistant_with_tool_calls() {
+        let message = ChatMessage::assistant_with_tool_calls(
+            None,
+            vec![ToolCall::function("call_1", "lookup", r#"{"id":7}"#)],
+        );
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["role"], "assistant");
+        assert_eq!(json["tool_calls"][0]["id"], "call_1");
+        assert_eq!(json["tool_calls"][0]["type"], "function");
+        assert_eq!(json["tool_calls"][0]["function"]["name"], "lookup");
+        assert_eq!(json["tool_calls"][0]["function"]["arguments"], r#"{"id":7}"#);
+        assert!(json["tool_calls"][0].get("name").is_none());
+    }
+
+    #[test]
+    fn test_user_with_image_file_errors() {
+        assert!(matches!(
+            ChatMessage::user_with_image_file("x", "notes.txt"),
+            Err(OpenAIAgentError::Config(_))
+        ));
+        assert!(matches!(
+            ChatMessage::user_with_image_file("x", "/nonexistent/agio/image.png"),
+            Err(OpenAIAgentError::Io(_))
+        ));
+
+        // Sized without writing the bytes, so the check runs before any read
+        let path = std::env::temp_dir().join(format!("agio-test-{}.png", uuid::Uuid::new_v4()));
+        std::fs::File::create(&path).unwrap().set_len(MAX_IMAGE_BYTES as u64 + 1).unwrap();
+        let result = ChatMessage::user_with_image_file("x", &path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(OpenAIAgentError::Config(message)) if message.contains("per-image limit")));
+    }
+
+    #[test]
+    fn test_prediction_usage() {
+        let json = serde_json::to_value(PredictionContent::text("fn main() {}")).unwrap();
+        assert_eq!(json, serde_json::json!({"type": "content", "content": "fn main() {}"}));
+
+        let usage: Usage = serde_json::from_str(
+            r#"{"prompt_tokens":10,"completion_tokens":20,"total_tokens":30,
+                "completion_tokens_details":{"accepted_prediction_tokens":15,"rejected_prediction_tokens":3}}"#,
+        )
+        .unwrap();
+        assert_eq!(usage.accepted_prediction_tokens(), 15);
+        assert_eq!(usage.rejected_prediction_tokens(), 3);
+        assert_eq!(Usage::default().accepted_prediction_tokens(), 0);
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("get_weather-v2").is_ok());
+        assert!(validate_name(&"a".repeat(64)).is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name(&"a".repeat(65)).is_err());
+        assert!(matches!(validate_name("get weather"), Err(OpenAIAgentError::Config(_))));
+        assert!(validate_name("wetter.abrufen").is_err());
+
+        assert!(ChatMessage::builder().name("alice").text("Hi").try_build().is_ok());
+        assert!(ChatMessage::builder().name("Alice Smith").text("Hi").try_build().is_err());
+        assert!(ChatMessage::builder().text("Hi").try_build().is_ok());
+    }
+}
@@ -0,0 +1,119 @@
+//! Lenient parsing of incomplete JSON.
+//!
+//! Used by `Agent::run_typed_stream` to turn a JSON answer that is still being
+//! streamed into the most complete value it describes so far.
+
+use serde_json::Value;
+
+/// Parses the JSON object or array at the start of `text`, closing any strings,
+/// objects and arrays left open by truncation.
+///
+/// Text before the first `{` or `[` (such as a markdown code fence) and after the
+/// value is complete is ignored. Members whose key or value has not fully arrived
+/// are left out, except string values, which are returned as far as they go.
+/// Returns `None` if no value has started yet.
+pub(crate) fn parse_partial_json(text: &str) -> Option<Value> {
+    let start = text.find(['{', '['])?;
+    let text = &text[start..];
+
+    let mut stack = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut end = text.len();
+    // Cut points where the text so far is a valid prefix once its containers are
+    // closed: just after an opening bracket, or just before a comma
+    let mut cut_points: Vec<(usize, Vec<char>)> = Vec::new();
+
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => {
+                stack.push(if c == '{' { '}' } else { ']' });
+                cut_points.push((i + 1, stack.clone()));
+            }
+            '}' | ']' => {
+                stack.pop();
+                if stack.is_empty() {
+                    end = i + 1;
+                    break;
+                }
+            }
+            ',' => cut_points.push((i, stack.clone())),
+            _ => {}
+        }
+    }
+
+    let text = &text[..end];
+    if stack.is_empty() {
+        return serde_json::from_str(text).ok();
+    }
+
+    // First try keeping everything, ending an unterminated string where it stops
+    let mut candidate = text.to_string();
+    if in_string {
+        if escaped {
+            candidate.pop();
+        } else if let Some(pos) = candidate.rfind("\\u").filter(|pos| candidate.len() - pos < 6) {
+            candidate.truncate(pos);
+        }
+        candidate.push('"');
+    }
+    if let Some(value) = close_and_parse(candidate, &stack) {
+        return Some(value);
+    }
+
+    cut_points
+        .into_iter()
+        .rev()
+        .find_map(|(pos, stack)| close_and_parse(text[..pos].to_string(), &stack))
+}
+
+/// Appends the closing brackets for `stack` and parses the result.
+fn close_and_parse(mut text: String, stack: &[char]) -> Option<Value> {
+    text.extend(stack.iter().rev());
+    serde_json::from_str(&text).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_complete_json() {
+        assert_eq!(parse_partial_json(r#"{"a": 1, "b": [true]}"#), Some(json!({"a": 1, "b": [true]})));
+        assert_eq!(parse_partial_json("```json\n[1, 2]\n```"), Some(json!([1, 2])));
+    }
+
+    #[test]
+    fn test_truncated_json() {
+        assert_eq!(parse_partial_json(""), None);
+        assert_eq!(parse_partial_json("```json\n"), None);
+        assert_eq!(parse_partial_json("{"), Some(json!({})));
+        assert_eq!(parse_partial_json(r#"{"na"#), Some(json!({})));
+        assert_eq!(parse_partial_json(r#"{"name":"#), Some(json!({})));
+        assert_eq!(parse_partial_json(r#"{"name": "Ad"#), Some(json!({"name": "Ad"})));
+        assert_eq!(parse_partial_json(r#"{"name": "Ada", "age": 3"#), Some(json!({"name": "Ada", "age": 3})));
+        assert_eq!(parse_partial_json(r#"{"name": "Ada", "admin": tr"#), Some(json!({"name": "Ada"})));
+        assert_eq!(parse_partial_json(r#"{"tags": ["x", "y"#), Some(json!({"tags": ["x", "y"]})));
+        assert_eq!(parse_partial_json(r#"{"inner": {"k": [1, {"#), Some(json!({"inner": {"k": [1, {}]}})));
+    }
+
+    #[test]
+    fn test_truncated_escapes() {
+        assert_eq!(parse_partial_json(r#"{"q": "say \"hi\"#), Some(json!({"q": "say \"hi"})));
+        assert_eq!(parse_partial_json(r##"{"q": "say \"hi\""##), Some(json!({"q": "say \"hi\""})));
+        assert_eq!(parse_partial_json(r#"{"q": "a\"#), Some(json!({"q": "a"})));
+        assert_eq!(parse_partial_json(r#"{"q": "a\u00"#), Some(json!({"q": "a"})));
+    }
+}
@@ -3,19 +3,77 @@
 //! This module provides traits and implementations for persisting agent state,
 //! conversations, and other data to various storage backends.
 
-use crate::agent::AgentState;
+use crate::agent::{AgentState, TurnTools};
 use crate::error::OpenAIAgentError;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 use uuid::Uuid;
 
 /// Unique identifier for stored entities
 pub type EntityId = String;
 
-/// Generate a new unique ID
+/// Source of new entity IDs.
+///
+/// The default is `UuidV4Generator`. Replace it for the whole process with
+/// `set_id_generator`, or for a single agent with `AgentBuilder::with_id_generator`.
+pub trait IdGenerator: Send + Sync {
+    /// Generate a new unique ID
+    fn generate(&self) -> EntityId;
+}
+
+/// Generates random UUIDv4 IDs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidV4Generator;
+
+impl IdGenerator for UuidV4Generator {
+    fn generate(&self) -> EntityId {
+        Uuid::new_v4().to_string()
+    }
+}
+
+/// Generates ULIDs, which sort in creation order (requires the `ulid` feature)
+#[cfg(feature = "ulid")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UlidGenerator;
+
+#[cfg(feature = "ulid")]
+impl IdGenerator for UlidGenerator {
+    fn generate(&self) -> EntityId {
+        ulid::Ulid::new().to_string()
+    }
+}
+
+/// Process-wide generator used by `generate_id`; `None` means UUIDv4
+static ID_GENERATOR: RwLock<Option<Arc<dyn IdGenerator>>> = RwLock::new(None);
+
+/// Set the generator used by `generate_id` throughout the process
+///
+/// Call it at start-up, before any agents are built.
+pub fn set_id_generator(generator: Arc<dyn IdGenerator>) {
+    let mut current = ID_GENERATOR.write().unwrap_or_else(|e| e.into_inner());
+    *current = Some(generator);
+}
+
+/// Generate a new unique ID with the process-wide generator (UUIDv4 by default)
 pub fn generate_id() -> EntityId {
-    Uuid::new_v4().to_string()
+    let current = ID_GENERATOR.read().unwrap_or_else(|e| e.into_inner());
+    match current.as_ref() {
+        Some(generator) => generator.generate(),
+        None => UuidV4Generator.generate(),
+    }
+}
+
+/// Derive a stable ID from a namespace and a key.
+///
+/// Returns a UUIDv5, so the same namespace and key always produce the same ID.
+/// The namespace is itself hashed into a UUID first, so equal keys in different
+/// namespaces never collide.
+pub fn derive_id(namespace: &str, key: &str) -> EntityId {
+    let root = Uuid::new_v5(&Uuid::NAMESPACE_URL, b"https://github.com/nikothomas/agio");
+    let namespace = Uuid::new_v5(&root, namespace.as_bytes());
+    Uuid::new_v5(&namespace, key.as_bytes()).to_string()
 }
 
 /// Metadata for stored conversations
@@ -44,17 +102,114 @@ pub trait PersistenceStore: Send + Sync {
     /// Retrieve a conversation by ID
     async fn get_conversation(&self, id: &str) -> Result<Option<AgentState>, OpenAIAgentError>;
     
+    /// Retrieve several conversations at once, keyed by ID. IDs with no stored
+    /// conversation are left out.
+    ///
+    /// The default implementation loads them one at a time; backends should
+    /// override it with a single batched read where possible.
+    async fn get_conversations(&self, ids: &[EntityId]) -> Result<HashMap<EntityId, AgentState>, OpenAIAgentError> {
+        let mut states = HashMap::with_capacity(ids.len());
+        for id in ids {
+            if let Some(state) = self.get_conversation(id).await? {
+                states.insert(id.clone(), state);
+            }
+        }
+        Ok(states)
+    }
+
+    /// Retrieve the tools offered to the model on each turn of a conversation,
+    /// oldest first (see `AgentState::turn_tools`), or `None` if it isn't stored.
+    ///
+    /// The default implementation loads the whole conversation; backends that
+    /// keep the list separately should override it to skip the messages.
+    async fn get_turn_tools(&self, id: &str) -> Result<Option<Vec<TurnTools>>, OpenAIAgentError> {
+        Ok(self.get_conversation(id).await?.map(|state| state.turn_tools))
+    }
+
     /// Delete a conversation
     async fn delete_conversation(&self, id: &str) -> Result<(), OpenAIAgentError>;
     
     /// List available conversations with metadata
     async fn list_conversations(&self, limit: usize, offset: usize) -> Result<Vec<ConversationMetadata>, OpenAIAgentError>;
+
+    /// Check that the store is reachable and able to serve requests.
+    ///
+    /// The default implementation always succeeds; backends that talk to a
+    /// server should override it with a cheap round trip.
+    async fn ping(&self) -> Result<(), OpenAIAgentError> {
+        Ok(())
+    }
+
+    /// Delete several conversations, returning how many existed and were deleted.
+    ///
+    /// The default implementation checks and deletes them one at a time.
+    async fn delete_conversations(&self, ids: &[EntityId]) -> Result<usize, OpenAIAgentError> {
+        let mut deleted = 0;
+        for id in ids {
+            if self.get_conversation(id).await?.is_some() {
+                self.delete_conversation(id).await?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Export conversations as an OpenAI fine-tuning file in JSON Lines format,
+    /// one example per conversation, in the order given.
+    ///
+    /// See `AgentState::to_finetune_jsonl` for the format and `include_tools`.
+    /// Conversations without an assistant reply are skipped; an ID with no
+    /// stored conversation is an error.
+    async fn export_finetune(&self, ids: &[EntityId], include_tools: bool) -> Result<String, OpenAIAgentError> {
+        let mut out = String::new();
+        for id in ids {
+            let state = self
+                .get_conversation(id)
+                .await?
+                .ok_or_else(|| OpenAIAgentError::Agent(format!("Conversation not found: {}", id)))?;
+            out.push_str(&state.to_finetune_jsonl(include_tools)?);
+        }
+        Ok(out)
+    }
+
+    /// Delete every conversation last updated before `cutoff`, returning how many were deleted.
+    ///
+    /// The default implementation pages through `list_conversations`; backends
+    /// should override it with a single bulk delete where possible.
+    async fn delete_older_than(&self, cutoff: chrono::DateTime<chrono::Utc>) -> Result<usize, OpenAIAgentError> {
+        const PAGE_SIZE: usize = 100;
+
+        let mut stale = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = self.list_conversations(PAGE_SIZE, offset).await?;
+            let fetched = page.len();
+            stale.extend(page.into_iter().filter(|meta| meta.updated_at < cutoff).map(|meta| meta.id));
+            if fetched < PAGE_SIZE {
+                break;
+            }
+            offset += fetched;
+        }
+
+        for id in &stale {
+            self.delete_conversation(id).await?;
+        }
+        Ok(stale.len())
+    }
 }
 
 // Re-export implementations
+pub mod audit;
+pub mod blob;
 pub mod memory;
 pub mod postgres;
+pub mod vector;
 
 // Re-export implementations for easier access
+pub use audit::AuditStore;
+pub use blob::{BlobStore, Codec, JsonCodec, KvBackend, MemoryBackend};
+#[cfg(feature = "cbor")]
+pub use blob::CborCodec;
 pub use memory::MemoryStore;
-pub use postgres::PostgresStore; 
\ No newline at end of file
+pub use postgres::{PgStoreOptions, PostgresStore};
+pub use vector::{VectorMemory, VectorMemoryTool}; 
\ No newline at end of file
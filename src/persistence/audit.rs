@@ -0,0 +1,271 @@
+//! Append-only audit logging for any persistence store.
+//!
+//! `AuditStore` wraps another `PersistenceStore` and records every message it
+//! sees in a separate log that deletes, summarization and history trimming never
+//! touch, giving an immutable record of each conversation.
+
+use super::{ConversationMetadata, EntityId, PersistenceStore};
+use crate::agent::{AgentState, TurnTools};
+use crate::error::OpenAIAgentError;
+use crate::models::ChatMessage;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+
+/// One line of a file-backed audit log
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditEntry {
+    conversation_id: EntityId,
+    recorded_at: chrono::DateTime<chrono::Utc>,
+    /// The message's stable ID, which its own serialization leaves out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message_id: Option<String>,
+    message: ChatMessage,
+}
+
+/// Where audited messages are appended
+enum AuditLog {
+    Memory(Mutex<HashMap<EntityId, Vec<ChatMessage>>>),
+    File {
+        path: PathBuf,
+        // Serializes appends so concurrent batches are not interleaved
+        write_lock: tokio::sync::Mutex<()>,
+    },
+}
+
+/// The last message logged for a conversation, with its position counted from
+/// the start of the conversation (including messages since dropped from history)
+#[derive(Clone)]
+struct LoggedTail {
+    position: usize,
+    id: Option<String>,
+    message: serde_json::Value,
+}
+
+/// Persistence store decorator that keeps an append-only audit log of messages.
+///
+/// Every `store_conversation` appends the messages added since the previous call
+/// to the log before passing the state on to the wrapped store. Nothing ever
+/// removes entries from the log: `delete_conversation` and the bulk deletes only
+/// reach the wrapped store. Use `read_audit` to get a conversation's full record.
+///
+/// New messages are found by position and by the ID of the last logged message, so
+/// history trimmed with `with_max_history_messages` or compacted by summarization
+/// is handled. If that message was rewritten since, e.g. a streamed answer saved
+/// at a checkpoint or an edited last message, its new version is logged again
+/// under the same ID. If a history was rewritten so that the last logged message
+/// is gone, all of its current messages are logged again: the log may then
+/// contain duplicates, but never misses a message.
+pub struct AuditStore<S: PersistenceStore> {
+    inner: S,
+    log: AuditLog,
+    tails: Mutex<HashMap<EntityId, LoggedTail>>,
+}
+
+impl<S: PersistenceStore> AuditStore<S> {
+    /// Wrap a store, keeping the audit log in memory
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            log: AuditLog::Memory(Mutex::new(HashMap::new())),
+            tails: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wrap a store, appending the audit log to a JSON Lines file
+    ///
+    /// The file is created if needed and never truncated; each line records the
+    /// conversation ID, the time it was logged and the message.
+    pub fn with_file(inner: S, path: impl AsRef<Path>) -> Self {
+        Self {
+            inner,
+            log: AuditLog::File {
+                path: path.as_ref().to_path_buf(),
+                write_lock: tokio::sync::Mutex::new(()),
+            },
+            tails: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The wrapped store
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Every message logged for a conversation, oldest first
+    ///
+    /// Includes messages of deleted conversations and messages since dropped
+    /// from the stored history. Messages keep their IDs, so the versions of a
+    /// rewritten message can be told apart from new ones.
+    pub async fn read_audit(&self, id: &str) -> Result<Vec<ChatMessage>, OpenAIAgentError> {
+        match &self.log {
+            AuditLog::Memory(log) => {
+                let log = log.lock().map_err(|e| {
+                    OpenAIAgentError::Persistence(format!("Failed to acquire audit log lock: {}", e))
+                })?;
+                Ok(log.get(id).cloned().unwrap_or_default())
+            }
+            AuditLog::File { path, .. } => {
+                let contents = match tokio::fs::read_to_string(path).await {
+                    Ok(contents) => contents,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                    Err(e) => return Err(e.into()),
+                };
+
+                let mut messages = Vec::new();
+                for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                    let entry: AuditEntry = serde_json::from_str(line).map_err(|e| {
+                        OpenAIAgentError::Deserialization(format!("Invalid audit log entry: {}", e))
+                    })?;
+                    if entry.conversation_id == id {
+                        let mut message = entry.message;
+                        message.id = entry.message_id;
+                        messages.push(message);
+                    }
+                }
+                Ok(messages)
+            }
+        }
+    }
+
+    /// Returns the messages of `state` not yet logged for `id` and the tail to
+    /// remember once they are.
+    async fn unlogged<'a>(
+        &self,
+        id: &str,
+        state: &'a AgentState,
+    ) -> Result<(&'a [ChatMessage], Option<LoggedTail>), OpenAIAgentError> {
+        let messages = &state.messages;
+        let Some(last) = messages.last() else {
+            return Ok((&[], None));
+        };
+        let new_tail = LoggedTail {
+            position: state.dropped_count + messages.len(),
+            id: last.id.clone(),
+            message: serde_json::to_value(last)?,
+        };
+
+        let cached = self.tails.lock().ok().and_then(|tails| tails.get(id).cloned());
+        let tail = match cached {
+            Some(tail) => Some(tail),
+            // Not seen since start-up: continue after the last message in the log
+            None => match self.read_audit(id).await?.last() {
+                Some(message) => Some(LoggedTail {
+                    position: 0,
+                    id: message.id.clone(),
+                    message: serde_json::to_value(message)?,
+                }),
+                None => None,
+            },
+        };
+        let Some(tail) = tail else {
+            return Ok((messages, Some(new_tail)));
+        };
+
+        let same_content =
+            |index: usize| serde_json::to_value(&messages[index]).is_ok_and(|value| value == tail.message);
+        // Messages that never went through an agent's history have no ID to match
+        let matches = |index: usize| match &tail.id {
+            Some(id) => messages[index].id.as_ref() == Some(id),
+            None => same_content(index),
+        };
+        let by_position = tail
+            .position
+            .checked_sub(state.dropped_count + 1)
+            .filter(|&index| index < messages.len() && matches(index));
+        let start = match by_position.or_else(|| (0..messages.len()).rev().find(|&index| matches(index))) {
+            // Rewritten since it was logged: log the new version too
+            Some(index) if !same_content(index) => index,
+            Some(index) => index + 1,
+            None => 0,
+        };
+        Ok((&messages[start..], Some(new_tail)))
+    }
+
+    /// Appends messages to the log
+    async fn append(&self, id: &str, messages: &[ChatMessage]) -> Result<(), OpenAIAgentError> {
+        match &self.log {
+            AuditLog::Memory(log) => {
+                let mut log = log.lock().map_err(|e| {
+                    OpenAIAgentError::Persistence(format!("Failed to acquire audit log lock: {}", e))
+                })?;
+                log.entry(id.to_string()).or_default().extend_from_slice(messages);
+            }
+            AuditLog::File { path, write_lock } => {
+                let recorded_at = chrono::Utc::now();
+                let mut lines = String::new();
+                for message in messages {
+                    let entry = AuditEntry {
+                        conversation_id: id.to_string(),
+                        recorded_at,
+                        message_id: message.id.clone(),
+                        message: message.clone(),
+                    };
+                    lines.push_str(&serde_json::to_string(&entry)?);
+                    lines.push('\n');
+                }
+
+                let _guard = write_lock.lock().await;
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?;
+                file.write_all(lines.as_bytes()).await?;
+                file.flush().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S: PersistenceStore> PersistenceStore for AuditStore<S> {
+    async fn store_conversation(&self, id: &str, state: &AgentState) -> Result<(), OpenAIAgentError> {
+        // Log first, so a message is never stored without being audited
+        let (new_messages, tail) = self.unlogged(id, state).await?;
+        if !new_messages.is_empty() {
+            self.append(id, new_messages).await?;
+        }
+        if let (Some(tail), Ok(mut tails)) = (tail, self.tails.lock()) {
+            tails.insert(id.to_string(), tail);
+        }
+
+        self.inner.store_conversation(id, state).await
+    }
+
+    async fn get_conversation(&self, id: &str) -> Result<Option<AgentState>, OpenAIAgentError> {
+        self.inner.get_conversation(id).await
+    }
+
+    async fn get_conversations(&self, ids: &[EntityId]) -> Result<HashMap<EntityId, AgentState>, OpenAIAgentError> {
+        self.inner.get_conversations(ids).await
+    }
+
+    async fn get_turn_tools(&self, id: &str) -> Result<Option<Vec<TurnTools>>, OpenAIAgentError> {
+        self.inner.get_turn_tools(id).await
+    }
+
+    async fn delete_conversation(&self, id: &str) -> Result<(), OpenAIAgentError> {
+        self.inner.delete_conversation(id).await
+    }
+
+    async fn list_conversations(&self, limit: usize, offset: usize) -> Result<Vec<ConversationMetadata>, OpenAIAgentError> {
+        self.inner.list_conversations(limit, offset).await
+    }
+
+    async fn delete_conversations(&self, ids: &[EntityId]) -> Result<usize, OpenAIAgentError> {
+        self.inner.delete_conversations(ids).await
+    }
+
+    async fn delete_older_than(&self, cutoff: chrono::DateTime<chrono::Utc>) -> Result<usize, OpenAIAgentError> {
+        self.inner.delete_older_than(cutoff).await
+    }
+
+    async fn ping(&self) -> Result<(), OpenAIAgentError> {
+        self.inner.ping().await
+    }
+}
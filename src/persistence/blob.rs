@@ -0,0 +1,276 @@
+//! Blob-based persistence over any key-value backend.
+//!
+//! `BlobStore` serializes each conversation, with its metadata, into a single
+//! value under one key. Backends only implement the four operations of
+//! `KvBackend`, which makes it a small extension point for object stores and
+//! embedded databases; the encoding is chosen with a `Codec`.
+
+use super::{ConversationMetadata, PersistenceStore};
+use crate::agent::{AgentState, TurnTools, TurnUsage};
+use crate::error::OpenAIAgentError;
+use crate::models::ChatMessage;
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+/// Prefix of the keys conversations are stored under
+const KEY_PREFIX: &str = "conversations/";
+
+/// Minimal key-value storage used by `BlobStore`.
+#[async_trait]
+pub trait KvBackend: Send + Sync {
+    /// Returns the value stored under `key`, if any
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, OpenAIAgentError>;
+
+    /// Stores `value` under `key`, replacing any previous value
+    async fn put(&self, key: &str, value: Vec<u8>) -> Result<(), OpenAIAgentError>;
+
+    /// Removes `key`; removing a missing key is not an error
+    async fn delete(&self, key: &str) -> Result<(), OpenAIAgentError>;
+
+    /// Returns every key starting with `prefix`
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, OpenAIAgentError>;
+}
+
+/// Encoding of the values written by `BlobStore`.
+pub trait Codec: Send + Sync {
+    /// Serializes a value to bytes
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, OpenAIAgentError>;
+
+    /// Deserializes a value from bytes
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, OpenAIAgentError>;
+}
+
+/// Stores values as JSON (the default codec)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, OpenAIAgentError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, OpenAIAgentError> {
+        serde_json::from_slice(bytes).map_err(|e| OpenAIAgentError::Deserialization(e.to_string()))
+    }
+}
+
+/// Stores values as CBOR, which is more compact than JSON (requires the `cbor` feature)
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl Codec for CborCodec {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, OpenAIAgentError> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes)
+            .map_err(|e| OpenAIAgentError::Persistence(format!("Failed to encode CBOR: {}", e)))?;
+        Ok(bytes)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, OpenAIAgentError> {
+        ciborium::from_reader(bytes).map_err(|e| OpenAIAgentError::Deserialization(e.to_string()))
+    }
+}
+
+/// In-memory `KvBackend`, for tests and as a reference implementation
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    entries: RwLock<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryBackend {
+    /// Create an empty backend
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl KvBackend for MemoryBackend {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, OpenAIAgentError> {
+        let entries = self.entries.read().map_err(|e| {
+            OpenAIAgentError::Agent(format!("Failed to acquire read lock: {}", e))
+        })?;
+        Ok(entries.get(key).cloned())
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>) -> Result<(), OpenAIAgentError> {
+        let mut entries = self.entries.write().map_err(|e| {
+            OpenAIAgentError::Agent(format!("Failed to acquire write lock: {}", e))
+        })?;
+        entries.insert(key.to_string(), value);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), OpenAIAgentError> {
+        let mut entries = self.entries.write().map_err(|e| {
+            OpenAIAgentError::Agent(format!("Failed to acquire write lock: {}", e))
+        })?;
+        entries.remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, OpenAIAgentError> {
+        let entries = self.entries.read().map_err(|e| {
+            OpenAIAgentError::Agent(format!("Failed to acquire read lock: {}", e))
+        })?;
+        Ok(entries.keys().filter(|key| key.starts_with(prefix)).cloned().collect())
+    }
+}
+
+/// A message as stored, keeping the local-only flags `ChatMessage` does not serialize
+#[derive(Serialize, Deserialize)]
+struct StoredMessage {
+    #[serde(flatten)]
+    message: ChatMessage,
+    #[serde(default)]
+    incomplete: bool,
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default)]
+    id: Option<String>,
+}
+
+/// The value written for one conversation
+#[derive(Serialize, Deserialize)]
+struct StoredConversation {
+    metadata: ConversationMetadata,
+    messages: Vec<StoredMessage>,
+    token_count: usize,
+    version: u64,
+    dropped_count: usize,
+    #[serde(default)]
+    turn_usage: Vec<TurnUsage>,
+    #[serde(default)]
+    turn_tools: Vec<TurnTools>,
+}
+
+impl StoredConversation {
+    fn into_state(self) -> AgentState {
+        let messages = self
+            .messages
+            .into_iter()
+            .map(|stored| ChatMessage {
+                incomplete: stored.incomplete,
+                pinned: stored.pinned,
+                id: stored.id,
+                ..stored.message
+            })
+            .collect();
+
+        AgentState {
+            messages,
+            token_count: self.token_count,
+            version: self.version,
+            dropped_count: self.dropped_count,
+            turn_usage: self.turn_usage,
+            turn_tools: self.turn_tools,
+        }
+    }
+}
+
+/// Persistence store that keeps each conversation as one encoded blob in a
+/// `KvBackend`.
+///
+/// Conversations are stored under `conversations/{id}` with their metadata, so
+/// a save is a single write. Listing decodes every stored conversation, so it
+/// suits backends with modest numbers of conversations or cheap reads.
+pub struct BlobStore<B: KvBackend, C: Codec = JsonCodec> {
+    backend: B,
+    codec: C,
+}
+
+impl<B: KvBackend> BlobStore<B> {
+    /// Create a store over `backend` that encodes conversations as JSON
+    pub fn new(backend: B) -> Self {
+        Self::with_codec(backend, JsonCodec)
+    }
+}
+
+impl<B: KvBackend, C: Codec> BlobStore<B, C> {
+    /// Create a store over `backend` that encodes conversations with `codec`
+    pub fn with_codec(backend: B, codec: C) -> Self {
+        Self { backend, codec }
+    }
+
+    /// Returns the underlying backend
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    async fn load(&self, id: &str) -> Result<Option<StoredConversation>, OpenAIAgentError> {
+        match self.backend.get(&format!("{}{}", KEY_PREFIX, id)).await? {
+            Some(bytes) => Ok(Some(self.codec.decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl<B: KvBackend, C: Codec> PersistenceStore for BlobStore<B, C> {
+    async fn store_conversation(&self, id: &str, state: &AgentState) -> Result<(), OpenAIAgentError> {
+        let now = Utc::now();
+        let created_at = self.load(id).await?.map_or(now, |existing| existing.metadata.created_at);
+
+        let conversation = StoredConversation {
+            metadata: ConversationMetadata {
+                id: id.to_string(),
+                name: None,
+                created_at,
+                updated_at: now,
+                message_count: state.message_count(),
+                token_count: state.token_count(),
+            },
+            messages: state
+                .messages
+                .iter()
+                .map(|message| StoredMessage {
+                    message: message.clone(),
+                    incomplete: message.incomplete,
+                    pinned: message.pinned,
+                    id: message.id.clone(),
+                })
+                .collect(),
+            token_count: state.token_count,
+            version: state.version,
+            dropped_count: state.dropped_count,
+            turn_usage: state.turn_usage.clone(),
+            turn_tools: state.turn_tools.clone(),
+        };
+
+        let bytes = self.codec.encode(&conversation)?;
+        self.backend.put(&format!("{}{}", KEY_PREFIX, id), bytes).await
+    }
+
+    async fn get_conversation(&self, id: &str) -> Result<Option<AgentState>, OpenAIAgentError> {
+        Ok(self.load(id).await?.map(StoredConversation::into_state))
+    }
+
+    async fn delete_conversation(&self, id: &str) -> Result<(), OpenAIAgentError> {
+        self.backend.delete(&format!("{}{}", KEY_PREFIX, id)).await
+    }
+
+    async fn list_conversations(&self, limit: usize, offset: usize) -> Result<Vec<ConversationMetadata>, OpenAIAgentError> {
+        let mut metadata = Vec::new();
+        for key in self.backend.list(KEY_PREFIX).await? {
+            // A key deleted since listing is simply skipped
+            if let Some(conversation) = self.load(&key[KEY_PREFIX.len()..]).await? {
+                metadata.push(conversation.metadata);
+            }
+        }
+
+        // Sort by updated_at (most recent first)
+        metadata.sort_by_key(|meta| std::cmp::Reverse(meta.updated_at));
+        Ok(metadata.into_iter().skip(offset).take(limit).collect())
+    }
+
+    async fn ping(&self) -> Result<(), OpenAIAgentError> {
+        // A read of a key that is never written is a cheap round trip
+        self.backend.get(KEY_PREFIX).await.map(|_| ())
+    }
+}
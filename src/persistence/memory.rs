@@ -7,7 +7,7 @@ use super::{ConversationMetadata, EntityId, PersistenceStore};
 use crate::agent::AgentState;
 use crate::error::OpenAIAgentError;
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
@@ -33,6 +33,14 @@ impl Default for MemoryStore {
 
 #[async_trait]
 impl PersistenceStore for MemoryStore {
+    async fn ping(&self) -> Result<(), OpenAIAgentError> {
+        // A poisoned lock would fail every later call
+        let _conversations = self.conversations.read().map_err(|e| {
+            OpenAIAgentError::Agent(format!("Failed to acquire read lock: {}", e))
+        })?;
+        Ok(())
+    }
+
     async fn store_conversation(&self, id: &str, state: &AgentState) -> Result<(), OpenAIAgentError> {
         let mut conversations = self.conversations.write().map_err(|e| {
             OpenAIAgentError::Agent(format!("Failed to acquire write lock: {}", e))
@@ -69,6 +77,17 @@ impl PersistenceStore for MemoryStore {
         Ok(conversations.get(id).map(|(state, _)| state.clone()))
     }
     
+    async fn get_conversations(&self, ids: &[EntityId]) -> Result<HashMap<EntityId, AgentState>, OpenAIAgentError> {
+        let conversations = self.conversations.read().map_err(|e| {
+            OpenAIAgentError::Agent(format!("Failed to acquire read lock: {}", e))
+        })?;
+        
+        Ok(ids
+            .iter()
+            .filter_map(|id| conversations.get(id).map(|(state, _)| (id.clone(), state.clone())))
+            .collect())
+    }
+    
     async fn delete_conversation(&self, id: &str) -> Result<(), OpenAIAgentError> {
         let mut conversations = self.conversations.write().map_err(|e| {
             OpenAIAgentError::Agent(format!("Failed to acquire write lock: {}", e))
@@ -88,7 +107,7 @@ impl PersistenceStore for MemoryStore {
             .collect();
         
         // Sort by updated_at (most recent first)
-        metadata.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        metadata.sort_by_key(|meta| std::cmp::Reverse(meta.updated_at));
         
         let end = (offset + limit).min(metadata.len());
         if offset >= metadata.len() {
@@ -97,4 +116,22 @@ impl PersistenceStore for MemoryStore {
         
         Ok(metadata[offset..end].to_vec())
     }
+    
+    async fn delete_conversations(&self, ids: &[EntityId]) -> Result<usize, OpenAIAgentError> {
+        let mut conversations = self.conversations.write().map_err(|e| {
+            OpenAIAgentError::Agent(format!("Failed to acquire write lock: {}", e))
+        })?;
+        
+        Ok(ids.iter().filter(|id| conversations.remove(*id).is_some()).count())
+    }
+    
+    async fn delete_older_than(&self, cutoff: DateTime<Utc>) -> Result<usize, OpenAIAgentError> {
+        let mut conversations = self.conversations.write().map_err(|e| {
+            OpenAIAgentError::Agent(format!("Failed to acquire write lock: {}", e))
+        })?;
+        
+        let before = conversations.len();
+        conversations.retain(|_, (_, meta)| meta.updated_at >= cutoff);
+        Ok(before - conversations.len())
+    }
 } 
\ No newline at end of file
@@ -4,11 +4,55 @@
 //! trait for production use.
 
 use super::{ConversationMetadata, EntityId, PersistenceStore};
-use crate::agent::AgentState;
+use crate::agent::{AgentState, TurnTools};
 use crate::error::OpenAIAgentError;
-use crate::models::ChatMessage;
+use crate::models::{ChatMessage, MessageContent};
 use async_trait::async_trait;
-use sqlx::{postgres::PgPoolOptions, PgPool, Row};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use sqlx::{postgres::{PgPoolOptions, PgRow}, PgPool, Row};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Connection pool settings for `PostgresStore`
+#[derive(Debug, Clone)]
+pub struct PgStoreOptions {
+    /// Maximum number of connections in the pool
+    pub max_connections: u32,
+    /// Minimum number of idle connections to maintain
+    pub min_connections: u32,
+    /// How long to wait for a connection before giving up
+    pub acquire_timeout: Duration,
+}
+
+impl Default for PgStoreOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: 5,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl PgStoreOptions {
+    fn validate(&self) -> Result<(), OpenAIAgentError> {
+        if self.max_connections == 0 {
+            return Err(OpenAIAgentError::Config("max_connections must be at least 1".to_string()));
+        }
+        if self.min_connections > self.max_connections {
+            return Err(OpenAIAgentError::Config(format!(
+                "min_connections ({}) cannot exceed max_connections ({})",
+                self.min_connections, self.max_connections
+            )));
+        }
+        if self.acquire_timeout.is_zero() {
+            return Err(OpenAIAgentError::Config("acquire_timeout must be greater than zero".to_string()));
+        }
+        Ok(())
+    }
+}
 
 /// PostgreSQL implementation of PersistenceStore
 pub struct PostgresStore {
@@ -16,19 +60,47 @@ pub struct PostgresStore {
 }
 
 impl PostgresStore {
-    /// Create a new PostgreSQL store with the given connection string
+    /// Create a new PostgreSQL store with the given connection string and default pool options
     pub async fn new(connection_string: &str) -> Result<Self, OpenAIAgentError> {
+        Self::with_options(connection_string, PgStoreOptions::default()).await
+    }
+
+    /// Create a new PostgreSQL store with custom connection pool options
+    pub async fn with_options(connection_string: &str, options: PgStoreOptions) -> Result<Self, OpenAIAgentError> {
+        options.validate()?;
+
         let pool = PgPoolOptions::new()
-            .max_connections(5)
+            .max_connections(options.max_connections)
+            .min_connections(options.min_connections)
+            .acquire_timeout(options.acquire_timeout)
             .connect(connection_string)
             .await
             .map_err(|e| OpenAIAgentError::Agent(format!("Database connection error: {}", e)))?;
             
+        Self::from_pool(pool).await
+    }
+
+    /// Create a store on top of an existing pool, so it can be shared with the application
+    pub async fn from_pool(pool: PgPool) -> Result<Self, OpenAIAgentError> {
         // Initialize tables
         Self::init_tables(&pool).await?;
         
         Ok(Self { pool })
     }
+
+    /// The underlying connection pool
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Check that the database is reachable
+    pub async fn health_check(&self) -> Result<(), OpenAIAgentError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Database health check failed: {}", e)))?;
+        Ok(())
+    }
     
     async fn init_tables(pool: &PgPool) -> Result<(), OpenAIAgentError> {
         // Create tables if they don't exist - split into separate queries
@@ -41,7 +113,9 @@ impl PostgresStore {
                 created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
                 updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
                 message_count INTEGER NOT NULL DEFAULT 0,
-                token_count INTEGER NOT NULL DEFAULT 0
+                token_count INTEGER NOT NULL DEFAULT 0,
+                turn_usage JSONB,
+                turn_tools JSONB
             )
             "#
         )
@@ -54,15 +128,20 @@ impl PostgresStore {
         sqlx::query(
             r#"
             CREATE TABLE IF NOT EXISTS messages (
-                id TEXT PRIMARY KEY,
+                id TEXT NOT NULL,
                 conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
                 role TEXT NOT NULL,
                 content TEXT,
+                content_parts JSONB,
                 name TEXT,
                 tool_call_id TEXT,
                 tool_calls JSONB,
+                incomplete BOOLEAN NOT NULL DEFAULT FALSE,
+                refusal TEXT,
+                pinned BOOLEAN NOT NULL DEFAULT FALSE,
                 position INTEGER NOT NULL,
-                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (conversation_id, id)
             )
             "#
         )
@@ -92,6 +171,64 @@ impl PostgresStore {
             return Err(OpenAIAgentError::Agent("conversation_id column does not exist in messages table".to_string()));
         }
         
+        // Add columns introduced after the initial schema to existing tables
+        sqlx::query("ALTER TABLE messages ADD COLUMN IF NOT EXISTS content_parts JSONB")
+            .execute(pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to add content_parts column: {}", e)))?;
+
+        sqlx::query("ALTER TABLE messages ADD COLUMN IF NOT EXISTS incomplete BOOLEAN NOT NULL DEFAULT FALSE")
+            .execute(pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to add incomplete column: {}", e)))?;
+
+        sqlx::query("ALTER TABLE conversations ADD COLUMN IF NOT EXISTS turn_usage JSONB")
+            .execute(pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to add turn_usage column: {}", e)))?;
+
+        sqlx::query("ALTER TABLE conversations ADD COLUMN IF NOT EXISTS turn_tools JSONB")
+            .execute(pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to add turn_tools column: {}", e)))?;
+
+        sqlx::query("ALTER TABLE messages ADD COLUMN IF NOT EXISTS refusal TEXT")
+            .execute(pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to add refusal column: {}", e)))?;
+
+        sqlx::query("ALTER TABLE messages ADD COLUMN IF NOT EXISTS pinned BOOLEAN NOT NULL DEFAULT FALSE")
+            .execute(pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to add pinned column: {}", e)))?;
+
+        sqlx::query("ALTER TABLE conversations ADD COLUMN IF NOT EXISTS dropped_count INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to add dropped_count column: {}", e)))?;
+
+        // Message IDs used to be unique across all conversations; they are now
+        // scoped to their conversation so forked conversations can share them
+        sqlx::query(
+            r#"
+            DO $$
+            BEGIN
+                IF NOT EXISTS (
+                    SELECT 1 FROM information_schema.key_column_usage
+                    WHERE table_name = 'messages'
+                        AND constraint_name = 'messages_pkey'
+                        AND column_name = 'conversation_id'
+                ) THEN
+                    ALTER TABLE messages DROP CONSTRAINT IF EXISTS messages_pkey;
+                    ALTER TABLE messages ADD PRIMARY KEY (conversation_id, id);
+                END IF;
+            END $$
+            "#
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| OpenAIAgentError::Agent(format!("Failed to update messages primary key: {}", e)))?;
+        
         println!("Creating index on messages.conversation_id...");
         sqlx::query(
             r#"
@@ -117,17 +254,25 @@ impl PersistenceStore for PostgresStore {
         // Insert or update conversation metadata
         sqlx::query(
             r#"
-            INSERT INTO conversations (id, message_count, token_count, updated_at)
-            VALUES ($1, $2, $3, NOW())
+            INSERT INTO conversations (id, message_count, token_count, turn_usage, turn_tools, dropped_count, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, NOW())
             ON CONFLICT (id) DO UPDATE SET
                 message_count = $2,
                 token_count = $3,
+                turn_usage = $4,
+                turn_tools = $5,
+                dropped_count = $6,
                 updated_at = NOW()
             "#
         )
         .bind(id)
         .bind(state.message_count() as i32)
         .bind(state.token_count() as i32)
+        .bind(serde_json::to_value(state.turn_usage())
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to serialize turn usage: {}", e)))?)
+        .bind(serde_json::to_value(state.turn_tools())
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to serialize turn tools: {}", e)))?)
+        .bind(state.dropped_count as i32)
         .execute(&mut *tx)
         .await
         .map_err(|e| OpenAIAgentError::Agent(format!("Failed to update conversation: {}", e)))?;
@@ -148,23 +293,39 @@ impl PersistenceStore for PostgresStore {
                 serde_json::Value::Null
             };
             
+            // Plain text goes in `content`; multi-part content is stored as JSON
+            let (content, content_parts) = match &message.content {
+                Some(MessageContent::Text(text)) => (Some(text.clone()), serde_json::Value::Null),
+                Some(MessageContent::Parts(parts)) => (
+                    None,
+                    serde_json::to_value(parts)
+                        .map_err(|e| OpenAIAgentError::Agent(format!("Failed to serialize content parts: {}", e)))?,
+                ),
+                None => (None, serde_json::Value::Null),
+            };
+            
             sqlx::query(
                 r#"
                 INSERT INTO messages (
                     id, conversation_id, role, content, name, 
-                    tool_call_id, tool_calls, position, created_at
+                    tool_call_id, tool_calls, position, created_at, content_parts, incomplete, refusal, pinned
                 )
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW())
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW(), $9, $10, $11, $12)
                 "#
             )
-            .bind(format!("{}-msg-{}", id, i))
+            // Messages that never went through an agent's history have no ID yet
+            .bind(message.id.clone().unwrap_or_else(|| Uuid::new_v4().to_string()))
             .bind(id)
             .bind(&message.role)
-            .bind(&message.content)
+            .bind(content)
             .bind(&message.name)
             .bind(&message.tool_call_id)
             .bind(tool_calls_json)
             .bind(i as i32)
+            .bind(content_parts)
+            .bind(message.incomplete)
+            .bind(&message.refusal)
+            .bind(message.pinned)
             .execute(&mut *tx)
             .await
             .map_err(|e| OpenAIAgentError::Agent(format!("Failed to insert message: {}", e)))?;
@@ -189,17 +350,21 @@ impl PersistenceStore for PostgresStore {
             return Ok(None);
         }
         
-        // Get token count
-        let token_count: i32 = sqlx::query_scalar("SELECT token_count FROM conversations WHERE id = $1")
+        // Get token count and per-turn usage
+        let row = sqlx::query("SELECT token_count, turn_usage, turn_tools, dropped_count FROM conversations WHERE id = $1")
             .bind(id)
             .fetch_one(&self.pool)
             .await
             .map_err(|e| OpenAIAgentError::Agent(format!("Failed to get token count: {}", e)))?;
+        let token_count: i32 = row.get("token_count");
+        let dropped_count: i32 = row.get("dropped_count");
+        let turn_usage = json_list_from_row(&row, "turn_usage")?;
+        let turn_tools = json_list_from_row(&row, "turn_tools")?;
         
         // Get messages
         let rows = sqlx::query(
             r#"
-            SELECT role, content, name, tool_call_id, tool_calls
+            SELECT id, role, content, content_parts, name, tool_call_id, tool_calls, incomplete, refusal, pinned
             FROM messages
             WHERE conversation_id = $1
             ORDER BY position ASC
@@ -210,45 +375,74 @@ impl PersistenceStore for PostgresStore {
         .await
         .map_err(|e| OpenAIAgentError::Agent(format!("Failed to fetch messages: {}", e)))?;
         
-        let mut messages = Vec::with_capacity(rows.len());
-        
-        for row in rows {
-            let role: String = row.get("role");
-            let content: Option<String> = row.get("content");
-            let name: Option<String> = row.get("name");
-            let tool_call_id: Option<String> = row.get("tool_call_id");
-            let tool_calls_json: Option<serde_json::Value> = row.get("tool_calls");
-            
-            let tool_calls = if let Some(json) = tool_calls_json {
-                if json.is_null() {
-                    None
-                } else {
-                    Some(serde_json::from_value(json)
-                        .map_err(|e| OpenAIAgentError::Deserialization(e.to_string()))?)
-                }
-            } else {
-                None
-            };
-            
-            let message = ChatMessage {
-                role,
-                content,
-                name,
-                tool_call_id,
-                tool_calls,
-            };
-            
-            messages.push(message);
-        }
+        let messages = rows.iter().map(message_from_row).collect::<Result<Vec<_>, _>>()?;
         
         let state = AgentState {
             messages,
             token_count: token_count as usize,
+            version: 0,
+            dropped_count: dropped_count as usize,
+            turn_usage,
+            turn_tools,
         };
         
         Ok(Some(state))
     }
     
+    async fn get_conversations(&self, ids: &[EntityId]) -> Result<HashMap<EntityId, AgentState>, OpenAIAgentError> {
+        let rows = sqlx::query("SELECT id, token_count, turn_usage, turn_tools, dropped_count FROM conversations WHERE id = ANY($1)")
+            .bind(ids)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to fetch conversations: {}", e)))?;
+        
+        let mut states = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let token_count: i32 = row.get("token_count");
+            states.insert(row.get::<String, _>("id"), AgentState {
+                messages: Vec::new(),
+                token_count: token_count as usize,
+                version: 0,
+                dropped_count: row.get::<i32, _>("dropped_count") as usize,
+                turn_usage: json_list_from_row(&row, "turn_usage")?,
+                turn_tools: json_list_from_row(&row, "turn_tools")?,
+            });
+        }
+        
+        // One query for every conversation's messages, in order within each conversation
+        let rows = sqlx::query(
+            r#"
+            SELECT conversation_id, id, role, content, content_parts, name, tool_call_id, tool_calls, incomplete, refusal, pinned
+            FROM messages
+            WHERE conversation_id = ANY($1)
+            ORDER BY conversation_id, position ASC
+            "#
+        )
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| OpenAIAgentError::Agent(format!("Failed to fetch messages: {}", e)))?;
+        
+        for row in &rows {
+            let conversation_id: String = row.get("conversation_id");
+            if let Some(state) = states.get_mut(&conversation_id) {
+                state.messages.push(message_from_row(row)?);
+            }
+        }
+        
+        Ok(states)
+    }
+    
+    async fn get_turn_tools(&self, id: &str) -> Result<Option<Vec<TurnTools>>, OpenAIAgentError> {
+        let row = sqlx::query("SELECT turn_tools FROM conversations WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to get turn tools: {}", e)))?;
+        
+        row.map(|row| json_list_from_row(&row, "turn_tools")).transpose()
+    }
+    
     async fn delete_conversation(&self, id: &str) -> Result<(), OpenAIAgentError> {
         // The messages will be deleted automatically due to the ON DELETE CASCADE constraint
         sqlx::query("DELETE FROM conversations WHERE id = $1")
@@ -292,4 +486,89 @@ impl PersistenceStore for PostgresStore {
         
         Ok(metadata)
     }
-} 
\ No newline at end of file
+    
+    async fn delete_conversations(&self, ids: &[EntityId]) -> Result<usize, OpenAIAgentError> {
+        // Messages are removed by ON DELETE CASCADE
+        let result = sqlx::query("DELETE FROM conversations WHERE id = ANY($1)")
+            .bind(ids)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to delete conversations: {}", e)))?;
+        
+        Ok(result.rows_affected() as usize)
+    }
+    
+    async fn delete_older_than(&self, cutoff: DateTime<Utc>) -> Result<usize, OpenAIAgentError> {
+        let result = sqlx::query("DELETE FROM conversations WHERE updated_at < $1")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to delete old conversations: {}", e)))?;
+        
+        Ok(result.rows_affected() as usize)
+    }
+
+    async fn ping(&self) -> Result<(), OpenAIAgentError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(|e| OpenAIAgentError::Persistence(format!("Database unreachable: {}", e)))?;
+
+        Ok(())
+    }
+} 
+
+/// Reads a JSON list column such as `turn_usage` of a `conversations` row,
+/// treating NULL as empty.
+fn json_list_from_row<T: DeserializeOwned>(row: &PgRow, column: &str) -> Result<Vec<T>, OpenAIAgentError> {
+    match row.get::<Option<serde_json::Value>, _>(column) {
+        Some(json) if !json.is_null() => serde_json::from_value(json)
+            .map_err(|e| OpenAIAgentError::Deserialization(e.to_string())),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Builds a message from a `messages` row.
+fn message_from_row(row: &PgRow) -> Result<ChatMessage, OpenAIAgentError> {
+    let id: String = row.get("id");
+    let role: String = row.get("role");
+    let content: Option<String> = row.get("content");
+    let content_parts_json: Option<serde_json::Value> = row.get("content_parts");
+    let name: Option<String> = row.get("name");
+    let tool_call_id: Option<String> = row.get("tool_call_id");
+    let tool_calls_json: Option<serde_json::Value> = row.get("tool_calls");
+    let incomplete: bool = row.get("incomplete");
+    let refusal: Option<String> = row.get("refusal");
+    let pinned: bool = row.get("pinned");
+    
+    let tool_calls = if let Some(json) = tool_calls_json {
+        if json.is_null() {
+            None
+        } else {
+            Some(serde_json::from_value(json)
+                .map_err(|e| OpenAIAgentError::Deserialization(e.to_string()))?)
+        }
+    } else {
+        None
+    };
+    
+    let content = match content_parts_json {
+        Some(json) if !json.is_null() => Some(MessageContent::Parts(
+            serde_json::from_value(json)
+                .map_err(|e| OpenAIAgentError::Deserialization(e.to_string()))?,
+        )),
+        _ => content.map(MessageContent::Text),
+    };
+    
+    Ok(ChatMessage {
+        role,
+        content,
+        name,
+        tool_call_id,
+        tool_calls,
+        refusal,
+        incomplete,
+        pinned,
+        id: Some(id),
+    })
+}
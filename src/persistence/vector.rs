@@ -0,0 +1,243 @@
+//! In-memory vector store for retrieval over past conversations.
+//!
+//! This module provides a lightweight `VectorMemory` that keeps text snippets
+//! alongside their embeddings and supports cosine-similarity search, plus a
+//! `VectorMemoryTool` that lets an agent query it during a conversation.
+//! It performs a linear scan and is intended for small corpora.
+
+use super::{generate_id, EntityId};
+use crate::client::{embedding_chunks, ChunkStrategy, OpenAIClient};
+use crate::config::OpenAIConfig;
+use crate::error::OpenAIAgentError;
+use crate::models::ToolDefinition;
+use crate::tools::RegisteredTool;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::sync::{Arc, RwLock};
+
+/// Default embedding model used by `VectorMemoryTool`.
+const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+
+/// Default number of results returned by `VectorMemoryTool`.
+const DEFAULT_TOP_K: usize = 3;
+
+/// A single stored memory entry
+#[derive(Debug, Clone)]
+pub struct MemoryEntry {
+    /// Unique identifier
+    pub id: EntityId,
+    /// Embedding vector of the text
+    pub embedding: Vec<f32>,
+    /// The remembered text
+    pub text: String,
+}
+
+/// In-memory vector store using cosine similarity search
+pub struct VectorMemory {
+    entries: RwLock<Vec<MemoryEntry>>,
+}
+
+impl VectorMemory {
+    /// Create a new empty vector memory
+    pub fn new() -> Self {
+        Self {
+            entries: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Add a text with its embedding, returning the id of the new entry
+    pub fn add(&self, text: impl Into<String>, embedding: Vec<f32>) -> Result<EntityId, OpenAIAgentError> {
+        let mut entries = self.entries.write().map_err(|e| {
+            OpenAIAgentError::Agent(format!("Failed to acquire write lock: {}", e))
+        })?;
+
+        let id = generate_id();
+        entries.push(MemoryEntry {
+            id: id.clone(),
+            embedding,
+            text: text.into(),
+        });
+        Ok(id)
+    }
+
+    /// Return the `k` most similar texts to the query embedding, best match first
+    pub fn search(&self, query_embedding: &[f32], k: usize) -> Result<Vec<(String, f32)>, OpenAIAgentError> {
+        let entries = self.entries.read().map_err(|e| {
+            OpenAIAgentError::Agent(format!("Failed to acquire read lock: {}", e))
+        })?;
+
+        let mut scored: Vec<(String, f32)> = entries
+            .iter()
+            .map(|entry| (entry.text.clone(), cosine_similarity(query_embedding, &entry.embedding)))
+            .collect();
+
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        scored.truncate(k);
+        Ok(scored)
+    }
+
+    /// Number of stored entries
+    pub fn len(&self) -> usize {
+        self.entries.read().map(|entries| entries.len()).unwrap_or(0)
+    }
+
+    /// Whether the memory is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for VectorMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cosine similarity between two vectors; 0.0 if either is empty, zero or their lengths differ
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+/// Tool that lets an agent recall relevant entries from a `VectorMemory`.
+///
+/// The model calls it with a `query`; the query is embedded with the OpenAI
+/// Embeddings API and the closest stored texts are returned as JSON.
+pub struct VectorMemoryTool {
+    memory: Arc<VectorMemory>,
+    client: OpenAIClient,
+    embedding_model: String,
+    top_k: usize,
+}
+
+impl VectorMemoryTool {
+    /// Create a recall tool over the given memory, using `config` for embedding requests
+    pub fn new(memory: Arc<VectorMemory>, config: OpenAIConfig) -> Result<Self, OpenAIAgentError> {
+        Ok(Self {
+            memory,
+            client: OpenAIClient::new(config)?,
+            embedding_model: DEFAULT_EMBEDDING_MODEL.to_string(),
+            top_k: DEFAULT_TOP_K,
+        })
+    }
+
+    /// Set the embedding model used for queries and `remember`
+    pub fn with_embedding_model(mut self, model: impl Into<String>) -> Self {
+        self.embedding_model = model.into();
+        self
+    }
+
+    /// Set how many results are returned per query
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// Embed a text and store it in the underlying memory
+    ///
+    /// Fails if the text is over the embedding model's token limit; see
+    /// `remember_document` for long texts.
+    pub async fn remember(&self, text: impl Into<String>) -> Result<EntityId, OpenAIAgentError> {
+        let text = text.into();
+        let embedding = self.embed(&text).await?;
+        self.memory.add(text, embedding)
+    }
+
+    /// Split a long text into chunks within the embedding model's token limit and
+    /// store each one, returning the ids of the new entries in order
+    pub async fn remember_document(&self, text: &str) -> Result<Vec<EntityId>, OpenAIAgentError> {
+        let chunks = embedding_chunks(text, &self.embedding_model, ChunkStrategy::Split)?;
+        let embeddings = self
+            .client
+            .embeddings(&self.embedding_model, chunks.clone(), ChunkStrategy::Error)
+            .await?;
+
+        chunks
+            .into_iter()
+            .zip(embeddings)
+            .map(|(chunk, embedding)| self.memory.add(chunk, embedding))
+            .collect()
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, OpenAIAgentError> {
+        self.client
+            .embeddings(&self.embedding_model, vec![text.to_string()], ChunkStrategy::Error)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| OpenAIAgentError::Parse("No embedding returned".to_string()))
+    }
+}
+
+#[async_trait]
+impl RegisteredTool for VectorMemoryTool {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "recall_memory".to_string(),
+            description: "Searches long-term memory for past information relevant to a query.".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "What to search memory for."
+                    }
+                },
+                "required": ["query"],
+                "additionalProperties": false
+            }),
+            strict: Some(true),
+        }
+    }
+
+    async fn execute(&self, arguments: Value) -> Result<String, OpenAIAgentError> {
+        let query = arguments
+            .get("query")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| OpenAIAgentError::Tool("Missing 'query' argument".to_string()))?;
+
+        let embedding = self.embed(query).await?;
+        let results: Vec<Value> = self
+            .memory
+            .search(&embedding, self.top_k)?
+            .into_iter()
+            .map(|(text, score)| json!({ "text": text, "score": score }))
+            .collect();
+
+        Ok(Value::Array(results).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_orders_by_similarity() {
+        let memory = VectorMemory::new();
+        memory.add("east", vec![1.0, 0.0]).unwrap();
+        memory.add("north", vec![0.0, 1.0]).unwrap();
+        memory.add("north-east", vec![1.0, 1.0]).unwrap();
+
+        let results = memory.search(&[0.0, 2.0], 2).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "north");
+        assert_eq!(results[1].0, "north-east");
+    }
+
+    #[test]
+    fn test_cosine_similarity_mismatched_lengths() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[1.0]), 0.0);
+    }
+}
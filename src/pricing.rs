@@ -0,0 +1,113 @@
+//! Cost estimation for chat completion token usage.
+//!
+//! This module provides per-model token prices and helpers to turn a `Usage`
+//! report into an estimated cost in USD, accounting for cached prompt tokens.
+//! Prices are a snapshot of OpenAI's published rates and may drift; pass your
+//! own `ModelPricing` where accuracy matters.
+
+use crate::models::Usage;
+
+/// Token prices for a model, in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    /// Price of uncached prompt tokens
+    pub input: f64,
+
+    /// Price of prompt tokens served from the prompt cache
+    pub cached_input: f64,
+
+    /// Price of completion tokens (including reasoning tokens)
+    pub output: f64,
+}
+
+/// Known prices, matched by model prefix. More specific prefixes come first.
+const KNOWN_PRICING: &[(&str, ModelPricing)] = &[
+    ("gpt-4o-mini", ModelPricing { input: 0.15, cached_input: 0.075, output: 0.60 }),
+    ("gpt-4o", ModelPricing { input: 2.50, cached_input: 1.25, output: 10.00 }),
+    ("gpt-4.1-nano", ModelPricing { input: 0.10, cached_input: 0.025, output: 0.40 }),
+    ("gpt-4.1-mini", ModelPricing { input: 0.40, cached_input: 0.10, output: 1.60 }),
+    ("gpt-4.1", ModelPricing { input: 2.00, cached_input: 0.50, output: 8.00 }),
+    ("gpt-4-turbo", ModelPricing { input: 10.00, cached_input: 10.00, output: 30.00 }),
+    ("gpt-4", ModelPricing { input: 30.00, cached_input: 30.00, output: 60.00 }),
+    ("gpt-3.5-turbo", ModelPricing { input: 0.50, cached_input: 0.50, output: 1.50 }),
+    ("o1-mini", ModelPricing { input: 1.10, cached_input: 0.55, output: 4.40 }),
+    ("o1", ModelPricing { input: 15.00, cached_input: 7.50, output: 60.00 }),
+    ("o3-mini", ModelPricing { input: 1.10, cached_input: 0.55, output: 4.40 }),
+    ("o4-mini", ModelPricing { input: 1.10, cached_input: 0.275, output: 4.40 }),
+];
+
+impl ModelPricing {
+    /// Create pricing from per-million-token prices
+    pub fn new(input: f64, cached_input: f64, output: f64) -> Self {
+        Self { input, cached_input, output }
+    }
+
+    /// Look up the built-in pricing for a model, if known
+    pub fn for_model(model: &str) -> Option<Self> {
+        KNOWN_PRICING
+            .iter()
+            .find(|(prefix, _)| model.starts_with(prefix))
+            .map(|(_, pricing)| *pricing)
+    }
+
+    /// Estimate the cost of a usage report in USD.
+    ///
+    /// Cached prompt tokens are billed at `cached_input`, the rest of the prompt
+    /// at `input`. Reasoning tokens are already part of `completion_tokens`.
+    pub fn estimate_cost(&self, usage: &Usage) -> f64 {
+        let cached = usage.cached_tokens().min(usage.prompt_tokens);
+        let uncached = usage.prompt_tokens - cached;
+
+        (uncached as f64 * self.input
+            + cached as f64 * self.cached_input
+            + usage.completion_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// Estimate the cost of a usage report in USD using the built-in pricing for `model`.
+///
+/// Returns `None` if the model has no known pricing.
+pub fn estimate_cost(model: &str, usage: &Usage) -> Option<f64> {
+    ModelPricing::for_model(model).map(|pricing| pricing.estimate_cost(usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cached_tokens_are_discounted() {
+        let usage: Usage = serde_json::from_str(
+            r#"{
+                "prompt_tokens": 1000000,
+                "completion_tokens": 0,
+                "total_tokens": 1000000,
+                "prompt_tokens_details": { "cached_tokens": 500000 }
+            }"#,
+        )
+        .unwrap();
+
+        let pricing = ModelPricing::new(2.0, 1.0, 8.0);
+        assert!((pricing.estimate_cost(&usage) - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_usage_without_details() {
+        let usage: Usage = serde_json::from_str(
+            r#"{"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15}"#,
+        )
+        .unwrap();
+
+        assert!(usage.prompt_tokens_details.is_none());
+        assert_eq!(usage.cached_tokens(), 0);
+        assert_eq!(usage.reasoning_tokens(), 0);
+    }
+
+    #[test]
+    fn test_for_model_prefers_specific_prefix() {
+        assert_eq!(ModelPricing::for_model("gpt-4o-mini-2024-07-18"), ModelPricing::for_model("gpt-4o-mini"));
+        assert_ne!(ModelPricing::for_model("gpt-4o-mini"), ModelPricing::for_model("gpt-4o"));
+        assert!(ModelPricing::for_model("unknown-model").is_none());
+    }
+}
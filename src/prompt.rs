@@ -0,0 +1,118 @@
+//! Prompt templates with `{{variable}}` substitution.
+//!
+//! This module provides `PromptTemplate`, a small alternative to building prompts
+//! with `format!`, where literal braces in the prompt text would otherwise need
+//! escaping.
+
+use crate::error::OpenAIAgentError;
+use std::collections::HashMap;
+
+/// A prompt with `{{name}}` placeholders filled in by `render`.
+///
+/// Whitespace inside the braces is ignored, so `{{ name }}` and `{{name}}` are
+/// equivalent. Single braces are left untouched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptTemplate {
+    template: String,
+    defaults: HashMap<String, String>,
+}
+
+impl PromptTemplate {
+    /// Create a template from its text
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+            defaults: HashMap::new(),
+        }
+    }
+
+    /// Set a default value used when a variable is not provided to `render`
+    pub fn with_default(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.defaults.insert(name.into(), value.into());
+        self
+    }
+
+    /// The raw template text
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Fill in the placeholders.
+    ///
+    /// Fails with `OpenAIAgentError::Config` if a placeholder has neither a value
+    /// in `vars` nor a default, or if a `{{` is never closed.
+    pub fn render(&self, vars: &HashMap<String, String>) -> Result<String, OpenAIAgentError> {
+        let mut out = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let end = after.find("}}").ok_or_else(|| {
+                OpenAIAgentError::Config(format!(
+                    "Unclosed placeholder in prompt template near: {{{{{}",
+                    after.chars().take(20).collect::<String>()
+                ))
+            })?;
+
+            let name = after[..end].trim();
+            let value = vars
+                .get(name)
+                .or_else(|| self.defaults.get(name))
+                .ok_or_else(|| {
+                    OpenAIAgentError::Config(format!("Missing prompt template variable: {}", name))
+                })?;
+            out.push_str(value);
+
+            rest = &after[end + 2..];
+        }
+
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+impl From<&str> for PromptTemplate {
+    fn from(template: &str) -> Self {
+        Self::new(template)
+    }
+}
+
+impl From<String> for PromptTemplate {
+    fn from(template: String) -> Self {
+        Self::new(template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render_substitutes_and_keeps_single_braces() {
+        let template = PromptTemplate::new("You are {{ role }}. Reply as JSON like {\"answer\": ...} for {{user}}.");
+        let rendered = template.render(&vars(&[("role", "a tutor"), ("user", "Sam")])).unwrap();
+        assert_eq!(rendered, "You are a tutor. Reply as JSON like {\"answer\": ...} for Sam.");
+    }
+
+    #[test]
+    fn test_render_uses_defaults() {
+        let template = PromptTemplate::new("Tone: {{tone}}").with_default("tone", "friendly");
+        assert_eq!(template.render(&HashMap::new()).unwrap(), "Tone: friendly");
+        assert_eq!(template.render(&vars(&[("tone", "formal")])).unwrap(), "Tone: formal");
+    }
+
+    #[test]
+    fn test_render_missing_variable() {
+        let template = PromptTemplate::new("Hello {{name}}");
+        assert!(matches!(template.render(&HashMap::new()), Err(OpenAIAgentError::Config(_))));
+        assert!(matches!(PromptTemplate::new("Hello {{name").render(&HashMap::new()), Err(OpenAIAgentError::Config(_))));
+    }
+}
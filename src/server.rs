@@ -4,17 +4,246 @@
 //! including caching, eviction, and database persistence.
 
 use crate::agent::{Agent, AgentBuilder};
+use crate::client::OpenAIClient;
 use crate::config::OpenAIConfig;
 use crate::error::OpenAIAgentError;
+use crate::events::{AgentEvent, AgentEventSender};
+use crate::models::ChatMessage;
 use crate::persistence::{EntityId, PersistenceStore, ConversationMetadata};
+use crate::transport::ChatTransport;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use futures::Stream;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+
+/// Result of `AgentManager::run_message_delta`
+#[derive(Debug, Clone)]
+pub struct RunDelta {
+    /// Final assistant response
+    pub response: String,
+    /// Messages added to the history by this run, including the user message
+    pub messages: Vec<ChatMessage>,
+    /// History version after the run
+    pub version: u64,
+}
+
+/// Settings for the circuit breaker enabled by `AgentManager::with_circuit_breaker`.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive API failures that open the circuit
+    pub failure_threshold: u32,
+
+    /// Failures only count as consecutive if they all fall within this window
+    pub window: Duration,
+
+    /// How long the circuit stays open before a trial request is allowed
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+/// State of the circuit breaker, for health endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests flow normally
+    Closed,
+    /// Requests are rejected until the cooldown ends
+    Open {
+        /// Time left before a trial request is allowed
+        retry_after: Duration,
+    },
+    /// The cooldown has ended and a single trial request decides whether to close again
+    HalfOpen,
+}
+
+/// Trips after repeated upstream failures and rejects runs while open.
+///
+/// Only errors that indicate an upstream problem (`OpenAIAgentError::is_retryable`)
+/// count as failures; any other outcome means the API answered and counts as a success.
+#[derive(Debug)]
+struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    inner: Mutex<BreakerInner>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerInner {
+    /// Consecutive failures and when the streak started
+    failures: u32,
+    streak_started: Option<Instant>,
+    /// When the circuit opened, if it is open or half-open
+    opened_at: Option<Instant>,
+    /// When the half-open trial request was admitted, if it is in flight
+    trial_started: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(BreakerInner::default()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerInner> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn state(&self) -> CircuitState {
+        let inner = self.lock();
+        match inner.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) => {
+                let elapsed = opened_at.elapsed();
+                if elapsed < self.config.cooldown {
+                    CircuitState::Open { retry_after: self.config.cooldown - elapsed }
+                } else {
+                    CircuitState::HalfOpen
+                }
+            }
+        }
+    }
+
+    /// Admits a request, or rejects it while the circuit is open
+    fn acquire(&self) -> Result<(), OpenAIAgentError> {
+        let mut inner = self.lock();
+        let Some(opened_at) = inner.opened_at else {
+            return Ok(());
+        };
+
+        // A trial that never reported back (e.g. a cancelled request) is replaced
+        // after another cooldown
+        let trial_pending = inner
+            .trial_started
+            .is_some_and(|started| started.elapsed() < self.config.cooldown);
+        if opened_at.elapsed() < self.config.cooldown || trial_pending {
+            return Err(OpenAIAgentError::Agent("circuit open".to_string()));
+        }
+        inner.trial_started = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Records the outcome of an admitted request
+    fn record<T>(&self, result: &Result<T, OpenAIAgentError>) {
+        let mut inner = self.lock();
+        let failed = matches!(result, Err(e) if e.is_retryable());
+
+        if !failed {
+            *inner = BreakerInner::default();
+            return;
+        }
+
+        if inner.opened_at.is_some() {
+            // The trial failed: stay open for another cooldown
+            inner.opened_at = Some(Instant::now());
+            inner.trial_started = None;
+            return;
+        }
+
+        let now = Instant::now();
+        match inner.streak_started {
+            Some(started) if now.duration_since(started) <= self.config.window => inner.failures += 1,
+            _ => {
+                inner.failures = 1;
+                inner.streak_started = Some(now);
+            }
+        }
+        if inner.failures >= self.config.failure_threshold {
+            inner.opened_at = Some(now);
+        }
+    }
+}
+
+/// Per-conversation token buckets enforcing `AgentManager::with_rate_limit`.
+///
+/// Each conversation may send up to `max_per_minute` messages in a burst; the
+/// allowance then refills continuously at `max_per_minute` per minute.
+#[derive(Debug)]
+struct RateLimiter {
+    max_per_minute: u32,
+    buckets: Mutex<HashMap<EntityId, Bucket>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// Messages that may still be sent right now
+    tokens: f64,
+    /// When `tokens` was last brought up to date
+    updated: Instant,
+}
+
+impl RateLimiter {
+    fn new(max_per_minute: u32) -> Self {
+        Self {
+            max_per_minute: max_per_minute.max(1),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<EntityId, Bucket>> {
+        self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Returns the bucket's allowance at `now`, capped at the burst size
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let capacity = self.max_per_minute as f64;
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * capacity / 60.0;
+        (bucket.tokens + refill).min(capacity)
+    }
+
+    /// Takes one message from the conversation's allowance, or rejects it
+    fn acquire(&self, id: &str) -> Result<(), OpenAIAgentError> {
+        let now = Instant::now();
+        let mut buckets = self.lock();
+        let capacity = self.max_per_minute as f64;
+        let bucket = buckets
+            .entry(id.to_string())
+            .or_insert(Bucket { tokens: capacity, updated: now });
+
+        let tokens = self.refilled(bucket, now);
+        if tokens < 1.0 {
+            return Err(OpenAIAgentError::Agent("rate limited".to_string()));
+        }
+        *bucket = Bucket { tokens: tokens - 1.0, updated: now };
+        Ok(())
+    }
+
+    /// Drops the bucket of a deleted conversation
+    fn remove(&self, id: &str) {
+        self.lock().remove(id);
+    }
+
+    /// Drops buckets that have refilled completely, which behave like new ones
+    fn collect_garbage(&self) {
+        let now = Instant::now();
+        let capacity = self.max_per_minute as f64;
+        self.lock().retain(|_, bucket| self.refilled(bucket, now) < capacity);
+    }
+}
+
+/// Default number of events buffered per `AgentManager::subscribe` stream
+const DEFAULT_EVENT_CAPACITY: usize = 1024;
+
+/// Cache size used by `AgentManager::from_env` when `AGIO_MAX_CACHED_AGENTS` is not set
+const DEFAULT_MAX_CACHED_AGENTS: usize = 100;
 
 /// Server-side agent manager for handling multiple concurrent agents
 pub struct AgentManager {
     /// Default configuration for new agents
     config: OpenAIConfig,
+
+    /// Transport shared by every managed agent, so they use one connection pool
+    transport: Option<Arc<dyn ChatTransport>>,
     
     /// Persistence store for agent data
     store: Arc<dyn PersistenceStore>,
@@ -24,36 +253,209 @@ pub struct AgentManager {
     
     /// Maximum number of agents to keep in memory
     max_cached_agents: usize,
+
+    /// Whether agents save after every run
+    autosave: bool,
+
+    /// Optional circuit breaker guarding runs
+    breaker: Option<CircuitBreaker>,
+
+    /// Optional per-conversation message rate limit
+    rate_limiter: Option<RateLimiter>,
+
+    /// Channel every managed agent publishes its activity to
+    events: AgentEventSender,
 }
 
 impl AgentManager {
     /// Create a new agent manager
+    ///
+    /// The manager builds one `OpenAIClient` from `config` and shares it between
+    /// all of its agents, so they reuse a single connection pool. If the config
+    /// cannot produce a client (e.g. no API key), each agent tries to build its
+    /// own and reports the error.
     pub fn new(config: OpenAIConfig, store: Arc<dyn PersistenceStore>, max_cached_agents: usize) -> Self {
+        let transport = OpenAIClient::new(config.clone())
+            .ok()
+            .map(|client| Arc::new(client) as Arc<dyn ChatTransport>);
+
         Self {
             config,
+            transport,
             store,
             active_agents: RwLock::new(HashMap::new()),
             max_cached_agents,
+            autosave: true,
+            breaker: None,
+            rate_limiter: None,
+            events: broadcast::channel(DEFAULT_EVENT_CAPACITY).0,
+        }
+    }
+
+    /// Create an agent manager configured entirely from the environment.
+    ///
+    /// The default config comes from `OpenAIConfig::from_env` (`OPENAI_API_KEY`,
+    /// `OPENAI_ORG_ID`, `OPENAI_PROJECT_ID`), using the model in `AGIO_MODEL` if
+    /// set. `AGIO_MAX_CACHED_AGENTS` sets the cache size (default 100).
+    ///
+    /// Fails with `OpenAIAgentError::Config` if `OPENAI_API_KEY` is missing or a
+    /// variable holds an invalid value, so a misconfigured server stops at startup
+    /// rather than on its first request.
+    pub fn from_env(store: Arc<dyn PersistenceStore>) -> Result<Self, OpenAIAgentError> {
+        let mut config = OpenAIConfig::from_env();
+        if config.api_key().is_empty() {
+            return Err(OpenAIAgentError::Config(
+                "API key not provided; set OPENAI_API_KEY".to_string(),
+            ));
+        }
+        if let Some(model) = std::env::var("AGIO_MODEL").ok().filter(|model| !model.is_empty()) {
+            config = config.with_model(model);
+        }
+        if let Some(problem) = config.problems().into_iter().next() {
+            return Err(OpenAIAgentError::Config(problem));
+        }
+
+        let max_cached_agents = parse_max_cached_agents(std::env::var("AGIO_MAX_CACHED_AGENTS").ok().as_deref())?;
+        Ok(Self::new(config, store, max_cached_agents))
+    }
+
+    /// Use `transport` for every managed agent instead of the shared HTTP client.
+    ///
+    /// Must be called before any agent is created or loaded.
+    pub fn with_transport(mut self, transport: Arc<dyn ChatTransport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Set how many events are buffered for each `subscribe` stream (default 1024).
+    ///
+    /// A subscriber that falls further behind than this skips the oldest events.
+    /// Must be called before any agent is created or loaded.
+    pub fn with_event_capacity(mut self, capacity: usize) -> Self {
+        self.events = broadcast::channel(capacity.max(1)).0;
+        self
+    }
+
+    /// Subscribe to activity from every managed agent: messages added, turns
+    /// completed, tools executed and failed runs.
+    ///
+    /// Only events published after subscribing are received. Runs never wait for
+    /// subscribers; a subscriber that lags behind by more than the event capacity
+    /// skips the events it missed and continues with the oldest one still buffered.
+    pub fn subscribe(&self) -> impl Stream<Item = AgentEvent> + Send + 'static {
+        futures::stream::unfold(self.events.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    /// Set whether managed agents save after every run (default `true`).
+    ///
+    /// With autosave disabled, state is written by `flush`, by `spawn_periodic_flush`,
+    /// when an agent is evicted from the cache, and by `shutdown`. Call `shutdown`
+    /// before the program exits so no conversation is lost.
+    pub fn with_autosave(mut self, autosave: bool) -> Self {
+        self.autosave = autosave;
+        self
+    }
+    
+    /// Enable a circuit breaker for `run_message` and `run_message_delta`.
+    ///
+    /// After `failure_threshold` consecutive upstream failures (timeouts, rate
+    /// limits, 5xx responses) within `window`, runs fail immediately with
+    /// `OpenAIAgentError::Agent("circuit open")` for `cooldown`. Then one trial run
+    /// is let through: success closes the circuit, failure opens it again.
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.breaker = Some(CircuitBreaker::new(config));
+        self
+    }
+
+    /// Limit each conversation to `max_per_minute` messages per minute.
+    ///
+    /// Uses a token bucket per conversation: up to `max_per_minute` messages may
+    /// arrive in a burst, after which the allowance refills steadily. Once it is
+    /// used up, `run_message` and `run_message_delta` fail with
+    /// `OpenAIAgentError::Agent("rate limited")` without running the agent. This
+    /// protects the service independently of OpenAI's own rate limits.
+    pub fn with_rate_limit(mut self, max_per_minute: u32) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(max_per_minute));
+        self
+    }
+
+    /// Current circuit breaker state; always `Closed` if no breaker is enabled.
+    pub fn circuit_state(&self) -> CircuitState {
+        self.breaker
+            .as_ref()
+            .map_or(CircuitState::Closed, CircuitBreaker::state)
+    }
+
+    /// Check that the manager is ready to serve traffic.
+    ///
+    /// Pings the persistence store and, if `check_api_key` is set, also confirms
+    /// the API accepts the configured key (one request to the models endpoint).
+    /// Returns the first failure, so a dead database is noticed before the first
+    /// write fails.
+    pub async fn ready(&self, check_api_key: bool) -> Result<(), OpenAIAgentError> {
+        self.store.ping().await?;
+        if check_api_key {
+            OpenAIClient::new(self.config.clone())?.validate_api_key().await?;
         }
+        Ok(())
+    }
+
+    /// Counts a message against the conversation's rate limit, if enabled
+    fn check_rate_limit(&self, agent_id: &str) -> Result<(), OpenAIAgentError> {
+        match &self.rate_limiter {
+            Some(limiter) => limiter.acquire(agent_id),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs a message through the circuit breaker, if enabled
+    async fn run_guarded(&self, agent: &mut Agent, message: &str) -> Result<String, OpenAIAgentError> {
+        let Some(breaker) = &self.breaker else {
+            return agent.run(message).await;
+        };
+
+        breaker.acquire()?;
+        let result = agent.run(message).await;
+        breaker.record(&result);
+        result
     }
     
-    /// Create a new agent
-    pub async fn create_agent(&self) -> Result<EntityId, OpenAIAgentError> {
-        let agent = AgentBuilder::new()
+    /// Builder preconfigured with the manager's settings and shared transport
+    fn agent_builder(&self) -> AgentBuilder {
+        let builder = AgentBuilder::new()
             .with_config(self.config.clone())
             .with_persistence(self.store.clone())
-            .build_async()
-            .await?;
+            .with_autosave(self.autosave)
+            .with_event_sender(self.events.clone());
+
+        match &self.transport {
+            Some(transport) => builder.with_transport(transport.clone()),
+            None => builder,
+        }
+    }
+
+    /// Create a new agent
+    pub async fn create_agent(&self) -> Result<EntityId, OpenAIAgentError> {
+        let agent = self.agent_builder().build_async().await?;
             
         let id = agent.id().to_string();
         let agent = Arc::new(RwLock::new(agent));
         
         // Add to cache
-        {
+        let evicted = {
             let mut agents = self.active_agents.write().await;
             agents.insert(id.clone(), agent);
-            self.evict_if_needed(&mut agents).await;
-        }
+            self.evict_if_needed(&mut agents)
+        };
+        self.save_evicted(evicted).await?;
         
         Ok(id)
     }
@@ -69,35 +471,160 @@ impl AgentManager {
         }
         
         // Load agent from persistence
-        let agent = AgentBuilder::new()
-            .with_id(id)
-            .with_config(self.config.clone())
-            .with_persistence(self.store.clone())
-            .build_async()
-            .await?;
-            
-        // Check if agent was actually loaded
-        if agent.state().message_count() == 0 {
-            return Err(OpenAIAgentError::Agent(format!("Agent not found: {}", id)));
-        }
+        let agent = self
+            .load_agent(id)
+            .await?
+            .ok_or_else(|| OpenAIAgentError::Agent(format!("Agent not found: {}", id)))?;
         
         let agent = Arc::new(RwLock::new(agent));
         
         // Add to cache
-        {
+        let evicted = {
             let mut agents = self.active_agents.write().await;
             agents.insert(id.to_string(), agent.clone());
-            self.evict_if_needed(&mut agents).await;
-        }
+            self.evict_if_needed(&mut agents)
+        };
+        self.save_evicted(evicted).await?;
         
         Ok(agent)
     }
     
+    /// Load the given conversations into the cache with one batched store read,
+    /// so their first request does not pay for a store lookup.
+    ///
+    /// Ids that are already cached or have no stored conversation are skipped.
+    /// At most `max_cached_agents` conversations are loaded, so preloading never
+    /// evicts the ones it just loaded. Returns the number of agents loaded.
+    pub async fn preload(&self, ids: &[EntityId]) -> Result<usize, OpenAIAgentError> {
+        let pending: Vec<EntityId> = {
+            let agents = self.active_agents.read().await;
+            ids.iter()
+                .filter(|id| !agents.contains_key(id.as_str()))
+                .take(self.max_cached_agents)
+                .cloned()
+                .collect()
+        };
+
+        let loaded = self.load_agents(&pending).await?;
+        Ok(self.cache_loaded(loaded).await?.len())
+    }
+
+    /// Preload the `n` most recently updated conversations; see `preload`
+    pub async fn preload_recent(&self, n: usize) -> Result<usize, OpenAIAgentError> {
+        let ids: Vec<EntityId> = self
+            .store
+            .list_conversations(n.min(self.max_cached_agents), 0)
+            .await?
+            .into_iter()
+            .map(|metadata| metadata.id)
+            .collect();
+        self.preload(&ids).await
+    }
+
+    /// Get several agents by ID, loading the uncached ones with a single batched
+    /// store read (see `PersistenceStore::get_conversations`) instead of one
+    /// lookup per agent.
+    ///
+    /// Loaded agents are added to the cache as with `get_agent`. IDs with no
+    /// stored conversation are left out of the result.
+    pub async fn load_many(&self, ids: &[EntityId]) -> Result<HashMap<EntityId, Arc<RwLock<Agent>>>, OpenAIAgentError> {
+        let mut found = HashMap::with_capacity(ids.len());
+        let mut missing = Vec::new();
+        {
+            let agents = self.active_agents.read().await;
+            for id in ids {
+                match agents.get(id) {
+                    Some(agent) => {
+                        found.insert(id.clone(), agent.clone());
+                    }
+                    None => missing.push(id.clone()),
+                }
+            }
+        }
+
+        let loaded = self.load_agents(&missing).await?;
+        found.extend(self.cache_loaded(loaded).await?);
+        Ok(found)
+    }
+
+    /// Build agents for `ids` from one batched store read, skipping IDs without
+    /// a stored conversation
+    async fn load_agents(&self, ids: &[EntityId]) -> Result<Vec<(EntityId, Agent)>, OpenAIAgentError> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut states = self.store.get_conversations(ids).await?;
+        let mut agents = Vec::with_capacity(states.len());
+        for id in ids {
+            let Some(state) = states.remove(id).filter(|state| state.message_count() > 0) else {
+                continue;
+            };
+            let mut agent = self.agent_builder().with_id(id.as_str()).build()?;
+            agent.restore_state(state);
+            agents.push((id.clone(), agent));
+        }
+        Ok(agents)
+    }
+
+    /// Add freshly loaded agents to the cache, returning the cached handles
+    async fn cache_loaded(&self, loaded: Vec<(EntityId, Agent)>) -> Result<Vec<(EntityId, Arc<RwLock<Agent>>)>, OpenAIAgentError> {
+        let mut cached = Vec::with_capacity(loaded.len());
+        let evicted = {
+            let mut agents = self.active_agents.write().await;
+            for (id, agent) in loaded {
+                // A request may have loaded it in the meantime
+                let agent = agents
+                    .entry(id.clone())
+                    .or_insert_with(|| Arc::new(RwLock::new(agent)))
+                    .clone();
+                cached.push((id, agent));
+            }
+            self.evict_if_needed(&mut agents)
+        };
+        self.save_evicted(evicted).await?;
+        Ok(cached)
+    }
+
+    /// Load an agent from the store, or `None` if it has no stored conversation
+    async fn load_agent(&self, id: &str) -> Result<Option<Agent>, OpenAIAgentError> {
+        let agent = self.agent_builder().with_id(id).build_async().await?;
+        Ok((agent.state().message_count() > 0).then_some(agent))
+    }
+
     /// Run a message through an agent
     pub async fn run_message(&self, agent_id: &str, message: &str) -> Result<String, OpenAIAgentError> {
+        self.check_rate_limit(agent_id)?;
         let agent_lock = self.get_agent(agent_id).await?;
         let mut agent = agent_lock.write().await;
-        agent.run(message).await
+        self.run_guarded(&mut agent, message).await
+    }
+
+    /// Run a message through an agent, returning only the messages it added
+    /// and the new history version so clients can sync incrementally
+    pub async fn run_message_delta(&self, agent_id: &str, message: &str) -> Result<RunDelta, OpenAIAgentError> {
+        self.check_rate_limit(agent_id)?;
+        let agent_lock = self.get_agent(agent_id).await?;
+        let mut agent = agent_lock.write().await;
+        // Trimming and summarization during the run shift local positions, so
+        // count the added messages over the whole conversation
+        let start = agent.state().total_message_count();
+        let response = self.run_guarded(&mut agent, message).await?;
+
+        let state = agent.state();
+        let added = state.total_message_count().saturating_sub(start);
+        Ok(RunDelta {
+            response,
+            messages: state.messages_since(state.message_count().saturating_sub(added)).to_vec(),
+            version: state.version(),
+        })
+    }
+
+    /// Get the messages of an agent from `index` onwards, with the current history version
+    pub async fn messages_since(&self, agent_id: &str, index: usize) -> Result<(Vec<ChatMessage>, u64), OpenAIAgentError> {
+        let agent_lock = self.get_agent(agent_id).await?;
+        let agent = agent_lock.read().await;
+        Ok((agent.state().messages_since(index).to_vec(), agent.state().version()))
     }
     
     /// Delete an agent and its data
@@ -107,6 +634,9 @@ impl AgentManager {
             let mut agents = self.active_agents.write().await;
             agents.remove(id);
         }
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.remove(id);
+        }
         
         // Delete from storage
         self.store.delete_conversation(id).await
@@ -117,21 +647,258 @@ impl AgentManager {
         self.store.list_conversations(limit, offset).await
     }
     
-    /// Evict agents from cache if needed
-    async fn evict_if_needed(&self, agents: &mut HashMap<EntityId, Arc<RwLock<Agent>>>) {
+    /// Delete conversations last updated before `cutoff` and evict them from the cache.
+    ///
+    /// Returns the number of conversations deleted. Cached agents are evicted if
+    /// their conversation no longer exists in the store afterwards, which costs
+    /// one lookup per cached agent that has history.
+    pub async fn prune(&self, cutoff: chrono::DateTime<chrono::Utc>) -> Result<usize, OpenAIAgentError> {
+        let deleted = self.store.delete_older_than(cutoff).await?;
+        if deleted == 0 {
+            return Ok(0);
+        }
+        
+        let cached: Vec<_> = self
+            .active_agents
+            .read()
+            .await
+            .iter()
+            .map(|(id, agent)| (id.clone(), agent.clone()))
+            .collect();
+        
+        let mut pruned = Vec::new();
+        for (id, agent) in cached {
+            // Agents that were never saved are not affected by pruning
+            if agent.read().await.state().message_count() == 0 {
+                continue;
+            }
+            if self.store.get_conversation(&id).await?.is_none() {
+                pruned.push(id);
+            }
+        }
+        
+        let mut agents = self.active_agents.write().await;
+        for id in pruned {
+            agents.remove(&id);
+        }
+        
+        Ok(deleted)
+    }
+
+    /// Save every cached agent to the persistence store
+    pub async fn flush(&self) -> Result<(), OpenAIAgentError> {
+        let agents: Vec<_> = self.active_agents.read().await.values().cloned().collect();
+        for agent in agents {
+            agent.read().await.save().await?;
+        }
+        Ok(())
+    }
+
+    /// Spawn a background task that calls `flush` every `interval`.
+    ///
+    /// Flush errors are passed to `on_error` and do not stop the task; abort the
+    /// returned handle to stop it.
+    pub fn spawn_periodic_flush(
+        self: &Arc<Self>,
+        interval: Duration,
+        mut on_error: impl FnMut(OpenAIAgentError) + Send + 'static,
+    ) -> JoinHandle<()> {
+        let manager = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = manager.flush().await {
+                    on_error(e);
+                }
+            }
+        })
+    }
+
+    /// Save every cached agent and empty the cache; call before the program exits
+    pub async fn shutdown(&self) -> Result<(), OpenAIAgentError> {
+        self.flush().await?;
+        self.active_agents.write().await.clear();
+        Ok(())
+    }
+
+    /// Save agents removed from the cache, unless they already save after every run
+    async fn save_evicted(&self, evicted: Vec<Arc<RwLock<Agent>>>) -> Result<(), OpenAIAgentError> {
+        if self.autosave {
+            return Ok(());
+        }
+        for agent in evicted {
+            agent.read().await.save().await?;
+        }
+        Ok(())
+    }
+    
+    /// Evict agents from cache if needed, returning the evicted agents
+    fn evict_if_needed(&self, agents: &mut HashMap<EntityId, Arc<RwLock<Agent>>>) -> Vec<Arc<RwLock<Agent>>> {
         if agents.len() <= self.max_cached_agents {
-            return;
+            return Vec::new();
+        }
+
+        // Buckets of idle conversations go with them; a bucket that is still
+        // draining is kept, so eviction never resets a conversation's limit
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.collect_garbage();
         }
         
         // Simple LRU-like eviction - remove oldest entries first
         // In a real implementation, you'd want to track last access time
-        if agents.len() > self.max_cached_agents {
-            let keys: Vec<_> = agents.keys().cloned().collect();
-            let to_remove = keys.len() - self.max_cached_agents;
-            
-            for key in keys.into_iter().take(to_remove) {
-                agents.remove(&key);
-            }
+        let keys: Vec<_> = agents.keys().cloned().collect();
+        let to_remove = keys.len() - self.max_cached_agents;
+        
+        keys.into_iter()
+            .take(to_remove)
+            .filter_map(|key| agents.remove(&key))
+            .collect()
+    }
+} 
+
+/// Reads the `AGIO_MAX_CACHED_AGENTS` value, falling back to the default when unset
+fn parse_max_cached_agents(value: Option<&str>) -> Result<usize, OpenAIAgentError> {
+    match value.map(str::trim).filter(|value| !value.is_empty()) {
+        None => Ok(DEFAULT_MAX_CACHED_AGENTS),
+        Some(value) => value.parse::<usize>().ok().filter(|max| *max > 0).ok_or_else(|| {
+            OpenAIAgentError::Config(format!(
+                "AGIO_MAX_CACHED_AGENTS must be a positive integer, got '{}'",
+                value
+            ))
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_buckets() {
+        let limiter = RateLimiter::new(2);
+        limiter.acquire("a").unwrap();
+        limiter.acquire("a").unwrap();
+        assert!(matches!(limiter.acquire("a"), Err(OpenAIAgentError::Agent(message)) if message == "rate limited"));
+        // Conversations have separate buckets
+        limiter.acquire("b").unwrap();
+
+        // A draining bucket survives garbage collection
+        limiter.collect_garbage();
+        assert_eq!(limiter.lock().len(), 2);
+
+        // Half a minute refills one message at 2 per minute
+        for bucket in limiter.lock().values_mut() {
+            bucket.updated -= Duration::from_secs(30);
         }
+        limiter.acquire("a").unwrap();
+        assert!(limiter.acquire("a").is_err());
+
+        // "b" has refilled completely and is dropped
+        limiter.collect_garbage();
+        assert_eq!(limiter.lock().keys().collect::<Vec<_>>(), vec!["a"]);
+        limiter.remove("a");
+        assert!(limiter.lock().is_empty());
+    }
+
+    fn upstream_error() -> Result<(), OpenAIAgentError> {
+        Err(OpenAIAgentError::Api { status: 503, message: "unavailable".to_string() })
+    }
+
+    #[test]
+    fn test_circuit_breaker_opens_and_recovers() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_millis(20),
+        });
+
+        for _ in 0..2 {
+            breaker.acquire().unwrap();
+            breaker.record(&upstream_error());
+        }
+        assert!(matches!(breaker.state(), CircuitState::Open { .. }));
+        assert!(breaker.acquire().is_err());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.acquire().unwrap();
+        // Only one trial request at a time
+        assert!(breaker.acquire().is_err());
+
+        breaker.record(&Ok::<(), OpenAIAgentError>(()));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_circuit_breaker_ignores_client_errors() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            ..Default::default()
+        });
+
+        breaker.record(&Err::<(), _>(OpenAIAgentError::Api { status: 400, message: "bad".to_string() }));
+        assert_eq!(breaker.state(), CircuitState::Closed);
     }
-} 
\ No newline at end of file
+
+    /// Puts an agent built by `configure` into the manager's cache under `id`
+    async fn insert_agent(manager: &AgentManager, id: &str, configure: impl FnOnce(AgentBuilder) -> AgentBuilder) {
+        let agent = configure(manager.agent_builder().with_id(id)).build().unwrap();
+        manager.active_agents.write().await.insert(id.to_string(), Arc::new(RwLock::new(agent)));
+    }
+
+    #[test]
+    fn test_run_message_delta_survives_history_rewrites() {
+        use crate::agent::SummarizeConfig;
+        use crate::persistence::MemoryStore;
+        use crate::transport::MockTransport;
+
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let transport = Arc::new(MockTransport::new());
+            for response in ["One", "Two", "Three", "First answer", "Summary", "Second answer"] {
+                transport.push_response(MockTransport::text_response(response));
+            }
+            let manager = AgentManager::new(OpenAIConfig::new(), Arc::new(MemoryStore::new()), 10)
+                .with_transport(transport);
+
+            // Trimming drops older messages during each run
+            insert_agent(&manager, "capped", |builder| builder.with_max_history_messages(2)).await;
+            for (question, answer) in [("First", "One"), ("Second", "Two"), ("Third", "Three")] {
+                let delta = manager.run_message_delta("capped", question).await.unwrap();
+                let texts: Vec<_> = delta.messages.iter().map(|m| m.text().unwrap_or_default()).collect();
+                assert_eq!(texts, [question, answer]);
+            }
+
+            // Summarization replaces older messages with a summary during the run
+            insert_agent(&manager, "summarized", |builder| {
+                builder.with_auto_summarize(SummarizeConfig {
+                    trigger_tokens: usize::MAX,
+                    trigger_messages: Some(2),
+                    keep_recent: 1,
+                    ..Default::default()
+                })
+            })
+            .await;
+            manager.run_message_delta("summarized", "First").await.unwrap();
+            let delta = manager.run_message_delta("summarized", "Second").await.unwrap();
+            let texts: Vec<_> = delta.messages.iter().map(|m| m.text().unwrap_or_default()).collect();
+            assert_eq!(texts, ["Second", "Second answer"]);
+
+            let agent = manager.get_agent("summarized").await.unwrap();
+            let agent = agent.read().await;
+            assert_eq!(agent.state().message_count(), 3);
+            assert_eq!(agent.state().total_message_count(), 4);
+        });
+    }
+
+    #[test]
+    fn test_parse_max_cached_agents() {
+        assert_eq!(parse_max_cached_agents(None).unwrap(), DEFAULT_MAX_CACHED_AGENTS);
+        assert_eq!(parse_max_cached_agents(Some("")).unwrap(), DEFAULT_MAX_CACHED_AGENTS);
+        assert_eq!(parse_max_cached_agents(Some(" 250 ")).unwrap(), 250);
+        assert!(matches!(parse_max_cached_agents(Some("0")), Err(OpenAIAgentError::Config(_))));
+        assert!(matches!(parse_max_cached_agents(Some("lots")), Err(OpenAIAgentError::Config(_))));
+    }
+}
@@ -0,0 +1,337 @@
+//! Support for streamed chat completions.
+//!
+//! This module parses the server-sent events returned by the Chat Completions API
+//! when `stream` is enabled, and assembles the resulting chunks back into a
+//! complete assistant message so the agent can treat streamed and non-streamed
+//! turns the same way.
+
+use crate::error::OpenAIAgentError;
+use crate::models::{
+    ChatChoice, ChatCompletionChunk, ChatDelta, ChatMessage, ChatResponse, ChunkChoice,
+    FunctionCall, FunctionCallDelta, LogProbs, MessageContent, ToolCall, ToolCallDelta, Usage,
+};
+use futures::stream::{self, Stream, StreamExt};
+use std::pin::Pin;
+
+/// A stream of chat completion chunks.
+pub type ChunkStream = Pin<Box<dyn Stream<Item = Result<ChatCompletionChunk, OpenAIAgentError>> + Send>>;
+
+/// Parses a server-sent event byte stream into chat completion chunks.
+///
+/// Each `data:` line carries one JSON chunk; the stream ends at `data: [DONE]`
+/// or when the underlying byte stream is exhausted.
+pub(crate) fn parse_sse<S, B, E>(bytes: S) -> ChunkStream
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]>,
+    E: Into<OpenAIAgentError>,
+{
+    struct SseState<S> {
+        bytes: Pin<Box<S>>,
+        buffer: Vec<u8>,
+        eof: bool,
+        done: bool,
+    }
+
+    let state = SseState {
+        bytes: Box::pin(bytes),
+        buffer: Vec::new(),
+        eof: false,
+        done: false,
+    };
+
+    Box::pin(stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+
+        loop {
+            if let Some(pos) = state.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = state.buffer.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    // Blank separators, comments and other fields carry no chunk
+                    continue;
+                };
+
+                let data = data.trim();
+                if data == "[DONE]" {
+                    return None;
+                }
+
+                let chunk = serde_json::from_str::<ChatCompletionChunk>(data).map_err(|e| {
+                    OpenAIAgentError::Parse(format!("Failed to parse stream chunk: {}", e))
+                });
+                return Some((chunk, state));
+            }
+
+            if state.eof {
+                return None;
+            }
+
+            match state.bytes.next().await {
+                Some(Ok(bytes)) => state.buffer.extend_from_slice(bytes.as_ref()),
+                Some(Err(e)) => {
+                    state.done = true;
+                    return Some((Err(e.into()), state));
+                }
+                None => {
+                    // Flush a final line that was not newline-terminated
+                    state.eof = true;
+                    state.buffer.push(b'\n');
+                }
+            }
+        }
+    }))
+}
+
+/// Converts a complete response into a single equivalent chunk.
+///
+/// Used by transports that do not support streaming, so the streaming code path
+/// works with them unchanged.
+pub(crate) fn response_into_chunk(response: ChatResponse) -> ChatCompletionChunk {
+    let choices = response
+        .choices
+        .into_iter()
+        .map(|choice| {
+            let tool_calls = choice.message.tool_calls.map(|calls| {
+                calls
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, call)| ToolCallDelta {
+                        index,
+                        id: Some(call.id.clone()),
+                        call_type: Some(call.call_type.clone()),
+                        function: Some(FunctionCallDelta {
+                            name: Some(call.get_name()),
+                            arguments: Some(call.get_arguments()),
+                        }),
+                    })
+                    .collect()
+            });
+
+            ChunkChoice {
+                index: choice.index,
+                delta: ChatDelta {
+                    role: Some(choice.message.role),
+                    content: choice.message.content.map(|content| content.to_text()),
+                    tool_calls,
+                    refusal: choice.message.refusal,
+                },
+                finish_reason: Some(choice.finish_reason),
+                logprobs: choice.logprobs,
+            }
+        })
+        .collect();
+
+    ChatCompletionChunk {
+        id: response.id,
+        object: "chat.completion.chunk".to_string(),
+        created: response.created,
+        model: response.model,
+        choices,
+        usage: response.usage,
+    }
+}
+
+/// Consumes a chunk stream and assembles the complete response.
+pub(crate) async fn collect_stream(mut chunks: ChunkStream) -> Result<ChatResponse, OpenAIAgentError> {
+    let mut accumulator = StreamAccumulator::default();
+    while let Some(chunk) = chunks.next().await {
+        accumulator.push(chunk?);
+    }
+    Ok(accumulator.finish())
+}
+
+/// Assembles streamed chunks of the first choice into a complete response.
+#[derive(Debug, Default)]
+pub(crate) struct StreamAccumulator {
+    id: String,
+    created: u64,
+    model: String,
+    role: Option<String>,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    refusal: String,
+    finish_reason: Option<String>,
+    logprobs: Option<LogProbs>,
+    usage: Option<Usage>,
+}
+
+impl StreamAccumulator {
+    /// Applies a chunk, returning the content fragment it added, if any
+    pub(crate) fn push(&mut self, chunk: ChatCompletionChunk) -> Option<String> {
+        if self.id.is_empty() {
+            self.id = chunk.id;
+            self.created = chunk.created;
+            self.model = chunk.model;
+        }
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage;
+        }
+
+        let choice = chunk.choices.into_iter().find(|choice| choice.index == 0)?;
+
+        if choice.finish_reason.is_some() {
+            self.finish_reason = choice.finish_reason;
+        }
+        if let Some(logprobs) = choice.logprobs {
+            match &mut self.logprobs {
+                Some(existing) => {
+                    if let Some(content) = logprobs.content {
+                        existing.content.get_or_insert_with(Vec::new).extend(content);
+                    }
+                }
+                None => self.logprobs = Some(logprobs),
+            }
+        }
+
+        let delta = choice.delta;
+        if delta.role.is_some() {
+            self.role = delta.role;
+        }
+
+        for fragment in delta.tool_calls.unwrap_or_default() {
+            while self.tool_calls.len() <= fragment.index {
+                self.tool_calls.push(ToolCall {
+                    id: String::new(),
+                    call_type: "function".to_string(),
+                    function: FunctionCall::default(),
+                    name: None,
+                    arguments: None,
+                });
+            }
+
+            let call = &mut self.tool_calls[fragment.index];
+            if let Some(id) = fragment.id {
+                call.id = id;
+            }
+            if let Some(call_type) = fragment.call_type {
+                call.call_type = call_type;
+            }
+            if let Some(function) = fragment.function {
+                if let Some(name) = function.name {
+                    call.function.name.push_str(&name);
+                }
+                if let Some(arguments) = function.arguments {
+                    call.function.arguments.push_str(&arguments);
+                }
+            }
+        }
+
+        if let Some(refusal) = delta.refusal {
+            self.refusal.push_str(&refusal);
+        }
+
+        let content = delta.content.filter(|content| !content.is_empty())?;
+        self.content.push_str(&content);
+        Some(content)
+    }
+
+    /// Content received so far
+    pub(crate) fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// The assistant message as received so far
+    pub(crate) fn message(&self) -> ChatMessage {
+        ChatMessage {
+            role: self.role.clone().unwrap_or_else(|| "assistant".to_string()),
+            content: (!self.content.is_empty()).then(|| MessageContent::Text(self.content.clone())),
+            tool_calls: (!self.tool_calls.is_empty()).then(|| self.tool_calls.clone()),
+            refusal: (!self.refusal.is_empty()).then(|| self.refusal.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Finishes the stream, producing the equivalent complete response
+    pub(crate) fn finish(self) -> ChatResponse {
+        let message = self.message();
+        ChatResponse {
+            id: self.id,
+            object: "chat.completion".to_string(),
+            created: self.created,
+            model: self.model,
+            choices: vec![ChatChoice {
+                index: 0,
+                message,
+                tool_calls: Vec::new(),
+                finish_reason: self.finish_reason.unwrap_or_else(|| "stop".to_string()),
+                logprobs: self.logprobs,
+            }],
+            usage: self.usage,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sse_and_accumulate() {
+        let body = concat!(
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hel\"}}]}\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"},",
+            "\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        // Split mid-line to exercise buffering across byte chunks
+        let (first, second) = body.split_at(40);
+        let bytes = stream::iter(vec![
+            Ok::<_, OpenAIAgentError>(first.as_bytes().to_vec()),
+            Ok(second.as_bytes().to_vec()),
+        ]);
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let chunks: Vec<_> = rt.block_on(parse_sse(bytes).collect());
+        assert_eq!(chunks.len(), 2);
+
+        let mut accumulator = StreamAccumulator::default();
+        let deltas: Vec<_> = chunks
+            .into_iter()
+            .filter_map(|chunk| accumulator.push(chunk.unwrap()))
+            .collect();
+        assert_eq!(deltas, vec!["Hel", "lo"]);
+
+        let response = accumulator.finish();
+        assert_eq!(response.choices[0].message.text().as_deref(), Some("Hello"));
+        assert_eq!(response.choices[0].finish_reason, "stop");
+    }
+
+    #[test]
+    fn test_accumulate_tool_call_fragments() {
+        let mut accumulator = StreamAccumulator::default();
+        let fragments = [
+            r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"reverse","arguments":""}}]}}]}"#,
+            r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"text\":"}}]}}]}"#,
+            r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"abc\"}"}}]},"finish_reason":"tool_calls"}]}"#,
+        ];
+        for fragment in fragments {
+            assert!(accumulator.push(serde_json::from_str(fragment).unwrap()).is_none());
+        }
+
+        let response = accumulator.finish();
+        let calls = response.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].function.name, "reverse");
+        assert_eq!(calls[0].function.arguments, r#"{"text":"abc"}"#);
+        assert_eq!(response.choices[0].finish_reason, "tool_calls");
+    }
+
+    #[test]
+    fn test_accumulate_refusal() {
+        let mut accumulator = StreamAccumulator::default();
+        let fragments = [
+            r#"{"choices":[{"index":0,"delta":{"role":"assistant","refusal":"I can't "}}]}"#,
+            r#"{"choices":[{"index":0,"delta":{"refusal":"help with that."},"finish_reason":"stop"}]}"#,
+        ];
+        for fragment in fragments {
+            assert!(accumulator.push(serde_json::from_str(fragment).unwrap()).is_none());
+        }
+
+        let message = &accumulator.finish().choices[0].message;
+        assert!(message.content.is_none());
+        assert_eq!(message.refusal.as_deref(), Some("I can't help with that."));
+    }
+}
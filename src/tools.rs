@@ -5,12 +5,13 @@
 //! the agent with capabilities to interact with external systems or perform specific tasks.
 
 use crate::error::OpenAIAgentError;
-use crate::models::{ToolDefinition, ToolSpec};
+use crate::models::{validate_name, ToolCall, ToolDefinition, ToolSpec};
 use async_trait::async_trait;
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
-use std::fmt::Debug;
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug};
 use std::future::Future;
 use std::marker::PhantomData;
 use std::sync::Arc;
@@ -37,6 +38,381 @@ pub trait RegisteredTool: Send + Sync {
     ///
     /// A Result containing either the tool's output as a string or an error
     async fn execute(&self, arguments: Value) -> Result<String, OpenAIAgentError>;
+
+    /// Executes the tool with access to the values registered with
+    /// `AgentBuilder::with_context_value`.
+    ///
+    /// This is what the agent calls. The default implementation ignores the
+    /// context and calls `execute`; override it in tools that need shared
+    /// resources such as a database pool or the current user's session.
+    async fn execute_with_context(
+        &self,
+        arguments: Value,
+        _context: &ToolContext<'_>,
+    ) -> Result<String, OpenAIAgentError> {
+        self.execute(arguments).await
+    }
+
+    /// Whether a call to this tool pauses the run to wait for the user.
+    ///
+    /// When `true`, the agent does not execute the tool. It ends the run and returns
+    /// the call's `question` argument in `RunOutcome::needs_user_input`, and the input
+    /// of the next run is sent to the model as the tool's result. See `AskUser`.
+    fn pauses_for_user(&self) -> bool {
+        false
+    }
+}
+
+/// Values available to tools, keyed by their type.
+///
+/// Holds at most one value per type, like `http::Extensions`. Values are shared
+/// behind an `Arc`, so cloning the map is cheap.
+#[derive(Clone, Default)]
+pub struct ContextMap {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl ContextMap {
+    /// Creates an empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a value, replacing any earlier value of the same type.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Returns the value of type `T`, if one was inserted.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>()).and_then(|value| value.downcast_ref())
+    }
+
+    /// Returns whether a value of type `T` was inserted.
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns whether the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl Debug for ContextMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ContextMap").field("len", &self.values.len()).finish()
+    }
+}
+
+/// What a tool knows about the call it is executing, passed to
+/// `RegisteredTool::execute_with_context`.
+#[derive(Debug, Clone, Copy)]
+pub struct ToolContext<'a> {
+    tool_call_id: &'a str,
+    values: &'a ContextMap,
+}
+
+impl<'a> ToolContext<'a> {
+    /// Creates a context for a tool call.
+    pub fn new(tool_call_id: &'a str, values: &'a ContextMap) -> Self {
+        Self { tool_call_id, values }
+    }
+
+    /// Returns the ID of the tool call being executed.
+    pub fn tool_call_id(&self) -> &'a str {
+        self.tool_call_id
+    }
+
+    /// Returns the context value of type `T`, or `None` if the agent has none.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&'a T> {
+        self.values.get()
+    }
+}
+
+/// How tool output is written into the `tool` message sent back to the model.
+///
+/// JSON formatting only applies to output that parses as JSON; anything else is
+/// passed through unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToolResultFormatter {
+    /// The tool's output exactly as returned
+    #[default]
+    Raw,
+
+    /// JSON output re-serialized with indentation, which some models parse more reliably
+    PrettyJson,
+
+    /// JSON output re-serialized without whitespace, to save context
+    Minified,
+
+    /// Output wrapped as `{"tool": <name>, "result": <output>}`, embedding JSON
+    /// output as JSON and anything else as a string
+    WithToolName,
+}
+
+impl ToolResultFormatter {
+    /// Formats a tool's output for the model.
+    pub(crate) fn format(&self, tool_name: &str, output: String) -> String {
+        let json = || serde_json::from_str::<Value>(&output).ok();
+        match self {
+            Self::Raw => output,
+            Self::PrettyJson => json()
+                .and_then(|value| serde_json::to_string_pretty(&value).ok())
+                .unwrap_or(output),
+            Self::Minified => json()
+                .and_then(|value| serde_json::to_string(&value).ok())
+                .unwrap_or(output),
+            Self::WithToolName => {
+                let result = json().unwrap_or_else(|| Value::String(output.clone()));
+                serde_json::json!({ "tool": tool_name, "result": result }).to_string()
+            }
+        }
+    }
+}
+
+/// A tool failure with a machine-readable type, returned to the model as
+/// `{"error": {"type": ..., "message": ...}}`.
+///
+/// Tools return it through `OpenAIAgentError::ToolFailed` (it converts with `?` or
+/// `.into()`). With `AgentBuilder::with_structured_tool_errors`, every tool failure
+/// is reported to the model in this shape instead of ending the run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolError {
+    /// Short category such as `not_found` or `invalid_arguments`
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    /// Human-readable description of what went wrong
+    pub message: String,
+}
+
+impl ToolError {
+    /// Creates an error of the given type.
+    pub fn new(kind: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            kind: kind.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Returns the JSON content of the `tool` message sent to the model.
+    pub fn to_content(&self) -> String {
+        serde_json::json!({ "error": self }).to_string()
+    }
+
+    /// Describes any agent error as a tool error, keeping structured ones as they are.
+    pub(crate) fn from_agent_error(error: OpenAIAgentError) -> Self {
+        match error {
+            OpenAIAgentError::ToolFailed(error) => error,
+            OpenAIAgentError::Tool(message) => Self::new("tool_error", message),
+            OpenAIAgentError::Parse(message) => Self::new("invalid_arguments", message),
+            other => Self::new("execution_error", other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+/// Built-in tool that lets the model ask the user a clarifying question.
+///
+/// Calling it pauses the run: `Agent::run_detailed` returns the question in
+/// `RunOutcome::needs_user_input`, and the next `run` call delivers the user's
+/// answer to the model. Only one question is asked per pause: further
+/// `ask_user` calls in the same response get a tool result asking the model to
+/// wait for the answer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AskUser;
+
+impl AskUser {
+    /// Name of the tool as seen by the model
+    pub const NAME: &'static str = "ask_user";
+}
+
+#[async_trait]
+impl RegisteredTool for AskUser {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: "Ask the user a clarifying question and wait for their answer. \
+                Use this only when the request is ambiguous or missing information you need."
+                .to_string(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "question": {
+                        "type": "string",
+                        "description": "The question to ask the user"
+                    }
+                },
+                "required": ["question"],
+                "additionalProperties": false
+            }),
+            strict: Some(true),
+        }
+    }
+
+    async fn execute(&self, arguments: Value) -> Result<String, OpenAIAgentError> {
+        // Only reached if the tool is executed directly; the agent pauses instead
+        Ok(arguments["question"].as_str().unwrap_or_default().to_string())
+    }
+
+    fn pauses_for_user(&self) -> bool {
+        true
+    }
+}
+
+/// Validates tool arguments against a tool's declared `parameters` schema.
+///
+/// Returns a description of every violation on failure, suitable for feeding
+/// back to the model so it can correct the call.
+pub(crate) fn validate_arguments(schema: &Value, arguments: &Value) -> Result<(), String> {
+    let validator = jsonschema::validator_for(schema)
+        .map_err(|e| format!("Invalid tool parameter schema: {}", e))?;
+
+    let errors: Vec<String> = validator
+        .iter_errors(arguments)
+        .map(|e| {
+            let path = e.instance_path.to_string();
+            if path.is_empty() {
+                e.to_string()
+            } else {
+                format!("{}: {}", path, e)
+            }
+        })
+        .collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+/// Checks that a tool's `parameters` is a usable JSON Schema for an object.
+pub(crate) fn check_parameters_schema(schema: &Value) -> Result<(), String> {
+    if !schema.is_object() {
+        return Err("parameters must be a JSON object".to_string());
+    }
+    if let Some(schema_type) = schema.get("type")
+        && schema_type != "object"
+    {
+        return Err(format!("parameters must have type \"object\", found {}", schema_type));
+    }
+    jsonschema::validator_for(schema)
+        .map(|_| ())
+        .map_err(|e| format!("invalid JSON Schema: {}", e))
+}
+
+/// Keywords OpenAI's strict mode rejects, with no equivalent to rewrite them to.
+const STRICT_UNSUPPORTED_KEYWORDS: &[&str] = &[
+    "patternProperties",
+    "unevaluatedProperties",
+    "propertyNames",
+    "dependentRequired",
+    "dependentSchemas",
+    "prefixItems",
+    "contains",
+    "not",
+    "if",
+    "then",
+    "else",
+];
+
+/// Rewrites a schema in place to meet OpenAI's strict mode requirements,
+/// returning whether it could be made to comply.
+///
+/// Every object schema, including nested ones and those in `$defs`, gets
+/// `additionalProperties: false` and lists all of its properties in `required`
+/// (optional fields stay nullable, so the model can pass `null`). `oneOf` becomes
+/// `anyOf`, and a single-element `allOf` is merged into its parent. Maps, tuples,
+/// multi-element `allOf` and the keywords in `STRICT_UNSUPPORTED_KEYWORDS` cannot
+/// be expressed and make the schema non-compliant.
+pub(crate) fn make_strict_schema(schema: &mut Value) -> bool {
+    let Some(object) = schema.as_object_mut() else {
+        // `true`/`false` schemas accept anything or nothing
+        return false;
+    };
+
+    if STRICT_UNSUPPORTED_KEYWORDS.iter().any(|keyword| object.contains_key(*keyword)) {
+        return false;
+    }
+
+    if let Some(all_of) = object.remove("allOf") {
+        match all_of {
+            Value::Array(mut parts) if parts.len() == 1 => {
+                if let Value::Object(part) = parts.remove(0) {
+                    for (key, value) in part {
+                        object.entry(key).or_insert(value);
+                    }
+                }
+            }
+            _ => return false,
+        }
+    }
+    if let Some(one_of) = object.remove("oneOf") {
+        object.insert("anyOf".to_string(), one_of);
+    }
+
+    let is_object = object.contains_key("properties")
+        || match object.get("type") {
+            Some(Value::String(t)) => t == "object",
+            Some(Value::Array(types)) => types.iter().any(|t| t == "object"),
+            _ => false,
+        };
+    if is_object {
+        match object.get("additionalProperties") {
+            None | Some(Value::Bool(false)) => {}
+            // A map with arbitrary keys
+            Some(_) => return false,
+        }
+        object.insert("additionalProperties".to_string(), Value::Bool(false));
+
+        let properties = object
+            .entry("properties")
+            .or_insert_with(|| Value::Object(Default::default()));
+        let Some(properties) = properties.as_object_mut() else {
+            return false;
+        };
+        let required: Vec<Value> = properties.keys().cloned().map(Value::String).collect();
+        for property in properties.values_mut() {
+            if !make_strict_schema(property) {
+                return false;
+            }
+        }
+        object.insert("required".to_string(), Value::Array(required));
+    }
+
+    if let Some(items) = object.get_mut("items")
+        && !make_strict_schema(items)
+    {
+        return false;
+    }
+    if let Some(Value::Array(variants)) = object.get_mut("anyOf")
+        && !variants.iter_mut().all(make_strict_schema)
+    {
+        return false;
+    }
+    for defs in ["$defs", "definitions"] {
+        if let Some(Value::Object(definitions)) = object.get_mut(defs)
+            && !definitions.values_mut().all(make_strict_schema)
+        {
+            return false;
+        }
+    }
+
+    true
 }
 
 /// A wrapper that turns a function into a tool.
@@ -109,6 +485,17 @@ where
             })
         });
 
+        // Use strict mode when the schema can be made to satisfy its constraints
+        let mut strict_schema = schema_value.clone();
+        if make_strict_schema(&mut strict_schema) {
+            return ToolDefinition {
+                name: self.name.clone(),
+                description: self.description.clone(),
+                parameters: strict_schema,
+                strict: Some(true),
+            };
+        }
+
         // Ensure schema is an object
         if let Some(schema_obj) = schema_value.as_object_mut() {
             // Explicitly add additionalProperties: false at the top level
@@ -122,7 +509,7 @@ where
             name: self.name.clone(),
             description: self.description.clone(),
             parameters: schema_value,
-            strict: Some(true),
+            strict: None,
         }
     }
 
@@ -143,6 +530,86 @@ where
     }
 }
 
+/// A tool with a ready-made definition, executed by a closure over the raw arguments.
+///
+/// Created by `ToolRegistry::register_with_definition`.
+struct DefinedTool<F> {
+    definition: ToolDefinition,
+    function: F,
+}
+
+#[async_trait]
+impl<F, Fut, R> RegisteredTool for DefinedTool<F>
+where
+    F: Fn(Value) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<R, OpenAIAgentError>> + Send + 'static,
+    R: ToString + Send + 'static,
+{
+    fn definition(&self) -> ToolDefinition {
+        self.definition.clone()
+    }
+
+    async fn execute(&self, arguments: Value) -> Result<String, OpenAIAgentError> {
+        let result = (self.function)(arguments).await?;
+        Ok(result.to_string())
+    }
+}
+
+/// A built-in tool that OpenAI runs server-side, registered with
+/// `ToolRegistry::register_hosted`.
+///
+/// Hosted tools have no local implementation: the agent offers them to the model
+/// and never executes their calls itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostedTool {
+    /// Web search (`web_search_preview`)
+    WebSearch,
+
+    /// Search over the given vector stores (`file_search`)
+    FileSearch {
+        /// IDs of the vector stores to search
+        vector_store_ids: Vec<String>,
+    },
+}
+
+impl HostedTool {
+    /// The tool's `type` in API requests.
+    pub fn tool_type(&self) -> &'static str {
+        match self {
+            HostedTool::WebSearch => "web_search_preview",
+            HostedTool::FileSearch { .. } => "file_search",
+        }
+    }
+
+    /// The tool as sent in the request's `tools` list.
+    pub fn spec(&self) -> ToolSpec {
+        let mut options = serde_json::Map::new();
+        if let HostedTool::FileSearch { vector_store_ids } = self {
+            options.insert("vector_store_ids".to_string(), Value::from(vector_store_ids.clone()));
+        }
+        ToolSpec {
+            r#type: self.tool_type().to_string(),
+            function: None,
+            options,
+        }
+    }
+}
+
+/// What `ToolRegistry::merge` and `ToolRegistry::extend` do with a tool whose
+/// name is already registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToolConflict {
+    /// Fail without adding any of the tools
+    #[default]
+    Error,
+
+    /// Replace the registered tool, keeping its position
+    Overwrite,
+
+    /// Keep the registered tool and skip the new one
+    KeepFirst,
+}
+
 /// Registry for managing the tools available to an agent.
 ///
 /// This struct stores all the tools that can be used by the agent,
@@ -151,6 +618,24 @@ where
 pub struct ToolRegistry {
     /// Map of tool names to their implementations
     tools: HashMap<String, Arc<dyn RegisteredTool>>,
+
+    /// Tool names in registration order, so definitions are sent in a stable order
+    order: Vec<String>,
+
+    /// Names that were registered more than once
+    duplicates: Vec<String>,
+
+    /// Names the API would reject, found at registration
+    invalid_names: Vec<String>,
+
+    /// Priorities set with `set_priority`; unlisted tools have priority 0
+    priorities: HashMap<String, i32>,
+
+    /// Server-side tools, in registration order
+    hosted: Vec<HostedTool>,
+
+    /// How `merge` and `extend` resolve name conflicts
+    conflict: ToolConflict,
 }
 
 impl ToolRegistry {
@@ -158,6 +643,12 @@ impl ToolRegistry {
     pub fn new() -> Self {
         Self {
             tools: HashMap::new(),
+            order: Vec::new(),
+            duplicates: Vec::new(),
+            invalid_names: Vec::new(),
+            priorities: HashMap::new(),
+            hosted: Vec::new(),
+            conflict: ToolConflict::default(),
         }
     }
 
@@ -170,8 +661,22 @@ impl ToolRegistry {
     where
         T: RegisteredTool + 'static,
     {
+        self.insert(Arc::new(tool));
+    }
+
+    /// Adds a tool, recording a repeated name as a duplicate for `validate`.
+    fn insert(&mut self, tool: Arc<dyn RegisteredTool>) {
         let definition = tool.definition();
-        self.tools.insert(definition.name.clone(), Arc::new(tool));
+        // Checked once here; tool messages then reuse the name as is
+        if validate_name(&definition.name).is_err() && !self.invalid_names.contains(&definition.name) {
+            self.invalid_names.push(definition.name.clone());
+        }
+        // Re-registering a name replaces the tool but keeps its original position
+        if self.tools.insert(definition.name.clone(), tool).is_none() {
+            self.order.push(definition.name);
+        } else if !self.duplicates.contains(&definition.name) {
+            self.duplicates.push(definition.name);
+        }
     }
 
     /// Registers a function as a tool.
@@ -204,6 +709,217 @@ impl ToolRegistry {
         self
     }
 
+    /// Registers a function as a tool, appending a usage hint to its description.
+    ///
+    /// The hint (e.g. "Prefer this for numeric questions.") helps the model choose
+    /// between similar tools when many are registered.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the tool
+    /// * `description` - A description of what the tool does
+    /// * `hint` - When the model should prefer this tool
+    /// * `function` - The function to execute
+    ///
+    /// # Returns
+    ///
+    /// A reference to self for method chaining
+    pub fn register_fn_with_hint<F, Args, Fut, R>(
+        &mut self,
+        name: impl Into<String>,
+        description: impl Into<String>,
+        hint: impl Into<String>,
+        function: F,
+    ) -> &mut Self
+    where
+        F: Fn(Args) -> Fut + Send + Sync + Clone + 'static,
+        Args: DeserializeOwned + Serialize + Debug + Send + Sync + 'static + schemars::JsonSchema,
+        Fut: Future<Output = Result<R, OpenAIAgentError>> + Send + 'static + std::marker::Sync,
+        R: ToString + Send + Sync + 'static,
+    {
+        let description = description.into();
+        let hint = hint.into();
+        let description = match (description.trim_end(), hint.trim()) {
+            (description, "") => description.to_string(),
+            ("", hint) => hint.to_string(),
+            (description, hint) => format!("{} {}", description, hint),
+        };
+        self.register_fn(name, description, function)
+    }
+
+    /// Registers a tool from an existing definition, such as one loaded from a
+    /// JSON file shared with another service.
+    ///
+    /// The definition is sent to the model as is; no schema is derived from Rust
+    /// types. The function receives the call's arguments as raw JSON, already
+    /// checked against `definition.parameters` by the agent. An invalid schema is
+    /// reported when the agent is built.
+    ///
+    /// # Arguments
+    ///
+    /// * `definition` - The tool's name, description and parameters schema
+    /// * `function` - The function to execute
+    ///
+    /// # Returns
+    ///
+    /// A reference to self for method chaining
+    pub fn register_with_definition<F, Fut, R>(&mut self, definition: ToolDefinition, function: F) -> &mut Self
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, OpenAIAgentError>> + Send + 'static,
+        R: ToString + Send + 'static,
+    {
+        self.register(DefinedTool { definition, function });
+        self
+    }
+
+    /// Sets how `merge` and `extend` handle a tool whose name is already
+    /// registered (default: `ToolConflict::Error`). Plain `register` calls are
+    /// not affected.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The conflict policy
+    ///
+    /// # Returns
+    ///
+    /// A reference to self for method chaining
+    pub fn set_conflict_policy(&mut self, policy: ToolConflict) -> &mut Self {
+        self.conflict = policy;
+        self
+    }
+
+    /// Adds every tool of another registry, e.g. to assemble an agent's tools
+    /// from registries built in separate modules.
+    ///
+    /// Name conflicts are resolved by this registry's conflict policy (see
+    /// `set_conflict_policy`); with `ToolConflict::Error` nothing is added if any
+    /// name is taken. Priorities of the added tools and the other registry's
+    /// hosted tools are carried over.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The registry to merge in
+    ///
+    /// # Returns
+    ///
+    /// A reference to self for method chaining, or `OpenAIAgentError::Config`
+    /// naming the conflicting tool
+    pub fn merge(&mut self, mut other: ToolRegistry) -> Result<&mut Self, OpenAIAgentError> {
+        let tools = other
+            .order
+            .iter()
+            .filter_map(|name| other.tools.get(name).cloned())
+            .collect();
+        for name in self.add_all(tools)? {
+            if let Some(priority) = other.priorities.remove(&name) {
+                self.priorities.insert(name, priority);
+            }
+        }
+        for name in other.duplicates {
+            if !self.duplicates.contains(&name) {
+                self.duplicates.push(name);
+            }
+        }
+        for tool in other.hosted {
+            self.register_hosted(tool);
+        }
+        Ok(self)
+    }
+
+    /// Adds already constructed tools, such as those returned by `get`, resolving
+    /// name conflicts like `merge`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tools` - The tools to add
+    ///
+    /// # Returns
+    ///
+    /// A reference to self for method chaining, or `OpenAIAgentError::Config`
+    /// naming the conflicting tool
+    pub fn extend(
+        &mut self,
+        tools: impl IntoIterator<Item = Arc<dyn RegisteredTool>>,
+    ) -> Result<&mut Self, OpenAIAgentError> {
+        self.add_all(tools.into_iter().collect())?;
+        Ok(self)
+    }
+
+    /// Adds tools under the conflict policy, returning the names actually added
+    /// or replaced.
+    fn add_all(&mut self, tools: Vec<Arc<dyn RegisteredTool>>) -> Result<Vec<String>, OpenAIAgentError> {
+        if self.conflict == ToolConflict::Error {
+            let mut seen: HashSet<String> = self.tools.keys().cloned().collect();
+            for tool in &tools {
+                let name = tool.definition().name;
+                if !seen.insert(name.clone()) {
+                    return Err(OpenAIAgentError::Config(format!(
+                        "Tool '{}' is already registered",
+                        name
+                    )));
+                }
+            }
+        }
+
+        let mut added = Vec::new();
+        for tool in tools {
+            let name = tool.definition().name;
+            match self.tools.get_mut(&name) {
+                Some(_) if self.conflict == ToolConflict::KeepFirst => continue,
+                Some(existing) => *existing = tool,
+                None => self.insert(tool),
+            }
+            added.push(name);
+        }
+        Ok(added)
+    }
+
+    /// Registers a built-in tool that OpenAI runs server-side.
+    ///
+    /// Hosted tools are listed after the function tools in `definitions`. Their
+    /// calls are resolved by the API, so the agent never executes them locally.
+    /// Registering the same tool twice has no effect.
+    ///
+    /// # Arguments
+    ///
+    /// * `tool` - The hosted tool to offer
+    ///
+    /// # Returns
+    ///
+    /// A reference to self for method chaining
+    pub fn register_hosted(&mut self, tool: HostedTool) -> &mut Self {
+        if !self.hosted.contains(&tool) {
+            self.hosted.push(tool);
+        }
+        self
+    }
+
+    /// Returns whether a tool call is resolved server-side by a hosted tool,
+    /// rather than by a locally registered function.
+    ///
+    /// Only the call's type is checked, so a function tool named like a hosted
+    /// tool, such as `file_search`, still runs locally.
+    pub(crate) fn is_hosted_call(&self, call: &ToolCall) -> bool {
+        !matches!(call.call_type.as_str(), "function" | "")
+    }
+
+    /// Sets a tool's priority. Tools with a higher priority are listed first in
+    /// `definitions`; tools with equal priority keep their registration order.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the tool
+    /// * `priority` - The priority (default 0)
+    ///
+    /// # Returns
+    ///
+    /// A reference to self for method chaining
+    pub fn set_priority(&mut self, name: impl Into<String>, priority: i32) -> &mut Self {
+        self.priorities.insert(name.into(), priority);
+        self
+    }
+
     /// Looks up a tool by name.
     ///
     /// # Arguments
@@ -221,26 +937,205 @@ impl ToolRegistry {
     ///
     /// # Returns
     ///
-    /// `true` if there are no registered tools, `false` otherwise
+    /// `true` if there are no registered tools, hosted or local, `false` otherwise
     pub fn is_empty(&self) -> bool {
-        self.tools.is_empty()
+        self.tools.is_empty() && self.hosted.is_empty()
+    }
+
+    /// Names that were registered more than once, in the order first repeated.
+    pub(crate) fn duplicate_names(&self) -> &[String] {
+        &self.duplicates
+    }
+
+    /// Names that do not meet the API's naming rules, in registration order.
+    pub(crate) fn invalid_names(&self) -> &[String] {
+        &self.invalid_names
     }
 
     /// Returns the definitions of all tools in the proper format for API requests.
     ///
+    /// Function tools are returned by descending priority (see `set_priority`),
+    /// then in registration order, which keeps requests identical across runs for
+    /// prompt caching and reproducible tests. Hosted tools follow, in
+    /// registration order.
+    ///
     /// # Returns
     ///
     /// A vector of ToolSpec objects containing the tool definitions
     pub fn definitions(&self) -> Vec<ToolSpec> {
-        self.tools
-            .values()
-            .map(|t| {
-                let def = t.definition();
-                ToolSpec {
-                    r#type: "function".to_string(),
-                    function: def,
-                }
-            })
+        let mut names: Vec<&String> = self.order.iter().collect();
+        names.sort_by_key(|name| std::cmp::Reverse(self.priorities.get(*name).copied().unwrap_or(0)));
+
+        names
+            .into_iter()
+            .filter_map(|name| self.tools.get(name))
+            .map(|t| ToolSpec::function(t.definition()))
+            .chain(self.hosted.iter().map(HostedTool::spec))
+            .collect()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+    struct TextArgs {
+        text: String,
+    }
+
+    async fn reverse(args: TextArgs) -> Result<String, OpenAIAgentError> {
+        Ok(args.text.chars().rev().collect())
+    }
+
+    #[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+    enum Unit {
+        Celsius,
+        Fahrenheit,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+    struct Location {
+        city: String,
+        country: Option<String>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+    struct ForecastArgs {
+        location: Location,
+        stops: Vec<Location>,
+        unit: Unit,
+        days: Option<u8>,
+    }
+
+    async fn forecast(args: ForecastArgs) -> Result<String, OpenAIAgentError> {
+        Ok(format!("{} stops after {}", args.stops.len(), args.location.city))
+    }
+
+    #[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+    struct TagArgs {
+        tags: HashMap<String, String>,
+    }
+
+    async fn tag(args: TagArgs) -> Result<String, OpenAIAgentError> {
+        Ok(format!("{} tags", args.tags.len()))
+    }
+
+    fn names(registry: &ToolRegistry) -> Vec<String> {
+        registry.definitions().into_iter().filter_map(|spec| spec.function).map(|function| function.name).collect()
+    }
+
+    fn descriptions(registry: &ToolRegistry) -> Vec<(String, String)> {
+        registry
+            .definitions()
+            .into_iter()
+            .filter_map(|spec| spec.function)
+            .map(|function| (function.name, function.description))
             .collect()
     }
-}
\ No newline at end of file
+
+    /// Asserts every object schema forbids extra properties and requires all of its properties
+    fn assert_strict_objects(schema: &Value) {
+        match schema {
+            Value::Object(object) => {
+                if let Some(properties) = object.get("properties").and_then(|p| p.as_object()) {
+                    assert_eq!(object.get("additionalProperties"), Some(&Value::Bool(false)));
+                    let mut required: Vec<&str> =
+                        object["required"].as_array().unwrap().iter().filter_map(|r| r.as_str()).collect();
+                    let mut names: Vec<&str> = properties.keys().map(String::as_str).collect();
+                    names.sort();
+                    required.sort();
+                    assert_eq!(required, names);
+                }
+                assert!(!object.contains_key("oneOf"));
+                object.values().for_each(assert_strict_objects);
+            }
+            Value::Array(values) => values.iter().for_each(assert_strict_objects),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_definitions_in_registration_order() {
+        let mut registry = ToolRegistry::new();
+        for name in ["zeta", "alpha", "mid", "beta", "omega"] {
+            registry.register_fn(name, "Reverses a given string of text.", reverse);
+        }
+        // Re-registering keeps the original position
+        registry.register_fn("alpha", "Reverses text again.", reverse);
+
+        assert_eq!(names(&registry), vec!["zeta", "alpha", "mid", "beta", "omega"]);
+    }
+
+    #[test]
+    fn test_hints_and_priorities() {
+        let mut registry = ToolRegistry::new();
+        registry.register_fn("first", "Reverses text.", reverse);
+        registry.register_fn_with_hint("second", "Reverses text.", "Prefer this for palindromes.", reverse);
+        registry.register_fn("third", "Reverses text.", reverse);
+        registry.set_priority("third", 10);
+
+        assert_eq!(names(&registry), vec!["third", "first", "second"]);
+        assert_eq!(descriptions(&registry)[2].1, "Reverses text. Prefer this for palindromes.");
+    }
+
+    #[test]
+    fn test_merge_under_each_conflict_policy() -> Result<(), OpenAIAgentError> {
+        let math = || {
+            let mut registry = ToolRegistry::new();
+            registry.register_fn("add", "Adds numbers.", reverse);
+            registry.register_fn("shared", "Math version.", reverse);
+            registry
+        };
+        let search = || {
+            let mut registry = ToolRegistry::new();
+            registry.register_fn("shared", "Search version.", reverse);
+            registry.register_fn("search", "Searches.", reverse);
+            registry
+        };
+
+        // Error (the default) leaves the registry untouched
+        let mut registry = math();
+        assert!(matches!(registry.merge(search()), Err(OpenAIAgentError::Config(_))));
+        assert_eq!(descriptions(&registry).len(), 2);
+
+        let mut registry = math();
+        registry.set_conflict_policy(ToolConflict::Overwrite).merge(search())?;
+        assert_eq!(
+            descriptions(&registry),
+            vec![
+                ("add".to_string(), "Adds numbers.".to_string()),
+                ("shared".to_string(), "Search version.".to_string()),
+                ("search".to_string(), "Searches.".to_string()),
+            ]
+        );
+
+        let mut registry = math();
+        registry.set_conflict_policy(ToolConflict::KeepFirst).merge(search())?;
+        assert_eq!(descriptions(&registry)[1], ("shared".to_string(), "Math version.".to_string()));
+        assert_eq!(descriptions(&registry).len(), 3);
+        assert!(registry.invalid_names().is_empty());
+
+        // extend takes individual tools, e.g. picked from another registry
+        let mut registry = ToolRegistry::new();
+        registry.extend(search().get("search"))?;
+        assert_eq!(descriptions(&registry), vec![("search".to_string(), "Searches.".to_string())]);
+        assert!(registry.extend(search().get("search")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_function_tool_schemas_meet_strict_mode() {
+        let mut registry = ToolRegistry::new();
+        registry.register_fn("forecast", "Weather forecast along a route", forecast);
+        registry.register_fn("tag", "Tag a resource", tag);
+
+        let nested = registry.get("forecast").unwrap().definition();
+        assert_eq!(nested.strict, Some(true));
+        assert_strict_objects(&nested.parameters);
+        assert!(nested.parameters.get("$defs").is_some_and(|defs| defs.get("Location").is_some()));
+
+        // Maps can't be expressed in strict mode, so the tool falls back to non-strict
+        let map = registry.get("tag").unwrap().definition();
+        assert_eq!(map.strict, None);
+    }
+}
@@ -0,0 +1,175 @@
+//! Transport abstraction for chat completion requests.
+//!
+//! The agent sends every chat completion through a `ChatTransport`. By default this
+//! is the HTTP-based `OpenAIClient`, but any implementation can be injected with
+//! `AgentBuilder::with_transport`. `MockTransport` replays queued canned responses,
+//! which makes it possible to test the turn loop and tool execution offline.
+
+use crate::client::OpenAIClient;
+use crate::error::OpenAIAgentError;
+use crate::models::{ChatChoice, ChatMessage, ChatRequest, ChatResponse, ToolCall, Usage};
+use crate::stream::{response_into_chunk, ChunkStream};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Sends chat completion requests and returns the responses.
+#[async_trait]
+pub trait ChatTransport: Send + Sync {
+    /// Sends a chat completion request
+    async fn chat_completion(&self, request: ChatRequest) -> Result<ChatResponse, OpenAIAgentError>;
+
+    /// Sends one attempt of a logical chat completion request.
+    ///
+    /// Every retry of the same logical request passes the same `idempotency_key`,
+    /// so transports that support idempotency can let the server deduplicate
+    /// them. The default implementation ignores the key.
+    async fn chat_completion_with_key(
+        &self,
+        request: ChatRequest,
+        idempotency_key: &str,
+    ) -> Result<ChatResponse, OpenAIAgentError> {
+        let _ = idempotency_key;
+        self.chat_completion(request).await
+    }
+
+    /// Sends a chat completion request and streams the response.
+    ///
+    /// The default implementation waits for the complete response and yields it
+    /// as a single chunk, so transports without streaming support still work.
+    async fn chat_completion_stream(&self, request: ChatRequest) -> Result<ChunkStream, OpenAIAgentError> {
+        let response = self.chat_completion(request).await?;
+        let chunk = response_into_chunk(response);
+        Ok(Box::pin(futures::stream::once(async move { Ok(chunk) })))
+    }
+}
+
+#[async_trait]
+impl ChatTransport for OpenAIClient {
+    async fn chat_completion(&self, request: ChatRequest) -> Result<ChatResponse, OpenAIAgentError> {
+        OpenAIClient::chat_completion(self, request).await
+    }
+
+    async fn chat_completion_with_key(
+        &self,
+        request: ChatRequest,
+        idempotency_key: &str,
+    ) -> Result<ChatResponse, OpenAIAgentError> {
+        OpenAIClient::chat_completion_with_key(self, request, idempotency_key).await
+    }
+
+    async fn chat_completion_stream(&self, request: ChatRequest) -> Result<ChunkStream, OpenAIAgentError> {
+        OpenAIClient::chat_completion_stream(self, request).await
+    }
+}
+
+/// Transport that returns queued canned responses instead of calling the API.
+///
+/// Responses are returned in the order they were queued, and every request
+/// received is recorded so tests can assert on what was sent.
+#[derive(Default)]
+pub struct MockTransport {
+    responses: Mutex<VecDeque<Result<ChatResponse, OpenAIAgentError>>>,
+    requests: Mutex<Vec<ChatRequest>>,
+}
+
+impl MockTransport {
+    /// Create a mock transport with no queued responses
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a response, returning the transport for chaining
+    pub fn with_response(self, response: ChatResponse) -> Self {
+        self.push_response(response);
+        self
+    }
+
+    /// Queue an error, returning the transport for chaining
+    pub fn with_error(self, error: OpenAIAgentError) -> Self {
+        self.push_error(error);
+        self
+    }
+
+    /// Queue a response
+    pub fn push_response(&self, response: ChatResponse) {
+        if let Ok(mut responses) = self.responses.lock() {
+            responses.push_back(Ok(response));
+        }
+    }
+
+    /// Queue an error to be returned instead of a response
+    pub fn push_error(&self, error: OpenAIAgentError) {
+        if let Ok(mut responses) = self.responses.lock() {
+            responses.push_back(Err(error));
+        }
+    }
+
+    /// All requests received so far, in order
+    pub fn requests(&self) -> Vec<ChatRequest> {
+        self.requests.lock().map(|r| r.clone()).unwrap_or_default()
+    }
+
+    /// Number of queued responses not yet consumed
+    pub fn remaining(&self) -> usize {
+        self.responses.lock().map(|r| r.len()).unwrap_or(0)
+    }
+
+    /// Build a response whose single choice is the given assistant message
+    pub fn message_response(message: ChatMessage, finish_reason: impl Into<String>) -> ChatResponse {
+        ChatResponse {
+            id: "mock-completion".to_string(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: "mock".to_string(),
+            choices: vec![ChatChoice {
+                index: 0,
+                message,
+                tool_calls: Vec::new(),
+                finish_reason: finish_reason.into(),
+                logprobs: None,
+            }],
+            usage: Some(Usage::default()),
+        }
+    }
+
+    /// Build a response with plain assistant text
+    pub fn text_response(content: impl Into<String>) -> ChatResponse {
+        Self::message_response(ChatMessage::assistant(content), "stop")
+    }
+
+    /// Build a response requesting the given tool calls, as `(id, name, arguments)` tuples
+    pub fn tool_call_response<I, S>(calls: I) -> ChatResponse
+    where
+        I: IntoIterator<Item = (S, S, S)>,
+        S: Into<String>,
+    {
+        let tool_calls = calls
+            .into_iter()
+            .map(|(id, name, arguments)| ToolCall::function(id, name, arguments))
+            .collect();
+
+        let message = ChatMessage::assistant_with_tool_calls(None, tool_calls);
+
+        Self::message_response(message, "tool_calls")
+    }
+}
+
+#[async_trait]
+impl ChatTransport for MockTransport {
+    async fn chat_completion(&self, request: ChatRequest) -> Result<ChatResponse, OpenAIAgentError> {
+        if let Ok(mut requests) = self.requests.lock() {
+            requests.push(request);
+        }
+
+        self.responses
+            .lock()
+            .map_err(|e| OpenAIAgentError::Agent(format!("Failed to acquire lock: {}", e)))?
+            .pop_front()
+            .unwrap_or_else(|| {
+                Err(OpenAIAgentError::Agent(
+                    "MockTransport has no queued responses".to_string(),
+                ))
+            })
+    }
+}
@@ -2,13 +2,25 @@
 //!
 //! This module provides helper functions for common tasks like token counting,
 //! text truncation, and implementing retry logic for API requests.
+//!
+//! Token counting requires the `tokenizer` feature (enabled by default). Without
+//! it, the token functions return `OpenAIAgentError::Config`, and the agent falls
+//! back to approximate counts where it needs them.
 
 use crate::error::OpenAIAgentError;
+use crate::models::ChatMessage;
+#[cfg(feature = "tokenizer")]
 use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+#[cfg(feature = "tokenizer")]
 use tiktoken_rs::{cl100k_base, p50k_base, r50k_base, p50k_edit, o200k_base, CoreBPE};
+#[cfg(feature = "tokenizer")]
+use std::sync::OnceLock;
 
 /// Gets the appropriate BPE tokenizer for a given OpenAI model name.
 ///
+/// Each tokenizer is built on first use and then shared for the life of the
+/// process, so counting from many agents and threads at once stays cheap.
+///
 /// # Arguments
 ///
 /// * `model` - The name of the OpenAI model
@@ -16,28 +28,41 @@ use tiktoken_rs::{cl100k_base, p50k_base, r50k_base, p50k_edit, o200k_base, Core
 /// # Returns
 ///
 /// A Result containing either the CoreBPE tokenizer or an error
-fn bpe_for_model(model: &str) -> Result<CoreBPE, OpenAIAgentError> {
+#[cfg(feature = "tokenizer")]
+fn bpe_for_model(model: &str) -> Result<&'static CoreBPE, OpenAIAgentError> {
+    static CL100K_BASE: OnceLock<CoreBPE> = OnceLock::new();
+    static P50K_BASE: OnceLock<CoreBPE> = OnceLock::new();
+    static R50K_BASE: OnceLock<CoreBPE> = OnceLock::new();
+    static P50K_EDIT: OnceLock<CoreBPE> = OnceLock::new();
+    static O200K_BASE: OnceLock<CoreBPE> = OnceLock::new();
+
     // Get the tokenizer type based on the model name
     let tokenizer_enum = get_tokenizer(model)
         .ok_or_else(|| OpenAIAgentError::Parse(format!("Unknown model: {}", model)))?;
 
-    // Initialize the appropriate BPE tokenizer
-    let bpe = match tokenizer_enum {
-        Tokenizer::Cl100kBase => cl100k_base()
-            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to init cl100k_base: {}", e)))?,
-        Tokenizer::P50kBase => p50k_base()
-            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to init p50k_base: {}", e)))?,
-        Tokenizer::R50kBase => r50k_base()
-            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to init r50k_base: {}", e)))?,
-        Tokenizer::P50kEdit => p50k_edit()
-            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to init p50k_edit: {}", e)))?,
-        Tokenizer::O200kBase => o200k_base()
-            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to init o200k_base: {}", e)))?,
-        Tokenizer::Gpt2 => p50k_base()
-            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to init p50k_base for GPT-2: {}", e)))?,
+    // Pick the shared slot and the constructor for the tokenizer;
+    // GPT-2 uses the p50k_base vocabulary
+    type Init = fn() -> Result<CoreBPE, OpenAIAgentError>;
+    let (cell, init): (&OnceLock<CoreBPE>, Init) = match tokenizer_enum {
+        Tokenizer::Cl100kBase => (&CL100K_BASE, || cl100k_base()
+            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to init cl100k_base: {}", e)))),
+        Tokenizer::P50kBase | Tokenizer::Gpt2 => (&P50K_BASE, || p50k_base()
+            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to init p50k_base: {}", e)))),
+        Tokenizer::R50kBase => (&R50K_BASE, || r50k_base()
+            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to init r50k_base: {}", e)))),
+        Tokenizer::P50kEdit => (&P50K_EDIT, || p50k_edit()
+            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to init p50k_edit: {}", e)))),
+        Tokenizer::O200kBase => (&O200K_BASE, || o200k_base()
+            .map_err(|e| OpenAIAgentError::Parse(format!("Failed to init o200k_base: {}", e)))),
     };
 
-    Ok(bpe)
+    if let Some(bpe) = cell.get() {
+        return Ok(bpe);
+    }
+
+    // Threads racing here may each build the tokenizer; only the first is kept
+    let bpe = init()?;
+    Ok(cell.get_or_init(|| bpe))
 }
 
 /// Counts the number of tokens in a text string for a given model.
@@ -53,12 +78,55 @@ fn bpe_for_model(model: &str) -> Result<CoreBPE, OpenAIAgentError> {
 /// # Returns
 ///
 /// A Result containing either the token count or an error
+#[cfg(feature = "tokenizer")]
 pub fn count_tokens(text: &str, model: &str) -> Result<usize, OpenAIAgentError> {
     let bpe = bpe_for_model(model)?;
     let tokens = bpe.encode_with_special_tokens(text);
     Ok(tokens.len())
 }
 
+/// Counts the prompt tokens a list of chat messages will consume for a given model.
+///
+/// This follows OpenAI's accounting for chat formatting: each message carries a
+/// small fixed overhead on top of its role, content, name and tool calls, and
+/// every reply is primed with a few additional tokens.
+///
+/// # Arguments
+///
+/// * `messages` - The messages to count tokens for
+/// * `model` - The name of the model to use for tokenization
+///
+/// # Returns
+///
+/// A Result containing either the token count or an error
+#[cfg(feature = "tokenizer")]
+pub fn count_message_tokens(messages: &[ChatMessage], model: &str) -> Result<usize, OpenAIAgentError> {
+    let bpe = bpe_for_model(model)?;
+    let count = |text: &str| bpe.encode_with_special_tokens(text).len();
+
+    // Every reply is primed with <|start|>assistant<|message|>
+    let mut total = 3;
+
+    for message in messages {
+        total += 3;
+        total += count(&message.role);
+        if let Some(content) = &message.content {
+            total += count(&content.to_text());
+        }
+        if let Some(name) = &message.name {
+            total += count(name) + 1;
+        }
+        if let Some(tool_calls) = &message.tool_calls {
+            for tool_call in tool_calls {
+                total += count(&tool_call.get_name());
+                total += count(&tool_call.get_arguments());
+            }
+        }
+    }
+
+    Ok(total)
+}
+
 /// Truncates text to a maximum number of tokens for a given model.
 ///
 /// This function ensures that text stays within token limits by
@@ -73,6 +141,8 @@ pub fn count_tokens(text: &str, model: &str) -> Result<usize, OpenAIAgentError>
 /// # Returns
 ///
 /// A Result containing either the truncated text or an error
+#[cfg(feature = "tokenizer")]
+#[allow(dead_code)]
 pub fn truncate_text_to_tokens(text: &str, max_tokens: usize, model: &str) -> Result<String, OpenAIAgentError> {
     let bpe = bpe_for_model(model)?;
 
@@ -94,6 +164,153 @@ pub fn truncate_text_to_tokens(text: &str, max_tokens: usize, model: &str) -> Re
     Ok(truncated_text)
 }
 
+/// Error returned by the token functions when the `tokenizer` feature is disabled.
+#[cfg(not(feature = "tokenizer"))]
+fn tokenizer_disabled() -> OpenAIAgentError {
+    OpenAIAgentError::Config("token counting requires the `tokenizer` feature".to_string())
+}
+
+/// Counts the number of tokens in a text string; unavailable without the `tokenizer` feature.
+#[cfg(not(feature = "tokenizer"))]
+pub fn count_tokens(_text: &str, _model: &str) -> Result<usize, OpenAIAgentError> {
+    Err(tokenizer_disabled())
+}
+
+/// Counts the prompt tokens of chat messages; unavailable without the `tokenizer` feature.
+#[cfg(not(feature = "tokenizer"))]
+pub fn count_message_tokens(_messages: &[ChatMessage], _model: &str) -> Result<usize, OpenAIAgentError> {
+    Err(tokenizer_disabled())
+}
+
+/// Truncates text to a number of tokens; unavailable without the `tokenizer` feature.
+#[cfg(not(feature = "tokenizer"))]
+#[allow(dead_code)]
+pub fn truncate_text_to_tokens(_text: &str, _max_tokens: usize, _model: &str) -> Result<String, OpenAIAgentError> {
+    Err(tokenizer_disabled())
+}
+
+/// Maximum number of input tokens accepted by OpenAI's embedding models.
+pub(crate) const EMBEDDING_TOKEN_LIMIT: usize = 8191;
+
+/// Counts the tokens in a text, estimating one token per three characters when
+/// the model's tokenizer is unavailable (an overestimate for most text, so limits
+/// checked against it are respected).
+pub(crate) fn count_tokens_or_estimate(text: &str, model: &str) -> usize {
+    count_tokens(text, model).unwrap_or_else(|_| text.chars().count().div_ceil(3))
+}
+
+/// Returns a function counting tokens like `count_tokens_or_estimate`, loading the
+/// model's tokenizer only once.
+fn token_counter(model: &str) -> Box<dyn Fn(&str) -> usize> {
+    #[cfg(feature = "tokenizer")]
+    if let Ok(bpe) = bpe_for_model(model) {
+        return Box::new(move |text| bpe.encode_with_special_tokens(text).len());
+    }
+
+    let _ = model;
+    Box::new(|text| text.chars().count().div_ceil(3))
+}
+
+/// Splits text into chunks of at most `max_tokens` tokens each.
+///
+/// Chunks break between sentences where possible, then between words, and only
+/// split a single over-long word as a last resort. Whitespace around chunks is
+/// trimmed and empty chunks are dropped.
+///
+/// # Arguments
+///
+/// * `text` - The text to split
+/// * `max_tokens` - The maximum number of tokens per chunk
+/// * `model` - The name of the model to use for tokenization
+pub(crate) fn split_text_by_tokens(text: &str, max_tokens: usize, model: &str) -> Vec<String> {
+    let count = token_counter(model);
+    let mut chunks = Vec::new();
+    pack_pieces(text, max_tokens.max(1), &*count, &mut chunks);
+    chunks
+}
+
+/// Greedily packs the pieces of `text` into chunks, splitting pieces that are
+/// too large on their own more finely.
+fn pack_pieces(text: &str, max_tokens: usize, count: &dyn Fn(&str) -> usize, chunks: &mut Vec<String>) {
+    let flush = |current: &mut String, chunks: &mut Vec<String>| {
+        let chunk = current.trim();
+        if !chunk.is_empty() {
+            chunks.push(chunk.to_string());
+        }
+        current.clear();
+    };
+
+    let pieces = split_pieces(text);
+    if pieces.len() == 1 {
+        // A single character can't be split further
+        flush(&mut text.to_string(), chunks);
+        return;
+    }
+
+    let mut current = String::new();
+    let mut current_tokens = 0;
+    for piece in pieces {
+        let tokens = count(piece);
+        if tokens > max_tokens {
+            flush(&mut current, chunks);
+            current_tokens = 0;
+            pack_pieces(piece, max_tokens, count, chunks);
+            continue;
+        }
+        if current_tokens + tokens > max_tokens {
+            flush(&mut current, chunks);
+            current_tokens = 0;
+        }
+        current.push_str(piece);
+        current_tokens += tokens;
+    }
+    flush(&mut current, chunks);
+}
+
+/// Splits text into sentences if it has several, otherwise into words, otherwise
+/// into two halves. Pieces keep their whitespace, so joining them gives `text`.
+fn split_pieces(text: &str) -> Vec<&str> {
+    fn split_at_ends(text: &str, is_end: impl Fn(char, Option<char>) -> bool) -> Vec<&str> {
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        let mut chars = text.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            let next = chars.peek().map(|&(_, next)| next);
+            if is_end(c, next) {
+                let end = i + c.len_utf8();
+                pieces.push(&text[start..end]);
+                start = end;
+            }
+        }
+        if start < text.len() {
+            pieces.push(&text[start..]);
+        }
+        pieces
+    }
+
+    let sentences = split_at_ends(text, |c, next| {
+        c == '\n' || (matches!(c, '.' | '!' | '?') && next.is_some_and(char::is_whitespace))
+    });
+    if sentences.len() > 1 {
+        return sentences;
+    }
+
+    // Words, each keeping the whitespace that follows it
+    let words = split_at_ends(text, |c, next| {
+        c.is_whitespace() && next.is_some_and(|next| !next.is_whitespace())
+    });
+    if words.len() > 1 {
+        return words;
+    }
+
+    let chars = text.chars().count();
+    if chars < 2 {
+        return vec![text];
+    }
+    let (mid, _) = text.char_indices().nth(chars / 2).unwrap_or((text.len(), ' '));
+    vec![&text[..mid], &text[mid..]]
+}
+
 /// Implements retry logic for API calls with exponential backoff.
 ///
 /// This function will retry a failing operation a specified number of times,
@@ -128,16 +345,15 @@ where
                     return Err(err);
                 }
 
-                // Only retry on request errors that might be temporary
-                match &err {
-                    OpenAIAgentError::Reqwest(req_err) if req_err.is_timeout() || req_err.is_connect() => {
-                        // Exponential backoff
-                        tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
-                        delay_ms *= 2;
-                        retries += 1;
-                    },
-                    _ => return Err(err),
+                // Only retry on errors that might be temporary
+                if !err.is_retryable() {
+                    return Err(err);
                 }
+
+                // Exponential backoff
+                tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+                delay_ms *= 2;
+                retries += 1;
             }
         }
     }
@@ -148,6 +364,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[cfg(feature = "tokenizer")]
     fn test_count_tokens() {
         let text = "Hello, world!";
         let result = count_tokens(text, "gpt-3.5-turbo");
@@ -155,6 +372,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "tokenizer")]
     fn test_truncate_text() {
         let text = "This is a long text that needs to be truncated to fit within token limits.";
         let result = truncate_text_to_tokens(text, 5, "gpt-3.5-turbo");
@@ -163,4 +381,63 @@ mod tests {
         let truncated = result.unwrap();
         assert!(truncated.len() < text.len());
     }
-}
\ No newline at end of file
+
+    #[test]
+    #[cfg(feature = "tokenizer")]
+    fn test_tokenizer_is_shared() {
+        // Models with the same vocabulary get the same cached tokenizer
+        let gpt4 = bpe_for_model("gpt-4").unwrap();
+        assert!(std::ptr::eq(gpt4, bpe_for_model("gpt-3.5-turbo").unwrap()));
+
+        let expected = count_tokens("Hello, world!", "gpt-4o").unwrap();
+        let counts: Vec<usize> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| count_tokens("Hello, world!", "gpt-4o").unwrap()))
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        assert!(counts.iter().all(|&count| count == expected));
+    }
+
+    #[test]
+    #[cfg(not(feature = "tokenizer"))]
+    fn test_token_functions_need_tokenizer() {
+        assert!(matches!(count_tokens("Hello, world!", "gpt-4o"), Err(OpenAIAgentError::Config(_))));
+        assert!(matches!(
+            count_message_tokens(&[ChatMessage::user("Hello")], "gpt-4o"),
+            Err(OpenAIAgentError::Config(_))
+        ));
+        assert!(matches!(
+            truncate_text_to_tokens("Hello, world!", 1, "gpt-4o"),
+            Err(OpenAIAgentError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_count_tokens_or_estimate() {
+        // Unknown models are estimated with or without the `tokenizer` feature
+        assert_eq!(count_tokens_or_estimate("abcdefg", "my-local-model"), 3);
+        assert_eq!(count_tokens_or_estimate("", "my-local-model"), 0);
+        assert!(count_tokens_or_estimate("Hello, world!", "gpt-4o") > 0);
+    }
+
+    #[test]
+    fn test_split_text_by_tokens() {
+        let text = "First sentence here. Second one follows! A third? Done.";
+        let chunks = split_text_by_tokens(text, 12, "text-embedding-3-small");
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| count_tokens_or_estimate(chunk, "text-embedding-3-small") <= 12));
+        // Breaks fall between sentences
+        assert!(chunks.iter().all(|chunk| chunk.ends_with(['.', '!', '?'])));
+        assert_eq!(chunks.join(" "), text);
+
+        assert_eq!(split_text_by_tokens("Short.", 100, "text-embedding-3-small"), vec!["Short."]);
+        assert!(split_text_by_tokens("  ", 100, "text-embedding-3-small").is_empty());
+
+        // A single long word is still split
+        let word = "x".repeat(200);
+        let chunks = split_text_by_tokens(&word, 10, "text-embedding-3-small");
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), word);
+    }
+}
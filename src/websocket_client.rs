@@ -9,15 +9,21 @@ use base64::Engine;
 use base64::engine::general_purpose;
 use crate::config::OpenAIConfig;
 use crate::error::OpenAIAgentError;
+use crate::tools::ToolRegistry;
 use futures_util::{SinkExt, StreamExt};
 use rand::RngCore;
 // Bring in Rustls so we can check the crypto provider
 use rustls::crypto::CryptoProvider;
 
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpStream;
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
-use tokio_tungstenite::tungstenite::{handshake::client::Request, Message};
+use tokio::sync::watch;
+use tokio_tungstenite::{connect_async_with_config, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 use url::Url;
 
@@ -33,8 +39,240 @@ pub struct RealtimeEvent {
 /// Adjust fields based on the official Realtime specs.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ServerEvent {
+    /// Event type, e.g. `response.done`; read from the API's `type` field
+    #[serde(alias = "type")]
     pub event_type: String,
-    // Add more fields matching your Realtime server messages...
+
+    /// All other fields of the event
+    #[serde(flatten)]
+    pub data: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ServerEvent {
+    /// Returns a string field of the event, if present.
+    pub fn get_str(&self, field: &str) -> Option<&str> {
+        self.data.get(field).and_then(|value| value.as_str())
+    }
+}
+
+/// Session settings sent to the Realtime API with a `session.update` event.
+///
+/// The defaults match the API's documented session defaults, so
+/// `RealtimeSessionConfig::default()` only needs the fields you want to change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RealtimeSessionConfig {
+    /// Output modalities, e.g. `["text", "audio"]`
+    pub modalities: Vec<String>,
+
+    /// System instructions for the session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+
+    /// Voice used for audio output, e.g. `alloy`
+    pub voice: String,
+
+    /// Format of input audio: `pcm16`, `g711_ulaw` or `g711_alaw`
+    pub input_audio_format: String,
+
+    /// Format of output audio: `pcm16`, `g711_ulaw` or `g711_alaw`
+    pub output_audio_format: String,
+
+    /// Optional transcription of input audio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_audio_transcription: Option<InputAudioTranscription>,
+
+    /// Voice activity detection; `None` disables it so turns are committed manually
+    pub turn_detection: Option<TurnDetection>,
+
+    /// Sampling temperature (0.6 to 1.2)
+    pub temperature: f32,
+
+    /// Function tools the model may call, in the Realtime tool format
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<serde_json::Value>,
+}
+
+impl Default for RealtimeSessionConfig {
+    fn default() -> Self {
+        Self {
+            modalities: vec!["text".to_string(), "audio".to_string()],
+            instructions: None,
+            voice: "alloy".to_string(),
+            input_audio_format: "pcm16".to_string(),
+            output_audio_format: "pcm16".to_string(),
+            input_audio_transcription: None,
+            turn_detection: Some(TurnDetection::default()),
+            temperature: 0.8,
+            tools: Vec::new(),
+        }
+    }
+}
+
+impl RealtimeSessionConfig {
+    /// Creates a session config with the API defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the output modalities.
+    pub fn with_modalities<I, S>(mut self, modalities: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.modalities = modalities.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the session instructions.
+    pub fn with_instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = Some(instructions.into());
+        self
+    }
+
+    /// Sets the output voice.
+    pub fn with_voice(mut self, voice: impl Into<String>) -> Self {
+        self.voice = voice.into();
+        self
+    }
+
+    /// Sets the input audio format.
+    pub fn with_input_audio_format(mut self, format: impl Into<String>) -> Self {
+        self.input_audio_format = format.into();
+        self
+    }
+
+    /// Sets the output audio format.
+    pub fn with_output_audio_format(mut self, format: impl Into<String>) -> Self {
+        self.output_audio_format = format.into();
+        self
+    }
+
+    /// Enables transcription of input audio with the given model, e.g. `whisper-1`.
+    pub fn with_input_audio_transcription(mut self, model: impl Into<String>) -> Self {
+        self.input_audio_transcription = Some(InputAudioTranscription { model: model.into() });
+        self
+    }
+
+    /// Sets voice activity detection, or disables it with `None`.
+    pub fn with_turn_detection(mut self, turn_detection: Option<TurnDetection>) -> Self {
+        self.turn_detection = turn_detection;
+        self
+    }
+
+    /// Sets the sampling temperature.
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Offers every tool in the registry to the model.
+    ///
+    /// Use with `Agent::handle_realtime_tool_calls` to run the calls.
+    pub fn with_tools(mut self, registry: &ToolRegistry) -> Self {
+        self.tools = registry
+            .definitions()
+            .into_iter()
+            .filter_map(|spec| spec.function)
+            .map(|function| {
+                serde_json::json!({
+                    "type": "function",
+                    "name": function.name,
+                    "description": function.description,
+                    "parameters": function.parameters,
+                })
+            })
+            .collect();
+        self
+    }
+}
+
+/// Input audio transcription settings for a Realtime session.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InputAudioTranscription {
+    /// Transcription model, e.g. `whisper-1`
+    pub model: String,
+}
+
+/// Server-side voice activity detection settings for a Realtime session.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TurnDetection {
+    /// Detection type; currently only `server_vad`
+    pub r#type: String,
+
+    /// Activation threshold (0.0 to 1.0)
+    pub threshold: f32,
+
+    /// Audio included before detected speech, in milliseconds
+    pub prefix_padding_ms: u32,
+
+    /// Silence that ends a turn, in milliseconds
+    pub silence_duration_ms: u32,
+}
+
+impl Default for TurnDetection {
+    fn default() -> Self {
+        Self {
+            r#type: "server_vad".to_string(),
+            threshold: 0.5,
+            prefix_padding_ms: 300,
+            silence_duration_ms: 500,
+        }
+    }
+}
+
+/// Signal for stopping `WebSocketClient::process_incoming_until` or
+/// `Agent::run_stream_with_stop` from another task.
+///
+/// Clones share the same signal. Once stopped, a handle stays stopped.
+#[derive(Clone, Debug)]
+pub struct StopHandle {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl StopHandle {
+    /// Creates a handle that has not been stopped.
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self { sender: Arc::new(sender) }
+    }
+
+    /// Asks every loop watching this handle to stop.
+    pub fn stop(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Returns whether `stop` has been called.
+    pub fn is_stopped(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Waits until `stop` is called.
+    pub async fn stopped(&self) {
+        let mut receiver = self.sender.subscribe();
+        while !*receiver.borrow_and_update() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for StopHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Why an event processing loop returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessEnd {
+    /// The server closed the connection
+    Closed,
+    /// The loop was stopped through its `StopHandle`; the connection was closed
+    Stopped,
+    /// No message arrived within the idle timeout; the connection is left open
+    IdleTimeout,
 }
 
 /// Handler for an OpenAI Realtime WebSocket connection.
@@ -44,6 +282,28 @@ pub struct WebSocketClient {
 
     /// The underlying WebSocket connection, if established.
     connection: Option<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+
+    /// Limit on the handshake; `None` uses the config's request timeout.
+    connect_timeout: Option<Duration>,
+
+    /// Largest message accepted, overriding tungstenite's default (64 MiB)
+    max_message_size: Option<usize>,
+
+    /// Largest frame accepted, overriding tungstenite's default (16 MiB)
+    max_frame_size: Option<usize>,
+}
+
+impl fmt::Debug for WebSocketClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The config's own Debug masks the API key
+        f.debug_struct("WebSocketClient")
+            .field("config", &self.config)
+            .field("connected", &self.connection.is_some())
+            .field("connect_timeout", &self.connect_timeout)
+            .field("max_message_size", &self.max_message_size)
+            .field("max_frame_size", &self.max_frame_size)
+            .finish()
+    }
 }
 
 impl WebSocketClient {
@@ -59,31 +319,52 @@ impl WebSocketClient {
         Ok(Self {
             config,
             connection: None,
+            connect_timeout: None,
+            max_message_size: None,
+            max_frame_size: None,
         })
     }
 
+    /// Sets how long `connect` waits for the handshake to complete.
+    ///
+    /// Defaults to the config's `timeout()`.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the largest message the connection accepts, in bytes.
+    ///
+    /// Raise it for large audio payloads; tungstenite's default is 64 MiB.
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = Some(size);
+        self
+    }
+
+    /// Sets the largest single frame the connection accepts, in bytes.
+    ///
+    /// Tungstenite's default is 16 MiB.
+    pub fn with_max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = Some(size);
+        self
+    }
+
+    /// Opens the Realtime connection for `model_name`.
+    ///
+    /// Fails with `OpenAIAgentError::Request` if the handshake fails or does not
+    /// complete within the connect timeout.
     pub async fn connect(&mut self, model_name: &str) -> Result<(), OpenAIAgentError> {
-        // Convert the base URL from https->wss, etc., removing any trailing slash.
-        let base_url = self.config.base_url();
-        let ws_base = base_url
-            .replace("https://", "wss://")
-            .replace("http://", "ws://")
-            .trim_end_matches('/')
-            .to_string();
-
-        // Append "/realtime" so you end with something like "wss://api.openai.com/v1/realtime"
-        let realtime_path = format!("{}/realtime", ws_base);
-
-        // Build the URL with the "model" query parameter.
-        let url = Url::parse_with_params(&realtime_path, &[("model", model_name)])
+        // Something like "wss://api.openai.com/v1/realtime", plus the "model" query parameter
+        let realtime_url = self.config.realtime_url();
+        let url = Url::parse_with_params(&realtime_url, &[("model", model_name)])
             .map_err(|e| OpenAIAgentError::Config(format!("Invalid WebSocket URL: {}", e)))?;
 
         println!("Connecting to Realtime API... {}", url);
 
         // Generate a valid Sec-WebSocket-Key.
         let mut key = [0u8; 16];
-        rand::thread_rng().fill_bytes(&mut key);
-        let key_base64 = general_purpose::STANDARD.encode(&key);
+        rand::rng().fill_bytes(&mut key);
+        let key_base64 = general_purpose::STANDARD.encode(key);
 
         // Extract the host from the URL to use in the header.
         let host = url.host_str().ok_or_else(|| {
@@ -102,14 +383,43 @@ impl WebSocketClient {
             headers.insert("Host", host.parse().unwrap());
             headers.insert("Authorization", format!("Bearer {}", self.config.api_key()).parse().unwrap());
             headers.insert("OpenAI-Beta", "realtime=v1".parse().unwrap());
+            headers.insert(
+                "User-Agent",
+                self.config.user_agent().parse().map_err(|_| {
+                    OpenAIAgentError::Config("Invalid user agent format".to_string())
+                })?,
+            );
+            if let Some(project) = self.config.project() {
+                headers.insert(
+                    "OpenAI-Project",
+                    project.parse().map_err(|_| {
+                        OpenAIAgentError::Config("Invalid project ID format".to_string())
+                    })?,
+                );
+            }
             headers.insert("Sec-WebSocket-Key", key_base64.parse().unwrap());
             headers.insert("Sec-WebSocket-Version", "13".parse().unwrap());
         }
 
-        // Perform the async WebSocket handshake.
-        let (ws_stream, response) = connect_async(request)
-            .await
-            .map_err(|e| OpenAIAgentError::Request(format!("WebSocket connection failed: {}", e)))?;
+        let mut ws_config = WebSocketConfig::default();
+        if let Some(size) = self.max_message_size {
+            ws_config = ws_config.max_message_size(Some(size));
+        }
+        if let Some(size) = self.max_frame_size {
+            ws_config = ws_config.max_frame_size(Some(size));
+        }
+
+        // Perform the async WebSocket handshake, giving up on a dead endpoint.
+        let connect_timeout = self.connect_timeout.unwrap_or_else(|| self.config.timeout());
+        let (ws_stream, response) = tokio::time::timeout(
+            connect_timeout,
+            connect_async_with_config(request, Some(ws_config), false),
+        )
+        .await
+        .map_err(|_| {
+            OpenAIAgentError::Request(format!("WebSocket connection timed out after {:?}", connect_timeout))
+        })?
+        .map_err(|e| OpenAIAgentError::Request(format!("WebSocket connection failed: {}", e)))?;
 
         println!("Connected to Realtime API with HTTP status: {}", response.status());
         self.connection = Some(ws_stream);
@@ -130,6 +440,32 @@ impl WebSocketClient {
 
         let payload = serde_json::to_string(event)
             .map_err(OpenAIAgentError::Serialization)?;
+        Self::send_text(connection, payload).await
+    }
+
+    /// Sends a `session.update` event applying the given session settings.
+    pub async fn update_session(&mut self, config: &RealtimeSessionConfig) -> Result<(), OpenAIAgentError> {
+        self.send_json(&serde_json::json!({
+            "type": "session.update",
+            "session": config,
+        }))
+        .await
+    }
+
+    /// Sends an arbitrary JSON event, for events `RealtimeEvent` cannot express.
+    pub async fn send_json(&mut self, event: &serde_json::Value) -> Result<(), OpenAIAgentError> {
+        let connection = match &mut self.connection {
+            Some(conn) => conn,
+            None => return Err(OpenAIAgentError::Request("Not connected".to_string())),
+        };
+
+        Self::send_text(connection, event.to_string()).await
+    }
+
+    async fn send_text(
+        connection: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+        payload: String,
+    ) -> Result<(), OpenAIAgentError> {
         connection
             .send(Message::Text(payload.into()))
             .await
@@ -142,11 +478,65 @@ impl WebSocketClient {
     /// calling the provided handler function for each one.
     ///
     /// This will run until the server closes the connection or an error occurs.
-    pub async fn process_incoming<F>(&mut self, mut on_event: F) -> Result<(), OpenAIAgentError>
+    pub async fn process_incoming<F>(&mut self, on_event: F) -> Result<(), OpenAIAgentError>
     where
     // Handler: given a parsed ServerEvent, return a result or fail
         F: FnMut(ServerEvent) -> Result<(), OpenAIAgentError>,
     {
+        self.process_incoming_until(&StopHandle::new(), None, on_event).await?;
+        Ok(())
+    }
+
+    /// Like `process_incoming`, but can be stopped from outside and can give up
+    /// when the connection goes quiet.
+    ///
+    /// Calling `stop` on the handle ends the loop and closes the connection
+    /// gracefully. If `idle_timeout` is set and no event arrives within it, the
+    /// loop returns `ProcessEnd::IdleTimeout` and leaves the connection open, so
+    /// processing can be resumed.
+    pub async fn process_incoming_until<F>(
+        &mut self,
+        stop: &StopHandle,
+        idle_timeout: Option<Duration>,
+        mut on_event: F,
+    ) -> Result<ProcessEnd, OpenAIAgentError>
+    where
+        F: FnMut(ServerEvent) -> Result<(), OpenAIAgentError>,
+    {
+        let end = loop {
+            let next = async {
+                match idle_timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, self.next_event()).await.ok(),
+                    None => Some(self.next_event().await),
+                }
+            };
+
+            let event = tokio::select! {
+                biased;
+                _ = stop.stopped() => break ProcessEnd::Stopped,
+                next = next => match next {
+                    Some(event) => event?,
+                    None => break ProcessEnd::IdleTimeout,
+                },
+            };
+
+            match event {
+                Some(server_event) => on_event(server_event)?,
+                None => break ProcessEnd::Closed,
+            }
+        };
+
+        if end == ProcessEnd::Stopped {
+            self.close().await?;
+        }
+
+        Ok(end)
+    }
+
+    /// Waits for the next server event, returning `None` once the connection closes.
+    ///
+    /// Binary, ping/pong and unparseable messages are skipped.
+    pub async fn next_event(&mut self) -> Result<Option<ServerEvent>, OpenAIAgentError> {
         let connection = match &mut self.connection {
             Some(conn) => conn,
             None => return Err(OpenAIAgentError::Request("Not connected".to_string())),
@@ -160,9 +550,7 @@ impl WebSocketClient {
                 Message::Text(txt) => {
                     // Typically, the server event is JSON. Let's parse it:
                     match serde_json::from_str::<ServerEvent>(&txt) {
-                        Ok(server_event) => {
-                            on_event(server_event)?;
-                        }
+                        Ok(server_event) => return Ok(Some(server_event)),
                         Err(parse_err) => {
                             eprintln!("Failed to parse inbound JSON: {}", parse_err);
                             // Depending on your logic, you might skip or return an error here
@@ -175,7 +563,7 @@ impl WebSocketClient {
                 }
                 Message::Close(frame) => {
                     println!("Server closed the connection: {:?}", frame);
-                    break; // Exit the loop
+                    return Ok(None);
                 }
                 _ => {
                     // Ping/Pong or other messages—handle if desired
@@ -183,7 +571,7 @@ impl WebSocketClient {
             }
         }
 
-        Ok(())
+        Ok(None)
     }
 
     /// Closes the WebSocket connection gracefully.
@@ -201,12 +589,100 @@ impl WebSocketClient {
 impl Drop for WebSocketClient {
     fn drop(&mut self) {
         // Attempt to close on drop if still connected
-        if self.connection.is_some() {
-            if let Ok(handle) = tokio::runtime::Handle::try_current() {
-                handle.block_on(async {
-                    let _ = self.close().await;
-                });
-            }
+        if self.connection.is_some()
+            && let Ok(handle) = tokio::runtime::Handle::try_current()
+        {
+            handle.block_on(async {
+                let _ = self.close().await;
+            });
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+    struct TextArgs {
+        /// Text to reverse
+        text: String,
+    }
+
+    async fn reverse(args: TextArgs) -> Result<String, OpenAIAgentError> {
+        Ok(args.text.chars().rev().collect())
+    }
+
+    #[test]
+    fn test_session_config_defaults() {
+        let value = serde_json::to_value(RealtimeSessionConfig::default()).unwrap();
+        let session = value.as_object().unwrap();
+
+        assert_eq!(session["modalities"], json!(["text", "audio"]));
+        assert_eq!(session["voice"], "alloy");
+        assert_eq!(session["input_audio_format"], "pcm16");
+        assert_eq!(session["output_audio_format"], "pcm16");
+        assert_eq!(session["turn_detection"]["type"], "server_vad");
+        assert_eq!(session["turn_detection"]["threshold"], 0.5);
+        assert_eq!(session["turn_detection"]["prefix_padding_ms"], 300);
+        assert_eq!(session["turn_detection"]["silence_duration_ms"], 500);
+        assert!((session["temperature"].as_f64().unwrap() - 0.8).abs() < 1e-6);
+
+        // Unset optional fields are left out, so the server keeps its own values
+        for field in ["instructions", "input_audio_transcription", "tools"] {
+            assert!(!session.contains_key(field), "{} should be omitted", field);
+        }
+    }
+
+    #[test]
+    fn test_session_config_builders() {
+        let config = RealtimeSessionConfig::new()
+            .with_modalities(["text"])
+            .with_instructions("Be brief.")
+            .with_voice("verse")
+            .with_input_audio_format("g711_ulaw")
+            .with_output_audio_format("g711_alaw")
+            .with_input_audio_transcription("whisper-1")
+            .with_temperature(0.6);
+        let value = serde_json::to_value(&config).unwrap();
+
+        assert_eq!(value["modalities"], json!(["text"]));
+        assert_eq!(value["instructions"], "Be brief.");
+        assert_eq!(value["voice"], "verse");
+        assert_eq!(value["input_audio_format"], "g711_ulaw");
+        assert_eq!(value["output_audio_format"], "g711_alaw");
+        assert_eq!(value["input_audio_transcription"], json!({"model": "whisper-1"}));
+
+        let round_trip: RealtimeSessionConfig = serde_json::from_value(value).unwrap();
+        assert_eq!(round_trip, config);
+    }
+
+    #[test]
+    fn test_disabled_turn_detection_is_sent_as_null() {
+        let config = RealtimeSessionConfig::new().with_turn_detection(None);
+        let value = serde_json::to_value(config).unwrap();
+
+        // The API only disables detection for an explicit null
+        assert_eq!(value.get("turn_detection"), Some(&serde_json::Value::Null));
+    }
+
+    #[test]
+    fn test_with_tools_uses_realtime_tool_format() {
+        let mut registry = ToolRegistry::new();
+        registry.register_fn("reverse", "Reverses text.", reverse);
+
+        let config = RealtimeSessionConfig::new().with_tools(&registry);
+        let value = serde_json::to_value(config).unwrap();
+        let tools = value["tools"].as_array().unwrap();
+
+        assert_eq!(tools.len(), 1);
+        // Realtime tools are flat, unlike the nested chat completions format
+        assert_eq!(tools[0]["type"], "function");
+        assert_eq!(tools[0]["name"], "reverse");
+        assert_eq!(tools[0]["description"], "Reverses text.");
+        assert!(tools[0].get("function").is_none());
+        assert_eq!(tools[0]["parameters"]["type"], "object");
+        assert!(tools[0]["parameters"]["properties"]["text"].is_object());
+    }
+}
@@ -7,11 +7,9 @@ use tokio::runtime::Runtime;
 use agio::{
     AgentBuilder, Config, Error,
     RegisteredTool, ToolRegistry, ToolDefinition,
-    tool_fn // Now properly exported in lib.rs
 };
 
 use async_trait::async_trait;
-use rustls::crypto::CryptoProvider;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use uuid::Uuid;
@@ -88,7 +86,7 @@ fn test_random_string_reverse_both_approaches() -> Result<(), Box<dyn std::error
     let rt = Runtime::new()?;
 
     // 4) Test traditional approach
-    let traditional_test = rt.block_on(async {
+    rt.block_on(async {
         // Directly use the async function without BoxFuture wrapper
         let registry = setup_traditional_tools().await;
         test_approach(
@@ -101,7 +99,7 @@ fn test_random_string_reverse_both_approaches() -> Result<(), Box<dyn std::error
     })?;
 
     // 5) Test function-based approach
-    let function_test = rt.block_on(async {
+    rt.block_on(async {
         // Directly use the async function without BoxFuture wrapper
         let registry = setup_function_tools().await;
         test_approach(
@@ -0,0 +1,2376 @@
+// tests/mock_transport_test.rs
+//
+// Deterministic tests of the agent turn loop using MockTransport.
+// These do not require network access or an OPENAI_API_KEY.
+
+use std::future::Future;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+use agio::{AgentBuilder, ChatResponse, Config, Error, MockTransport, ToolRegistry};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+struct ReverseArgs {
+    /// The string to reverse
+    text: String,
+}
+
+async fn reverse_string(args: ReverseArgs) -> Result<String, Error> {
+    Ok(args.text.chars().rev().collect())
+}
+
+fn reverse_registry() -> ToolRegistry {
+    let mut registry = ToolRegistry::new();
+    registry.register_fn("reverse_string", "Reverses a given string of text.", reverse_string);
+    registry
+}
+
+fn mock_config() -> Config {
+    Config::new().with_model("gpt-4o").with_temperature(0.0)
+}
+
+/// Runs an async test body on a fresh runtime.
+fn run_async(test: impl Future<Output = Result<(), Error>>) -> Result<(), Box<dyn std::error::Error>> {
+    Runtime::new()?.block_on(test)?;
+    Ok(())
+}
+
+/// Returns a builder with `mock_config` over a MockTransport that answers with
+/// `responses` in order, and the transport for inspecting the requests.
+fn agent_with(responses: impl IntoIterator<Item = ChatResponse>) -> (AgentBuilder, Arc<MockTransport>) {
+    let transport = Arc::new(MockTransport::new());
+    for response in responses {
+        transport.push_response(response);
+    }
+    let builder = AgentBuilder::new().with_config(mock_config()).with_transport(transport.clone());
+    (builder, transport)
+}
+
+#[test]
+fn test_tool_call_then_answer() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::tool_call_response(vec![(
+                "call_1",
+                "reverse_string",
+                r#"{"text":"abc"}"#,
+            )]),
+            MockTransport::text_response("The reversed text is cba."),
+        ]);
+
+        let mut agent = builder
+            .with_tools(reverse_registry())
+            .build()?;
+
+        let response = agent.run("Please reverse abc").await?;
+        assert_eq!(response, "The reversed text is cba.");
+
+        // user, assistant (tool call), tool result, assistant
+        assert_eq!(agent.state().message_count(), 4);
+        let tool_message = agent.state().messages().nth(2).unwrap();
+        assert_eq!(tool_message.role, "tool");
+        assert_eq!(tool_message.text().as_deref(), Some("cba"));
+
+        assert_eq!(transport.requests().len(), 2);
+        assert_eq!(transport.remaining(), 0);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_max_turns_exceeded() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, _) = agent_with([
+            MockTransport::tool_call_response(vec![(
+                "call_1",
+                "reverse_string",
+                r#"{"text":"one"}"#,
+            )]),
+            MockTransport::tool_call_response(vec![(
+                "call_2",
+                "reverse_string",
+                r#"{"text":"two"}"#,
+            )]),
+        ]);
+
+        let mut agent = builder
+            .with_tools(reverse_registry())
+            .with_max_turns(2)
+            .build()?;
+
+        let result = agent.run("Keep reversing").await;
+        assert!(matches!(result, Err(Error::Agent(_))));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_developer_prompt_is_sent_first() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([MockTransport::text_response("ok")]);
+
+        let mut agent = builder
+            .with_developer_prompt("Answer tersely.")
+            .build()?;
+
+        agent.run("Hello").await?;
+
+        let requests = transport.requests();
+        assert_eq!(requests[0].messages[0].role, "developer");
+        assert_eq!(requests[0].messages[0].text().as_deref(), Some("Answer tersely."));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_response_transform_leaves_history_untouched() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, _) = agent_with([MockTransport::text_response("Answer: 42")]);
+
+        let mut agent = builder
+            .with_response_transform(|content| content.trim_start_matches("Answer: ").to_string())
+            .build()?;
+
+        let response = agent.run("What is the answer?").await?;
+        assert_eq!(response, "42");
+
+        let stored = agent.state().messages().last().unwrap();
+        assert_eq!(stored.text().as_deref(), Some("Answer: 42"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_version_and_messages_since() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, _) = agent_with([
+            MockTransport::text_response("First answer"),
+            MockTransport::text_response("Second answer"),
+        ]);
+
+        let mut agent = builder.build()?;
+
+        agent.run("First question").await?;
+        let synced = agent.state().message_count();
+        let version = agent.state().version();
+        assert!(version > 0);
+
+        agent.run("Second question").await?;
+        assert!(agent.state().version() > version);
+
+        let new_messages = agent.state().messages_since(synced);
+        assert_eq!(new_messages.len(), 2);
+        assert_eq!(new_messages[1].text().as_deref(), Some("Second answer"));
+        assert!(agent.state().messages_since(100).is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_autosave_disabled() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::{MemoryStore, PersistenceStore};
+
+    run_async(async {
+        let store = Arc::new(MemoryStore::new());
+        let (builder, _) = agent_with([MockTransport::text_response("Hi")]);
+
+        let mut agent = builder
+            .with_persistence(store.clone())
+            .with_autosave(false)
+            .build()?;
+
+        agent.run("Hello").await?;
+        assert!(store.get_conversation(agent.id()).await?.is_none());
+
+        agent.save().await?;
+        let saved = store.get_conversation(agent.id()).await?.unwrap();
+        assert_eq!(saved.message_count(), 2);
+        Ok(())
+    })
+}
+
+struct RepeatTool;
+
+#[async_trait::async_trait]
+impl agio::RegisteredTool for RepeatTool {
+    fn definition(&self) -> agio::ToolDefinition {
+        agio::ToolDefinition {
+            name: "repeat".to_string(),
+            description: "Repeats a word a number of times.".to_string(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "word": { "type": "string" },
+                    "times": { "type": "integer", "minimum": 1 }
+                },
+                "required": ["word", "times"],
+                "additionalProperties": false
+            }),
+            strict: Some(true),
+        }
+    }
+
+    async fn execute(&self, arguments: serde_json::Value) -> Result<String, Error> {
+        let word = arguments["word"].as_str().unwrap_or_default();
+        let times = arguments["times"].as_u64().unwrap_or_default() as usize;
+        Ok(word.repeat(times))
+    }
+}
+
+#[test]
+fn test_invalid_tool_arguments_reported_to_model() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, _) = agent_with([
+            MockTransport::tool_call_response(vec![(
+                "call_1",
+                "repeat",
+                r#"{"word":"hi","times":0}"#,
+            )]),
+            MockTransport::text_response("Sorry about that."),
+        ]);
+
+        let mut registry = ToolRegistry::new();
+        registry.register(RepeatTool);
+
+        let mut agent = builder
+            .with_tools(registry)
+            .build()?;
+
+        agent.run("Repeat hi zero times").await?;
+
+        let tool_message = agent.state().messages().nth(2).unwrap();
+        assert_eq!(tool_message.role, "tool");
+        assert!(tool_message.text().unwrap().starts_with("Error: invalid arguments"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_run_stream_yields_content() -> Result<(), Box<dyn std::error::Error>> {
+    use futures::StreamExt;
+
+    run_async(async {
+        let (builder, _) = agent_with([
+            MockTransport::tool_call_response(vec![(
+                "call_1",
+                "reverse_string",
+                r#"{"text":"abc"}"#,
+            )]),
+            MockTransport::text_response("Reversed: cba"),
+        ]);
+
+        let mut agent = builder
+            .with_tools(reverse_registry())
+            .build()?;
+
+        let mut streamed = String::new();
+        {
+            let mut stream = agent.run_stream("Please reverse abc");
+            while let Some(delta) = stream.next().await {
+                streamed.push_str(&delta?);
+            }
+        }
+
+        assert_eq!(streamed, "Reversed: cba");
+        assert_eq!(agent.state().message_count(), 4);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_run_with_callback_receives_tokens() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, _) = agent_with([
+            MockTransport::tool_call_response(vec![(
+                "call_1",
+                "reverse_string",
+                r#"{"text":"abc"}"#,
+            )]),
+            MockTransport::text_response("Reversed: cba"),
+        ]);
+
+        let mut agent = builder
+            .with_tools(reverse_registry())
+            .build()?;
+
+        let mut tokens = Vec::new();
+        let response = agent
+            .run_with_callback("Please reverse abc", |token| tokens.push(token.to_string()))
+            .await?;
+
+        assert_eq!(response, "Reversed: cba");
+        // Only content is passed on, never the tool call
+        assert_eq!(tokens.concat(), "Reversed: cba");
+        assert_eq!(agent.state().message_count(), 4);
+        Ok(())
+    })
+}
+
+struct TagInterceptor;
+
+impl agio::Interceptor for TagInterceptor {
+    fn before_request(&self, request: &mut agio::ChatRequest) -> Result<(), Error> {
+        request.max_tokens = Some(16);
+        Ok(())
+    }
+
+    fn after_response(&self, response: &mut ChatResponse) -> Result<(), Error> {
+        for choice in &mut response.choices {
+            if let Some(text) = choice.message.text() {
+                choice.message.content = Some(text.replace("secret", "[redacted]").into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_interceptors_mutate_request_and_response() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([MockTransport::text_response("The secret is out")]);
+
+        let mut agent = builder
+            .with_interceptor(Arc::new(TagInterceptor))
+            .build()?;
+
+        let response = agent.run("Tell me").await?;
+        assert_eq!(response, "The [redacted] is out");
+        assert_eq!(transport.requests()[0].max_tokens, Some(16));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_max_turns_return_partial() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let mut first = MockTransport::tool_call_response(vec![(
+            "call_1",
+            "reverse_string",
+            r#"{"text":"one"}"#,
+        )]);
+        first.choices[0].message.content = Some("Working on it".into());
+
+        let (builder, _) = agent_with([
+            first,
+            MockTransport::tool_call_response(vec![(
+                "call_2",
+                "reverse_string",
+                r#"{"text":"two"}"#,
+            )]),
+        ]);
+
+        let mut agent = builder
+            .with_tools(reverse_registry())
+            .with_max_turns(2)
+            .with_max_turns_behavior(agio::MaxTurnsBehavior::ReturnPartial)
+            .build()?;
+
+        let outcome = agent.run_detailed("Keep reversing").await?;
+        assert!(outcome.truncated);
+        assert_eq!(outcome.content, "Working on it");
+        assert_eq!(outcome.turns, 2);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_tool_call_budget_leaves_history_runnable() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::tool_call_response(vec![
+                ("call_1", "reverse_string", r#"{"text":"one"}"#),
+                ("call_2", "reverse_string", r#"{"text":"two"}"#),
+            ]),
+            MockTransport::text_response("Fine, no tools."),
+        ]);
+        let mut agent = builder
+            .with_tools(reverse_registry())
+            .with_max_tool_calls(1)
+            .build()?;
+
+        assert!(matches!(agent.run("Reverse both").await, Err(Error::Agent(_))));
+        assert_eq!(agent.tool_call_count(), 0);
+        // Only the user message is kept: no tool calls are left without results
+        assert_eq!(agent.state().message_count(), 1);
+        assert!(agent.state().messages().all(|message| message.tool_calls.is_none()));
+
+        assert_eq!(agent.run("Just answer").await?, "Fine, no tools.");
+        let request = &transport.requests()[1];
+        assert!(request.messages.iter().all(|message| message.tool_calls.is_none() && message.role != "tool"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_merged_registries_build_an_agent() -> Result<(), Box<dyn std::error::Error>> {
+    let mut merged = reverse_registry();
+    let mut other = ToolRegistry::new();
+    other.register_fn("reverse_string", "Another reverser.", reverse_string);
+    other.register_fn("reverse_again", "Reverses a given string of text.", reverse_string);
+    merged.set_conflict_policy(agio::ToolConflict::KeepFirst).merge(other)?;
+
+    run_async(async {
+        let (builder, transport) = agent_with([MockTransport::text_response("ok")]);
+        let mut agent = builder.with_tools(merged).build()?;
+        agent.run("Hi").await?;
+        assert_eq!(transport.requests()[0].tools.as_ref().map(Vec::len), Some(2));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_replay_with_recorded_tool_results() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::MemoryStore;
+    use agio::ReplayTools;
+
+    run_async(async {
+        let store = Arc::new(MemoryStore::new());
+        let (builder, _) = agent_with([
+            MockTransport::tool_call_response(vec![(
+                "call_1",
+                "reverse_string",
+                r#"{"text":"abc"}"#,
+            )]),
+            MockTransport::text_response("It is cba."),
+        ]);
+
+        let mut agent = builder
+            .with_tools(reverse_registry())
+            .with_persistence(store.clone())
+            .build()?;
+        agent.run("Please reverse abc").await?;
+
+        let (builder, replay_transport) = agent_with([
+            MockTransport::tool_call_response(vec![(
+                "call_9",
+                "reverse_string",
+                r#"{"text":"abc"}"#,
+            )]),
+            MockTransport::text_response("Reversed: cba"),
+        ]);
+        let replayer = builder.build()?;
+
+        // No tools are registered, so the result must come from the recording
+        let results = replayer.replay_with(store.as_ref(), agent.id(), ReplayTools::Recorded).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.text().as_deref(), Some("It is cba."));
+        assert_eq!(results[0].1, "Reversed: cba");
+
+        let requests = replay_transport.requests();
+        let tool_message = requests[1].messages.last().unwrap();
+        assert_eq!(tool_message.role, "tool");
+        assert_eq!(tool_message.text().as_deref(), Some("cba"));
+        assert_eq!(replayer.state().message_count(), 0);
+
+        let missing = replayer.replay(store.as_ref(), "missing").await;
+        assert!(missing.is_err());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_builder_validate_reports_all_problems() {
+    let mut registry = reverse_registry();
+    registry.register_fn("reverse_string", "Registered twice.", reverse_string);
+    registry.register(BadSchemaTool);
+
+    let builder = AgentBuilder::new()
+        .with_config(Config::new().with_model("gpt-4o").with_temperature(3.5))
+        .with_tools(registry)
+        .with_max_turns(0);
+
+    // Missing API key, temperature, max_turns, duplicate tool, malformed schema
+    let problems = builder.validate().unwrap_err();
+    assert_eq!(problems.len(), 5, "{:?}", problems);
+
+    match builder.build() {
+        Err(err) => assert!(err.to_string().contains("5 configuration problems")),
+        Ok(_) => panic!("build should fail validation"),
+    }
+
+    // A custom transport does not need an API key
+    let ok = AgentBuilder::new()
+        .with_config(mock_config())
+        .with_tools(reverse_registry())
+        .with_transport(Arc::new(MockTransport::new()));
+    assert!(ok.validate().is_ok());
+}
+
+struct BadSchemaTool;
+
+#[async_trait::async_trait]
+impl agio::RegisteredTool for BadSchemaTool {
+    fn definition(&self) -> agio::ToolDefinition {
+        agio::ToolDefinition {
+            name: "bad_schema".to_string(),
+            description: "Has a malformed schema.".to_string(),
+            parameters: serde_json::json!({ "type": "object", "properties": { "x": { "type": 12 } } }),
+            strict: None,
+        }
+    }
+
+    async fn execute(&self, _arguments: serde_json::Value) -> Result<String, Error> {
+        Ok(String::new())
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, schemars::JsonSchema)]
+struct Weather {
+    city: String,
+    celsius: i32,
+}
+
+#[test]
+fn test_run_typed_falls_back_when_json_schema_unsupported() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let transport = Arc::new(
+            MockTransport::new()
+                .with_error(Error::Api {
+                    status: 400,
+                    message: "Invalid parameter: 'response_format' of type 'json_schema' is not supported with this model.".to_string(),
+                })
+                .with_response(MockTransport::text_response(
+                    "```json\n{\"city\": \"Oslo\", \"celsius\": 4}\n```",
+                )),
+        );
+
+        let mut agent = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_system_prompt("You report the weather.")
+            .with_transport(transport.clone())
+            .build()?;
+
+        let weather: Weather = agent.run_typed("Weather in Oslo?").await?;
+        assert_eq!(weather.city, "Oslo");
+        assert_eq!(weather.celsius, 4);
+
+        let requests = transport.requests();
+        assert_eq!(requests[0].response_format.as_ref().unwrap()["type"], "json_schema");
+        assert_eq!(requests[1].response_format.as_ref().unwrap()["type"], "json_object");
+        let system = requests[1].messages[0].text().unwrap();
+        assert!(system.starts_with("You report the weather."));
+        assert!(system.contains("JSON Schema"));
+
+        // The rejected attempt is not kept: system, user, assistant
+        assert_eq!(agent.state().message_count(), 3);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_run_typed_parse_failure_includes_raw_content() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::StructuredOutputMode;
+
+    run_async(async {
+        let (builder, _) = agent_with([MockTransport::text_response("It is sunny.")]);
+        let mut agent = builder
+            .with_structured_output_modes([StructuredOutputMode::PlainText])
+            .build()?;
+
+        match agent.run_typed::<Weather>("Weather in Oslo?").await {
+            Err(Error::Parse(message)) => assert!(message.contains("It is sunny.")),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn test_max_history_messages_keeps_tool_pairs() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::tool_call_response(vec![(
+                "call_1",
+                "reverse_string",
+                r#"{"text":"abc"}"#,
+            )]),
+            MockTransport::text_response("cba"),
+            MockTransport::text_response("Hello again"),
+        ]);
+
+        let mut agent = builder
+            .with_system_prompt("Be brief.")
+            .with_tools(reverse_registry())
+            .with_max_history_messages(3)
+            .build()?;
+
+        agent.run("Reverse abc").await?;
+        agent.run("Hi").await?;
+
+        // Before the third request the history was: user, call, tool, answer, user.
+        // Dropping two would orphan the tool result, so it goes as well.
+        let last_request = transport.requests().pop().unwrap();
+        let roles: Vec<_> = last_request.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "assistant", "user"]);
+
+        assert_eq!(agent.state().oldest_kept_index(), 4);
+        assert_eq!(agent.state().message_count(), 4);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_max_history_messages_keeps_current_exchange() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::text_response("Hello"),
+            MockTransport::tool_call_response(vec![(
+                "call_1",
+                "reverse_string",
+                r#"{"text":"abc"}"#,
+            )]),
+            MockTransport::text_response("cba"),
+        ]);
+
+        let mut agent = builder
+            .with_system_prompt("Be brief.")
+            .with_tools(reverse_registry())
+            .with_max_history_messages(1)
+            .build()?;
+
+        agent.run("Hi").await?;
+        assert_eq!(agent.run("Reverse abc").await?, "cba");
+
+        // Mid tool loop the cap is exceeded rather than cutting the current exchange
+        let requests = transport.requests();
+        let roles: Vec<_> = requests[1].messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user"]);
+        let roles: Vec<_> = requests[2].messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "tool"]);
+        assert_eq!(requests[2].messages[1].text().as_deref(), Some("Reverse abc"));
+        assert_eq!(agent.state().oldest_kept_index(), 3);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_store_and_metadata_passthrough() -> Result<(), Box<dyn std::error::Error>> {
+    use std::collections::HashMap;
+
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::text_response("One"),
+            MockTransport::text_response("Two"),
+        ]);
+
+        let mut plain = builder.build()?;
+        plain.run("Hello").await?;
+
+        let metadata = HashMap::from([("team".to_string(), "support".to_string())]);
+        let mut tagged = AgentBuilder::new()
+            .with_config(mock_config().with_store(true).with_request_metadata(metadata))
+            .with_transport(transport.clone())
+            .build()?;
+        tagged.run("Hello").await?;
+
+        let requests = transport.requests();
+        let unset = serde_json::to_value(&requests[0])?;
+        assert!(unset.get("store").is_none());
+        assert!(unset.get("metadata").is_none());
+
+        let set = serde_json::to_value(&requests[1])?;
+        assert_eq!(set["store"], true);
+        assert_eq!(set["metadata"]["team"], "support");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_logprobs_requested_and_surfaced() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::{LogProbs, TokenLogProb};
+
+    run_async(async {
+        let mut scored = MockTransport::text_response("Yes");
+        scored.choices[0].logprobs = Some(LogProbs {
+            content: Some(vec![TokenLogProb {
+                token: "Yes".to_string(),
+                logprob: -0.25,
+                bytes: None,
+                top_logprobs: Vec::new(),
+            }]),
+            refusal: None,
+        });
+        let transport = Arc::new(
+            MockTransport::new()
+                .with_response(scored)
+                .with_response(MockTransport::text_response("No")),
+        );
+
+        let mut agent = AgentBuilder::new()
+            .with_config(mock_config().with_logprobs(true).with_top_logprobs(3))
+            .with_transport(transport.clone())
+            .build()?;
+
+        let outcome = agent.run_detailed("Is it?").await?;
+        let tokens = outcome.logprobs.and_then(|logprobs| logprobs.content).unwrap();
+        assert_eq!(tokens[0].token, "Yes");
+        assert_eq!(tokens[0].logprob, -0.25);
+
+        // A response without logprobs leaves the outcome's field empty
+        assert!(agent.run_detailed("Really?").await?.logprobs.is_none());
+
+        let request = serde_json::to_value(&transport.requests()[0])?;
+        assert_eq!(request["logprobs"], true);
+        assert_eq!(request["top_logprobs"], 3);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_edit_last_message() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::{MemoryStore, PersistenceStore};
+    use agio::{AskUser, ChatMessage, ToolCall};
+
+    run_async(async {
+        let store = Arc::new(MemoryStore::new());
+        let (builder, transport) = agent_with([
+            MockTransport::text_response("Paris is in Italy."),
+            MockTransport::text_response("Noted."),
+            MockTransport::tool_call_response(vec![(
+                "call_1",
+                "ask_user",
+                r#"{"question":"Which city?"}"#,
+            )]),
+        ]);
+
+        let mut registry = reverse_registry();
+        registry.register(AskUser);
+        let mut agent = builder
+            .with_tools(registry)
+            .with_persistence(store.clone())
+            .build()?;
+
+        agent.run("Where is Paris?").await?;
+        let version = agent.state().version;
+        agent.last_message_mut().unwrap().content = Some("Paris is in France.".into());
+        assert!(agent.state().version > version);
+        agent.save().await?;
+        let saved = store.get_conversation(agent.id()).await?.unwrap();
+        assert_eq!(saved.messages[1].text().as_deref(), Some("Paris is in France."));
+
+        // The edit is what the model sees next turn
+        agent.run("Thanks").await?;
+        assert_eq!(transport.requests()[1].messages[1].text().as_deref(), Some("Paris is in France."));
+
+        agent.replace_last_message(ChatMessage::assistant("Noted, thanks."))?;
+        assert_eq!(agent.state().messages[3].text().as_deref(), Some("Noted, thanks."));
+        assert!(agent.state().messages[3].id.is_some());
+        let with_calls = ChatMessage::assistant_with_tool_calls(None, vec![ToolCall::function("call_2", "x", "{}")]);
+        assert!(matches!(agent.replace_last_message(with_calls), Err(Error::Agent(_))));
+        assert!(matches!(
+            agent.replace_last_message(ChatMessage::tool_result("y", "x", "call_2")),
+            Err(Error::Agent(_))
+        ));
+
+        // Paused on a tool call, the last message can't be touched
+        agent.run("Weather?").await?;
+        assert!(agent.last_message_mut().is_none());
+        assert!(matches!(
+            agent.replace_last_message(ChatMessage::assistant("Edited")),
+            Err(Error::Agent(_))
+        ));
+
+        let mut empty = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_transport(transport.clone())
+            .build()?;
+        assert!(empty.last_message_mut().is_none());
+        assert!(matches!(empty.replace_last_message(ChatMessage::assistant("x")), Err(Error::Agent(_))));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_empty_input_rejected_unless_allowed() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([MockTransport::text_response("ok")]);
+        let mut agent = builder.build()?;
+        for input in ["", "   ", "\n\t "] {
+            assert!(matches!(agent.push_user_message(input), Err(Error::Agent(_))));
+            assert!(matches!(agent.run(input).await, Err(Error::Agent(_))));
+        }
+        assert_eq!(agent.state().message_count(), 0);
+        assert!(transport.requests().is_empty());
+
+        let mut lenient = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_transport(transport.clone())
+            .with_allow_empty_input(true)
+            .build()?;
+        lenient.push_user_message("")?;
+        lenient.push_user_message("   ")?;
+        assert_eq!(lenient.state().message_count(), 2);
+        assert_eq!(lenient.run(" ").await?, "ok");
+        assert_eq!(transport.requests()[0].messages.len(), 3);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_ask_user_pauses_and_resumes() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::AskUser;
+
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::tool_call_response(vec![(
+                "call_1",
+                "ask_user",
+                r#"{"question":"Which city?"}"#,
+            )]),
+            MockTransport::text_response("It is sunny in Oslo."),
+        ]);
+
+        let mut registry = reverse_registry();
+        registry.register(AskUser);
+        let mut agent = builder
+            .with_tools(registry)
+            .build()?;
+
+        let paused = agent.run_detailed("What's the weather?").await?;
+        assert_eq!(paused.needs_user_input.as_deref(), Some("Which city?"));
+        assert_eq!(paused.content, "Which city?");
+        assert_eq!(transport.requests().len(), 1);
+
+        let answer = agent.run_detailed("Oslo").await?;
+        assert!(answer.needs_user_input.is_none());
+        assert_eq!(answer.content, "It is sunny in Oslo.");
+
+        // The answer was sent as the result of the ask_user call
+        let resumed = transport.requests().pop().unwrap();
+        let tool_message = resumed.messages.last().unwrap();
+        assert_eq!(tool_message.role, "tool");
+        assert_eq!(tool_message.tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(tool_message.text().as_deref(), Some("Oslo"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_ask_user_asks_one_question_at_a_time() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::AskUser;
+
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::tool_call_response(vec![
+                ("call_1", "ask_user", r#"{"question":"Which city?"}"#),
+                ("call_2", "ask_user", r#"{"question":"Which day?"}"#),
+            ]),
+            MockTransport::text_response("It will be sunny in Oslo."),
+        ]);
+
+        let mut registry = reverse_registry();
+        registry.register(AskUser);
+        let mut agent = builder
+            .with_tools(registry)
+            .build()?;
+
+        let paused = agent.run_detailed("What's the weather?").await?;
+        assert_eq!(paused.needs_user_input.as_deref(), Some("Which city?"));
+
+        let answer = agent.run_detailed("Oslo").await?;
+        assert_eq!(answer.content, "It will be sunny in Oslo.");
+
+        // Both calls have results: the second was refused, the first answered
+        let resumed = transport.requests().pop().unwrap();
+        let results: Vec<_> = resumed.messages.iter().filter(|m| m.role == "tool").collect();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].tool_call_id.as_deref(), Some("call_2"));
+        assert!(results[0].text().unwrap().contains("one question"));
+        assert_eq!(results[1].tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(results[1].text().as_deref(), Some("Oslo"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_streamed_usage_is_estimated_when_missing() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let mut response = MockTransport::text_response("Hello there, how can I help?");
+        response.usage = None;
+        let transport = Arc::new(MockTransport::new().with_response(response));
+
+        let mut agent = AgentBuilder::new()
+            .with_config(mock_config().with_stream(true))
+            .with_transport(transport)
+            .build()?;
+
+        let outcome = agent.run_detailed("Hi").await?;
+        assert!(outcome.streamed);
+        assert!(outcome.usage_estimated);
+        assert!(outcome.usage.prompt_tokens > 0);
+        assert!(outcome.usage.completion_tokens > 0);
+        assert_eq!(agent.state().token_count(), outcome.usage.total_tokens);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_ask_ephemeral_leaves_history_untouched() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::text_response("Hello!"),
+            MockTransport::text_response("You said hello."),
+        ]);
+
+        let mut agent = builder
+            .with_tools(reverse_registry())
+            .build()?;
+        agent.run("Hello").await?;
+        let version = agent.state().version();
+
+        let answer = agent.ask_ephemeral("What did I say?").await?;
+        assert_eq!(answer, "You said hello.");
+        assert_eq!(agent.state().message_count(), 2);
+        assert_eq!(agent.state().version(), version);
+
+        let side_request = transport.requests().pop().unwrap();
+        assert_eq!(side_request.messages.len(), 3);
+        assert!(side_request.tools.is_none());
+        Ok(())
+    })
+}
+
+struct JsonTool;
+
+#[async_trait::async_trait]
+impl agio::RegisteredTool for JsonTool {
+    fn definition(&self) -> agio::ToolDefinition {
+        agio::ToolDefinition {
+            name: "lookup".to_string(),
+            description: "Looks up a record.".to_string(),
+            parameters: serde_json::json!({ "type": "object", "properties": {} }),
+            strict: None,
+        }
+    }
+
+    async fn execute(&self, _arguments: serde_json::Value) -> Result<String, Error> {
+        Ok("{ \"id\": 7,\n  \"name\": \"Ada\" }".to_string())
+    }
+}
+
+#[test]
+fn test_tool_result_formatter() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::ToolResultFormatter;
+
+    run_async(async {
+        for (formatter, expected) in [
+            (ToolResultFormatter::Raw, "{ \"id\": 7,\n  \"name\": \"Ada\" }"),
+            (ToolResultFormatter::Minified, r#"{"id":7,"name":"Ada"}"#),
+            (ToolResultFormatter::PrettyJson, "{\n  \"id\": 7,\n  \"name\": \"Ada\"\n}"),
+        ] {
+            let transport = Arc::new(
+                MockTransport::new()
+                    .with_response(MockTransport::tool_call_response(vec![("call_1", "lookup", "{}")]))
+                    .with_response(MockTransport::text_response("Found Ada.")),
+            );
+            let mut registry = ToolRegistry::new();
+            registry.register(JsonTool);
+
+            let mut agent = AgentBuilder::new()
+                .with_config(mock_config())
+                .with_tools(registry)
+                .with_transport(transport)
+                .with_tool_result_formatter(formatter)
+                .build()?;
+            agent.run("Find record 7").await?;
+
+            let tool_message = agent.state().messages().nth(2).unwrap();
+            assert_eq!(tool_message.text().as_deref(), Some(expected), "{:?}", formatter);
+        }
+
+        let (builder, _) = agent_with([
+            MockTransport::tool_call_response(vec![("call_1", "lookup", "{}")]),
+            MockTransport::text_response("Found Ada."),
+        ]);
+        let mut registry = ToolRegistry::new();
+        registry.register(JsonTool);
+        let mut agent = builder
+            .with_tools(registry)
+            .with_tool_result_formatter(ToolResultFormatter::WithToolName)
+            .build()?;
+        agent.run("Find record 7").await?;
+
+        let tool_message = agent.state().messages().nth(2).unwrap();
+        let wrapped: serde_json::Value = serde_json::from_str(&tool_message.text().unwrap())?;
+        assert_eq!(wrapped, serde_json::json!({ "tool": "lookup", "result": { "id": 7, "name": "Ada" } }));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_event_sender_publishes_run_activity() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::AgentEventKind;
+
+    run_async(async {
+        let (events, mut receiver) = tokio::sync::broadcast::channel(64);
+        let transport = Arc::new(
+            MockTransport::new()
+                .with_response(MockTransport::tool_call_response(vec![(
+                    "call_1",
+                    "reverse_string",
+                    r#"{"text":"abc"}"#,
+                )]))
+                .with_response(MockTransport::text_response("The reversed text is cba."))
+                .with_error(Error::Agent("boom".to_string())),
+        );
+
+        let mut agent = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_tools(reverse_registry())
+            .with_transport(transport)
+            .with_event_sender(events)
+            .build()?;
+        agent.run("Please reverse abc").await?;
+        assert!(agent.run("Again").await.is_err());
+
+        let mut kinds = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            assert_eq!(event.agent_id, agent.id());
+            kinds.push(match event.kind {
+                AgentEventKind::Message(message) => format!("message:{}", message.role),
+                AgentEventKind::TurnCompleted { turn, .. } => format!("turn:{}", turn),
+                AgentEventKind::ToolExecuted { name, call_id, .. } => format!("tool:{}:{}", name, call_id),
+                AgentEventKind::Error(_) => "error".to_string(),
+            });
+        }
+
+        assert_eq!(
+            kinds,
+            vec![
+                "message:user",
+                "turn:1",
+                "message:assistant",
+                "tool:reverse_string:call_1",
+                "message:tool",
+                "turn:2",
+                "message:assistant",
+                "message:user",
+                "error",
+            ]
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_run_typed_stream_ends_with_complete_value() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::PartialValue;
+    use futures::StreamExt;
+
+    run_async(async {
+        let (builder, _) = agent_with([
+            MockTransport::text_response(r#"{"city": "Oslo", "celsius": 4}"#),
+            MockTransport::text_response(r#"{"city": "Oslo", "celsius": "#),
+        ]);
+        let mut agent = builder.build()?;
+
+        let items: Vec<_> = agent.run_typed_stream::<Weather>("Weather in Oslo?").collect().await;
+        let items = items.into_iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[0],
+            PartialValue::Partial(serde_json::json!({ "city": "Oslo", "celsius": 4 }))
+        );
+        assert_eq!(items[1], PartialValue::Complete(Weather { city: "Oslo".to_string(), celsius: 4 }));
+
+        // A truncated answer still yields what was parsed, then a parse error
+        let items: Vec<_> = agent.run_typed_stream::<Weather>("And tomorrow?").collect().await;
+        assert_eq!(items.len(), 2);
+        assert!(matches!(&items[0], Ok(PartialValue::Partial(value)) if value == &serde_json::json!({ "city": "Oslo" })));
+        assert!(matches!(items[1], Err(Error::Parse(_))));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_content_filter_is_reported_and_optionally_retried() -> Result<(), Box<dyn std::error::Error>> {
+    fn filtered(content: &str) -> ChatResponse {
+        let mut response = MockTransport::text_response(content);
+        response.choices[0].finish_reason = "content_filter".to_string();
+        response
+    }
+
+    run_async(async {
+        let (builder, _) = agent_with([filtered("Here is how")]);
+        let mut agent = builder.build()?;
+
+        match agent.run("Tell me something").await {
+            Err(Error::ContentFiltered { partial_content }) => {
+                assert_eq!(partial_content.as_deref(), Some("Here is how"));
+            }
+            other => panic!("expected ContentFiltered, got {:?}", other),
+        }
+        // The filtered message is not kept
+        assert_eq!(agent.state().message_count(), 1);
+
+        let (builder, transport) = agent_with([
+            filtered(""),
+            MockTransport::text_response("A safer answer."),
+        ]);
+        let mut agent = builder
+            .with_content_filter_retry(true)
+            .build()?;
+
+        assert_eq!(agent.run("Tell me something").await?, "A safer answer.");
+        assert_eq!(agent.state().message_count(), 2);
+        let retry = &transport.requests()[1];
+        assert_eq!(retry.messages[0].role, "system");
+        assert!(retry.messages[0].text().unwrap().contains("content filter"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_max_request_bytes() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([MockTransport::text_response("ok")]);
+        let agent = builder.build()?;
+        let size = agent.request_byte_size("Hello")?;
+        assert!(agent.request_byte_size("Hello there, a longer question")? > size);
+        assert_eq!(agent.state().message_count(), 0);
+
+        let limited = || {
+            AgentBuilder::new()
+                .with_config(mock_config())
+                .with_transport(transport.clone())
+                .with_max_request_bytes(size)
+                .build()
+        };
+        assert!(matches!(limited()?.run("Hello, but longer").await, Err(Error::Agent(_))));
+        assert!(transport.requests().is_empty());
+
+        assert_eq!(limited()?.run("Hello").await?, "ok");
+        assert_eq!(serde_json::to_vec(&transport.requests()[0])?.len(), size);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_token_limit_field_per_model() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        for (config, field, other) in [
+            (mock_config(), "max_completion_tokens", "max_tokens"),
+            (mock_config().with_model("gpt-3.5-turbo"), "max_tokens", "max_completion_tokens"),
+            (mock_config().with_legacy_max_tokens(true), "max_tokens", "max_completion_tokens"),
+        ] {
+            let transport = Arc::new(MockTransport::new().with_response(MockTransport::text_response("ok")));
+            let mut agent = AgentBuilder::new()
+                .with_config(config.with_max_completion_tokens(64))
+                .with_transport(transport.clone())
+                .build()?;
+            agent.run("Hello").await?;
+
+            let body = serde_json::to_value(&transport.requests()[0])?;
+            assert_eq!(body[field], 64);
+            assert!(body.get(other).is_none());
+        }
+        Ok(())
+    })
+}
+
+struct UserSession {
+    name: String,
+}
+
+struct Greeting(&'static str);
+
+struct GreetTool;
+
+#[async_trait::async_trait]
+impl agio::RegisteredTool for GreetTool {
+    fn definition(&self) -> agio::ToolDefinition {
+        agio::ToolDefinition {
+            name: "greet".to_string(),
+            description: "Greets the current user.".to_string(),
+            parameters: serde_json::json!({ "type": "object", "properties": {} }),
+            strict: None,
+        }
+    }
+
+    async fn execute(&self, _arguments: serde_json::Value) -> Result<String, Error> {
+        Err(Error::Tool("greet needs a context".to_string()))
+    }
+
+    async fn execute_with_context(
+        &self,
+        _arguments: serde_json::Value,
+        context: &agio::ToolContext<'_>,
+    ) -> Result<String, Error> {
+        let greeting = context.get::<Greeting>().map_or("Hello", |g| g.0);
+        let user = context.get::<UserSession>().map_or("stranger", |s| s.name.as_str());
+        assert!(context.get::<String>().is_none());
+        Ok(format!("{}, {} ({})", greeting, user, context.tool_call_id()))
+    }
+}
+
+#[test]
+fn test_tools_read_context_values_by_type() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        for (with_values, expected) in [(true, "Howdy, Ada (call_1)"), (false, "Hello, stranger (call_1)")] {
+            let transport = Arc::new(
+                MockTransport::new()
+                    .with_response(MockTransport::tool_call_response(vec![("call_1", "greet", "{}")]))
+                    .with_response(MockTransport::text_response("Done.")),
+            );
+            let mut registry = ToolRegistry::new();
+            registry.register(GreetTool);
+
+            let mut builder = AgentBuilder::new()
+                .with_config(mock_config())
+                .with_tools(registry)
+                .with_transport(transport);
+            if with_values {
+                builder = builder
+                    .with_context_value(UserSession { name: "Ada".to_string() })
+                    .with_context_value(Greeting("Hi"))
+                    .with_context_value(Greeting("Howdy"));
+            }
+            let mut agent = builder.build()?;
+            agent.run("Greet me").await?;
+
+            let tool_message = agent.state().messages().nth(2).unwrap();
+            assert_eq!(tool_message.text().as_deref(), Some(expected));
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn test_fixed_overhead_tokens() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let build = |system: Option<&str>, with_tools: bool| {
+            let mut builder = AgentBuilder::new()
+                .with_config(mock_config())
+                .with_transport(Arc::new(MockTransport::new().with_response(MockTransport::text_response("ok"))));
+            if let Some(system) = system {
+                builder = builder.with_system_prompt(system);
+            }
+            if with_tools {
+                let mut registry = ToolRegistry::new();
+                registry.register(GreetTool);
+                builder = builder.with_tools(registry);
+            }
+            builder.build()
+        };
+
+        assert_eq!(build(None, false)?.fixed_overhead_tokens(), 0);
+        let system_only = build(Some("You are a helpful assistant."), false)?.fixed_overhead_tokens();
+        assert!(system_only > 0);
+        assert!(build(Some("You are a helpful assistant. Answer in French."), false)?.fixed_overhead_tokens() > system_only);
+
+        let mut agent = build(Some("You are a helpful assistant."), true)?;
+        let overhead = agent.fixed_overhead_tokens();
+        assert!(overhead > system_only);
+
+        // Conversation history is not part of the overhead
+        agent.run("Hello").await?;
+        assert_eq!(agent.fixed_overhead_tokens(), overhead);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_refusal_is_returned_and_kept() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let refusal = agio::ChatMessage {
+            role: "assistant".to_string(),
+            refusal: Some("I can't help with that.".to_string()),
+            ..Default::default()
+        };
+        let (builder, _) = agent_with([MockTransport::message_response(refusal, "stop")]);
+        let mut agent = builder.build()?;
+
+        match agent.run("Do something forbidden").await {
+            Err(Error::Refusal(text)) => assert_eq!(text, "I can't help with that."),
+            other => panic!("expected a refusal, got {:?}", other),
+        }
+        let stored = agent.state().messages().last().unwrap();
+        assert_eq!(stored.refusal.as_deref(), Some("I can't help with that."));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_run_with_prediction() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::text_response("let x = 2;"),
+            MockTransport::text_response("Done."),
+        ]);
+        let mut agent = builder.build()?;
+
+        let outcome = agent.run_with_prediction("Change x to 2", "let x = 1;").await?;
+        assert_eq!(outcome.content, "let x = 2;");
+        agent.run("Thanks").await?;
+
+        let requests = transport.requests();
+        assert_eq!(requests[0].prediction, Some(agio::PredictionContent::text("let x = 1;")));
+        assert_eq!(serde_json::to_value(&requests[0])?["prediction"]["type"], "content");
+        // The prediction only applies to its own run
+        assert!(requests[1].prediction.is_none());
+        Ok(())
+    })
+}
+
+#[test]
+fn test_tool_registered_from_json_definition() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let definition: agio::ToolDefinition = serde_json::from_str(
+            r#"{
+                "name": "lookup_order",
+                "description": "Look up an order by its number",
+                "parameters": {
+                    "type": "object",
+                    "properties": {"order_id": {"type": "integer"}},
+                    "required": ["order_id"],
+                    "additionalProperties": false
+                }
+            }"#,
+        )?;
+        let mut registry = ToolRegistry::new();
+        registry.register_with_definition(definition.clone(), |args: serde_json::Value| async move {
+            Ok::<_, Error>(format!("order {} shipped", args["order_id"]))
+        });
+
+        let (builder, transport) = agent_with([
+            MockTransport::tool_call_response(vec![("call_1", "lookup_order", r#"{"order_id": 7}"#)]),
+            MockTransport::text_response("It shipped."),
+        ]);
+        let mut agent = builder
+            .with_tools(registry)
+            .build()?;
+        agent.run("Where is order 7?").await?;
+
+        let tool_message = agent.state().messages().nth(2).unwrap();
+        assert_eq!(tool_message.text().as_deref(), Some("order 7 shipped"));
+        let sent = transport.requests()[0].tools.as_ref().unwrap()[0].function.clone().unwrap();
+        assert_eq!(sent.parameters, definition.parameters);
+        assert_eq!(sent.strict, None);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_websocket_connect_times_out() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::websocket_client::WebSocketClient;
+
+    run_async(async {
+        // Accepts connections but never answers the handshake
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let _server = tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                connections.push(socket);
+            }
+        });
+
+        let config = Config::new()
+            .with_api_key("test-key")
+            .with_base_url(format!("http://{}/v1", address));
+        let mut client = WebSocketClient::new(config)?
+            .with_connect_timeout(std::time::Duration::from_millis(100))
+            .with_max_message_size(128 * 1024 * 1024);
+
+        match client.connect("gpt-4o-realtime-preview").await {
+            Err(Error::Request(message)) => assert!(message.contains("timed out"), "{}", message),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn test_context_headroom() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let unknown = AgentBuilder::new()
+            .with_config(mock_config().with_model("my-local-model"))
+            .with_transport(Arc::new(MockTransport::new()))
+            .build()?;
+        assert_eq!(unknown.context_headroom(), None);
+        assert!(!unknown.is_near_context_limit(0.0));
+
+        let transport = Arc::new(MockTransport::new().with_response(MockTransport::text_response("word ".repeat(300))));
+        let mut agent = AgentBuilder::new()
+            .with_config(mock_config().with_context_window(1000).with_max_tokens(100))
+            .with_transport(transport)
+            .build()?;
+        let initial = agent.context_headroom().unwrap();
+        assert!(initial <= 900 && initial > 800, "{}", initial);
+        assert!(!agent.is_near_context_limit(0.5));
+
+        agent.run(&"word ".repeat(300)).await?;
+        let headroom = agent.context_headroom().unwrap();
+        assert!(headroom < initial - 500, "{}", headroom);
+        assert!(agent.is_near_context_limit(0.5));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_token_estimates_without_a_tokenizer() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        // No tokenizer knows this model, so counts fall back to estimates as they
+        // do for every model without the `tokenizer` feature
+        let (builder, transport) = agent_with([
+            MockTransport::text_response("They sent a long message."),
+            MockTransport::text_response("Done."),
+        ]);
+        let mut agent = builder
+            .with_config(
+                mock_config()
+                    .with_model("my-local-model")
+                    .with_context_window(1000)
+                    .with_max_tokens(100),
+            )
+            .with_messages(vec![agio::ChatMessage::user("x".repeat(400)), agio::ChatMessage::assistant("Ok.")])
+            .with_auto_summarize(agio::SummarizeConfig {
+                trigger_tokens: 50,
+                keep_recent: 1,
+                ..Default::default()
+            })
+            .build()?;
+
+        // About four characters per token, rounded up for each message
+        assert_eq!(agent.context_headroom(), Some(1000 - 101 - 100));
+
+        // The estimate crosses the summarization threshold
+        agent.run("Anything else?").await?;
+        assert_eq!(transport.requests().len(), 2);
+        let transcript = transport.requests()[0].messages[1].text().unwrap_or_default();
+        assert!(transcript.contains(&"x".repeat(400)), "{}", transcript);
+        assert_eq!(agent.state().messages().last().and_then(|m| m.text()).as_deref(), Some("Done."));
+        Ok(())
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+struct NoArgs {}
+
+async fn panicking_tool(_args: NoArgs) -> Result<String, Error> {
+    panic!("deliberate failure")
+}
+
+#[test]
+fn test_panicking_tool_is_reported_to_the_model() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, _) = agent_with([
+            MockTransport::tool_call_response(vec![("call_1", "explode", "{}")]),
+            MockTransport::text_response("That tool is broken."),
+        ]);
+        let mut registry = ToolRegistry::new();
+        registry.register_fn("explode", "Always panics.", panicking_tool);
+
+        let mut agent = builder
+            .with_tools(registry)
+            .build()?;
+
+        assert_eq!(agent.run("Try the tool").await?, "That tool is broken.");
+        let tool_message = agent.state().messages().nth(2).unwrap();
+        assert_eq!(
+            tool_message.text().as_deref(),
+            Some("Error: Tool error: tool panicked: deliberate failure")
+        );
+        Ok(())
+    })
+}
+
+async fn rate_limited_tool(_args: NoArgs) -> Result<String, Error> {
+    Err(agio::ToolError::new("rate_limited", "try again in a minute").into())
+}
+
+#[test]
+fn test_structured_tool_errors_are_reported_to_the_model() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, _) = agent_with([
+            MockTransport::tool_call_response(vec![
+                ("call_1", "limited", "{}"),
+                ("call_2", "missing", "{}"),
+                ("call_3", "explode", "{}"),
+            ]),
+            MockTransport::text_response("Nothing worked."),
+        ]);
+        let mut registry = ToolRegistry::new();
+        registry.register_fn("limited", "Always rate limited.", rate_limited_tool);
+        registry.register_fn("explode", "Always panics.", panicking_tool);
+
+        let mut agent = builder
+            .with_tools(registry)
+            .with_structured_tool_errors(true)
+            .build()?;
+
+        assert_eq!(agent.run("Try the tools").await?, "Nothing worked.");
+        let errors: Vec<serde_json::Value> = agent
+            .state()
+            .messages()
+            .skip(2)
+            .take(3)
+            .map(|message| serde_json::from_str(&message.text().unwrap()).unwrap())
+            .collect();
+        assert_eq!(
+            errors[0],
+            serde_json::json!({ "error": { "type": "rate_limited", "message": "try again in a minute" } })
+        );
+        assert_eq!(errors[1]["error"]["type"], "not_found");
+        assert_eq!(errors[2]["error"]["type"], "panic");
+
+        // Without the option a returned error still ends the run
+        let (builder, _) = agent_with([MockTransport::tool_call_response(vec![("call_1", "limited", "{}")])]);
+        let mut registry = ToolRegistry::new();
+        registry.register_fn("limited", "Always rate limited.", rate_limited_tool);
+        let mut agent = builder
+            .with_tools(registry)
+            .build()?;
+        match agent.run("Try the tool").await {
+            Err(Error::ToolFailed(error)) => assert_eq!(error.kind, "rate_limited"),
+            other => panic!("expected a tool failure, got {:?}", other),
+        }
+        Ok(())
+    })
+}
+
+/// Streams one chunk of text, then stalls until the run is cancelled.
+struct StallingTransport;
+
+#[async_trait::async_trait]
+impl agio::ChatTransport for StallingTransport {
+    async fn chat_completion(&self, _request: agio::ChatRequest) -> Result<ChatResponse, Error> {
+        Err(Error::Agent("only streaming is supported".to_string()))
+    }
+
+    async fn chat_completion_stream(&self, _request: agio::ChatRequest) -> Result<agio::ChunkStream, Error> {
+        use futures::StreamExt;
+
+        let chunk: agio::ChatCompletionChunk = serde_json::from_value(serde_json::json!({
+            "choices": [{ "index": 0, "delta": { "role": "assistant", "content": "Once upon a" } }]
+        }))?;
+        Ok(Box::pin(futures::stream::once(async move { Ok(chunk) }).chain(futures::stream::pending())))
+    }
+}
+
+#[test]
+fn test_cancelled_stream_keeps_partial_message() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::{MemoryStore, PersistenceStore};
+    use agio::websocket_client::StopHandle;
+    use futures::StreamExt;
+
+    run_async(async {
+        let store = Arc::new(MemoryStore::new());
+        let mut agent = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_transport(Arc::new(StallingTransport))
+            .with_persistence(store.clone())
+            .build()?;
+
+        let stop = StopHandle::new();
+        {
+            let mut stream = agent.run_stream_with_stop("Tell me a story", stop.clone());
+            assert_eq!(stream.next().await.transpose()?.as_deref(), Some("Once upon a"));
+            stop.stop();
+            assert!(matches!(stream.next().await, Some(Err(Error::Cancelled))));
+            assert!(stream.next().await.is_none());
+        }
+
+        let partial = agent.state().messages().last().unwrap();
+        assert_eq!(partial.role, "assistant");
+        assert_eq!(partial.text().as_deref(), Some("Once upon a"));
+        assert!(partial.incomplete);
+
+        let saved = store.get_conversation(agent.id()).await?.unwrap();
+        assert_eq!(saved.message_count(), 2);
+        assert!(saved.messages().last().unwrap().incomplete);
+
+        // A handle that is already stopped cancels before any request
+        let mut stream = agent.run_stream_with_stop("Another", stop);
+        assert!(matches!(stream.next().await, Some(Err(Error::Cancelled))));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_cancelled_stream_checkpoint_stays_incomplete() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::{MemoryStore, PersistenceStore};
+    use agio::websocket_client::StopHandle;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    run_async(async {
+        let store = Arc::new(MemoryStore::new());
+        let mut agent = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_transport(Arc::new(StallingTransport))
+            .with_persistence(store.clone())
+            .with_autosave(false)
+            .with_stream_checkpoint_interval(Duration::ZERO)
+            .build()?;
+
+        let stop = StopHandle::new();
+        {
+            let mut stream = agent.run_stream_with_stop("Tell me a story", stop.clone());
+            assert_eq!(stream.next().await.transpose()?.as_deref(), Some("Once upon a"));
+            stop.stop();
+            assert!(matches!(stream.next().await, Some(Err(Error::Cancelled))));
+        }
+
+        // Without autosave, the last checkpoint is what the store keeps
+        let saved = store.get_conversation(agent.id()).await?.unwrap();
+        let partial = saved.messages().last().unwrap();
+        assert_eq!(partial.text().as_deref(), Some("Once upon a"));
+        assert!(partial.incomplete);
+        Ok(())
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+enum Unit {
+    Celsius,
+    Fahrenheit,
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+struct Location {
+    city: String,
+    country: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+struct ForecastArgs {
+    location: Location,
+    stops: Vec<Location>,
+    unit: Unit,
+    days: Option<u8>,
+}
+
+async fn forecast(args: ForecastArgs) -> Result<String, Error> {
+    Ok(format!("{} stops after {}", args.stops.len(), args.location.city))
+}
+
+#[test]
+fn test_strict_function_tool_runs_with_nested_arguments() -> Result<(), Box<dyn std::error::Error>> {
+    let mut registry = ToolRegistry::new();
+    registry.register_fn("forecast", "Weather forecast along a route", forecast);
+
+    run_async(async {
+        let (builder, _) = agent_with([
+            MockTransport::tool_call_response(vec![(
+                "call_1",
+                "forecast",
+                r#"{"location": {"city": "Lyon", "country": null}, "stops": [{"city": "Dijon", "country": "FR"}], "unit": "Celsius", "days": null}"#,
+            )]),
+            MockTransport::text_response("Done."),
+        ]);
+        let mut agent = builder
+            .with_tools(registry)
+            .build()?;
+        agent.run("Forecast my trip").await?;
+
+        let tool_message = agent.state().messages().nth(2).unwrap();
+        assert_eq!(tool_message.text().as_deref(), Some("1 stops after Lyon"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_turn_usage_is_recorded_per_response() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let with_usage = |mut response: ChatResponse, prompt: usize, completion: usize| {
+            response.usage = Some(agio::Usage {
+                prompt_tokens: prompt,
+                completion_tokens: completion,
+                total_tokens: prompt + completion,
+                ..Default::default()
+            });
+            response
+        };
+        let (builder, _) = agent_with([
+            with_usage(
+                MockTransport::tool_call_response(vec![("call_1", "reverse_string", r#"{"text":"abc"}"#)]),
+                100,
+                20,
+            ),
+            with_usage(MockTransport::text_response("cba"), 150, 10),
+            with_usage(MockTransport::text_response("Bye"), 120, 5),
+        ]);
+        let mut registry = ToolRegistry::new();
+        registry.register_fn("reverse_string", "Reverses a string", reverse_string);
+        let mut agent = builder
+            .with_tools(registry)
+            .build()?;
+
+        agent.run("Reverse abc").await?;
+        agent.run("Thanks").await?;
+
+        let turns = agent.state().turn_usage();
+        assert_eq!(turns.len(), 3);
+        assert_eq!(turns.iter().map(|t| t.message_index).collect::<Vec<_>>(), vec![1, 3, 5]);
+        assert_eq!((turns[0].prompt_tokens, turns[0].completion_tokens), (100, 20));
+        assert!(turns.iter().all(|t| !t.estimated));
+        assert_eq!(agent.state().most_expensive_turn(), Some(&turns[1]));
+        assert_eq!(
+            turns.iter().map(|t| t.total_tokens).sum::<usize>(),
+            agent.state().token_count()
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_run_realtime_streams_text() -> Result<(), Box<dyn std::error::Error>> {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    run_async(async {
+        // Answers each response.create with two text deltas
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+            let mut received = Vec::new();
+            while let Some(Ok(message)) = ws.next().await {
+                let Ok(event) = serde_json::from_str::<serde_json::Value>(message.to_text().unwrap_or_default()) else {
+                    continue;
+                };
+                received.push(event.clone());
+                if event["type"] == "response.create" {
+                    for event in [
+                        serde_json::json!({ "type": "response.created" }),
+                        serde_json::json!({ "type": "response.text.delta", "delta": "Hel" }),
+                        serde_json::json!({ "type": "response.text.delta", "delta": "lo!" }),
+                        serde_json::json!({ "type": "response.done" }),
+                    ] {
+                        ws.send(Message::text(event.to_string())).await.unwrap();
+                    }
+                }
+            }
+            received
+        });
+
+        let config = mock_config()
+            .with_api_key("test-key")
+            .with_base_url(format!("http://{}/v1", address));
+        let mut agent = AgentBuilder::new()
+            .with_config(config.clone())
+            .with_transport(Arc::new(MockTransport::new()))
+            .with_websocket_client(agio::websocket_client::WebSocketClient::new(config)?)
+            .build()?;
+        agent.connect_realtime("gpt-4o-realtime-preview").await?;
+
+        let chunks: Vec<String> = agent
+            .run_realtime("Say hello")
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<_, _>>()?;
+        assert_eq!(chunks, vec!["Hel", "lo!"]);
+
+        let messages = &agent.state().messages;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].text().as_deref(), Some("Say hello"));
+        assert_eq!(messages[1].text().as_deref(), Some("Hello!"));
+
+        agent.close_realtime().await?;
+        let received = server.await.unwrap();
+        assert_eq!(received[0]["type"], "conversation.item.create");
+        assert_eq!(received[0]["item"]["content"][0]["text"], "Say hello");
+        assert_eq!(received[1]["type"], "response.create");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_system_prompts_compose_in_order() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::SystemPromptMode;
+
+    let builder = || {
+        AgentBuilder::new()
+            .with_config(mock_config())
+            .with_transport(Arc::new(MockTransport::new()))
+            .with_message(agio::ChatMessage::user("Hi"))
+            .with_system_prompt("Feature instructions.")
+            .with_system_prompt_prepend("Base persona.")
+            .with_system_prompts(vec!["Rule one.".to_string(), "Rule two.".to_string()])
+            .with_system_prompt_append("Closing note.")
+    };
+
+    let separate = builder().build()?;
+    let texts: Vec<_> = separate
+        .state()
+        .messages
+        .iter()
+        .map(|m| (m.role.clone(), m.text().unwrap_or_default()))
+        .collect();
+    assert_eq!(
+        texts,
+        vec![
+            ("system".to_string(), "Base persona.".to_string()),
+            ("system".to_string(), "Feature instructions.".to_string()),
+            ("system".to_string(), "Rule one.".to_string()),
+            ("system".to_string(), "Rule two.".to_string()),
+            ("system".to_string(), "Closing note.".to_string()),
+            ("user".to_string(), "Hi".to_string()),
+        ]
+    );
+
+    let joined = builder().with_system_prompt_mode(SystemPromptMode::Joined).build()?;
+    let messages = &joined.state().messages;
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].role, "system");
+    assert_eq!(
+        messages[0].text().as_deref(),
+        Some("Base persona.\n\nFeature instructions.\n\nRule one.\n\nRule two.\n\nClosing note.")
+    );
+    assert_eq!(messages[1].role, "user");
+
+    Ok(())
+}
+
+#[test]
+fn test_hosted_tools_are_sent_and_not_executed() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::{ChatMessage, HostedTool, ToolCall};
+
+    run_async(async {
+        let mut registry = reverse_registry();
+        registry
+            .register_hosted(HostedTool::WebSearch)
+            .register_hosted(HostedTool::FileSearch { vector_store_ids: vec!["vs_123".to_string()] })
+            .register_hosted(HostedTool::WebSearch);
+
+        // The search was resolved server-side; only the answer is left to the agent
+        let search = ToolCall {
+            call_type: "web_search_preview".to_string(),
+            ..ToolCall::function("ws_1", "web_search_preview", "{}")
+        };
+        let (builder, transport) = agent_with([
+            MockTransport::message_response(
+        ChatMessage::assistant_with_tool_calls(Some("It is sunny.".to_string()), vec![search]),
+        "stop",
+    ),
+        ]);
+
+        let mut agent = builder
+            .with_tools(registry)
+            .build()?;
+        assert_eq!(agent.run("What's the weather?").await?, "It is sunny.");
+        assert_eq!(agent.tool_call_count(), 0);
+        assert_eq!(transport.requests().len(), 1);
+
+        let tools = serde_json::to_value(transport.requests()[0].tools.as_ref().unwrap())?;
+        assert_eq!(tools[0]["type"], "function");
+        assert_eq!(tools[1], serde_json::json!({ "type": "web_search_preview" }));
+        assert_eq!(
+            tools[2],
+            serde_json::json!({ "type": "file_search", "vector_store_ids": ["vs_123"] })
+        );
+        assert_eq!(tools.as_array().map(Vec::len), Some(3));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_function_named_like_hosted_tool_runs_locally() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::HostedTool;
+
+    run_async(async {
+        let mut registry = ToolRegistry::new();
+        registry
+            .register_fn("file_search", "Reverses a given string of text.", reverse_string)
+            .register_hosted(HostedTool::FileSearch { vector_store_ids: vec!["vs_123".to_string()] });
+
+        let (builder, transport) = agent_with([
+            MockTransport::tool_call_response([("call_1", "file_search", r#"{"text":"abc"}"#)]),
+            MockTransport::text_response("Done"),
+        ]);
+        let mut agent = builder.with_tools(registry).build()?;
+
+        assert_eq!(agent.run("Search my files").await?, "Done");
+        assert_eq!(agent.tool_call_count(), 1);
+        let requests = transport.requests();
+        let result = requests[1].messages.last().unwrap();
+        assert_eq!(result.role, "tool");
+        assert_eq!(result.text().as_deref(), Some("cba"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_pinned_messages_survive_trimming_and_summarization() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::text_response("Noted"),
+            MockTransport::text_response("Fine"),
+        ]);
+
+        let mut agent = builder
+            .with_system_prompt("Be brief.")
+            .with_message(agio::ChatMessage::user("Remember: my name is Ada.").pinned())
+            .with_max_history_messages(2)
+            .build()?;
+
+        agent.run("First").await?;
+        agent.run("Second").await?;
+        assert!(agent.pin_message(99).is_err());
+
+        // The pinned message outlives older, unpinned ones
+        let last_request = transport.requests().pop().unwrap();
+        let texts: Vec<_> = last_request.messages.iter().map(|m| m.text().unwrap_or_default()).collect();
+        assert_eq!(texts, vec!["Be brief.", "Remember: my name is Ada.", "Noted", "Second"]);
+        assert!(agent.state().messages[1].pinned);
+
+        let summarizing = Arc::new(
+            MockTransport::new().with_response(MockTransport::text_response("They said hello twice.")),
+        );
+        let mut agent = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_messages(vec![
+                agio::ChatMessage::user("Hello"),
+                agio::ChatMessage::assistant("Hi"),
+                agio::ChatMessage::user("Here is my document."),
+                agio::ChatMessage::user("Hello again"),
+                agio::ChatMessage::assistant("Hi again"),
+            ])
+            .with_transport(summarizing.clone())
+            .with_auto_summarize(agio::SummarizeConfig {
+                keep_recent: 0,
+                trigger_tokens: usize::MAX,
+                ..Default::default()
+            })
+            .build()?;
+        agent.pin_message(2)?;
+        agent.unpin_message(2)?;
+        agent.pin_message(2)?;
+
+        assert!(agent.summarize_now().await?);
+        let transcript = summarizing.requests()[0].messages[1].text().unwrap_or_default();
+        assert!(!transcript.contains("Here is my document."), "{}", transcript);
+
+        let texts: Vec<_> = agent.state().messages().map(|m| m.text().unwrap_or_default()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "Summary of the conversation so far:\nThey said hello twice.".to_string(),
+                "Here is my document.".to_string(),
+            ]
+        );
+        assert_eq!(agent.state().messages[0].role, "system");
+        assert!(agent.state().messages[1].pinned);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_auto_summarize_folds_previous_summary() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::Usage;
+
+    run_async(async {
+        let mut first_summary = MockTransport::text_response("They said hello.");
+        first_summary.usage = Some(Usage {
+            prompt_tokens: 7,
+            completion_tokens: 3,
+            total_tokens: 10,
+            ..Default::default()
+        });
+        let (builder, transport) = agent_with([
+            first_summary,
+            MockTransport::text_response("Answer one"),
+            MockTransport::text_response("They said hello and asked one question."),
+            MockTransport::text_response("Answer two"),
+        ]);
+
+        let mut agent = builder
+            .with_system_prompt("Be brief.")
+            .with_messages(vec![
+                agio::ChatMessage::user("Hello"),
+                agio::ChatMessage::assistant("Hi"),
+            ])
+            .with_auto_summarize(agio::SummarizeConfig {
+                trigger_tokens: usize::MAX,
+                trigger_messages: Some(3),
+                keep_recent: 1,
+                ..Default::default()
+            })
+            .build()?;
+
+        // The summarization call counts towards the run's usage
+        let outcome = agent.run_detailed("Question one").await?;
+        assert_eq!(outcome.usage.total_tokens, 10);
+        assert_eq!(agent.state().token_count, 10);
+
+        agent.run("Question two").await?;
+
+        // The second summary replaced the first instead of piling up after it
+        let transcript = transport.requests()[2].messages[1].text().unwrap_or_default();
+        assert!(transcript.contains("They said hello."), "{}", transcript);
+        let roles: Vec<_> = agent.state().messages().map(|m| m.role.clone()).collect();
+        assert_eq!(roles, vec!["system", "system", "user", "assistant"]);
+        assert_eq!(
+            agent.state().messages[1].text().as_deref(),
+            Some("Summary of the conversation so far:\nThey said hello and asked one question.")
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_finish_reason_handler() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::{ChatMessage, FinishAction};
+
+    run_async(async {
+        let transport = Arc::new(
+            MockTransport::new()
+                .with_response(MockTransport::message_response(ChatMessage::assistant("Once upon"), "length"))
+                .with_response(MockTransport::text_response("a time."))
+                .with_response(MockTransport::message_response(ChatMessage::assistant("Cut off"), "length")),
+        );
+
+        let mut agent = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_transport(transport.clone())
+            .with_finish_reason_handler(|reason, _message| match reason {
+                "length" => FinishAction::Continue,
+                _ => FinishAction::Default,
+            })
+            .build()?;
+
+        assert_eq!(agent.run("Tell a story").await?, "a time.");
+        // The request to continue is sent, but not kept in the history
+        let roles: Vec<_> = agent.state().messages().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["user", "assistant", "assistant"]);
+        assert_eq!(
+            transport.requests()[1].messages[2].text().as_deref(),
+            Some("Continue exactly where you left off.")
+        );
+        assert_eq!(transport.requests()[1].messages.len(), 3);
+
+        let mut strict = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_transport(transport.clone())
+            .with_finish_reason_handler(|reason, _message| match reason {
+                "length" => FinishAction::Error,
+                _ => FinishAction::Return,
+            })
+            .build()?;
+        assert!(matches!(strict.run("Another").await, Err(Error::Agent(_))));
+        assert_eq!(strict.state().message_count(), 1);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_auto_continue_stitches_truncated_responses() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::ChatMessage;
+
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::message_response(ChatMessage::assistant("Once "), "length"),
+            MockTransport::message_response(ChatMessage::assistant("upon "), "length"),
+            MockTransport::text_response("a time."),
+            MockTransport::message_response(ChatMessage::assistant("A"), "length"),
+            MockTransport::message_response(ChatMessage::assistant("B"), "length"),
+        ]);
+
+        let mut agent = builder
+            .with_auto_continue(2)
+            .build()?;
+
+        assert_eq!(agent.run("Tell a story").await?, "Once upon a time.");
+        let texts: Vec<_> = agent.state().messages().map(|m| m.text().unwrap_or_default()).collect();
+        assert_eq!(texts, vec!["Tell a story", "Once upon a time."]);
+
+        // The continuation request carried the stitched partial and the nudge
+        let roles: Vec<_> = transport.requests()[2].messages.iter().map(|m| m.role.clone()).collect();
+        assert_eq!(roles, vec!["user", "assistant", "user"]);
+        assert_eq!(transport.requests()[2].messages[1].text().as_deref(), Some("Once upon "));
+
+        // The cap stops continuing and returns what was stitched so far
+        let mut capped = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_transport(transport.clone())
+            .with_auto_continue(1)
+            .build()?;
+        assert_eq!(capped.run("Go").await?, "AB");
+        assert_eq!(capped.state().message_count(), 2);
+        assert_eq!(transport.remaining(), 0);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_failed_continuation_leaves_no_trace() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::{AgentEventKind, ChatMessage};
+
+    run_async(async {
+        let (events, mut receiver) = tokio::sync::broadcast::channel(64);
+        let (builder, transport) = agent_with([
+            MockTransport::message_response(ChatMessage::assistant("Once "), "length"),
+        ]);
+        transport.push_error(Error::Agent("boom".to_string()));
+
+        let mut agent = builder
+            .with_auto_continue(1)
+            .with_event_sender(events)
+            .build()?;
+        assert!(agent.run("Tell a story").await.is_err());
+
+        // The nudge only went out with the request
+        let roles: Vec<_> = transport.requests()[1].messages.iter().map(|m| m.role.clone()).collect();
+        assert_eq!(roles, vec!["user", "assistant", "user"]);
+        let texts: Vec<_> = agent.state().messages().map(|m| m.text().unwrap_or_default()).collect();
+        assert_eq!(texts, vec!["Tell a story"]);
+
+        let mut messages = 0;
+        while let Ok(event) = receiver.try_recv() {
+            if let AgentEventKind::Message(_) = event.kind {
+                messages += 1;
+            }
+        }
+        assert_eq!(messages, 1);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_sanitize_messages_strips_control_characters() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::text_response("Got it"),
+            MockTransport::text_response("Clean"),
+        ]);
+
+        let mut agent = builder
+            .with_sanitize_messages(true)
+            .build()?;
+        assert_eq!(agent.run("nul\u{0000}byte\tand\nlines").await?, "Got it");
+
+        // Only the request is cleaned; the history keeps what was sent in
+        let sent = transport.requests()[0].messages[0].text().unwrap_or_default();
+        assert_eq!(sent, "nulbyte\tand\nlines");
+        assert!(!serde_json::to_string(&transport.requests()[0])?.contains("\\u0000"));
+        assert_eq!(agent.state().messages[0].text().as_deref(), Some("nul\u{0000}byte\tand\nlines"));
+
+        let mut cleaning = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_transport(transport.clone())
+            .with_sanitize_messages(true)
+            .with_sanitize_history(true)
+            .build()?;
+        cleaning.run("bell\u{0007}").await?;
+        assert_eq!(cleaning.state().messages[0].text().as_deref(), Some("bell"));
+        Ok(())
+    })
+}
+
+#[test]
+fn test_runtime_setters_change_later_requests() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let (builder, transport) = agent_with([
+            MockTransport::text_response("First"),
+            MockTransport::text_response("Second"),
+        ]);
+
+        let mut agent = builder.build()?;
+        agent.run("Hello").await?;
+
+        agent.set_model("gpt-4o-mini")?;
+        agent.set_temperature(1.5)?;
+        agent.set_max_tokens(64)?;
+        agent.set_max_turns(20)?;
+
+        // Invalid values are rejected and leave the settings unchanged
+        assert!(matches!(agent.set_temperature(3.0), Err(Error::Config(_))));
+        assert!(matches!(agent.set_max_tokens(0), Err(Error::Config(_))));
+        assert!(matches!(agent.set_model(" "), Err(Error::Config(_))));
+        assert!(matches!(agent.set_max_turns(0), Err(Error::Config(_))));
+
+        agent.run("Again").await?;
+        let requests = transport.requests();
+        assert_eq!(requests[0].model, "gpt-4o");
+        assert_eq!(requests[0].temperature, Some(0.0));
+        assert_eq!(requests[1].model, "gpt-4o-mini");
+        assert_eq!(requests[1].temperature, Some(1.5));
+        assert_eq!(
+            requests[1].max_completion_tokens.or(requests[1].max_tokens),
+            Some(64)
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_raw_tool_calls_are_kept_per_response() -> Result<(), Box<dyn std::error::Error>> {
+    run_async(async {
+        let raw_args = r#"{ "text" :  "abc" }"#;
+        let (builder, _) = agent_with([
+            MockTransport::tool_call_response(vec![("call_1", "reverse_string", raw_args)]),
+            MockTransport::text_response("cba"),
+            MockTransport::tool_call_response(vec![(
+                "call_2",
+                "reverse_string",
+                r#"{"text":"two"}"#,
+            )]),
+        ]);
+
+        let mut agent = builder
+            .with_tools(reverse_registry())
+            .with_max_turns(2)
+            .build()?;
+
+        let outcome = agent.run_detailed("Reverse abc").await?;
+        assert_eq!(outcome.tool_calls.len(), 2);
+        assert_eq!(outcome.tool_calls[0][0].id, "call_1");
+        assert_eq!(outcome.tool_calls[0][0].function.arguments, raw_args);
+        assert!(outcome.tool_calls[1].is_empty());
+        assert_eq!(agent.last_tool_calls().len(), 2);
+        assert_eq!(agent.last_tool_calls()[0][0].id, "call_1");
+
+        // Still available when the run fails
+        agent.set_max_turns(1)?;
+        assert!(agent.run("Reverse two").await.is_err());
+        assert_eq!(agent.last_tool_calls().len(), 1);
+        assert_eq!(agent.last_tool_calls()[0][0].id, "call_2");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_invalid_tool_name_rejected_at_build() {
+    let mut registry = ToolRegistry::new();
+    registry.register_fn("reverse string", "Name has a space.", reverse_string);
+
+    let result = AgentBuilder::new()
+        .with_config(mock_config())
+        .with_tools(registry)
+        .with_transport(Arc::new(MockTransport::new()))
+        .build();
+    match result {
+        Err(Error::Config(message)) => assert!(message.contains("reverse string"), "{}", message),
+        _ => panic!("build should reject the tool name"),
+    }
+}
+
+#[test]
+fn test_retry_budget_is_shared_across_the_run() -> Result<(), Box<dyn std::error::Error>> {
+    let server_error = || Error::Api { status: 503, message: "overloaded".to_string() };
+
+    run_async(async {
+        let transport = Arc::new(
+            MockTransport::new()
+                .with_error(server_error())
+                .with_response(MockTransport::tool_call_response(vec![(
+                    "call_1",
+                    "reverse_string",
+                    r#"{"text":"abc"}"#,
+                )]))
+                .with_error(server_error())
+                .with_response(MockTransport::text_response("cba")),
+        );
+
+        let mut agent = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_tools(reverse_registry())
+            .with_transport(transport.clone())
+            .with_total_retries_per_run(2)
+            .build()?;
+
+        let outcome = agent.run_detailed("Reverse abc").await?;
+        assert_eq!(outcome.content, "cba");
+        assert_eq!(outcome.retries, 2);
+
+        // One retry for the whole run: the second failure ends it
+        transport.push_error(server_error());
+        transport.push_response(MockTransport::tool_call_response(vec![(
+            "call_2",
+            "reverse_string",
+            r#"{"text":"xyz"}"#,
+        )]));
+        transport.push_error(server_error());
+        let mut limited = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_tools(reverse_registry())
+            .with_transport(transport.clone())
+            .with_total_retries_per_run(1)
+            .build()?;
+        let result = limited.run("Reverse xyz").await;
+        assert!(matches!(result, Err(Error::Api { status: 503, .. })));
+
+        assert_eq!(transport.remaining(), 0);
+
+        // Client errors are never retried
+        let rejecting = Arc::new(
+            MockTransport::new()
+                .with_error(Error::Api { status: 400, message: "bad request".to_string() })
+                .with_response(MockTransport::text_response("unused")),
+        );
+        let mut strict = AgentBuilder::new()
+            .with_config(mock_config())
+            .with_transport(rejecting.clone())
+            .with_total_retries_per_run(3)
+            .build()?;
+        assert!(matches!(strict.run("Hi").await, Err(Error::Api { status: 400, .. })));
+        assert_eq!(rejecting.remaining(), 1);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_retry_budget_covers_summaries_and_streams() -> Result<(), Box<dyn std::error::Error>> {
+    use futures::StreamExt;
+
+    let server_error = || Error::Api { status: 503, message: "overloaded".to_string() };
+
+    run_async(async {
+        // The summarization call and the turn after it share the budget
+        let (builder, transport) = agent_with([]);
+        transport.push_error(server_error());
+        transport.push_response(MockTransport::text_response("They said hello."));
+        transport.push_error(server_error());
+        transport.push_response(MockTransport::text_response("Hello again"));
+        let mut agent = builder
+            .with_messages(vec![agio::ChatMessage::user("Hello"), agio::ChatMessage::assistant("Hi")])
+            .with_auto_summarize(agio::SummarizeConfig {
+                trigger_tokens: usize::MAX,
+                trigger_messages: Some(2),
+                keep_recent: 1,
+                ..Default::default()
+            })
+            .with_total_retries_per_run(2)
+            .build()?;
+        let outcome = agent.run_detailed("Hello?").await?;
+        assert_eq!(outcome.content, "Hello again");
+        assert_eq!(outcome.retries, 2);
+        assert_eq!(transport.remaining(), 0);
+
+        // A stream that fails to open is retried within the budget
+        let streaming = |retries| {
+            let transport = Arc::new(
+                MockTransport::new()
+                    .with_error(server_error())
+                    .with_response(MockTransport::text_response("Streamed")),
+            );
+            AgentBuilder::new()
+                .with_config(mock_config())
+                .with_transport(transport)
+                .with_total_retries_per_run(retries)
+                .build()
+        };
+        let mut agent = streaming(1)?;
+        let mut streamed = String::new();
+        {
+            let mut stream = agent.run_stream("Hi");
+            while let Some(delta) = stream.next().await {
+                streamed.push_str(&delta?);
+            }
+        }
+        assert_eq!(streamed, "Streamed");
+
+        let mut agent = streaming(0)?;
+        let mut stream = agent.run_stream("Hi");
+        let mut failed = false;
+        while let Some(delta) = stream.next().await {
+            failed |= matches!(delta, Err(Error::Api { status: 503, .. }));
+        }
+        assert!(failed);
+        Ok(())
+    })
+}
@@ -2,6 +2,7 @@
 
 use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::runtime::Runtime;
 
 // Import from the public API
@@ -9,7 +10,7 @@ use agio::{
     AgentBuilder, Config, Error,
 };
 // Import persistence and server modules
-use agio::persistence::{MemoryStore, PersistenceStore, ConversationMetadata, PostgresStore};
+use agio::persistence::{MemoryStore, PersistenceStore, ConversationMetadata, PgStoreOptions, PostgresStore};
 use agio::server::AgentManager;
 
 #[test]
@@ -228,4 +229,727 @@ fn test_postgres_persistence() -> Result<(), Box<dyn std::error::Error>> {
         
         Ok(())
     })
-} 
\ No newline at end of file
+} 
+
+#[test]
+fn test_postgres_invalid_pool_options() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        // Options are validated before any connection attempt
+        let options = PgStoreOptions {
+            max_connections: 2,
+            min_connections: 4,
+            ..Default::default()
+        };
+        let result = PostgresStore::with_options("postgres://localhost/unused", options).await;
+        assert!(matches!(result, Err(Error::Config(_))));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn test_memory_store_prune() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::AgentState;
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let store = MemoryStore::new();
+        for id in ["a", "b", "c"] {
+            store.store_conversation(id, &AgentState::default()).await?;
+        }
+
+        // Nothing is older than an hour ago
+        let hour_ago = chrono::Utc::now() - chrono::Duration::hours(1);
+        assert_eq!(store.delete_older_than(hour_ago).await?, 0);
+
+        assert_eq!(store.delete_conversations(&["a".to_string(), "missing".to_string()]).await?, 1);
+
+        let cutoff = chrono::Utc::now() + chrono::Duration::seconds(1);
+        assert_eq!(store.delete_older_than(cutoff).await?, 2);
+        assert!(store.list_conversations(10, 0).await?.is_empty());
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_derived_id_loads_existing_conversation() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::derive_id;
+    use agio::MockTransport;
+
+    assert_eq!(derive_id("users", "42"), derive_id("users", "42"));
+    assert_ne!(derive_id("users", "42"), derive_id("teams", "42"));
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let store = Arc::new(MemoryStore::new());
+        let transport = Arc::new(MockTransport::new().with_response(MockTransport::text_response("Hi")));
+
+        let mut agent = AgentBuilder::new()
+            .with_config(Config::new().with_model("gpt-4o"))
+            .with_transport(transport.clone())
+            .with_persistence(store.clone())
+            .with_derived_id("users", "42")
+            .build_async()
+            .await?;
+        agent.run("Hello").await?;
+
+        let reloaded = AgentBuilder::new()
+            .with_config(Config::new().with_model("gpt-4o"))
+            .with_transport(transport)
+            .with_persistence(store.clone())
+            .with_derived_id("users", "42")
+            .build_async()
+            .await?;
+        assert_eq!(reloaded.id(), agent.id());
+        assert_eq!(reloaded.state().message_count(), 2);
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_store_keeps_deleted_and_trimmed_messages() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::AuditStore;
+    use agio::MockTransport;
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let path = env::temp_dir().join(format!("agio-audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let stores = [
+            Arc::new(AuditStore::new(MemoryStore::new())),
+            Arc::new(AuditStore::with_file(MemoryStore::new(), &path)),
+        ];
+
+        for store in stores {
+            let transport = Arc::new(
+                MockTransport::new()
+                    .with_response(MockTransport::text_response("One"))
+                    .with_response(MockTransport::text_response("Two"))
+                    .with_response(MockTransport::text_response("Three")),
+            );
+            let mut agent = AgentBuilder::new()
+                .with_config(Config::new().with_model("gpt-4o"))
+                .with_transport(transport)
+                .with_persistence(store.clone())
+                .with_max_history_messages(2)
+                .build()?;
+
+            for question in ["First", "Second", "Third"] {
+                agent.run(question).await?;
+            }
+            store.delete_conversation(agent.id()).await?;
+            assert!(store.get_conversation(agent.id()).await?.is_none());
+
+            let audit: Vec<_> = store
+                .read_audit(agent.id())
+                .await?
+                .iter()
+                .map(|message| message.text().unwrap_or_default())
+                .collect();
+            assert_eq!(audit, ["First", "One", "Second", "Two", "Third", "Three"]);
+            assert!(store.read_audit("other").await?.is_empty());
+        }
+
+        std::fs::remove_file(&path)?;
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_store_logs_rewritten_messages_once() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::AuditStore;
+    use agio::MockTransport;
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let path = env::temp_dir().join(format!("agio-audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let stores = [
+            Arc::new(AuditStore::new(MemoryStore::new())),
+            Arc::new(AuditStore::with_file(MemoryStore::new(), &path)),
+        ];
+
+        for store in stores {
+            let transport = Arc::new(
+                MockTransport::new()
+                    .with_response(MockTransport::text_response("One"))
+                    .with_response(MockTransport::text_response("Two")),
+            );
+            let mut agent = AgentBuilder::new()
+                .with_config(Config::new().with_model("gpt-4o"))
+                .with_transport(transport)
+                .with_persistence(store.clone())
+                .build()?;
+
+            agent.run("First").await?;
+            agent.last_message_mut().unwrap().content = Some("One, edited".into());
+            agent.save().await?;
+            agent.save().await?;
+            agent.run("Second").await?;
+
+            // The edit is logged once as a new version, and nothing else is repeated
+            let audit = store.read_audit(agent.id()).await?;
+            let texts: Vec<_> = audit.iter().map(|message| message.text().unwrap_or_default()).collect();
+            assert_eq!(texts, ["First", "One", "One, edited", "Second", "Two"]);
+
+            let ids: Vec<_> = audit.iter().map(|message| message.id.clone().unwrap()).collect();
+            let current: Vec<_> = agent.state().messages().map(|message| message.id.clone().unwrap()).collect();
+            assert_eq!(ids[1], ids[2]);
+            assert_eq!([&ids[..2], &ids[3..]].concat(), current);
+        }
+
+        std::fs::remove_file(&path)?;
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_builder_id_generator() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::IdGenerator;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Sequential(AtomicUsize);
+
+    impl IdGenerator for Sequential {
+        fn generate(&self) -> agio::EntityId {
+            format!("conv-{:04}", self.0.fetch_add(1, Ordering::SeqCst))
+        }
+    }
+
+    let generator = Arc::new(Sequential(AtomicUsize::new(1)));
+    let build = |builder: AgentBuilder| {
+        builder
+            .with_config(Config::new().with_model("gpt-4o"))
+            .with_transport(Arc::new(agio::MockTransport::new()))
+            .with_id_generator(generator.clone())
+            .build()
+    };
+
+    assert_eq!(build(AgentBuilder::new())?.id(), "conv-0001");
+    assert_eq!(build(AgentBuilder::new())?.id(), "conv-0002");
+    assert_eq!(build(AgentBuilder::new().with_id("explicit"))?.id(), "explicit");
+
+    // Without a generator, IDs are UUIDv4
+    let agent = AgentBuilder::new()
+        .with_config(Config::new().with_model("gpt-4o"))
+        .with_transport(Arc::new(agio::MockTransport::new()))
+        .build()?;
+    assert_eq!(uuid::Uuid::parse_str(agent.id())?.get_version_num(), 4);
+    Ok(())
+}
+
+/// Store whose backend has gone away: every call fails
+struct UnreachableStore;
+
+#[async_trait::async_trait]
+impl PersistenceStore for UnreachableStore {
+    async fn store_conversation(&self, _id: &str, _state: &agio::AgentState) -> Result<(), Error> {
+        Err(Error::Persistence("connection refused".to_string()))
+    }
+
+    async fn get_conversation(&self, _id: &str) -> Result<Option<agio::AgentState>, Error> {
+        Err(Error::Persistence("connection refused".to_string()))
+    }
+
+    async fn delete_conversation(&self, _id: &str) -> Result<(), Error> {
+        Err(Error::Persistence("connection refused".to_string()))
+    }
+
+    async fn list_conversations(&self, _limit: usize, _offset: usize) -> Result<Vec<ConversationMetadata>, Error> {
+        Err(Error::Persistence("connection refused".to_string()))
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        Err(Error::Persistence("connection refused".to_string()))
+    }
+}
+
+#[test]
+fn test_store_ping_and_manager_readiness() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::AuditStore;
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        MemoryStore::new().ping().await?;
+        AuditStore::new(MemoryStore::new()).ping().await?;
+        assert!(AuditStore::new(UnreachableStore).ping().await.is_err());
+
+        let config = Config::new().with_model("gpt-4o");
+        AgentManager::new(config.clone(), Arc::new(MemoryStore::new()), 10).ready(false).await?;
+
+        let manager = AgentManager::new(config, Arc::new(UnreachableStore), 10);
+        assert!(matches!(manager.ready(false).await, Err(Error::Persistence(_))));
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+/// Store that can be read but rejects every write
+struct ReadOnlyStore;
+
+#[async_trait::async_trait]
+impl PersistenceStore for ReadOnlyStore {
+    async fn store_conversation(&self, _id: &str, _state: &agio::AgentState) -> Result<(), Error> {
+        Err(Error::Persistence("read-only".to_string()))
+    }
+
+    async fn get_conversation(&self, _id: &str) -> Result<Option<agio::AgentState>, Error> {
+        Ok(None)
+    }
+
+    async fn delete_conversation(&self, _id: &str) -> Result<(), Error> {
+        Err(Error::Persistence("read-only".to_string()))
+    }
+
+    async fn list_conversations(&self, _limit: usize, _offset: usize) -> Result<Vec<ConversationMetadata>, Error> {
+        Ok(Vec::new())
+    }
+}
+
+#[test]
+fn test_periodic_flush_reports_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let manager = Arc::new(
+            AgentManager::new(Config::new().with_model("gpt-4o"), Arc::new(ReadOnlyStore), 10)
+                .with_transport(Arc::new(agio::MockTransport::new()))
+                .with_autosave(false),
+        );
+        manager.create_agent().await?;
+
+        let (errors, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let flusher = manager.spawn_periodic_flush(Duration::from_millis(10), move |e| {
+            let _ = errors.send(e);
+        });
+        let error = tokio::time::timeout(Duration::from_secs(5), received.recv()).await;
+        flusher.abort();
+        assert!(matches!(error, Ok(Some(Error::Persistence(_)))));
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_export_finetune_jsonl() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::{AgentState, ChatMessage, ToolCall};
+
+    let state = AgentState {
+        messages: vec![
+            ChatMessage::developer("You are a weather bot."),
+            ChatMessage::user("Weather in Paris?"),
+            ChatMessage::assistant_with_tool_calls(
+                None,
+                vec![ToolCall::function("call_1", "get_weather", r#"{"city":"Paris"}"#)],
+            ),
+            ChatMessage::tool_result("Sunny", "get_weather", "call_1"),
+            ChatMessage::assistant("It is sunny in Paris."),
+        ],
+        ..Default::default()
+    };
+
+    let line: serde_json::Value = serde_json::from_str(state.to_finetune_jsonl(true)?.trim_end())?;
+    assert_eq!(
+        line,
+        serde_json::json!({"messages": [
+            {"role": "system", "content": "You are a weather bot."},
+            {"role": "user", "content": "Weather in Paris?"},
+            {"role": "assistant", "tool_calls": [
+                {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}}
+            ]},
+            {"role": "tool", "tool_call_id": "call_1", "content": "Sunny"},
+            {"role": "assistant", "content": "It is sunny in Paris."}
+        ]})
+    );
+
+    let line: serde_json::Value = serde_json::from_str(state.to_finetune_jsonl(false)?.trim_end())?;
+    let roles: Vec<_> = line["messages"].as_array().unwrap().iter().map(|m| m["role"].clone()).collect();
+    assert_eq!(roles, vec!["system", "user", "assistant"]);
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let store = MemoryStore::new();
+        store.store_conversation("weather", &state).await?;
+        let unanswered = AgentState { messages: vec![ChatMessage::user("Hello?")], ..Default::default() };
+        store.store_conversation("unanswered", &unanswered).await?;
+
+        let ids = vec!["weather".to_string(), "unanswered".to_string(), "weather".to_string()];
+        let export = store.export_finetune(&ids, false).await?;
+        assert_eq!(export.lines().count(), 2);
+        assert!(export.ends_with('\n'));
+
+        assert!(store.export_finetune(&["missing".to_string()], false).await.is_err());
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_agent_manager_shares_transport() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::MockTransport;
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let transport = Arc::new(
+            MockTransport::new()
+                .with_response(MockTransport::text_response("First"))
+                .with_response(MockTransport::text_response("Second")),
+        );
+        let manager = AgentManager::new(Config::new().with_model("gpt-4o"), Arc::new(MemoryStore::new()), 10)
+            .with_transport(transport.clone());
+
+        let first = manager.create_agent().await?;
+        let second = manager.create_agent().await?;
+        assert_eq!(manager.run_message(&first, "Hello").await?, "First");
+        assert_eq!(manager.run_message(&second, "Hello").await?, "Second");
+
+        // Both agents went through the one transport
+        assert_eq!(transport.requests().len(), 2);
+        assert_eq!(transport.remaining(), 0);
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_blob_store_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::{BlobStore, KvBackend, MemoryBackend};
+    use agio::{AgentState, ChatMessage};
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let store = BlobStore::new(MemoryBackend::new());
+
+        let mut partial = ChatMessage::assistant("Half an ans");
+        partial.incomplete = true;
+        let state = AgentState {
+            messages: vec![
+                ChatMessage::system("Be brief.").pinned(),
+                ChatMessage::user("Hello"),
+                partial,
+            ],
+            token_count: 42,
+            version: 3,
+            dropped_count: 1,
+            ..Default::default()
+        };
+        store.store_conversation("conv-1", &state).await?;
+        store.store_conversation("conv-2", &AgentState::default()).await?;
+        store.ping().await?;
+
+        // Stored as one JSON blob per conversation
+        let keys = store.backend().list("conversations/").await?;
+        assert_eq!(keys, vec!["conversations/conv-1", "conversations/conv-2"]);
+        let raw = store.backend().get("conversations/conv-1").await?.unwrap();
+        assert!(serde_json::from_slice::<serde_json::Value>(&raw).is_ok());
+
+        let loaded = store.get_conversation("conv-1").await?.unwrap();
+        assert_eq!(loaded.messages.len(), 3);
+        assert!(loaded.messages[0].pinned);
+        assert!(loaded.messages[2].incomplete);
+        assert_eq!(loaded.messages[2].text().as_deref(), Some("Half an ans"));
+        assert_eq!((loaded.token_count, loaded.version, loaded.dropped_count), (42, 3, 1));
+
+        let listed = store.list_conversations(10, 0).await?;
+        assert_eq!(listed.len(), 2);
+        let conv_1 = listed.iter().find(|meta| meta.id == "conv-1").unwrap();
+        assert_eq!((conv_1.message_count, conv_1.token_count), (3, 42));
+
+        store.delete_conversation("conv-1").await?;
+        assert!(store.get_conversation("conv-1").await?.is_none());
+        assert_eq!(store.list_conversations(10, 0).await?.len(), 1);
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_agent_manager_preload() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::{AgentState, ChatMessage, MockTransport};
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let store = Arc::new(MemoryStore::new());
+        for id in ["a", "b", "c"] {
+            let state = AgentState {
+                messages: vec![ChatMessage::user(format!("Hello from {}", id))],
+                ..Default::default()
+            };
+            store.store_conversation(id, &state).await?;
+        }
+
+        let manager = AgentManager::new(Config::new().with_model("gpt-4o"), store.clone(), 10)
+            .with_transport(Arc::new(MockTransport::new()));
+
+        // Unknown ids are skipped rather than failing the batch
+        let ids = vec!["a".to_string(), "missing".to_string(), "b".to_string()];
+        assert_eq!(manager.preload(&ids).await?, 2);
+        // Already cached, so nothing new to load
+        assert_eq!(manager.preload(&ids).await?, 0);
+
+        // Preloaded agents are served from the cache without the store
+        store.delete_conversation("a").await?;
+        let agent = manager.get_agent("a").await?;
+        assert_eq!(agent.read().await.state().message_count(), 1);
+
+        assert_eq!(manager.preload_recent(10).await?, 1);
+        store.delete_conversation("c").await?;
+        assert!(manager.get_agent("c").await.is_ok());
+
+        // Never loads more than the cache holds
+        let small = AgentManager::new(Config::new().with_model("gpt-4o"), store.clone(), 1)
+            .with_transport(Arc::new(MockTransport::new()));
+        assert_eq!(small.preload(&["b".to_string(), "c".to_string()]).await?, 1);
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_get_conversations_and_load_many() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::{AgentState, ChatMessage, MockTransport};
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let store = Arc::new(MemoryStore::new());
+        for id in ["a", "b", "c"] {
+            let state = AgentState {
+                messages: vec![ChatMessage::user(format!("Preview of {}", id))],
+                ..Default::default()
+            };
+            store.store_conversation(id, &state).await?;
+        }
+
+        let ids = vec!["a".to_string(), "missing".to_string(), "c".to_string()];
+        let states = store.get_conversations(&ids).await?;
+        assert_eq!(states.len(), 2);
+        assert_eq!(states["c"].messages[0].text().as_deref(), Some("Preview of c"));
+        assert!(!states.contains_key("missing"));
+
+        let manager = AgentManager::new(Config::new().with_model("gpt-4o"), store.clone(), 10)
+            .with_transport(Arc::new(MockTransport::new()));
+        let cached = manager.get_agent("a").await?;
+
+        let agents = manager.load_many(&ids).await?;
+        assert_eq!(agents.len(), 2);
+        // Cached agents are returned as they are
+        assert!(Arc::ptr_eq(&agents["a"], &cached));
+        let c = agents["c"].read().await;
+        assert_eq!(c.id(), "c");
+        assert_eq!(c.state().messages[0].text().as_deref(), Some("Preview of c"));
+        drop(c);
+
+        // Loaded agents are cached like get_agent's
+        store.delete_conversation("c").await?;
+        assert!(Arc::ptr_eq(&manager.get_agent("c").await?, &agents["c"]));
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_message_ids_are_stable_across_rewrites() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::{BlobStore, MemoryBackend};
+    use agio::MockTransport;
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let store: Arc<dyn PersistenceStore> = Arc::new(BlobStore::new(MemoryBackend::new()));
+        let transport = Arc::new(
+            MockTransport::new()
+                .with_response(MockTransport::text_response("One"))
+                .with_response(MockTransport::text_response("Two")),
+        );
+
+        let mut agent = AgentBuilder::new()
+            .with_config(Config::new().with_model("gpt-4o"))
+            .with_transport(transport.clone())
+            .with_persistence(store.clone())
+            .with_id("stable")
+            .with_max_history_messages(2)
+            .build()?;
+        agent.run("First").await?;
+        let ids = |agent: &agio::Agent| -> Vec<String> {
+            agent.state().messages().map(|message| message.id.clone().unwrap()).collect()
+        };
+        let first_ids = ids(&agent);
+        assert_eq!(first_ids.len(), 2);
+        assert_ne!(first_ids[0], first_ids[1]);
+
+        // Trimming shifts positions, but the surviving message keeps its ID
+        agent.run("Second").await?;
+        let second_ids = ids(&agent);
+        assert_eq!(second_ids.len(), 3);
+        assert_eq!(second_ids[0], first_ids[1]);
+
+        // Saved and reloaded with the same IDs
+        agent.save().await?;
+        let reloaded = AgentBuilder::new()
+            .with_config(Config::new().with_model("gpt-4o"))
+            .with_transport(transport)
+            .with_persistence(store)
+            .with_id("stable")
+            .build_async()
+            .await?;
+        assert_eq!(ids(&reloaded), second_ids);
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+/// Saves a conversation and a copy of it seeded into a second agent, which keeps
+/// the original message IDs.
+async fn save_forked_conversation(store: Arc<dyn PersistenceStore>, prefix: &str) -> Result<(), Error> {
+    use agio::MockTransport;
+
+    let transport = Arc::new(
+        MockTransport::new()
+            .with_response(MockTransport::text_response("One"))
+            .with_response(MockTransport::text_response("Two")),
+    );
+    let original_id = format!("{}-original", prefix);
+    let fork_id = format!("{}-fork", prefix);
+
+    let mut original = AgentBuilder::new()
+        .with_config(Config::new().with_model("gpt-4o"))
+        .with_transport(transport.clone())
+        .with_persistence(store.clone())
+        .with_id(original_id.clone())
+        .build()?;
+    original.run("First").await?;
+    original.save().await?;
+
+    let mut fork = AgentBuilder::new()
+        .with_config(Config::new().with_model("gpt-4o"))
+        .with_transport(transport)
+        .with_persistence(store.clone())
+        .with_id(fork_id.clone())
+        .with_messages(original.state().messages.clone())
+        .build()?;
+    fork.run("Second").await?;
+    fork.save().await?;
+
+    let ids = |state: &agio::AgentState| -> Vec<Option<String>> {
+        state.messages().map(|message| message.id.clone()).collect()
+    };
+    let stored_original = store.get_conversation(&original_id).await?.unwrap();
+    let stored_fork = store.get_conversation(&fork_id).await?.unwrap();
+    assert_eq!(ids(&stored_original), ids(original.state()));
+    assert_eq!(ids(&stored_fork)[..2], ids(&stored_original)[..]);
+    assert_eq!(stored_fork.message_count(), 4);
+
+    store.delete_conversation(&original_id).await?;
+    store.delete_conversation(&fork_id).await?;
+    Ok(())
+}
+
+#[test]
+fn test_forked_conversation_keeps_message_ids() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        save_forked_conversation(Arc::new(MemoryStore::new()), "memory").await?;
+
+        // Message IDs are only unique within a conversation in PostgreSQL too
+        let Ok(db_url) = env::var("DATABASE_URL") else {
+            println!("Skipping PostgreSQL part: DATABASE_URL not set");
+            return Ok(());
+        };
+        match PostgresStore::new(&db_url).await {
+            Ok(store) => save_forked_conversation(Arc::new(store), &uuid::Uuid::new_v4().to_string()).await,
+            Err(e) => {
+                println!("Skipping PostgreSQL part: Failed to connect to database: {}", e);
+                Ok(())
+            }
+        }
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_available_tools_are_recorded_per_turn() -> Result<(), Box<dyn std::error::Error>> {
+    use agio::persistence::{BlobStore, MemoryBackend};
+    use agio::{HostedTool, MockTransport, ToolDefinition, ToolRegistry};
+
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let store: Arc<dyn PersistenceStore> = Arc::new(BlobStore::new(MemoryBackend::new()));
+        let transport = Arc::new(
+            MockTransport::new()
+                .with_response(MockTransport::tool_call_response(vec![("call_1", "lookup", "{}")]))
+                .with_response(MockTransport::text_response("Found it")),
+        );
+        let mut registry = ToolRegistry::new();
+        registry.register_with_definition(
+            ToolDefinition {
+                name: "lookup".to_string(),
+                description: "Looks something up.".to_string(),
+                parameters: serde_json::json!({ "type": "object", "properties": {} }),
+                strict: None,
+            },
+            |_args: serde_json::Value| async move { Ok::<_, Error>("42") },
+        );
+        registry.register_hosted(HostedTool::WebSearch);
+
+        let mut agent = AgentBuilder::new()
+            .with_config(Config::new().with_model("gpt-4o"))
+            .with_transport(transport.clone())
+            .with_tools(registry)
+            .with_persistence(store.clone())
+            .with_id("tools")
+            .build()?;
+        agent.run("Look it up").await?;
+
+        let mut offered = agent.available_tools_at_turn(0).unwrap().to_vec();
+        offered.sort();
+        assert_eq!(offered, ["lookup", "web_search_preview"]);
+        assert_eq!(agent.available_tools_at_turn(1).unwrap().len(), 2);
+        assert_eq!(agent.available_tools_at_turn(2), None);
+        assert_eq!(agent.state().turn_tools()[1].message_index, 3);
+
+        // The list survives a save and reload, and can be read without the messages
+        agent.save().await?;
+        let reloaded = AgentBuilder::new()
+            .with_config(Config::new().with_model("gpt-4o"))
+            .with_transport(transport)
+            .with_persistence(store.clone())
+            .with_id("tools")
+            .build_async()
+            .await?;
+        assert_eq!(reloaded.state().turn_tools(), agent.state().turn_tools());
+        assert_eq!(store.get_turn_tools("tools").await?.as_deref(), Some(agent.state().turn_tools()));
+        assert_eq!(store.get_turn_tools("missing").await?, None);
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
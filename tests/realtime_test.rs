@@ -0,0 +1,370 @@
+// tests/realtime_test.rs
+//
+// Tests of the Realtime WebSocket client and the agent's Realtime tool handling,
+// against a local WebSocket server.
+// These do not require network access or an OPENAI_API_KEY.
+
+use std::future::Future;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+use agio::websocket_client::{ProcessEnd, RealtimeSessionConfig, StopHandle, WebSocketClient};
+use agio::{AgentBuilder, Config, Error, ToolRegistry};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+type ServerSocket = WebSocketStream<TcpStream>;
+
+/// Runs an async test body on a fresh runtime.
+///
+/// The body returns the clients it used, so that they are dropped outside the
+/// runtime once it has finished.
+fn run_async<T>(test: impl Future<Output = Result<T, Error>>) -> Result<T, Box<dyn std::error::Error>> {
+    Ok(Runtime::new()?.block_on(test)?)
+}
+
+/// Accepts a single Realtime connection on a local port and runs `script` on it.
+///
+/// Returns a config pointing at the server and the task running the script.
+async fn serve_once<F, Fut, T>(script: F) -> (Config, JoinHandle<T>)
+where
+    F: FnOnce(ServerSocket) -> Fut + Send + 'static,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+        script(socket).await
+    });
+
+    let config = Config::new()
+        .with_api_key("test-key")
+        .with_base_url(format!("http://{}", addr));
+    (config, server)
+}
+
+/// Sends each event to the client as a text frame.
+async fn send_events(socket: &mut ServerSocket, events: &[Value]) {
+    for event in events {
+        socket.send(Message::Text(event.to_string().into())).await.unwrap();
+    }
+}
+
+/// Collects the JSON events the client sends until it closes the connection.
+///
+/// Also returns whether the client sent a Close frame, rather than just dropping
+/// the connection.
+async fn receive_until_close(socket: &mut ServerSocket) -> (Vec<Value>, bool) {
+    let mut received = Vec::new();
+    while let Some(Ok(message)) = socket.next().await {
+        match message {
+            Message::Text(text) => received.push(serde_json::from_str(&text).unwrap()),
+            Message::Close(_) => return (received, true),
+            _ => {}
+        }
+    }
+    (received, false)
+}
+
+async fn connected_client(config: Config) -> Result<WebSocketClient, Error> {
+    let mut client = WebSocketClient::new(config)?;
+    client.connect("gpt-4o-realtime-preview").await?;
+    Ok(client)
+}
+
+#[test]
+fn test_stop_closes_the_connection() -> Result<(), Box<dyn std::error::Error>> {
+    let client = run_async(async {
+        let (config, server) = serve_once(|mut socket| async move {
+            send_events(&mut socket, &[json!({"type": "session.created"})]).await;
+            receive_until_close(&mut socket).await
+        })
+        .await;
+        let mut client = connected_client(config).await?;
+
+        let stop = StopHandle::new();
+        let remote = stop.clone();
+        let mut events = Vec::new();
+        let end = client
+            .process_incoming_until(&stop, None, |event| {
+                events.push(event.event_type);
+                // Stop from the handler, as another task would
+                remote.stop();
+                Ok(())
+            })
+            .await?;
+
+        assert_eq!(end, ProcessEnd::Stopped);
+        assert_eq!(events, vec!["session.created"]);
+        assert!(stop.is_stopped());
+
+        let (received, closed) = server.await.unwrap();
+        assert!(received.is_empty());
+        assert!(closed, "stopping should close the connection gracefully");
+        Ok(client)
+    })?;
+    drop(client);
+    Ok(())
+}
+
+#[test]
+fn test_stop_from_another_task() -> Result<(), Box<dyn std::error::Error>> {
+    let client = run_async(async {
+        let (config, server) = serve_once(|mut socket| async move {
+            receive_until_close(&mut socket).await
+        })
+        .await;
+        let mut client = connected_client(config).await?;
+
+        let stop = StopHandle::new();
+        let remote = stop.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            remote.stop();
+        });
+
+        // The server stays quiet, so only the stop can end the loop
+        let end = client.process_incoming_until(&stop, None, |_| Ok(())).await?;
+        assert_eq!(end, ProcessEnd::Stopped);
+
+        let (_, closed) = server.await.unwrap();
+        assert!(closed);
+        Ok(client)
+    })?;
+    drop(client);
+    Ok(())
+}
+
+#[test]
+fn test_idle_timeout_leaves_the_connection_open() -> Result<(), Box<dyn std::error::Error>> {
+    let client = run_async(async {
+        let (config, server) = serve_once(|mut socket| async move {
+            // Wait for the client to give up once, then send an event
+            let ready = socket.next().await;
+            assert!(matches!(ready, Some(Ok(Message::Text(_)))));
+            send_events(&mut socket, &[json!({"type": "response.done"})]).await;
+            receive_until_close(&mut socket).await
+        })
+        .await;
+        let mut client = connected_client(config).await?;
+        let stop = StopHandle::new();
+
+        let mut events = Vec::new();
+        let end = client
+            .process_incoming_until(&stop, Some(Duration::from_millis(50)), |event| {
+                events.push(event.event_type);
+                Ok(())
+            })
+            .await?;
+        assert_eq!(end, ProcessEnd::IdleTimeout);
+        assert!(events.is_empty());
+
+        // The connection is still usable after the timeout
+        client.send_json(&json!({"type": "response.create"})).await?;
+        let end = client
+            .process_incoming_until(&stop, Some(Duration::from_millis(500)), |event| {
+                events.push(event.event_type);
+                Ok(())
+            })
+            .await?;
+        assert_eq!(end, ProcessEnd::IdleTimeout);
+        assert_eq!(events, vec!["response.done"]);
+
+        client.close().await?;
+        let (received, closed) = server.await.unwrap();
+        assert!(received.is_empty());
+        assert!(closed);
+        Ok(client)
+    })?;
+    drop(client);
+    Ok(())
+}
+
+#[test]
+fn test_server_close_ends_processing() -> Result<(), Box<dyn std::error::Error>> {
+    let client = run_async(async {
+        let (config, server) = serve_once(|mut socket| async move {
+            send_events(
+                &mut socket,
+                &[json!({"type": "session.created"}), json!({"type": "response.done"})],
+            )
+            .await;
+            socket.close(None).await.unwrap();
+        })
+        .await;
+        let mut client = connected_client(config).await?;
+
+        let mut events = Vec::new();
+        let end = client
+            .process_incoming_until(&StopHandle::new(), Some(Duration::from_secs(5)), |event| {
+                events.push(event.event_type);
+                Ok(())
+            })
+            .await?;
+
+        assert_eq!(end, ProcessEnd::Closed);
+        assert_eq!(events, vec!["session.created", "response.done"]);
+        server.await.unwrap();
+        Ok(client)
+    })?;
+    drop(client);
+    Ok(())
+}
+
+#[test]
+fn test_update_session_sends_session_update() -> Result<(), Box<dyn std::error::Error>> {
+    let client = run_async(async {
+        let (config, server) = serve_once(|mut socket| async move {
+            receive_until_close(&mut socket).await
+        })
+        .await;
+        let mut client = connected_client(config).await?;
+
+        let session = RealtimeSessionConfig::new()
+            .with_modalities(["text"])
+            .with_instructions("Answer in one word.")
+            .with_turn_detection(None);
+        client.update_session(&session).await?;
+        client.close().await?;
+
+        let (received, _) = server.await.unwrap();
+        assert_eq!(received.len(), 1);
+        let event = &received[0];
+        assert_eq!(event["type"], "session.update");
+        assert_eq!(event["session"]["modalities"], json!(["text"]));
+        assert_eq!(event["session"]["instructions"], "Answer in one word.");
+        assert_eq!(event["session"]["voice"], "alloy");
+        assert!(event["session"]["turn_detection"].is_null());
+        assert!(event["session"].get("tools").is_none());
+        Ok(client)
+    })?;
+    drop(client);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+struct ReverseArgs {
+    /// The string to reverse
+    text: String,
+}
+
+async fn reverse_string(args: ReverseArgs) -> Result<String, Error> {
+    Ok(args.text.chars().rev().collect())
+}
+
+fn reverse_registry() -> ToolRegistry {
+    let mut registry = ToolRegistry::new();
+    registry.register_fn("reverse_string", "Reverses a given string of text.", reverse_string);
+    registry
+}
+
+#[test]
+fn test_realtime_tool_calls_send_outputs() -> Result<(), Box<dyn std::error::Error>> {
+    let agent = run_async(async {
+        let (config, server) = serve_once(|mut socket| async move {
+            send_events(
+                &mut socket,
+                &[json!({
+                    "type": "response.function_call_arguments.done",
+                    "call_id": "call_1",
+                    "name": "reverse_string",
+                    "arguments": r#"{"text":"abc"}"#,
+                })],
+            )
+            .await;
+
+            // The tool output, then the request to continue
+            let mut received = Vec::new();
+            while received.len() < 2 {
+                match socket.next().await {
+                    Some(Ok(Message::Text(text))) => received.push(serde_json::from_str::<Value>(&text).unwrap()),
+                    Some(Ok(_)) => {}
+                    _ => break,
+                }
+            }
+            send_events(&mut socket, &[json!({"type": "response.done"})]).await;
+            socket.close(None).await.unwrap();
+            received
+        })
+        .await;
+        let client = connected_client(config.clone()).await?;
+        let mut agent = AgentBuilder::new()
+            .with_config(config)
+            .with_tools(reverse_registry())
+            .with_websocket_client(client)
+            .build()?;
+
+        let mut events = Vec::new();
+        let end = agent
+            .handle_realtime_tool_calls(&StopHandle::new(), |event| {
+                events.push(event.event_type.clone());
+                Ok(())
+            })
+            .await?;
+
+        assert_eq!(end, ProcessEnd::Closed);
+        // Function call events are passed on too
+        assert_eq!(events, vec!["response.function_call_arguments.done", "response.done"]);
+
+        let received = server.await.unwrap();
+        assert_eq!(
+            received,
+            vec![
+                json!({
+                    "type": "conversation.item.create",
+                    "item": {
+                        "type": "function_call_output",
+                        "call_id": "call_1",
+                        "output": "cba",
+                    },
+                }),
+                json!({"type": "response.create"}),
+            ]
+        );
+        Ok(agent)
+    })?;
+    drop(agent);
+    Ok(())
+}
+
+#[test]
+fn test_realtime_tool_calls_stop() -> Result<(), Box<dyn std::error::Error>> {
+    let agent = run_async(async {
+        let (config, server) = serve_once(|mut socket| async move {
+            send_events(&mut socket, &[json!({"type": "session.created"})]).await;
+            receive_until_close(&mut socket).await
+        })
+        .await;
+        let client = connected_client(config.clone()).await?;
+        let mut agent = AgentBuilder::new()
+            .with_config(config)
+            .with_tools(reverse_registry())
+            .with_websocket_client(client)
+            .build()?;
+
+        let stop = StopHandle::new();
+        let end = agent
+            .handle_realtime_tool_calls(&stop, |_| {
+                stop.stop();
+                Ok(())
+            })
+            .await?;
+        assert_eq!(end, ProcessEnd::Stopped);
+
+        // Other events do not run tools or send anything
+        let (received, closed) = server.await.unwrap();
+        assert!(received.is_empty());
+        assert!(closed);
+        Ok(agent)
+    })?;
+    drop(agent);
+    Ok(())
+}